@@ -1,10 +1,14 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches, SubCommand};
 use easy_fs::{BlockDevice, EasyFileSystem, Inode};
+use std::convert::TryFrom;
 use std::fs::{read_dir, File, OpenOptions};
 use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
 const BLOCK_SZ: usize = 512;
+/// 内核交换区的块数, 要和 os/src/config.rs 里的 SWAP_BLOCK_COUNT 一致
+const SWAP_BLOCK_COUNT: u64 = 8192;
 
 struct BlockFile(Mutex<File>);
 
@@ -29,8 +33,367 @@ impl BlockDevice for BlockFile {
 }
 
 fn main() {
-    easy_fs_pack().expect("Error when packing easy-fs!");
-    //easy_fs_read_metadata().expect("Error read fs.img");
+    let matches = cli().get_matches();
+    std::process::exit(run(&matches));
+}
+
+/// Command line of easy-fs-fuse: 不带子命令时打包, 子命令检查或修改已有的镜像
+fn cli() -> App<'static, 'static> {
+    let image = || {
+        Arg::with_name("image")
+            .required(true)
+            .help("Path of the image")
+    };
+    let path = |help| Arg::with_name("path").required(true).help(help);
+    return App::new("EasyFileSystem packer")
+        .arg(
+            Arg::with_name("source")
+                .short("s")
+                .long("source")
+                .takes_value(true)
+                .help("Executable source dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("target")
+                .short("t")
+                .long("target")
+                .takes_value(true)
+                .help("Executable target dir(with backslash)"),
+        )
+        .arg(
+            Arg::with_name("root")
+                .short("r")
+                .long("root")
+                .takes_value(true)
+                .help("Host directory copied into the root directory recursively"),
+        )
+        .arg(
+            Arg::with_name("manifest")
+                .short("m")
+                .long("manifest")
+                .takes_value(true)
+                .help("Manifest of the image size and extra files"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .takes_value(true)
+                .help("Size of the filesystem, like 16M, the swap area is added after it"),
+        )
+        .arg(
+            Arg::with_name("inodes")
+                .long("inodes")
+                .takes_value(true)
+                .help("Max number of files in the image"),
+        )
+        .subcommand(
+            SubCommand::with_name("fsck")
+                .about("Check an easy-fs image")
+                .after_help(
+                    "Exit codes: 0 clean, 1 errors corrected, 4 errors left, 8 operational error",
+                )
+                .arg(image())
+                .arg(
+                    Arg::with_name("repair")
+                        .short("r")
+                        .long("repair")
+                        .help("Repair the errors found"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("ls")
+                .about("List a directory in an image")
+                .arg(image())
+                .arg(Arg::with_name("path").help("Path in the image, / by default")),
+        )
+        .subcommand(
+            SubCommand::with_name("cat")
+                .about("Print a file in an image")
+                .arg(image())
+                .arg(path("Path in the image")),
+        )
+        .subcommand(
+            SubCommand::with_name("put")
+                .about("Copy a host file into an image, overwrite the file if it exists")
+                .arg(image())
+                .arg(Arg::with_name("host").required(true).help("Host file"))
+                .arg(path("Path in the image, put into the directory if it is one")),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Copy a file out of an image")
+                .arg(image())
+                .arg(path("Path in the image"))
+                .arg(Arg::with_name("host").required(true).help("Host file")),
+        )
+        .subcommand(
+            SubCommand::with_name("rm")
+                .about("Remove a file or an empty directory in an image")
+                .arg(image())
+                .arg(path("Path in the image"))
+                .arg(
+                    Arg::with_name("recursive")
+                        .short("r")
+                        .help("Remove directories and their contents"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mkdir")
+                .about("Create a directory in an image")
+                .arg(image())
+                .arg(path("Path in the image"))
+                .arg(
+                    Arg::with_name("parents")
+                        .short("p")
+                        .help("Create parent directories as needed, no error if it exists"),
+                ),
+        );
+}
+
+/// Run the command line and return the exit code
+fn run(matches: &ArgMatches) -> i32 {
+    match matches.subcommand() {
+        ("fsck", Some(matches)) => {
+            let image = matches.value_of("image").unwrap();
+            return easy_fs_fsck(image, matches.is_present("repair"));
+        }
+        (command, Some(matches)) => match easy_fs_command(command, matches) {
+            Ok(()) => return 0,
+            Err(e) => {
+                eprintln!("{}: {}", command, e);
+                return 1;
+            }
+        },
+        _ => match easy_fs_pack(matches) {
+            Ok(()) => return 0,
+            Err(e) => {
+                eprintln!("Error when packing easy-fs: {}", e);
+                return 1;
+            }
+        },
+    }
+}
+
+/// Open an existing image and return its root directory
+fn open_image(path: &str) -> Result<Arc<Inode>, String> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .map_err(|e| format!("cannot open {}: {}", path, e))?;
+    let block_file: Arc<dyn BlockDevice> = Arc::new(BlockFile(Mutex::new(f)));
+    // 超级块不对时 open 会 panic
+    let efs = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        EasyFileSystem::open(block_file)
+    }))
+    .map_err(|_| format!("{} is not a valid easy-fs image", path))?;
+    return Ok(Arc::new(EasyFileSystem::root_inode(&efs)));
+}
+
+/// Split `path` into the parent directory and the last name
+fn split_path(path: &str) -> (&str, &str) {
+    let path = path.trim_end_matches('/');
+    match path.rfind('/') {
+        Some(i) => (&path[..i], &path[i + 1..]),
+        None => ("", path),
+    }
+}
+
+/// Read the whole file
+fn read_all(inode: &Inode) -> Vec<u8> {
+    let mut data = vec![0u8; inode.get_inode_size() as usize];
+    let len = inode.read_at(0, &mut data);
+    data.truncate(len);
+    return data;
+}
+
+/// Run a subcommand that reads or modifies an image
+fn easy_fs_command(command: &str, matches: &ArgMatches) -> Result<(), String> {
+    let image = matches.value_of("image").unwrap();
+    let root = open_image(image)?;
+    // 镜像里的inode或目录坏了的时候读写会 panic, 和 fsck 一样当作错误返回
+    return std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        run_command(&root, command, matches)
+    }))
+    .unwrap_or_else(|_| Err(format!("{} is corrupted, run fsck first", image)));
+}
+
+/// Run a subcommand on the image whose root directory is `root`
+fn run_command(root: &Arc<Inode>, command: &str, matches: &ArgMatches) -> Result<(), String> {
+    let path = matches.value_of("path").unwrap_or("/");
+    let find = |path: &str| {
+        root.find(path)
+            .ok_or(format!("{}: no such file or directory", path))
+    };
+    let find_file = |path: &str| {
+        let inode = find(path)?;
+        if inode.is_dir() {
+            return Err(format!("{}: is a directory", path));
+        }
+        return Ok(inode);
+    };
+    let find_dir = |path: &str| {
+        let inode = find(path)?;
+        if !inode.is_dir() {
+            return Err(format!("{}: not a directory", path));
+        }
+        return Ok(inode);
+    };
+    match command {
+        "ls" => {
+            let inode = find(path)?;
+            let mut names = if inode.is_dir() {
+                inode.ls()
+            } else {
+                vec![String::from(split_path(path).1)]
+            };
+            names.sort();
+            let dir = if inode.is_dir() { inode } else { find(split_path(path).0)? };
+            for name in names {
+                let inode = dir.find_nofollow(&name).unwrap();
+                let stat = inode.stat();
+                let (kind, target) = match inode.read_link() {
+                    Some(target) => ('l', format!(" -> {}", target)),
+                    None if stat.is_dir => ('d', String::new()),
+                    None => ('-', String::new()),
+                };
+                println!(
+                    "{} {:>5} {:>3} {:>10} {}{}",
+                    kind, stat.ino, stat.nlink, stat.size, name, target
+                );
+            }
+        }
+        "cat" => {
+            let data = read_all(&*find_file(path)?);
+            std::io::stdout()
+                .write_all(&data)
+                .map_err(|e| e.to_string())?;
+        }
+        "get" => {
+            let data = read_all(&*find_file(path)?);
+            let host = matches.value_of("host").unwrap();
+            std::fs::write(host, data).map_err(|e| format!("{}: {}", host, e))?;
+        }
+        "put" => {
+            let host = matches.value_of("host").unwrap();
+            let data = std::fs::read(host).map_err(|e| format!("{}: {}", host, e))?;
+            // 目标是目录的话, 用主机上的文件名
+            let (dir, name) = match root.find(path) {
+                Some(inode) if inode.is_dir() => {
+                    let name = std::path::Path::new(host).file_name().unwrap();
+                    (inode, String::from(name.to_str().unwrap()))
+                }
+                _ => {
+                    let (parent, name) = split_path(path);
+                    (find_dir(parent)?, String::from(name))
+                }
+            };
+            write_file(&dir, &name, &data)?;
+        }
+        "rm" => {
+            let (parent, name) = split_path(path);
+            remove(&*find_dir(parent)?, name, matches.is_present("recursive"))?;
+        }
+        "mkdir" => {
+            let parents = matches.is_present("parents");
+            let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+            let mut dir = Arc::clone(root);
+            for (i, name) in names.iter().enumerate() {
+                let last = i + 1 == names.len();
+                dir = match dir.find(name) {
+                    Some(inode) if !inode.is_dir() => {
+                        return Err(format!("{}: not a directory", name));
+                    }
+                    Some(_) if last && !parents => return Err(format!("{}: file exists", path)),
+                    Some(inode) => inode,
+                    None if last || parents => find_or_create_dir(&dir, name)?,
+                    None => return Err(format!("{}: no such file or directory", name)),
+                };
+            }
+        }
+        _ => unreachable!(),
+    }
+    return Ok(());
+}
+
+/// Remove `name` under `dir`, 目录要加 -r 才会连里面的内容一起删除
+fn remove(dir: &Inode, name: &str, recursive: bool) -> Result<(), String> {
+    let inode = dir
+        .find_nofollow(name)
+        .ok_or(format!("{}: no such file or directory", name))?;
+    if !inode.is_dir() {
+        if !dir.unlink(name) {
+            return Err(format!("{}: cannot remove file", name));
+        }
+        return Ok(());
+    }
+    if recursive {
+        for child in inode.ls() {
+            remove(&inode, &child, true)?;
+        }
+    }
+    if !dir.rmdir(name) {
+        return Err(format!("{}: cannot remove directory", name));
+    }
+    return Ok(());
+}
+
+/// Exit codes of fsck, 和 e2fsck 一样, 方便在脚本里判断
+const FSCK_OK: i32 = 0;
+const FSCK_CORRECTED: i32 = 1;
+const FSCK_UNCORRECTED: i32 = 4;
+const FSCK_ERROR: i32 = 8;
+
+/// Check the image at `path` and return the exit code
+fn easy_fs_fsck(path: &str, repair: bool) -> i32 {
+    let block_file: Arc<dyn BlockDevice> = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+    {
+        Ok(f) => Arc::new(BlockFile(Mutex::new(f))),
+        Err(e) => {
+            eprintln!("fsck: cannot open {}: {}", path, e);
+            return FSCK_ERROR;
+        }
+    };
+    // 超级块不对, 或者镜像坏得太厉害时会 panic
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let efs = EasyFileSystem::open(block_file);
+        let problems = efs.lock().check(repair);
+        // 修复之后再检查一次, 剩下的就是修不好的
+        let left = if repair && !problems.is_empty() {
+            efs.lock().check(false)
+        } else {
+            problems.clone()
+        };
+        return (problems, left);
+    }));
+    let (problems, left) = match result {
+        Ok(result) => result,
+        Err(_) => {
+            eprintln!("fsck: {} is not a valid easy-fs image", path);
+            return FSCK_ERROR;
+        }
+    };
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.is_empty() {
+        println!("{}: clean", path);
+        return FSCK_OK;
+    }
+    if left.is_empty() {
+        println!("{}: {} problems repaired", path, problems.len());
+        return FSCK_CORRECTED;
+    }
+    if repair {
+        for problem in left.iter() {
+            println!("not repaired: {}", problem);
+        }
+    }
+    println!("{}: {} problems left", path, left.len());
+    return FSCK_UNCORRECTED;
 }
 
 fn easy_fs_read_metadata() -> std::io::Result<()> {
@@ -107,38 +470,187 @@ fn tree(inode: &Arc<Inode>, name: &str, depth: usize) {
     }
 }
 
-#[allow(dead_code)]
-fn easy_fs_pack() -> std::io::Result<()> {
-    let matches = App::new("EasyFileSystem packer")
-        .arg(
-            Arg::with_name("source")
-                .short("s")
-                .long("source")
-                .takes_value(true)
-                .help("Executable source dir(with backslash)"),
-        )
-        .arg(
-            Arg::with_name("target")
-                .short("t")
-                .long("target")
-                .takes_value(true)
-                .help("Executable target dir(with backslash)"),
-        )
-        .get_matches();
-    let src_path = matches.value_of("source").unwrap();
-    let target_path = matches.value_of("target").unwrap();
+/// Default size of the image: 16MiB
+const DEFAULT_IMAGE_BLOCKS: u32 = 16 * 2048;
+/// Default max number of files
+const DEFAULT_INODES: u32 = 4096;
+
+/// Parse a size like 16M, 512K or 8192 (blocks) into the number of blocks
+fn parse_size(size: &str) -> Result<u32, String> {
+    let (number, unit) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len() - 1], 1024 / BLOCK_SZ as u64),
+        Some('M') | Some('m') => (&size[..size.len() - 1], 1024 * 1024 / BLOCK_SZ as u64),
+        _ => (size, 1),
+    };
+    let blocks = number
+        .parse::<u64>()
+        .map_err(|_| format!("bad size {}", size))?
+        * unit;
+    return u32::try_from(blocks).map_err(|_| format!("size {} is too large", size));
+}
+
+/// The manifest of the image, 每行一条:
+/// - `size <镜像大小>`, 例如 16M, 不带单位时是块数
+/// - `inodes <最多的文件数>`
+/// - `copy <主机路径> <镜像里的路径>`, 主机路径相对于 manifest 所在的目录, 目录递归复制
+#[derive(Default)]
+struct Manifest {
+    blocks: Option<u32>,
+    inodes: Option<u32>,
+    copies: Vec<(PathBuf, String)>,
+    /// (链接指向的路径, 镜像里的路径)
+    symlinks: Vec<(String, String)>,
+}
+
+impl Manifest {
+    fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        let mut manifest = Self::default();
+        for (i, line) in text.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let bad_line = || format!("{}:{}: bad line: {}", path, i + 1, line);
+            match words.as_slice() {
+                [] => {}
+                [word, ..] if word.starts_with('#') => {}
+                ["size", size] => manifest.blocks = Some(parse_size(size)?),
+                ["inodes", inodes] => {
+                    manifest.inodes = Some(inodes.parse().map_err(|_| bad_line())?)
+                }
+                ["copy", host, path] => manifest
+                    .copies
+                    .push((base.join(host), String::from(*path))),
+                ["symlink", target, path] => manifest
+                    .symlinks
+                    .push((String::from(*target), String::from(*path))),
+                _ => return Err(bad_line()),
+            }
+        }
+        return Ok(manifest);
+    }
+}
+
+/// Find the directory `name` under `dir`, create it if it does not exist
+fn find_or_create_dir(dir: &Arc<Inode>, name: &str) -> Result<Arc<Inode>, String> {
+    match dir.find(name) {
+        Some(inode) if inode.is_dir() => return Ok(inode),
+        Some(_) => return Err(format!("{}: not a directory", name)),
+        None => {
+            return dir
+                .create_dir(name)
+                .ok_or(format!("{}: cannot create directory", name))
+        }
+    }
+}
+
+/// Find the directory `path` under `root`, 不存在的目录都会被创建
+fn find_or_create_dirs(root: &Arc<Inode>, path: &str) -> Result<Arc<Inode>, String> {
+    let mut dir = Arc::clone(root);
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        dir = find_or_create_dir(&dir, name)?;
+    }
+    return Ok(dir);
+}
+
+/// Create the symlink `name` to `target` under `dir`, 已经存在的链接会被替换
+fn write_symlink(dir: &Arc<Inode>, name: &str, target: &str) -> Result<(), String> {
+    if let Some(inode) = dir.find_nofollow(name) {
+        if !inode.is_symlink() || !dir.unlink(name) {
+            return Err(format!("{}: file exists", name));
+        }
+    }
+    dir.symlink(name, target)
+        .ok_or(format!("{}: cannot create symlink", name))?;
+    return Ok(());
+}
+
+/// Write `data` into the file `name` under `dir`, the file is created or overwritten
+fn write_file(dir: &Arc<Inode>, name: &str, data: &[u8]) -> Result<Arc<Inode>, String> {
+    let file = match dir.find(name) {
+        Some(file) if file.is_dir() => return Err(format!("{}: is a directory", name)),
+        Some(file) => {
+            file.clear();
+            file
+        }
+        None => dir
+            .create(name)
+            .ok_or(format!("{}: cannot create file", name))?,
+    };
+    file.write_at(0, data);
+    return Ok(file);
+}
+
+/// Copy the host file or directory `host` to `name` under `dir`, 目录递归复制, 符号链接原样复制
+fn pack_path(dir: &Arc<Inode>, name: &str, host: &Path) -> Result<(), String> {
+    let host_error = |e: std::io::Error| format!("{}: {}", host.display(), e);
+    if std::fs::symlink_metadata(host)
+        .map_err(host_error)?
+        .file_type()
+        .is_symlink()
+    {
+        let target = std::fs::read_link(host).map_err(host_error)?;
+        return write_symlink(dir, name, &target.to_string_lossy());
+    }
+    if !host.is_dir() {
+        let data = std::fs::read(host).map_err(|e| format!("{}: {}", host.display(), e))?;
+        write_file(dir, name, &data)?;
+        return Ok(());
+    }
+    let dir = if name.is_empty() {
+        Arc::clone(dir)
+    } else {
+        find_or_create_dir(dir, name)?
+    };
+    let mut entries = read_dir(host)
+        .and_then(|entries| entries.collect::<std::io::Result<Vec<_>>>())
+        .map_err(|e| format!("{}: {}", host.display(), e))?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().into_string().unwrap();
+        pack_path(&dir, &name, &entry.path())?;
+    }
+    return Ok(());
+}
+
+fn easy_fs_pack(matches: &ArgMatches) -> Result<(), String> {
+    let src_path = matches.value_of("source").ok_or("missing --source")?;
+    let target_path = matches.value_of("target").ok_or("missing --target")?;
     println!("src_path={}\ntarget_path={}", src_path, target_path);
+    let manifest = match matches.value_of("manifest") {
+        Some(path) => Manifest::load(path)?,
+        None => Manifest::default(),
+    };
+    // 命令行的参数优先于 manifest
+    let total_blocks = match matches.value_of("size") {
+        Some(size) => parse_size(size)?,
+        None => manifest.blocks.unwrap_or(DEFAULT_IMAGE_BLOCKS),
+    };
+    let inodes = match matches.value_of("inodes") {
+        Some(inodes) => inodes.parse().map_err(|_| format!("bad inodes {}", inodes))?,
+        None => manifest.inodes.unwrap_or(DEFAULT_INODES),
+    };
+    // 一个位图块管 4096 个 inode
+    let inode_bitmap_blocks = ((inodes + 4095) / 4096).max(1);
+    let image = format!("{}{}", target_path, "fs.img");
     let block_file = Arc::new(BlockFile(Mutex::new({
         let f = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(format!("{}{}", target_path, "fs.img"))?;
-        f.set_len(16 * 2048 * 512).unwrap();
+            .open(&image)
+            .map_err(|e| format!("{}: {}", image, e))?;
+        // 文件系统之后保留一段区域给内核做交换区, 内核从超级块的总块数算出交换区的位置
+        f.set_len((total_blocks as u64 + SWAP_BLOCK_COUNT) * 512)
+            .unwrap();
         f
     })));
-    // 16MiB, at most 4096 files
-    let efs = EasyFileSystem::create(block_file, 16 * 2048, 1);
+    println!(
+        "image={} blocks={} inodes={}",
+        image,
+        total_blocks,
+        inode_bitmap_blocks * 4096
+    );
+    let efs = EasyFileSystem::create(block_file, total_blocks, inode_bitmap_blocks);
     let root_inode = Arc::new(EasyFileSystem::root_inode(&efs));
     let apps: Vec<_> = read_dir(src_path)
         .unwrap()
@@ -154,42 +666,56 @@ fn easy_fs_pack() -> std::io::Result<()> {
         let mut host_file = File::open(format!("{}{}", target_path, app)).unwrap();
         let mut all_data: Vec<u8> = Vec::new();
         host_file.read_to_end(&mut all_data).unwrap();
-        // create a file in easy-fs
-        let inode = root_inode.create(app.as_str()).unwrap();
-        // write data to easy-fs
-        inode.write_at(0, all_data.as_slice());
+        // create a file in easy-fs and write data to easy-fs
+        write_file(&root_inode, app.as_str(), all_data.as_slice())?;
     }
     // list apps
     for app in root_inode.ls() {
         println!("{}", app);
     }
-    // add dir A
-    let dir_a = root_inode.create_dir("dira").unwrap();
-    let file_c = dir_a.create("filec").unwrap();
-    let dir_b = dir_a.create_dir("dirb").unwrap();
-    let file_d = dir_b.create("filed").unwrap();
-
-    let file_c_content = "3333333";
-    let file_d_content = "4444444444444444444";
-    file_c.write_at(0, file_c_content.as_bytes());
-    file_d.write_at(0, file_d_content.as_bytes());
-    
+    // 把主机上的目录树复制到镜像的根目录
+    if let Some(root) = matches.value_of("root") {
+        pack_path(&root_inode, "", Path::new(root))?;
+    }
+    // 镜像里的路径, 父目录不存在就创建
+    for (host, path) in manifest.copies.iter() {
+        let (parent, name) = split_path(path);
+        pack_path(&find_or_create_dirs(&root_inode, parent)?, name, host)?;
+        println!("{} -> {}", host.display(), path);
+    }
+    for (target, path) in manifest.symlinks.iter() {
+        let (parent, name) = split_path(path);
+        write_symlink(&find_or_create_dirs(&root_inode, parent)?, name, target)?;
+        println!("{} -> {}", path, target);
+    }
     Ok(())
 }
 
+/// 所有测试共用全局的块缓存, 不能并行执行
+#[cfg(test)]
+static TEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// Create an easy-fs of `blocks` blocks on the image file `path`, 每个测试用自己的镜像文件
+#[cfg(test)]
+fn efs_image(path: &str, blocks: u32) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)?;
+    f.set_len(blocks as u64 * BLOCK_SZ as u64)?;
+    let block_file = Arc::new(BlockFile(Mutex::new(f)));
+    EasyFileSystem::create(block_file.clone(), blocks, 1);
+    Ok(block_file)
+}
+
 #[test]
 fn efs_test() -> std::io::Result<()> {
-    let block_file = Arc::new(BlockFile(Mutex::new({
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open("target/fs.img")?;
-        f.set_len(8192 * 512).unwrap();
-        f
-    })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs.img", 4096)?;
     let efs = EasyFileSystem::open(block_file.clone());
+    // 内核从这里算出交换区的起始块号
+    assert_eq!(efs.lock().total_blocks(), 4096);
     let root_inode = EasyFileSystem::root_inode(&efs);
     root_inode.create("filea");
     root_inode.create("fileb");
@@ -251,16 +777,8 @@ fn efs_test() -> std::io::Result<()> {
 
 #[test]
 fn efs_dir_test() -> std::io::Result<()> {
-    let block_file = Arc::new(BlockFile(Mutex::new({
-        let f = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open("target/fs.img")?;
-        f.set_len(8192 * 512).unwrap();
-        f
-    })));
-    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_dir.img", 4096)?;
     let efs = EasyFileSystem::open(block_file.clone());
     let root = Arc::new(EasyFileSystem::root_inode(&efs));
     root.create("filea");
@@ -279,3 +797,950 @@ fn efs_dir_test() -> std::io::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn efs_link_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_link.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+    let inode_bitmap = efs.lock().read_inode_bitmap();
+    let data_bitmap = efs.lock().read_data_bitmap();
+
+    // 硬链接: 删除原名字之后还能通过新名字读到内容, 两个名字都删除后inode和数据块在关闭之后被回收
+    let filea = root.create("filea").unwrap();
+    let content = "1234567890".repeat(200);
+    filea.write_at(0, content.as_bytes());
+    assert!(root.link("fileb", &filea));
+    assert!(!root.link("fileb", &filea));
+    assert_eq!(filea.stat().nlink, 2);
+    assert!(root.unlink("filea"));
+    assert!(root.find("filea").is_none());
+    let fileb = root.find("fileb").unwrap();
+    let mut buffer = [0u8; 2000];
+    assert_eq!(fileb.read_at(0, &mut buffer), content.len());
+    assert_eq!(&buffer[..], content.as_bytes());
+    assert!(root.unlink("fileb"));
+    assert!(!root.unlink("fileb"));
+    assert_eq!(root.ls().len(), 0);
+    // 打开着的文件还能读写, 它的块不会分给别的文件
+    let filec = root.create("filec").unwrap();
+    filec.write_at(0, &[0xffu8; 4096]);
+    assert_eq!(fileb.read_at(0, &mut buffer), content.len());
+    assert_eq!(&buffer[..], content.as_bytes());
+    assert!(root.unlink("filec"));
+    drop(filec);
+    assert_ne!(efs.lock().read_data_bitmap(), data_bitmap);
+    drop(filea);
+    drop(fileb);
+    assert_eq!(efs.lock().read_inode_bitmap(), inode_bitmap);
+    assert_eq!(efs.lock().read_data_bitmap(), data_bitmap);
+
+    // rmdir只能删除空目录, unlink不能删除目录
+    let dira = root.create_dir("dira").unwrap();
+    let dirb = dira.create_dir("dirb").unwrap();
+    dirb.create("filec").unwrap();
+    assert!(!root.unlink("dira"));
+    assert!(!root.rmdir("dira"));
+    assert!(!dirb.rmdir("filec"));
+
+    // rename: 同目录改名, 移动到别的目录, 替换已有文件, 目录不能移动到自己的子目录里
+    assert!(dirb.rename("filec", &dirb, "filed"));
+    assert_eq!(dirb.ls(), vec!["filed"]);
+    assert!(dirb.rename("filed", &root, "filed"));
+    assert!(dirb.ls().is_empty());
+    let filee = root.create("filee").unwrap();
+    filee.write_at(0, "eeee".as_bytes());
+    assert!(root.rename("filee", &root, "filed"));
+    assert_eq!(root.find("filed").unwrap().read_at(0, &mut buffer), 4);
+    assert!(!root.rename("dira", &dirb, "dira"));
+    assert!(!root.rename("filed", &root, "dira"));
+    assert!(root.rename("dira", &root, "dirc"));
+    assert!(root.find("dirc/dirb").is_some());
+    assert!(root.unlink("filed"));
+    drop(filee);
+    assert!(root.find("dirc").unwrap().rmdir("dirb"));
+    assert!(root.rmdir("dirc"));
+    // 删除的目录也要等没人拿着之后才回收
+    assert_ne!(efs.lock().read_inode_bitmap(), inode_bitmap);
+    drop(dira);
+    drop(dirb);
+    assert_eq!(efs.lock().read_inode_bitmap(), inode_bitmap);
+    assert_eq!(efs.lock().read_data_bitmap(), data_bitmap);
+
+    // 目录项多到需要一级索引块, 全部删除之后数据块也都要回收
+    let dir = root.create_dir("big").unwrap();
+    for i in 0..500 {
+        dir.create(format!("file-{}", i).as_str()).unwrap();
+    }
+    for i in (0..500).rev().step_by(2).chain((0..500).step_by(2)) {
+        assert!(dir.unlink(format!("file-{}", i).as_str()));
+    }
+    assert!(root.rmdir("big"));
+    drop(dir);
+    assert_eq!(efs.lock().read_inode_bitmap(), inode_bitmap);
+    assert_eq!(efs.lock().read_data_bitmap(), data_bitmap);
+    Ok(())
+}
+
+#[test]
+fn efs_stat_test() -> std::io::Result<()> {
+    use easy_fs::set_clock;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_stat.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+
+    static TIME: AtomicU64 = AtomicU64::new(100);
+    set_clock(|| TIME.load(Ordering::Relaxed));
+    let filea = root.create("filea").unwrap();
+    let stat = filea.stat();
+    assert!(!stat.is_dir);
+    assert_eq!((stat.mode, stat.nlink, stat.size), (0o644, 1, 0));
+    assert_eq!((stat.atime, stat.mtime, stat.ctime), (100, 100, 100));
+    assert_eq!(root.stat().ino, 0);
+    assert!(root.stat().is_dir);
+
+    // 写文件更新mtime/ctime, 读文件只更新atime
+    TIME.store(200, Ordering::Relaxed);
+    filea.write_at(0, &[1u8; 30 * BLOCK_SZ]);
+    let stat = filea.stat();
+    assert_eq!((stat.size, stat.blocks), (30 * BLOCK_SZ as u64, 31));
+    assert_eq!((stat.atime, stat.mtime, stat.ctime), (100, 200, 200));
+    TIME.store(300, Ordering::Relaxed);
+    filea.read_at(0, &mut [0u8; 16]);
+    let stat = filea.stat();
+    assert_eq!((stat.atime, stat.mtime, stat.ctime), (300, 200, 200));
+    assert_eq!(root.stat().mtime, 100);
+
+    // 改名只修改inode本身的ctime
+    TIME.store(400, Ordering::Relaxed);
+    assert!(root.rename("filea", &root, "fileb"));
+    let stat = root.find("fileb").unwrap().stat();
+    assert_eq!((stat.mtime, stat.ctime), (200, 400));
+    assert_eq!(root.stat().mtime, 400);
+    set_clock(|| 0);
+    Ok(())
+}
+
+#[test]
+fn efs_dots_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_dots.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+
+    // 根目录的 . 和 .. 都是自己, ls不列出 . 和 ..
+    assert_eq!(root.find("..").unwrap().stat().ino, 0);
+    assert_eq!(root.path().unwrap(), "/");
+    let dira = root.create_dir("dira").unwrap();
+    let dirb = dira.create_dir("dirb").unwrap();
+    let filec = dirb.create("filec").unwrap();
+    assert_eq!(root.ls(), vec!["dira"]);
+    assert_eq!(root.find("dira/dirb/../dirb/./filec").unwrap().stat().ino, filec.stat().ino);
+    assert_eq!(dirb.find("../..").unwrap().stat().ino, 0);
+    assert_eq!(dirb.path().unwrap(), "/dira/dirb");
+    assert!(filec.path().is_none());
+
+    // 目录的链接数: 父目录里的目录项 + 自己的 . + 每个子目录的 ..
+    assert_eq!(root.stat().nlink, 3);
+    assert_eq!(dira.stat().nlink, 3);
+    assert_eq!(dirb.stat().nlink, 2);
+    assert!(!dirb.rmdir("."));
+    assert!(!dira.rename("..", &root, "x"));
+    assert!(dirb.create(".").is_none());
+
+    // 目录移动到别的父目录之后, .. 也跟着改变
+    assert!(dira.rename("dirb", &root, "dirc"));
+    assert_eq!(dirb.path().unwrap(), "/dirc");
+    assert_eq!(dirb.find("..").unwrap().stat().ino, 0);
+    assert_eq!((root.stat().nlink, dira.stat().nlink), (4, 2));
+
+    // 删除之后就拿不到路径了, 也不能在里面创建文件
+    assert!(dirb.unlink("filec"));
+    assert!(root.rmdir("dirc"));
+    assert_eq!(root.stat().nlink, 3);
+    assert!(dirb.path().is_none());
+    assert!(dirb.create("filed").is_none());
+    Ok(())
+}
+
+#[test]
+fn efs_dirent_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_dirent.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+
+    // 目录项按磁盘上的顺序读出来, 最前面是 . 和 ..
+    let dira = root.create_dir("dira").unwrap();
+    let filea = root.create("filea").unwrap();
+    let entries = |dir: &Inode| {
+        let mut v = Vec::new();
+        let mut pos = 0;
+        while let Some((dirent, next)) = dir.read_dirent(pos) {
+            v.push((String::from(dirent.name()), dirent.inode_number(), dirent.is_dir()));
+            pos = next;
+        }
+        v
+    };
+    assert_eq!(
+        entries(&root),
+        vec![
+            (String::from("."), 0, true),
+            (String::from(".."), 0, true),
+            (String::from("dira"), dira.stat().ino, true),
+            (String::from("filea"), filea.stat().ino, false),
+        ]
+    );
+    assert_eq!(entries(&dira).len(), 2);
+    assert!(root.is_dir() && !filea.is_dir());
+    assert!(filea.read_dirent(0).is_none());
+
+    // 长文件名: 每个目录项200多字节, 一个块放两个, 目录会占好几个块
+    let name = |i: usize| format!("{:03}{}", i, "x".repeat(197));
+    for i in 0..20 {
+        dira.create(&name(i)).unwrap().write_at(0, name(i).as_bytes());
+    }
+    assert!(dira.create(&"y".repeat(256)).is_none());
+    assert!(dira.create(&"y".repeat(255)).is_some());
+    assert_eq!(dira.ls().len(), 21);
+    assert_eq!(entries(&dira).len(), 23);
+    let mut buf = [0u8; 200];
+    assert_eq!(dira.find(&name(7)).unwrap().read_at(0, &mut buf), 200);
+    assert_eq!(&buf[..], name(7).as_bytes());
+    let size = dira.stat().size;
+    assert_eq!(size as usize % BLOCK_SZ, 0);
+    // 删掉的位置可以被新的目录项重用, 末尾空了的块会被回收
+    for i in (0..20).step_by(2) {
+        assert!(dira.unlink(&name(i)));
+    }
+    for i in (0..20).step_by(2) {
+        dira.create(&name(i)).unwrap();
+    }
+    assert_eq!(dira.stat().size, size);
+    for i in 0..20 {
+        assert!(dira.unlink(&name(i)));
+    }
+    assert!(dira.unlink(&"y".repeat(255)));
+    assert_eq!(dira.stat().size as usize, BLOCK_SZ);
+    assert_eq!(entries(&dira).len(), 2);
+    assert!(root.rename("filea", &dira, &"z".repeat(255)));
+    assert_eq!(dira.ls(), vec!["z".repeat(255)]);
+    assert!(efs.lock().check(false).is_empty());
+    Ok(())
+}
+
+#[test]
+fn efs_symlink_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_symlink.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = Arc::new(EasyFileSystem::root_inode(&efs));
+
+    let usr = root.create_dir("usr").unwrap();
+    let hello = usr.create("hello").unwrap();
+    hello.write_at(0, b"hello elf");
+    let bin = root.create_dir("bin").unwrap();
+    // 绝对路径从根目录开始, 相对路径从链接所在的目录开始
+    let abs = bin.symlink("hello", "/usr/hello").unwrap();
+    bin.symlink("hi", "hello").unwrap();
+    root.symlink("lib", "usr").unwrap();
+    bin.symlink("up", "../usr/").unwrap();
+    assert_eq!(abs.read_link().unwrap(), "/usr/hello");
+    assert!(abs.is_symlink() && abs.stat().is_symlink);
+    assert_eq!(abs.stat().size, 10);
+    for path in ["bin/hello", "bin/hi", "lib/hello", "bin/up/hello", "bin/up/../bin/hi"] {
+        assert_eq!(root.find(path).unwrap().stat().ino, hello.stat().ino);
+    }
+    assert_eq!(read_all(&root.find("bin/hi").unwrap()), b"hello elf");
+    // 只有最后一个名字不跟随
+    assert_eq!(root.find_nofollow("bin/hi").unwrap().read_link().unwrap(), "hello");
+    assert_eq!(root.find_nofollow("lib/hello").unwrap().stat().ino, hello.stat().ino);
+    assert!(hello.read_link().is_none());
+
+    // 悬空的链接和链接成环都找不到
+    bin.symlink("dangling", "/nowhere").unwrap();
+    bin.symlink("loop1", "loop2").unwrap();
+    bin.symlink("loop2", "loop1").unwrap();
+    assert!(root.find("bin/dangling").is_none());
+    assert!(root.find("bin/loop1").is_none());
+    assert!(root.find_nofollow("bin/loop1").is_some());
+    // 40层以内的链接都能跟随
+    let mut prev = String::from("/usr/hello");
+    for i in 0..40 {
+        bin.symlink(&format!("chain{}", i), &prev).unwrap();
+        prev = format!("chain{}", i);
+    }
+    assert!(root.find("bin/chain39").is_some());
+    bin.symlink("chain40", "chain39").unwrap();
+    assert!(root.find("bin/chain40").is_none());
+    assert!(bin.symlink("empty", "").is_none());
+    assert!(bin.symlink("hello", "/usr").is_none());
+
+    // 目录项记着链接类型, 删除链接不影响目标, 链接也可以有硬链接
+    assert!(bin.link("hello2", &abs));
+    assert_eq!(abs.stat().nlink, 2);
+    assert!(bin.unlink("hello"));
+    assert!(root.find("bin/hello2").is_some());
+    assert!(bin.unlink("hello2"));
+    assert_eq!(hello.stat().nlink, 1);
+    let mut pos = 0;
+    while let Some((dirent, next)) = root.read_dirent(pos) {
+        assert_eq!(dirent.file_type() == easy_fs::FT_SYMLINK, dirent.name() == "lib");
+        pos = next;
+    }
+    drop(abs);
+    assert!(efs.lock().check(false).is_empty());
+    Ok(())
+}
+
+/// 写够`writes_left`次之后就"断电"了, 之后的写入全部丢掉
+#[cfg(test)]
+struct CrashBlockFile {
+    file: Arc<BlockFile>,
+    writes_left: Mutex<usize>,
+}
+
+#[cfg(test)]
+impl BlockDevice for CrashBlockFile {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+        self.file.read_block(block_id, buf);
+    }
+
+    fn write_block(&self, block_id: usize, buf: &[u8]) {
+        let mut writes_left = self.writes_left.lock().unwrap();
+        if *writes_left > 0 {
+            *writes_left -= 1;
+            self.file.write_block(block_id, buf);
+        }
+    }
+
+    fn handle_irq(&self) {
+        unimplemented!();
+    }
+}
+
+#[test]
+fn efs_journal_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_journal.img", 4096)?;
+    let count_ones = |bitmap: [u64; 64]| bitmap.iter().map(|bits| bits.count_ones()).sum::<u32>();
+    let mut outcomes = (false, false);
+    // 在创建目录的每一次写之后断电, 重新挂载之后要么什么都没发生, 要么目录完整地创建好了
+    for writes in 0..64 {
+        EasyFileSystem::create(block_file.clone(), 4096, 1);
+        let crash_file: Arc<dyn BlockDevice> = Arc::new(CrashBlockFile {
+            file: block_file.clone(),
+            writes_left: Mutex::new(writes),
+        });
+        let efs = EasyFileSystem::open(crash_file);
+        let root = EasyFileSystem::root_inode(&efs);
+        let dira = root.create_dir("dira").unwrap();
+        dira.create("filea").unwrap().write_at(0, b"hello");
+
+        let efs = EasyFileSystem::open(block_file.clone());
+        let root = EasyFileSystem::root_inode(&efs);
+        let inodes = count_ones(efs.lock().read_inode_bitmap());
+        let data_blocks = count_ones(efs.lock().read_data_bitmap());
+        assert!(efs.lock().check(false).is_empty());
+        match root.find("dira") {
+            None => {
+                outcomes.0 = true;
+                assert!(root.ls().is_empty());
+                assert_eq!((inodes, data_blocks), (1, 1));
+                assert_eq!(root.stat().nlink, 2);
+            }
+            Some(dira) => {
+                outcomes.1 = true;
+                assert_eq!(root.stat().nlink, 3);
+                assert_eq!(dira.find("..").unwrap().stat().ino, 0);
+                // filea 可能还没创建, 也可能已经写好了内容
+                match dira.find("filea") {
+                    None => assert_eq!((inodes, data_blocks), (2, 2)),
+                    Some(filea) => {
+                        let mut buf = [0u8; 8];
+                        let len = filea.read_at(0, &mut buf);
+                        assert!(len == 0 || &buf[..len] == b"hello");
+                        assert_eq!(inodes, 3);
+                        assert_eq!(data_blocks, if len == 0 { 2 } else { 3 });
+                    }
+                }
+            }
+        }
+    }
+    assert_eq!(outcomes, (true, true));
+    Ok(())
+}
+
+#[test]
+fn efs_fsck_test() -> std::io::Result<()> {
+    use easy_fs::FsckProblem;
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_fsck.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let dira = root.create_dir("dira").unwrap();
+    let filea = dira.create("filea").unwrap();
+    filea.write_at(0, &[1u8; 3 * BLOCK_SZ]);
+    let fileb = root.create("fileb").unwrap();
+    fileb.write_at(0, &[2u8; BLOCK_SZ]);
+    assert!(efs.lock().check(false).is_empty());
+
+    // 直接改磁盘上的块, 制造各种问题
+    let patch = |block_id: u32, offset: usize, bytes: &[u8]| {
+        let mut buf = [0u8; BLOCK_SZ];
+        block_file.read_block(block_id as usize, &mut buf);
+        buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+        block_file.write_block(block_id as usize, &buf);
+    };
+    let leaked = efs.lock().alloc_data();
+    let orphan = efs.lock().alloc_inode();
+    root.create("bad").unwrap();
+    let (filea_ino, fileb_ino) = (filea.stat().ino, fileb.stat().ino);
+    // 重新挂载, 把块缓存都写回去再改
+    let efs = EasyFileSystem::open(block_file.clone());
+    let inode_pos = |ino: u32| efs.lock().get_disk_inode_pos(ino);
+    let (block_id, offset) = inode_pos(filea_ino);
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(block_id as usize, &mut buf);
+    let filea_block = [
+        buf[offset + 4],
+        buf[offset + 5],
+        buf[offset + 6],
+        buf[offset + 7],
+    ];
+    // filea 的链接数改成 5
+    patch(block_id, offset + 122, &5u16.to_le_bytes());
+    // fileb 的第一个数据块和 filea 的一样
+    let (block_id, offset) = inode_pos(fileb_ino);
+    patch(block_id, offset + 4, &filea_block);
+    // 根目录里"bad"这个目录项指向不存在的inode, fileb 的目录项记成了目录
+    let (block_id, offset) = inode_pos(0);
+    block_file.read_block(block_id as usize, &mut buf);
+    let root_block = u32::from_le_bytes([
+        buf[offset + 4],
+        buf[offset + 5],
+        buf[offset + 6],
+        buf[offset + 7],
+    ]);
+    block_file.read_block(root_block as usize, &mut buf);
+    let name_pos = |name: &[u8]| buf.windows(name.len()).position(|w| w == name).unwrap();
+    // 目录项头部: inode(4) rec_len(2) name_len(1) file_type(1), 后面是名字
+    let (bad_pos, fileb_pos) = (name_pos(b"bad") - 8, name_pos(b"fileb") - 8);
+    patch(root_block, bad_pos, &9999u32.to_le_bytes());
+    patch(root_block, fileb_pos + 7, &[easy_fs::FT_DIR]);
+    drop(root);
+
+    let efs = EasyFileSystem::open(block_file.clone());
+    let problems = efs.lock().check(true);
+    let bad_ino = problems.iter().find_map(|problem| match problem {
+        FsckProblem::BadDirEntry { inode, .. } => Some(*inode),
+        _ => None,
+    });
+    assert_eq!(bad_ino, Some(9999));
+    assert!(problems.contains(&FsckProblem::WrongFileType {
+        dir: 0,
+        offset: fileb_pos,
+        inode: fileb_ino,
+    }));
+    assert!(problems.contains(&FsckProblem::LeakedBlock { block_id: leaked }));
+    assert!(problems.contains(&FsckProblem::OrphanInode { inode: orphan }));
+    assert!(problems.contains(&FsckProblem::WrongLinkCount {
+        inode: filea_ino,
+        found: 5,
+        expected: 1,
+    }));
+    // 先遍历到的 fileb 保留这个块, filea 拿到一份复制
+    assert!(problems.contains(&FsckProblem::DoubleAllocatedBlock {
+        block_id: u32::from_le_bytes(filea_block),
+        first: fileb_ino,
+        inode: filea_ino,
+    }));
+    // "bad" 这个inode没有被别的目录项引用, 也成了孤儿
+    assert!(problems
+        .iter()
+        .any(|problem| matches!(problem, FsckProblem::OrphanInode { .. })));
+
+    // 修复之后再检查是干净的, 两个文件的内容都还在
+    assert!(efs.lock().check(false).is_empty());
+    let efs = EasyFileSystem::open(block_file.clone());
+    assert!(efs.lock().check(false).is_empty());
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls(), vec!["dira", "fileb"]);
+    let filea = root.find("dira/filea").unwrap();
+    let mut buf = [0u8; 3 * BLOCK_SZ];
+    assert_eq!(filea.read_at(0, &mut buf), 3 * BLOCK_SZ);
+    assert!(buf.iter().all(|b| *b == 1));
+    assert_eq!(filea.stat().nlink, 1);
+    let fileb = root.find("fileb").unwrap();
+    assert_eq!(fileb.read_at(0, &mut buf), BLOCK_SZ);
+    assert!(buf[..BLOCK_SZ].iter().all(|b| *b == 1));
+    Ok(())
+}
+
+#[test]
+fn efs_fsck_bad_inode_test() -> std::io::Result<()> {
+    use easy_fs::FsckProblem;
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_fsck_bad_inode.img", 4096)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let dira = root.create_dir("dira").unwrap();
+    let filea = dira.create("filea").unwrap();
+    filea.write_at(0, &[1u8; BLOCK_SZ]);
+    let fileb = root.create("fileb").unwrap();
+    fileb.write_at(0, &[2u8; BLOCK_SZ]);
+    let (dira_ino, filea_ino) = (dira.stat().ino, filea.stat().ino);
+    drop((root, dira, filea, fileb));
+
+    // dira 的类型字节改成不存在的类型
+    let efs = EasyFileSystem::open(block_file.clone());
+    let (block_id, offset) = efs.lock().get_disk_inode_pos(dira_ino);
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(block_id as usize, &mut buf);
+    buf[offset + 124] = 0x07;
+    block_file.write_block(block_id as usize, &buf);
+
+    let efs = EasyFileSystem::open(block_file.clone());
+    let problems = efs.lock().check(true);
+    assert!(problems.contains(&FsckProblem::BadInode { inode: dira_ino }));
+    assert!(problems.iter().any(|problem| matches!(
+        problem,
+        FsckProblem::BadDirEntry { dir: 0, inode, .. } if *inode == dira_ino
+    )));
+    // 不能按坏的类型去改目录项
+    assert!(!problems
+        .iter()
+        .any(|problem| matches!(problem, FsckProblem::WrongFileType { .. })));
+    assert!(problems.contains(&FsckProblem::OrphanInode { inode: filea_ino }));
+
+    // 修复之后 dira 和它下面的文件都没了, fileb 还在
+    let efs = EasyFileSystem::open(block_file.clone());
+    assert!(efs.lock().check(false).is_empty());
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls(), vec!["fileb"]);
+    let fileb = root.find("fileb").unwrap();
+    assert_eq!(fileb.read_at(0, &mut buf), BLOCK_SZ);
+    assert!(buf.iter().all(|b| *b == 2));
+    Ok(())
+}
+
+#[test]
+fn efs_cache_test() -> std::io::Result<()> {
+    use easy_fs::{block_cache_stats, set_block_cache_size};
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_cache.img", 4096)?;
+    // 写文件时 inode 块, 索引块和数据块同时被引用, 缓存只有2个块也不能 panic
+    set_block_cache_size(2);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.create_dir("dira").unwrap().create("filea").unwrap();
+    let data: Vec<u8> = (0..200 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    filea.write_at(0, &data);
+    let before = block_cache_stats();
+    assert!(before.cached <= 2);
+    assert!(before.evictions > 0 && before.writebacks > 0);
+
+    // 重新挂载之后从磁盘读回来
+    set_block_cache_size(512);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.find("dira/filea").unwrap();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(filea.read_at(0, &mut buf), data.len());
+    assert!(buf == data);
+    let after = block_cache_stats();
+    assert!(after.misses > before.misses);
+    // 第二次读全部命中
+    filea.read_at(0, &mut buf);
+    let again = block_cache_stats();
+    assert_eq!(again.misses, after.misses);
+    assert!(again.hits > after.hits);
+    set_block_cache_size(16);
+    Ok(())
+}
+
+#[test]
+fn efs_large_file_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    // 文件要比旧格式的上限(21 + 128 + 128 * 128 个块)还大
+    let block_file = efs_image("target/efs_large_file.img", 20480)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    assert!(efs.lock().large_file());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.create("filea").unwrap();
+    // 19个直接索引, 一级, 二级索引都用满, 三级索引再放300个块
+    let blocks = 19 + 128 + 128 * 128 + 300;
+    let block = |i: usize| vec![(i % 251) as u8; BLOCK_SZ];
+    let data: Vec<u8> = (0..blocks).flat_map(block).collect();
+    assert_eq!(filea.write_at(0, &data), data.len());
+    let stat = filea.stat();
+    assert_eq!(stat.size, data.len() as u64);
+    // 索引块: 一级1个, 二级1 + 128个, 三级1 + 1 + 3个
+    assert_eq!(stat.blocks as usize, blocks + 1 + 129 + 5);
+    drop(filea);
+
+    // 重新挂载, 在每一级的边界上读
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.find("filea").unwrap();
+    for i in [0, 18, 19, 146, 147, 16530, 16531, 16659, blocks - 1] {
+        let mut buf = vec![0u8; BLOCK_SZ];
+        assert_eq!(filea.read_at(i * BLOCK_SZ, &mut buf), BLOCK_SZ);
+        assert!(buf == block(i));
+    }
+    assert!(efs.lock().check(false).is_empty());
+    // 删除之后索引块都回收了, 没有泄漏的块
+    assert!(root.unlink("filea"));
+    drop(filea);
+    assert!(efs.lock().check(false).is_empty());
+
+    // 旧镜像: 超级块没有特性位, inode的标志字节是0
+    EasyFileSystem::create(block_file.clone(), 4096, 1);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let (block_id, offset) = efs.lock().get_disk_inode_pos(0);
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(0, &mut buf);
+    buf[28..32].copy_from_slice(&0u32.to_le_bytes());
+    block_file.write_block(0, &buf);
+    block_file.read_block(block_id as usize, &mut buf);
+    buf[offset + 125] = 0;
+    block_file.write_block(block_id as usize, &buf);
+    let efs = EasyFileSystem::open(block_file.clone());
+    assert!(!efs.lock().large_file());
+    let root = EasyFileSystem::root_inode(&efs);
+    let fileb = root.create("fileb").unwrap();
+    // 旧格式有21个直接索引, 148个块只用到一级索引
+    let data: Vec<u8> = (0..148).flat_map(block).collect();
+    fileb.write_at(0, &data);
+    assert_eq!(fileb.stat().blocks, 149);
+    // 旧格式最多 16533 个块
+    assert_eq!(fileb.write_at(16533 * BLOCK_SZ, &[1]), 0);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let fileb = EasyFileSystem::root_inode(&efs).find("fileb").unwrap();
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(fileb.read_at(0, &mut buf), data.len());
+    assert!(buf == data);
+    assert!(efs.lock().check(false).is_empty());
+    Ok(())
+}
+
+#[test]
+fn efs_truncate_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_truncate.img", 20480)?;
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.create("filea").unwrap();
+    // 19个直接索引, 一级索引用满, 二级索引再放300个块
+    let blocks = 19 + 128 + 300;
+    let data: Vec<u8> = (0..blocks * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    assert_eq!(filea.write_at(0, &data), data.len());
+    assert_eq!(filea.stat().blocks as usize, blocks + 1 + 1 + 3);
+
+    // 变短时只回收后面的块, 二级索引下面空出来的索引块也回收
+    let size = (19 + 128 + 10) * BLOCK_SZ + 7;
+    assert!(filea.truncate(size as u64));
+    let stat = filea.stat();
+    assert_eq!(stat.size, size as u64);
+    assert_eq!(stat.blocks as usize, 158 + 1 + 1 + 1);
+    let mut buf = vec![0u8; BLOCK_SZ];
+    assert_eq!(filea.read_at(size - 100, &mut buf), 100);
+    assert!(buf[..100] == data[size - 100..size]);
+    assert!(efs.lock().check(false).is_empty());
+    // 二级索引整个不用了
+    assert!(filea.truncate(19 * BLOCK_SZ as u64 + 1));
+    assert_eq!(filea.stat().blocks, 20 + 1);
+    assert!(efs.lock().check(false).is_empty());
+
+    // 变长的部分是0, 包括原来最后一个块里文件末尾之后的部分
+    let size = (19 + 128 + 128 + 5) * BLOCK_SZ;
+    assert!(filea.truncate(size as u64));
+    assert_eq!(filea.stat().blocks as usize, 280 + 1 + 1 + 2);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    let filea = root.find("filea").unwrap();
+    let mut buf = vec![0xffu8; size];
+    assert_eq!(filea.read_at(0, &mut buf), size);
+    assert!(buf[..19 * BLOCK_SZ + 1] == data[..19 * BLOCK_SZ + 1]);
+    assert!(buf[19 * BLOCK_SZ + 1..].iter().all(|b| *b == 0));
+    assert!(efs.lock().check(false).is_empty());
+
+    // 超过最大长度和不是普通文件的不能截断
+    assert!(!filea.truncate(u64::MAX));
+    assert_eq!(filea.stat().size, size as u64);
+    root.create_dir("dira").unwrap();
+    assert!(!root.find("dira").unwrap().truncate(0));
+    assert!(filea.truncate(0));
+    assert_eq!(filea.stat().blocks, 0);
+    assert!(efs.lock().check(false).is_empty());
+    Ok(())
+}
+
+#[test]
+fn efs_cli_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let block_file = efs_image("target/efs_cli.img", 4096)?;
+    let run_args = |args: &[&str]| {
+        let args = ["easy-fs-fuse"].iter().chain(args.iter());
+        return run(&cli().get_matches_from(args));
+    };
+    let data: Vec<u8> = (0..3000).map(|i| (i % 256) as u8).collect();
+    std::fs::write("target/cli_in", &data)?;
+
+    assert_eq!(run_args(&["mkdir", "target/efs_cli.img", "dira/dirb"]), 1);
+    assert_eq!(run_args(&["mkdir", "-p", "target/efs_cli.img", "dira/dirb"]), 0);
+    assert_eq!(run_args(&["mkdir", "target/efs_cli.img", "dira"]), 1);
+    // 放进目录时用主机上的文件名, 已有的文件被覆盖
+    assert_eq!(run_args(&["put", "target/efs_cli.img", "target/cli_in", "dira/dirb"]), 0);
+    assert_eq!(run_args(&["put", "target/efs_cli.img", "target/cli_in", "dira/filea"]), 0);
+    assert_eq!(run_args(&["put", "target/efs_cli.img", "target/cli_in", "dira/filea"]), 0);
+    assert_eq!(run_args(&["put", "target/efs_cli.img", "target/cli_in", "nodir/filea"]), 1);
+    assert_eq!(run_args(&["ls", "target/efs_cli.img", "dira"]), 0);
+    assert_eq!(run_args(&["get", "target/efs_cli.img", "dira/dirb/cli_in", "target/cli_out"]), 0);
+    assert_eq!(std::fs::read("target/cli_out")?, data);
+    assert_eq!(run_args(&["get", "target/efs_cli.img", "dira", "target/cli_out"]), 1);
+    assert_eq!(run_args(&["cat", "target/efs_cli.img", "nothere"]), 1);
+
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.find("dira/filea").unwrap().get_inode_size(), 3000);
+    assert_eq!(root.find("dira").unwrap().ls(), vec!["dirb", "filea"]);
+
+    // 非空目录要加 -r
+    assert_eq!(run_args(&["rm", "target/efs_cli.img", "dira"]), 1);
+    assert_eq!(run_args(&["rm", "target/efs_cli.img", "dira/filea"]), 0);
+    assert_eq!(run_args(&["rm", "-r", "target/efs_cli.img", "dira"]), 0);
+    let efs = EasyFileSystem::open(block_file.clone());
+    assert!(EasyFileSystem::root_inode(&efs).ls().is_empty());
+    assert!(efs.lock().check(false).is_empty());
+    assert_eq!(run_args(&["ls", "target/cli_in"]), 1);
+
+    // 文件大小坏了的时候读文件会 panic, 要当作错误返回
+    assert_eq!(run_args(&["put", "target/efs_cli.img", "target/cli_in", "filea"]), 0);
+    let efs = EasyFileSystem::open(block_file.clone());
+    let ino = EasyFileSystem::root_inode(&efs).find("filea").unwrap().stat().ino;
+    let (block_id, offset) = efs.lock().get_disk_inode_pos(ino);
+    let mut buf = [0u8; BLOCK_SZ];
+    block_file.read_block(block_id as usize, &mut buf);
+    buf[offset..offset + 4].copy_from_slice(&0x7fffffffu32.to_le_bytes());
+    block_file.write_block(block_id as usize, &buf);
+    assert_eq!(run_args(&["cat", "target/efs_cli.img", "filea"]), 1);
+    assert_eq!(run_args(&["get", "target/efs_cli.img", "filea", "target/cli_out"]), 1);
+    Ok(())
+}
+
+#[test]
+fn efs_pack_test() -> std::io::Result<()> {
+    let _guard = TEST_LOCK.lock();
+    let dir = Path::new("target/pack_test");
+    if dir.exists() {
+        std::fs::remove_dir_all(dir)?;
+    }
+    for sub in ["src", "bin", "rootfs/etc", "rootfs/home/user"] {
+        std::fs::create_dir_all(dir.join(sub))?;
+    }
+    std::fs::write(dir.join("src/hello.rs"), "")?;
+    std::fs::write(dir.join("bin/hello"), "hello elf")?;
+    std::fs::write(dir.join("rootfs/etc/motd"), "welcome")?;
+    std::fs::write(dir.join("rootfs/home/user/notes"), "notes")?;
+    std::fs::write(dir.join("extra"), "extra file")?;
+    std::os::unix::fs::symlink("motd", dir.join("rootfs/etc/issue"))?;
+    std::fs::write(
+        dir.join("fs.manifest"),
+        "# test\nsize 2M\ninodes 100\ncopy rootfs /\ncopy extra /usr/share/extra\n\
+         symlink /hello /bin/hello\n",
+    )?;
+    let pack = |args: &[&str]| {
+        let args = ["easy-fs-fuse", "-s", "target/pack_test/src/", "-t", "target/pack_test/bin/"]
+            .iter()
+            .chain(args.iter());
+        return run(&cli().get_matches_from(args));
+    };
+    assert_eq!(pack(&["-m", "target/pack_test/fs.manifest"]), 0);
+    let image = std::fs::metadata(dir.join("bin/fs.img"))?;
+    assert_eq!(image.len(), (2 * 2048 + SWAP_BLOCK_COUNT) * BLOCK_SZ as u64);
+
+    let block_file = Arc::new(BlockFile(Mutex::new(
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(dir.join("bin/fs.img"))?,
+    )));
+    let efs = EasyFileSystem::open(block_file.clone());
+    let root = EasyFileSystem::root_inode(&efs);
+    assert_eq!(root.ls(), vec!["hello", "etc", "home", "usr", "bin"]);
+    let cat = |path: &str| String::from_utf8(read_all(&root.find(path).unwrap())).unwrap();
+    assert_eq!(cat("hello"), "hello elf");
+    assert_eq!(cat("etc/motd"), "welcome");
+    assert_eq!(cat("home/user/notes"), "notes");
+    assert_eq!(cat("usr/share/extra"), "extra file");
+    // 主机上的符号链接原样复制, manifest 里的链接父目录不存在就创建
+    assert_eq!(root.find_nofollow("etc/issue").unwrap().read_link().unwrap(), "motd");
+    assert_eq!(cat("etc/issue"), "welcome");
+    assert_eq!(cat("bin/hello"), "hello elf");
+    assert!(efs.lock().check(false).is_empty());
+
+    // 命令行的大小优先于 manifest, 大小写错了打包失败
+    assert_eq!(pack(&["-m", "target/pack_test/fs.manifest", "--size", "1M"]), 0);
+    let image = std::fs::metadata(dir.join("bin/fs.img"))?;
+    assert_eq!(image.len(), (2048 + SWAP_BLOCK_COUNT) * BLOCK_SZ as u64);
+    assert_eq!(pack(&["--size", "1X"]), 1);
+    Ok(())
+}
+
+#[cfg(test)]
+fn fat_image(path: &str, sectors: u64) -> std::io::Result<Arc<BlockFile>> {
+    let f = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)?;
+    f.set_len(sectors * BLOCK_SZ as u64)?;
+    Ok(Arc::new(BlockFile(Mutex::new(f))))
+}
+
+#[test]
+fn fat_test() -> std::io::Result<()> {
+    use easy_fs::{block_cache_sync_all, FatFileSystem};
+    let _guard = TEST_LOCK.lock();
+    let block_file = fat_image("target/fat.img", 65536)?;
+    let fs = FatFileSystem::format(block_file.clone(), 65536, 1);
+    let free = fs.lock().free_clusters();
+    let root = FatFileSystem::root_inode(&fs);
+    assert!(root.ls().is_empty());
+
+    // 8.3的名字不用长文件名, 大小写记在NTRes里; 其他名字用长文件名加上~1的短文件名
+    let hello = root.create("hello.txt").unwrap();
+    let long = root.create("Long File Name.text").unwrap();
+    root.create_dir("dir").unwrap();
+    assert!(root.create("HELLO.TXT").is_none());
+    assert_eq!(root.ls(), vec!["hello.txt", "Long File Name.text", "dir"]);
+    let data: Vec<u8> = (0..5 * BLOCK_SZ + 100).map(|i| (i % 251) as u8).collect();
+    assert_eq!(long.write_at(0, &data), data.len());
+    hello.write_at(0, b"hello");
+    block_cache_sync_all();
+    let mut root_cluster = [0u8; BLOCK_SZ];
+    // 32个保留扇区, 两份FAT表各512个扇区, 然后是根目录
+    block_file.read_block(32 + 2 * 512, &mut root_cluster);
+    assert_eq!(&root_cluster[..11], b"HELLO   TXT");
+    assert_eq!(root_cluster[12], 0x18);
+    assert_eq!(root_cluster[32], 0x42);
+    assert_eq!(root_cluster[32 + 11], 0x0f);
+    assert_eq!(&root_cluster[96..107], b"LONGFI~1TEX");
+
+    // 重新挂载之后内容还在, 名字不区分大小写, 空闲簇数从FSInfo读回来
+    let fs = FatFileSystem::open(block_file.clone()).unwrap();
+    let used = (data.len() + BLOCK_SZ - 1) / BLOCK_SZ + 2;
+    assert_eq!(fs.lock().free_clusters() as usize, free as usize - used);
+    let root = FatFileSystem::root_inode(&fs);
+    let long = root.find("LONG FILE NAME.TEXT").unwrap();
+    let mut buf = vec![0u8; data.len() + 10];
+    assert_eq!(long.read_at(0, &mut buf), data.len());
+    assert!(buf[..data.len()] == data[..]);
+    assert_eq!(root.find("longfi~1.tex").unwrap().stat().ino, long.stat().ino);
+    let mut buf = [0u8; 16];
+    let len = root.find("Hello.Txt").unwrap().read_at(0, &mut buf);
+    assert_eq!(&buf[..len], b"hello");
+
+    // 子目录有 . 和 .., 能一直走回根目录
+    let dir = root.find("dir").unwrap();
+    let sub = dir.create_dir("sub").unwrap();
+    assert_eq!(sub.path().unwrap(), "/dir/sub");
+    assert_eq!(sub.find("..").unwrap().stat().ino, dir.stat().ino);
+    assert_eq!(sub.find("../..").unwrap().stat().ino, root.stat().ino);
+    let mut names = Vec::new();
+    let mut pos = 0;
+    while let Some((dirent, next)) = dir.read_dirent(pos) {
+        names.push(dirent.name);
+        pos = next;
+    }
+    assert_eq!(names, vec![".", "..", "sub"]);
+
+    // 目录放不下时加簇
+    for i in 0..40 {
+        assert!(sub.create(&format!("a rather long file name {}", i)).is_some());
+    }
+    assert_eq!(sub.ls().len(), 40);
+    assert!(sub.stat().size > BLOCK_SZ as u64);
+    assert!(sub.find("A RATHER LONG FILE NAME 39").is_some());
+
+    // 打开的文件改名之后还是同一个文件, 目录不能移到自己下面, 非空目录不能删除
+    let hello = root.find("hello.txt").unwrap();
+    assert!(root.rename("hello.txt", &sub, "moved.txt"));
+    assert!(root.find("hello.txt").is_none());
+    hello.write_at(5, b" world");
+    let mut buf = [0u8; 16];
+    let len = sub.find("moved.txt").unwrap().read_at(0, &mut buf);
+    assert_eq!(&buf[..len], b"hello world");
+    assert_eq!(hello.stat().ino, sub.find("moved.txt").unwrap().stat().ino);
+    assert!(!root.rename("dir", &sub, "loop"));
+    assert!(root.rename("dir", &root, "Dir2"));
+    assert_eq!(sub.path().unwrap(), "/Dir2/sub");
+    assert!(!root.rmdir("Dir2"));
+    assert!(!root.unlink("Dir2"));
+
+    // 截断: 变短时回收后面的簇, 再变长的部分是0
+    let before = fs.lock().free_clusters();
+    assert!(long.truncate(100));
+    assert_eq!(fs.lock().free_clusters(), before + 5);
+    assert!(long.truncate(3 * BLOCK_SZ as u64));
+    assert_eq!(fs.lock().free_clusters(), before + 3);
+    let mut buf = vec![0xffu8; 3 * BLOCK_SZ];
+    assert_eq!(long.read_at(0, &mut buf), 3 * BLOCK_SZ);
+    assert!(buf[..100] == data[..100]);
+    assert!(buf[100..].iter().all(|b| *b == 0));
+    assert!(!root.find("Dir2").unwrap().truncate(0));
+
+    // 全部删掉之后空闲簇都回来了
+    let sub = root.find("Dir2/sub").unwrap();
+    for name in sub.ls() {
+        assert!(sub.unlink(&name));
+    }
+    assert!(root.find("Dir2").unwrap().rmdir("sub"));
+    assert!(root.rmdir("dir2"));
+    assert!(root.unlink("Long File Name.text"));
+    assert_eq!(long.read_at(0, &mut buf), 0);
+    assert!(root.ls().is_empty());
+    assert_eq!(fs.lock().free_clusters(), free);
+    Ok(())
+}
+
+#[test]
+fn fat_efs_shared_cache_test() -> std::io::Result<()> {
+    use easy_fs::{set_block_cache_size, FatFileSystem};
+    let _guard = TEST_LOCK.lock();
+    // 两个设备上同样的块号在缓存里是不同的块
+    let efs_file = efs_image("target/shared_cache_efs.img", 4096)?;
+    let fat_file = fat_image("target/shared_cache_fat.img", 65536)?;
+    let efs = EasyFileSystem::open(efs_file.clone());
+    let fat = FatFileSystem::format(fat_file.clone(), 65536, 8);
+    set_block_cache_size(4);
+    let efs_root = EasyFileSystem::root_inode(&efs);
+    let fat_root = FatFileSystem::root_inode(&fat);
+    let efs_data: Vec<u8> = (0..40 * BLOCK_SZ).map(|i| (i % 251) as u8).collect();
+    let fat_data: Vec<u8> = (0..40 * BLOCK_SZ).map(|i| (i % 13) as u8).collect();
+    let efs_file_a = efs_root.create("a").unwrap();
+    let fat_file_a = fat_root.create("a").unwrap();
+    for i in 0..40 {
+        let range = i * BLOCK_SZ..(i + 1) * BLOCK_SZ;
+        efs_file_a.write_at(range.start, &efs_data[range.clone()]);
+        fat_file_a.write_at(range.start, &fat_data[range]);
+    }
+    set_block_cache_size(16);
+
+    // 重新挂载easy-fs只丢掉它自己设备的缓存
+    let efs = EasyFileSystem::open(efs_file.clone());
+    let fat = FatFileSystem::open(fat_file.clone()).unwrap();
+    let mut buf = vec![0u8; 40 * BLOCK_SZ];
+    let efs_file_a = EasyFileSystem::root_inode(&efs).find("a").unwrap();
+    assert_eq!(efs_file_a.read_at(0, &mut buf), buf.len());
+    assert!(buf == efs_data);
+    let fat_file_a = FatFileSystem::root_inode(&fat).find("a").unwrap();
+    assert_eq!(fat_file_a.read_at(0, &mut buf), buf.len());
+    assert!(buf == fat_data);
+    assert!(efs.lock().check(false).is_empty());
+    Ok(())
+}
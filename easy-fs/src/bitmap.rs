@@ -33,7 +33,7 @@ impl Bitmap {
     pub fn alloc(&self, block_device: &Arc<dyn BlockDevice>) -> Option<usize> {
         for block_id in 0..self.blocks {
             let pos = get_block_cache(
-                block_id + self.start_block_id as usize,
+                block_id + self.start_block_id,
                 Arc::clone(block_device),
             )
             .lock()
@@ -46,7 +46,7 @@ impl Bitmap {
                 {
                     // modify cache
                     bitmap_block[bits64_pos] |= 1u64 << inner_pos;
-                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos as usize)
+                    Some(block_id * BLOCK_BITS + bits64_pos * 64 + inner_pos)
                 } else {
                     None
                 }
@@ -68,16 +68,38 @@ impl Bitmap {
                 bitmap_block[bits64_pos] -= 1u64 << inner_pos;
             });
     }
+    /// Whether `bit` is allocated
+    pub(crate) fn get(&self, block_device: &Arc<dyn BlockDevice>, bit: usize) -> bool {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .read(0, |bitmap_block: &BitmapBlock| {
+                bitmap_block[bits64_pos] & (1u64 << inner_pos) != 0
+            })
+    }
+    /// Mark `bit` as allocated or free, fsck修复位图时用
+    pub(crate) fn set(&self, block_device: &Arc<dyn BlockDevice>, bit: usize, allocated: bool) {
+        let (block_pos, bits64_pos, inner_pos) = decomposition(bit);
+        get_block_cache(block_pos + self.start_block_id, Arc::clone(block_device))
+            .lock()
+            .modify(0, |bitmap_block: &mut BitmapBlock| {
+                if allocated {
+                    bitmap_block[bits64_pos] |= 1u64 << inner_pos;
+                } else {
+                    bitmap_block[bits64_pos] &= !(1u64 << inner_pos);
+                }
+            });
+    }
     /// Get the max number of allocatable blocks
     pub fn maximum(&self) -> usize {
         self.blocks * BLOCK_BITS
     }
     /// Get the bitmapBlock info
     pub fn read_first_bitmap_block(&self, block_device: &Arc<dyn BlockDevice>) -> BitmapBlock {
-        get_block_cache(self.start_block_id as usize, Arc::clone(block_device))
+        get_block_cache(self.start_block_id, Arc::clone(block_device))
             .lock()
             .read(0, |bitmap_block: & BitmapBlock| {
-                return bitmap_block.clone();
+                return *bitmap_block;
             })
     }
 }
@@ -1,8 +1,10 @@
 use super::{BlockDevice, BLOCK_SZ};
-use alloc::collections::VecDeque;
+use crate::journal::{is_logged, log_block, logged_block};
+use alloc::collections::BTreeMap;
 use alloc::sync::Arc;
 use alloc::vec;
 use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazy_static::*;
 use spin::Mutex;
 
@@ -19,9 +21,16 @@ pub struct BlockCache {
 
 impl BlockCache {
     /// Load a new BlockCache from disk.
+    /// 正在进行的事务修改过的块还没有写回磁盘, 从事务里读
     pub fn new(block_id: usize, block_device: Arc<dyn BlockDevice>) -> Self {
-        let mut cache = vec![0u8; BLOCK_SZ];
-        block_device.read_block(block_id, &mut cache);
+        let cache = match logged_block(device_id(&block_device), block_id) {
+            Some(cache) => cache,
+            None => {
+                let mut cache = vec![0u8; BLOCK_SZ];
+                block_device.read_block(block_id, &mut cache);
+                cache
+            }
+        };
         return Self {
             cache,
             block_id,
@@ -29,6 +38,19 @@ impl BlockCache {
             modified: false,
         };
     }
+    /// A BlockCache of data already read from disk, 合并读的时候用
+    fn from_data(block_id: usize, block_device: Arc<dyn BlockDevice>, data: &[u8]) -> Self {
+        return Self {
+            cache: data.to_vec(),
+            block_id,
+            block_device,
+            modified: false,
+        };
+    }
+    /// Id of the device of this block
+    fn device(&self) -> usize {
+        return device_id(&self.block_device);
+    }
     /// Get the address of an offset inside the cached block data
     fn addr_of_offset(&self, offset: usize) -> usize {
         &self.cache[offset] as *const _ as usize
@@ -59,16 +81,38 @@ impl BlockCache {
         f(self.get_ref(offset))
     }
 
+    /// Modify the block, it is logged if a transaction is running
     pub fn modify<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        let ret = f(self.get_mut(offset));
+        log_block(self.device(), self.block_id, &self.cache);
+        return ret;
+    }
+
+    /// Modify the content of a regular file, which is not logged.
+    /// 除非这个块已经在事务里了, 那样的话必须继续记日志
+    pub fn modify_data<T, V>(&mut self, offset: usize, f: impl FnOnce(&mut T) -> V) -> V {
+        if is_logged(self.device(), self.block_id) {
+            return self.modify(offset, f);
+        }
         f(self.get_mut(offset))
     }
 
+    /// Write the block back if it is dirty, 事务里的块要等提交的时候再写
     pub fn sync(&mut self) {
-        if self.modified {
-            self.modified = false;
-            self.block_device.write_block(self.block_id, &self.cache);
+        if let Some(data) = self.take_dirty() {
+            self.block_device.write_block(self.block_id, &data);
         }
     }
+
+    /// Mark the block clean and return its content if it should be written back
+    fn take_dirty(&mut self) -> Option<Vec<u8>> {
+        if !self.modified || is_logged(self.device(), self.block_id) {
+            return None;
+        }
+        self.modified = false;
+        WRITEBACKS.fetch_add(1, Ordering::Relaxed);
+        return Some(self.cache.clone());
+    }
 }
 
 impl Drop for BlockCache {
@@ -77,17 +121,68 @@ impl Drop for BlockCache {
     }
 }
 
-//Use a block cache of 16 blocks
+/// Default number of cached blocks, 可以用 `set_block_cache_size` 修改
 const BLOCK_CACHE_SIZE: usize = 16;
 
+/// 写回磁盘的次数, 块是在 `BlockCache::sync` 里写回的, 那里拿不到管理器
+static WRITEBACKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Statistics of the block cache
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BlockCacheStats {
+    /// Max number of cached blocks, 所有块都在用时会暂时超过
+    pub capacity: usize,
+    /// Number of blocks cached now
+    pub cached: usize,
+    /// Lookups that found the block in the cache
+    pub hits: usize,
+    /// Lookups that read the block from the device
+    pub misses: usize,
+    /// Blocks dropped from the cache to make room
+    pub evictions: usize,
+    /// Dirty blocks written back to the device
+    pub writebacks: usize,
+}
+
+/// A cached block and the time it was last used
+struct CacheEntry {
+    cache: Arc<Mutex<BlockCache>>,
+    last_used: u64,
+}
+
+/// Id of a block device, 就是设备对象的地址, 同一个设备的所有 `Arc` 都一样
+pub(crate) fn device_id(block_device: &Arc<dyn BlockDevice>) -> usize {
+    return Arc::as_ptr(block_device) as *const () as usize;
+}
+
+/// A cached block: (device id, block id)
+type BlockKey = (usize, usize);
+
+/// A LRU cache of blocks of all the block devices.
+/// 被引用的块不会被换出, 所有块都被引用时缓存临时变大, 之后再缩回去
 pub struct BlockCacheManager {
-    queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+    /// (设备, 块号) -> 缓存
+    entries: BTreeMap<BlockKey, CacheEntry>,
+    /// 最后使用的时间 -> (设备, 块号), 最前面的是最久没用的
+    lru: BTreeMap<u64, BlockKey>,
+    /// 每次访问加一, 作为使用时间
+    clock: u64,
+    capacity: usize,
+    hits: usize,
+    misses: usize,
+    evictions: usize,
 }
 
 impl BlockCacheManager {
     pub fn new() -> Self {
         return Self {
-            queue: VecDeque::new(),
+            entries: BTreeMap::new(),
+            lru: BTreeMap::new(),
+            clock: 0,
+            capacity: BLOCK_CACHE_SIZE,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
         };
     }
 
@@ -96,30 +191,55 @@ impl BlockCacheManager {
         block_id: usize,
         block_device: Arc<dyn BlockDevice>,
     ) -> Arc<Mutex<BlockCache>> {
-        if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
-            Arc::clone(&pair.1)
-        } else {
-            // substitute
-            if self.queue.len() == BLOCK_CACHE_SIZE {
-                // from front to tail
-                if let Some((idx, _)) = self
-                    .queue
-                    .iter()
-                    .enumerate()
-                    .find(|(_, pair)| Arc::strong_count(&pair.1) == 1)
-                {
-                    self.queue.drain(idx..=idx);
-                } else {
-                    panic!("Run out of BlockCache!");
-                }
+        self.clock += 1;
+        let key = (device_id(&block_device), block_id);
+        if let Some(entry) = self.entries.get_mut(&key) {
+            self.hits += 1;
+            self.lru.remove(&entry.last_used);
+            self.lru.insert(self.clock, key);
+            entry.last_used = self.clock;
+            return Arc::clone(&entry.cache);
+        }
+        self.misses += 1;
+        // load block into mem
+        return self.insert(BlockCache::new(block_id, block_device));
+    }
+
+    /// Put a block just read from disk into the cache
+    fn insert(&mut self, block_cache: BlockCache) -> Arc<Mutex<BlockCache>> {
+        let key = (block_cache.device(), block_cache.block_id);
+        self.shrink(self.capacity - 1);
+        let block_cache = Arc::new(Mutex::new(block_cache));
+        self.entries.insert(
+            key,
+            CacheEntry {
+                cache: Arc::clone(&block_cache),
+                last_used: self.clock,
+            },
+        );
+        self.lru.insert(self.clock, key);
+        return block_cache;
+    }
+
+    /// Evict the least recently used blocks that are not referenced until at most `size` blocks are cached.
+    /// 换出的块在drop时写回
+    fn shrink(&mut self, size: usize) {
+        if self.entries.len() <= size {
+            return;
+        }
+        let mut victims = Vec::new();
+        for (last_used, key) in self.lru.iter() {
+            if self.entries.len() - victims.len() <= size {
+                break;
+            }
+            if Arc::strong_count(&self.entries[key].cache) == 1 {
+                victims.push((*last_used, *key));
             }
-            // load block into mem and push back
-            let block_cache = Arc::new(Mutex::new(BlockCache::new(
-                block_id,
-                Arc::clone(&block_device),
-            )));
-            self.queue.push_back((block_id, Arc::clone(&block_cache)));
-            return block_cache;
+        }
+        for (last_used, key) in victims {
+            self.lru.remove(&last_used);
+            self.entries.remove(&key);
+            self.evictions += 1;
         }
     }
 }
@@ -139,10 +259,102 @@ pub fn get_block_cache(
         .get_block_cache(block_id, block_device)
 }
 
-/// Sync all block cache to block device
-pub fn block_cache_sync_all(){
-    let manager=BLOCK_CACHE_MANAGER.lock();
-    for(_,cache) in manager.queue.iter(){
-        cache.lock().sync();
+/// Group consecutive block ids into runs of (first block id, number of blocks)
+fn runs(block_ids: impl Iterator<Item = usize>) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    for block_id in block_ids {
+        match runs.last_mut() {
+            Some((start, count)) if *start + *count == block_id => *count += 1,
+            _ => runs.push((block_id, 1)),
+        }
+    }
+    return runs;
+}
+
+/// Write all dirty blocks of all the devices back, 同一个设备上连续的脏块合并成一个请求.
+/// 事务提交时会调用; 内核也可以在 sync 或关机时调用
+pub fn block_cache_sync_all() {
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    // entries 按(设备, 块号)排序, 同一个设备的块排在一起
+    let mut dirty: BTreeMap<BlockKey, Vec<u8>> = BTreeMap::new();
+    let mut devices: BTreeMap<usize, Arc<dyn BlockDevice>> = BTreeMap::new();
+    for (key, entry) in manager.entries.iter() {
+        let mut cache = entry.cache.lock();
+        if let Some(data) = cache.take_dirty() {
+            dirty.insert(*key, data);
+            devices
+                .entry(key.0)
+                .or_insert_with(|| Arc::clone(&cache.block_device));
+        }
+    }
+    for (device, block_device) in devices.iter() {
+        let block_ids = dirty
+            .range((*device, 0)..=(*device, usize::MAX))
+            .map(|(key, _)| key.1);
+        for (start, count) in runs(block_ids) {
+            let mut buf = Vec::with_capacity(count * BLOCK_SZ);
+            for block_id in start..start + count {
+                buf.extend_from_slice(&dirty[&(*device, block_id)]);
+            }
+            block_device.write_blocks(start, &buf);
+        }
+    }
+}
+
+/// Read the blocks that are not cached into the cache, 连续的块合并成一个请求. 一次读多个块时用
+pub fn block_cache_prefetch(block_ids: &[usize], block_device: &Arc<dyn BlockDevice>) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    // 读进来的块比缓存还多的话, 前面的会被后面的换出去
+    let count = block_ids.len().min(manager.capacity);
+    let device = device_id(block_device);
+    let missing = block_ids[..count]
+        .iter()
+        .copied()
+        .filter(|block_id| {
+            !manager.entries.contains_key(&(device, *block_id)) && !is_logged(device, *block_id)
+        })
+        .collect::<Vec<_>>();
+    for (start, count) in runs(missing.into_iter()) {
+        let mut buf = vec![0u8; count * BLOCK_SZ];
+        block_device.read_blocks(start, &mut buf);
+        for (i, data) in buf.chunks(BLOCK_SZ).enumerate() {
+            manager.misses += 1;
+            manager.clock += 1;
+            manager.insert(BlockCache::from_data(
+                start + i,
+                Arc::clone(block_device),
+                data,
+            ));
+        }
     }
 }
+
+/// Sync and drop the cached blocks of a device, 挂载文件系统时从空的缓存开始. 其他设备的块不受影响
+pub fn block_cache_drop_device(block_device: &Arc<dyn BlockDevice>) {
+    block_cache_sync_all();
+    let device = device_id(block_device);
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.entries.retain(|key, _| key.0 != device);
+    manager.lru.retain(|_, key| key.0 != device);
+}
+
+/// Set the max number of cached blocks (at least 1)
+pub fn set_block_cache_size(size: usize) {
+    let mut manager = BLOCK_CACHE_MANAGER.lock();
+    manager.capacity = size.max(1);
+    let capacity = manager.capacity;
+    manager.shrink(capacity);
+}
+
+/// Get the statistics of the block cache
+pub fn block_cache_stats() -> BlockCacheStats {
+    let manager = BLOCK_CACHE_MANAGER.lock();
+    return BlockCacheStats {
+        capacity: manager.capacity,
+        cached: manager.entries.len(),
+        hits: manager.hits,
+        misses: manager.misses,
+        evictions: manager.evictions,
+        writebacks: WRITEBACKS.load(Ordering::Relaxed),
+    };
+}
@@ -1,13 +1,26 @@
+use super::BLOCK_SZ;
 use core::any::Any;
 
 /// Trait for block devices
 /// which reads and writes data in the unit of blocks
-
 pub trait BlockDevice: Send + Sync + Any {
     /// Read data from block to buffer
     fn read_block(&self, block_id: usize, buf: &mut [u8]);
     ///Write data from buffer to block
     fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Read consecutive blocks starting from `block_id`, the length of `buf` is a multiple of the block size.
+    /// 默认一个块一个块地读, 驱动可以一次提交多个请求
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        for (i, block) in buf.chunks_mut(BLOCK_SZ).enumerate() {
+            self.read_block(block_id + i, block);
+        }
+    }
+    /// Write consecutive blocks starting from `block_id`, the length of `buf` is a multiple of the block size
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        for (i, block) in buf.chunks(BLOCK_SZ).enumerate() {
+            self.write_block(block_id + i, block);
+        }
+    }
     /// handle irq
     fn handle_irq(&self);
 }
@@ -1,8 +1,17 @@
-use crate::{bitmap::BitmapBlock, vfs::Inode, BLOCK_SZ};
+use crate::{
+    bitmap::BitmapBlock,
+    block_cache::block_cache_drop_device,
+    journal::{
+        abort_transaction, begin_transaction, commit_transaction, in_transaction, init_journal,
+        replay_journal, JOURNAL_BLOCKS, JOURNAL_MAX_BLOCKS,
+    },
+    vfs::Inode,
+    BLOCK_SZ,
+};
 
 use super::{
     block_cache_sync_all, get_block_cache, Bitmap, BlockDevice, DiskInode, DiskInodeType,
-    SuperBlock,
+    SuperBlock, FEATURE_LARGE_FILE,
 };
 use alloc::{sync::Arc, vec::Vec};
 use spin::Mutex;
@@ -15,9 +24,18 @@ pub struct EasyFileSystem {
     pub inode_bitmap: Bitmap,
     ///Data bitmap
     pub data_bitmap: Bitmap,
-    inode_area_start_block: u32,
-    data_area_start_block: u32,
+    pub(crate) inode_area_start_block: u32,
+    pub(crate) data_area_start_block: u32,
+    /// 超级块里的总块数, 设备上这之后的块不归文件系统管
+    total_blocks: u32,
+    journal_blocks: u32,
+    /// 超级块里的特性位
+    features: u32,
+    /// 事务里释放的数据块, 提交时才在位图里释放, 这样同一个事务里不会再分配出去
+    freed_blocks: Vec<u32>,
 }
+/// The journal area starts right after the super block
+const JOURNAL_START_BLOCK: u32 = 1;
 /// A indirect block
 type IndirectBlock = [u32; BLOCK_SZ / 4];
 /// A data block
@@ -31,24 +49,34 @@ impl EasyFileSystem {
         inode_bitmap_blocks: u32, //在一个磁盘中, 索引位图块的数量
     ) -> Arc<Mutex<Self>> {
         // calculate block size of areas & create bitmaps
-        let inode_bitmap = Bitmap::new(1, inode_bitmap_blocks as usize);
+        let inode_bitmap_start_block = JOURNAL_START_BLOCK + JOURNAL_BLOCKS;
+        let inode_bitmap = Bitmap::new(
+            inode_bitmap_start_block as usize,
+            inode_bitmap_blocks as usize,
+        );
         let inode_num = inode_bitmap.maximum(); // 一个索引位图块 能指向64*64=4096个索引节点块
         let inode_area_blocks =
-            ((inode_num * core::mem::size_of::<DiskInode>() + BLOCK_SZ - 1) / BLOCK_SZ) as u32; //计算索引节点Inode 需要多少磁盘块, 先算出内存大小, 每个磁盘块存512bit
+            (inode_num * core::mem::size_of::<DiskInode>()).div_ceil(BLOCK_SZ) as u32; //计算索引节点Inode 需要多少磁盘块, 先算出内存大小, 每个磁盘块存512bit
         let inode_total_blocks = inode_bitmap_blocks + inode_area_blocks; //索引区域总共需要多少磁盘块 = 索引节点所需磁盘块 加上 索引位图所需磁盘块
-        let data_total_blocks = total_blocks - 1 - inode_total_blocks; //剩下都是数据总磁盘块 这里减一是减去了超级块
-        let data_bitmap_blocks = (data_total_blocks + 4096) / 4097; //?? 计算数据块位图 需要多少磁盘块
+        let data_total_blocks = total_blocks - inode_bitmap_start_block - inode_total_blocks; //剩下都是数据总磁盘块 这里减去了超级块和日志区
+        let data_bitmap_blocks = data_total_blocks.div_ceil(4097); //?? 计算数据块位图 需要多少磁盘块
         let data_area_blocks = data_total_blocks - data_bitmap_blocks; // 再算出数据区域 需要多少磁盘块
         let data_bitmap = Bitmap::new(
-            (1 + inode_bitmap_blocks + inode_area_blocks) as usize, //这里的1+ 也是超级块的位置
+            (inode_bitmap_start_block + inode_total_blocks) as usize,
             data_bitmap_blocks as usize,
         );
         let mut efs = Self {
             block_device: Arc::clone(&block_device),
             inode_bitmap,
             data_bitmap,
-            inode_area_start_block: 1 + inode_bitmap_blocks, //计算索引节点区域的起始块编号  最前面的1是超级块
-            data_area_start_block: 1 + inode_total_blocks + data_bitmap_blocks, //计算数据区域的起始块编号
+            inode_area_start_block: inode_bitmap_start_block + inode_bitmap_blocks, //计算索引节点区域的起始块编号
+            data_area_start_block: inode_bitmap_start_block
+                + inode_total_blocks
+                + data_bitmap_blocks, //计算数据区域的起始块编号
+            total_blocks,
+            journal_blocks: JOURNAL_BLOCKS,
+            features: FEATURE_LARGE_FILE,
+            freed_blocks: Vec::new(),
         };
         // clear all blocks
         for i in 0..total_blocks {
@@ -80,35 +108,62 @@ impl EasyFileSystem {
         get_block_cache(root_inode_block_id as usize, Arc::clone(&block_device))
             .lock()
             .modify(root_inode_offset, |disk_inode: &mut DiskInode| {
-                disk_inode.initialize(DiskInodeType::Directory); //给根目录写上数据
+                disk_inode.initialize(DiskInodeType::Directory, true); //给根目录写上数据
             });
+        let efs = Arc::new(Mutex::new(efs));
+        // 根目录的 . 和 .. 都指向自己
+        Self::root_inode(&efs).init_root_dots();
         block_cache_sync_all(); //写入磁盘
-        return Arc::new(Mutex::new(efs));
+        init_journal(&block_device, JOURNAL_START_BLOCK as usize);
+        return efs;
     }
-    /// Open a block device as a filesystem
+    /// Open a block device as a filesystem, replay the journal if the last mount crashed
     pub fn open(block_device: Arc<dyn BlockDevice>) -> Arc<Mutex<Self>> {
+        // 缓存里可能还有上次挂载没提交的事务, 丢掉它们, 再把已经提交的事务重放到磁盘上
+        block_cache_drop_device(&block_device);
+        abort_transaction();
+        replay_journal(&block_device, JOURNAL_START_BLOCK as usize);
         // read SuperBlock
         // 这里最核心的是参数block_id=0, 指定了超级块的编号也就是编号0.
         get_block_cache(0, Arc::clone(&block_device))
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error Loading EFS!"); //读出了超级块
+                let inode_bitmap_start_block = JOURNAL_START_BLOCK + super_block.journal_blocks;
                 let inode_total_blocks =
                     super_block.inode_bitmap_blocks + super_block.inode_area_blocks;
                 let efs = Self {
                     //构造了EasyFileSystem实例
                     block_device,
-                    inode_bitmap: Bitmap::new(1, super_block.inode_bitmap_blocks as usize),
+                    inode_bitmap: Bitmap::new(
+                        inode_bitmap_start_block as usize,
+                        super_block.inode_bitmap_blocks as usize,
+                    ),
                     data_bitmap: Bitmap::new(
-                        (1 + inode_total_blocks) as usize,
+                        (inode_bitmap_start_block + inode_total_blocks) as usize,
                         super_block.data_bitmap_blocks as usize,
                     ),
-                    inode_area_start_block: 1 + super_block.inode_bitmap_blocks,
-                    data_area_start_block: 1 + inode_total_blocks + super_block.data_bitmap_blocks,
+                    inode_area_start_block: inode_bitmap_start_block
+                        + super_block.inode_bitmap_blocks,
+                    data_area_start_block: inode_bitmap_start_block
+                        + inode_total_blocks
+                        + super_block.data_bitmap_blocks,
+                    total_blocks: super_block.total_blocks,
+                    journal_blocks: super_block.journal_blocks,
+                    features: super_block.features,
+                    freed_blocks: Vec::new(),
                 };
                 return Arc::new(Mutex::new(efs));
             })
     }
+    /// Number of blocks of the filesystem, 包括超级块和日志区
+    pub fn total_blocks(&self) -> u32 {
+        return self.total_blocks;
+    }
+    /// Whether new inodes use the large file layout, 旧镜像里新建的inode也用旧格式
+    pub fn large_file(&self) -> bool {
+        return self.features & FEATURE_LARGE_FILE != 0;
+    }
     /// Get the root inode of the filesystem
     pub fn root_inode(efs: &Arc<Mutex<Self>>) -> Inode {
         let block_device = Arc::clone(&efs.lock().block_device);
@@ -127,6 +182,13 @@ impl EasyFileSystem {
             (inode_id % inodes_per_block) as usize * inode_size,
         )
     }
+    /// Get inode id by the position of its disk inode, get_disk_inode_pos的反过程
+    pub fn get_inode_id(&self, block_id: u32, block_offset: usize) -> u32 {
+        let inode_size = core::mem::size_of::<DiskInode>();
+        let inodes_per_block = (BLOCK_SZ / inode_size) as u32;
+        (block_id - self.inode_area_start_block) * inodes_per_block
+            + (block_offset / inode_size) as u32
+    }
     /// Get data block by id
     pub fn get_data_block_id(&self, data_block_id: u32) -> u32 {
         self.data_area_start_block + data_block_id
@@ -135,26 +197,60 @@ impl EasyFileSystem {
     pub fn alloc_inode(&mut self) -> u32 {
         self.inode_bitmap.alloc(&self.block_device).unwrap() as u32
     }
+    /// Deallocate an inode
+    pub fn dealloc_inode(&mut self, inode_id: u32) {
+        self.inode_bitmap
+            .dealloc(&self.block_device, inode_id as usize)
+    }
     /// Allocate a data block
     pub fn alloc_data(&mut self) -> u32 {
-        self.data_bitmap.alloc(&self.block_device).unwrap() as u32 + self.data_area_start_block
+        return self.try_alloc_data().unwrap();
     }
-    /// Deallocate a data block
-    pub fn dealloc_data(&mut self, block_id: u32) {
-        // 根据指定的block_id 把这个磁盘块里面内容置零
+    /// Allocate a data block, None if the disk is full
+    pub fn try_alloc_data(&mut self) -> Option<u32> {
+        let block_id =
+            self.data_bitmap.alloc(&self.block_device)? as u32 + self.data_area_start_block;
+        // 把新分配的磁盘块里面内容置零. 这个块在磁盘上还是空闲的, 不用记日志
         get_block_cache(block_id as usize, Arc::clone(&self.block_device))
             .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            .modify_data(0, |data_block: &mut DataBlock| {
                 data_block.iter_mut().for_each(|p| {
                     *p = 0;
                 })
             });
+        return Some(block_id);
+    }
+    /// Deallocate a data block, 事务里释放的块等到提交的时候再释放
+    pub fn dealloc_data(&mut self, block_id: u32) {
+        if in_transaction() {
+            self.freed_blocks.push(block_id);
+            return;
+        }
         // 释放对应的数据块位图
         self.data_bitmap.dealloc(
             &self.block_device,
             (block_id - self.data_area_start_block) as usize,
         )
     }
+    /// Start a transaction, 修改文件系统的操作在开始修改之前调用
+    pub fn begin(&self) {
+        begin_transaction(
+            &self.block_device,
+            ((self.journal_blocks - 1) as usize).min(JOURNAL_MAX_BLOCKS),
+        );
+    }
+    /// Commit the running transaction and write everything back to disk
+    pub fn commit(&mut self) {
+        for block_id in core::mem::take(&mut self.freed_blocks) {
+            self.data_bitmap.dealloc(
+                &self.block_device,
+                (block_id - self.data_area_start_block) as usize,
+            );
+        }
+        // 普通文件的内容先写回, 提交之后的元数据不会指向还没写的数据
+        block_cache_sync_all();
+        commit_transaction(&self.block_device, JOURNAL_START_BLOCK as usize);
+    }
 
     /********Only from Testing and explore ****************/
     /// Open a block device and read out super block
@@ -164,14 +260,7 @@ impl EasyFileSystem {
             .lock()
             .read(0, |super_block: &SuperBlock| {
                 assert!(super_block.is_valid(), "Error Loading EFS!"); //读出了超级块
-                return SuperBlock::new(
-                    super_block.magic,
-                    super_block.total_blocks,
-                    super_block.inode_bitmap_blocks,
-                    super_block.inode_area_blocks,
-                    super_block.data_bitmap_blocks,
-                    super_block.data_area_blocks,
-                );
+                return *super_block;
             })
     }
 
@@ -198,9 +287,9 @@ impl EasyFileSystem {
             get_block_cache(block_id as usize, Arc::clone(&self.block_device))
                 .lock()
                 .read(inode_offset, |disk_inode: &DiskInode| {
-                    if disk_inode.size != 0 {
+                    if disk_inode.size() != 0 {
                         // only add not empty inode area
-                        v.push(disk_inode.clone());
+                        v.push(*disk_inode);
                     }
                 });
         }
@@ -212,7 +301,7 @@ impl EasyFileSystem {
         let data_block = get_block_cache(data_block_id, Arc::clone(&self.block_device))
             .lock()
             .read(0, |data_block: &DataBlock| {
-                return data_block.clone();
+                return *data_block;
             });
         return data_block;
     }
@@ -221,7 +310,7 @@ impl EasyFileSystem {
         let indirect_block = get_block_cache(indirect_block_id, Arc::clone(&self.block_device))
             .lock()
             .read(0, |indirect_block: &IndirectBlock| {
-                return indirect_block.clone();
+                return *indirect_block;
             });
         return indirect_block;
     }
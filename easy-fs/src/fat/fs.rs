@@ -0,0 +1,379 @@
+use super::layout::{
+    parse_fsinfo, write_fsinfo, BootSector, FAT_BAD, FAT_ENTRY_MASK, FAT_EOC, FAT_FREE,
+    FIRST_CLUSTER,
+};
+use super::vfs::{FatInode, FatNode};
+use crate::block_cache::{block_cache_drop_device, get_block_cache};
+use crate::{BlockDevice, BLOCK_SZ};
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Sectors reserved before the FATs when formatting, 和mkfs.fat一样
+const FORMAT_RESERVED_SECTORS: u32 = 32;
+/// Number of FATs when formatting
+const FORMAT_FAT_COUNT: u32 = 2;
+/// Sector of the FSInfo when formatting, 备份在引导扇区备份的后面
+const FORMAT_FSINFO_SECTOR: u32 = 1;
+/// Sector of the backup boot sector when formatting
+const FORMAT_BACKUP_BOOT_SECTOR: u32 = 6;
+
+/// A FAT32 filesystem on a block device.
+/// 块大小和扇区大小一样是512字节, 读写都经过块缓存
+pub struct FatFileSystem {
+    ///Real device
+    pub block_device: Arc<dyn BlockDevice>,
+    boot: BootSector,
+    /// 每个簇的字节数
+    cluster_bytes: usize,
+    /// 数据区的簇数, 簇号从2到cluster_count+1
+    cluster_count: u32,
+    /// 空闲的簇数, 写回FSInfo
+    free_count: u32,
+    /// 下次从这里开始找空闲的簇
+    next_free: u32,
+    /// The root directory, 它没有目录项
+    pub(crate) root: Arc<Mutex<FatNode>>,
+    /// 短目录项在磁盘上的位置 -> 打开的文件或目录, 同一个文件的所有inode共用一个节点
+    pub(crate) nodes: BTreeMap<u64, Weak<Mutex<FatNode>>>,
+}
+
+impl FatFileSystem {
+    /// Format the block device of `total_sectors` sectors as FAT32 with `sectors_per_cluster` sectors in a cluster
+    pub fn format(
+        block_device: Arc<dyn BlockDevice>,
+        total_sectors: u32,
+        sectors_per_cluster: u32,
+    ) -> Arc<Mutex<Self>> {
+        assert!(sectors_per_cluster.is_power_of_two() && sectors_per_cluster <= 128);
+        // FAT表要能记下所有的簇, 按没有FAT表时的簇数算会多出一点, 没关系
+        let clusters = (total_sectors - FORMAT_RESERVED_SECTORS) / sectors_per_cluster;
+        let fat_sectors = ((clusters + FIRST_CLUSTER) as usize * 4).div_ceil(BLOCK_SZ);
+        let boot = BootSector {
+            sectors_per_cluster,
+            reserved_sectors: FORMAT_RESERVED_SECTORS,
+            fat_count: FORMAT_FAT_COUNT,
+            total_sectors,
+            fat_sectors: fat_sectors as u32,
+            ext_flags: 0,
+            root_cluster: FIRST_CLUSTER,
+            fsinfo_sector: FORMAT_FSINFO_SECTOR,
+            backup_boot_sector: FORMAT_BACKUP_BOOT_SECTOR,
+            volume_id: crate::time::now() as u32,
+        };
+        assert!(boot.cluster_count() > 1, "Too small for FAT32!");
+        // 缓存里可能有这个设备以前的内容, 格式化直接写设备
+        block_cache_drop_device(&block_device);
+        let zeros = vec![0u8; BLOCK_SZ * sectors_per_cluster as usize];
+        for sector in (0..boot.data_start()).step_by(sectors_per_cluster as usize) {
+            let count = (boot.data_start() - sector).min(sectors_per_cluster) as usize;
+            block_device.write_blocks(sector as usize, &zeros[..count * BLOCK_SZ]);
+        }
+        // 根目录占第一个簇
+        block_device.write_blocks(boot.data_start() as usize, &zeros);
+        let mut sector = [0u8; BLOCK_SZ];
+        boot.write(&mut sector);
+        block_device.write_block(0, &sector);
+        block_device.write_block(FORMAT_BACKUP_BOOT_SECTOR as usize, &sector);
+        sector.fill(0);
+        write_fsinfo(&mut sector, boot.cluster_count() - 1, FIRST_CLUSTER + 1);
+        block_device.write_block(FORMAT_FSINFO_SECTOR as usize, &sector);
+        block_device.write_block((FORMAT_BACKUP_BOOT_SECTOR + 1) as usize, &sector);
+        // 0号表项是介质类型, 1号表项是结束标记, 2号是根目录
+        sector.fill(0);
+        sector[..12].copy_from_slice(&[
+            0xf8, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f, 0xff, 0xff, 0xff, 0x0f,
+        ]);
+        for fat in 0..FORMAT_FAT_COUNT {
+            block_device.write_block(
+                (boot.reserved_sectors + fat * boot.fat_sectors) as usize,
+                &sector,
+            );
+        }
+        return Self::open(block_device).unwrap();
+    }
+
+    /// Open the FAT32 on a block device, None if it is not FAT32
+    pub fn open(block_device: Arc<dyn BlockDevice>) -> Option<Arc<Mutex<Self>>> {
+        let boot = get_block_cache(0, Arc::clone(&block_device))
+            .lock()
+            .read(0, |sector: &[u8; BLOCK_SZ]| BootSector::parse(sector))?;
+        let cluster_count = boot.cluster_count();
+        if boot.root_cluster < FIRST_CLUSTER || boot.root_cluster >= cluster_count + FIRST_CLUSTER {
+            return None;
+        }
+        let root = Arc::new(Mutex::new(FatNode::root(boot.root_cluster)));
+        let mut fs = Self {
+            block_device,
+            boot,
+            cluster_bytes: boot.sectors_per_cluster as usize * BLOCK_SZ,
+            cluster_count,
+            free_count: 0,
+            next_free: FIRST_CLUSTER,
+            root,
+            nodes: BTreeMap::new(),
+        };
+        match fs.read_fsinfo() {
+            // FSInfo里的数字只是提示, 不知道(0xffffffff)或者不可信时数一遍FAT表
+            Some((free_count, next_free)) if free_count <= cluster_count => {
+                fs.free_count = free_count;
+                if fs.is_valid_cluster(next_free) {
+                    fs.next_free = next_free;
+                }
+            }
+            _ => {
+                fs.free_count = (FIRST_CLUSTER..cluster_count + FIRST_CLUSTER)
+                    .filter(|cluster| fs.fat_entry(*cluster) == FAT_FREE)
+                    .count() as u32;
+            }
+        }
+        return Some(Arc::new(Mutex::new(fs)));
+    }
+
+    /// The root directory
+    pub fn root_inode(fs: &Arc<Mutex<Self>>) -> FatInode {
+        let root = Arc::clone(&fs.lock().root);
+        return FatInode::new(Arc::clone(fs), root);
+    }
+
+    /// Number of free clusters
+    pub fn free_clusters(&self) -> u32 {
+        return self.free_count;
+    }
+
+    /// Number of clusters of the data area
+    pub fn cluster_count(&self) -> u32 {
+        return self.cluster_count;
+    }
+
+    /// Bytes in a cluster
+    pub fn cluster_bytes(&self) -> usize {
+        return self.cluster_bytes;
+    }
+
+    fn read_fsinfo(&self) -> Option<(u32, u32)> {
+        if !self.has_fsinfo() {
+            return None;
+        }
+        return get_block_cache(
+            self.boot.fsinfo_sector as usize,
+            Arc::clone(&self.block_device),
+        )
+        .lock()
+        .read(0, |sector: &[u8; BLOCK_SZ]| parse_fsinfo(sector));
+    }
+
+    /// Write the free cluster count back to the FSInfo
+    fn write_fsinfo(&self) {
+        if !self.has_fsinfo() {
+            return;
+        }
+        get_block_cache(
+            self.boot.fsinfo_sector as usize,
+            Arc::clone(&self.block_device),
+        )
+        .lock()
+        .modify(0, |sector: &mut [u8; BLOCK_SZ]| {
+            write_fsinfo(sector, self.free_count, self.next_free)
+        });
+    }
+
+    fn has_fsinfo(&self) -> bool {
+        return self.boot.fsinfo_sector != 0
+            && self.boot.fsinfo_sector < self.boot.reserved_sectors;
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        return cluster >= FIRST_CLUSTER && cluster < self.cluster_count + FIRST_CLUSTER;
+    }
+
+    /// The first sector of a cluster
+    pub(crate) fn cluster_sector(&self, cluster: u32) -> usize {
+        return (self.boot.data_start() + (cluster - FIRST_CLUSTER) * self.boot.sectors_per_cluster)
+            as usize;
+    }
+
+    /// (sector, offset in the sector) of the FAT entry of `cluster` in the `fat`th FAT
+    fn fat_entry_pos(&self, fat: u32, cluster: u32) -> (usize, usize) {
+        let offset = cluster as usize * 4;
+        let sector =
+            (self.boot.reserved_sectors + fat * self.boot.fat_sectors) as usize + offset / BLOCK_SZ;
+        return (sector, offset % BLOCK_SZ);
+    }
+
+    /// The FAT entry of `cluster`: the next cluster, FAT_FREE, FAT_BAD, or >= FAT_EOC - 7 at the end
+    pub(crate) fn fat_entry(&self, cluster: u32) -> u32 {
+        let fat = self.boot.active_fats()[0];
+        let (sector, offset) = self.fat_entry_pos(fat, cluster);
+        return get_block_cache(sector, Arc::clone(&self.block_device))
+            .lock()
+            .read(offset, |entry: &u32| *entry & FAT_ENTRY_MASK);
+    }
+
+    /// Set the FAT entry of `cluster` in every active FAT, 高4位是保留的, 不能改
+    fn set_fat_entry(&self, cluster: u32, value: u32) {
+        for fat in self.boot.active_fats() {
+            let (sector, offset) = self.fat_entry_pos(fat, cluster);
+            get_block_cache(sector, Arc::clone(&self.block_device))
+                .lock()
+                .modify(offset, |entry: &mut u32| {
+                    *entry = (*entry & !FAT_ENTRY_MASK) | (value & FAT_ENTRY_MASK)
+                });
+        }
+    }
+
+    /// The clusters of the chain starting at `first`, 0是空链. 链上有环或者坏簇时到那里为止
+    pub(crate) fn chain(&self, first: u32) -> Vec<u32> {
+        let mut chain = Vec::new();
+        let mut cluster = first;
+        while self.is_valid_cluster(cluster) && chain.len() < self.cluster_count as usize {
+            chain.push(cluster);
+            cluster = self.fat_entry(cluster);
+        }
+        return chain;
+    }
+
+    /// Allocate a zeroed cluster and append it after `last`, None if the disk is full
+    pub(crate) fn alloc_cluster(&mut self, last: Option<u32>) -> Option<u32> {
+        if self.free_count == 0 {
+            return None;
+        }
+        let start = self.next_free - FIRST_CLUSTER;
+        let cluster = (0..self.cluster_count)
+            .map(|i| (start + i) % self.cluster_count + FIRST_CLUSTER)
+            .find(|cluster| self.fat_entry(*cluster) == FAT_FREE)?;
+        self.set_fat_entry(cluster, FAT_EOC);
+        if let Some(last) = last {
+            self.set_fat_entry(last, cluster);
+        }
+        // 目录的新簇必须是空的, 文件的新簇清零之后读到的空洞才是0
+        let first_sector = self.cluster_sector(cluster);
+        for sector in first_sector..first_sector + self.boot.sectors_per_cluster as usize {
+            get_block_cache(sector, Arc::clone(&self.block_device))
+                .lock()
+                .modify_data(0, |data: &mut [u8; BLOCK_SZ]| data.fill(0));
+        }
+        self.free_count -= 1;
+        self.next_free = if cluster + 1 < self.cluster_count + FIRST_CLUSTER {
+            cluster + 1
+        } else {
+            FIRST_CLUSTER
+        };
+        self.write_fsinfo();
+        return Some(cluster);
+    }
+
+    /// Free the clusters of a chain, 从`keep`个簇之后开始释放, 留下的最后一个簇变成链尾.
+    /// Return the new first cluster, 全部释放时是0
+    pub(crate) fn truncate_chain(&mut self, first: u32, keep: usize) -> u32 {
+        let chain = self.chain(first);
+        if keep >= chain.len() {
+            return first;
+        }
+        if keep > 0 {
+            self.set_fat_entry(chain[keep - 1], FAT_EOC);
+        }
+        for cluster in chain[keep..].iter() {
+            // 坏簇是在 chain 里被截断的, 不会出现在这里
+            debug_assert_ne!(self.fat_entry(*cluster), FAT_BAD);
+            self.set_fat_entry(*cluster, FAT_FREE);
+            self.free_count += 1;
+        }
+        self.write_fsinfo();
+        return if keep > 0 { first } else { 0 };
+    }
+
+    /// Read or write the bytes at `offset` of the chain, `f` is called on each piece with
+    /// (sector, offset in the sector, offset in `len`, length)
+    pub(crate) fn for_each_piece(
+        &self,
+        chain: &[u32],
+        offset: usize,
+        len: usize,
+        mut f: impl FnMut(usize, usize, usize, usize),
+    ) {
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let cluster = match chain.get(pos / self.cluster_bytes) {
+                Some(cluster) => *cluster,
+                None => break,
+            };
+            let sector = self.cluster_sector(cluster) + pos % self.cluster_bytes / BLOCK_SZ;
+            let sector_offset = pos % BLOCK_SZ;
+            let piece = (BLOCK_SZ - sector_offset).min(len - done);
+            f(sector, sector_offset, done, piece);
+            done += piece;
+        }
+    }
+
+    /// Read the bytes at `offset` of the chain into `buf`
+    pub(crate) fn read_chain(&self, chain: &[u32], offset: usize, buf: &mut [u8]) {
+        self.for_each_piece(
+            chain,
+            offset,
+            buf.len(),
+            |sector, sector_offset, done, piece| {
+                get_block_cache(sector, Arc::clone(&self.block_device))
+                    .lock()
+                    .read(0, |data: &[u8; BLOCK_SZ]| {
+                        buf[done..done + piece]
+                            .copy_from_slice(&data[sector_offset..sector_offset + piece])
+                    });
+            },
+        );
+    }
+
+    /// Write `buf` to `offset` of the chain
+    pub(crate) fn write_chain(&self, chain: &[u32], offset: usize, buf: &[u8]) {
+        self.for_each_piece(
+            chain,
+            offset,
+            buf.len(),
+            |sector, sector_offset, done, piece| {
+                get_block_cache(sector, Arc::clone(&self.block_device))
+                    .lock()
+                    .modify_data(0, |data: &mut [u8; BLOCK_SZ]| {
+                        data[sector_offset..sector_offset + piece]
+                            .copy_from_slice(&buf[done..done + piece])
+                    });
+            },
+        );
+    }
+
+    /// Read the directory entry at byte position `pos` of the disk
+    pub(crate) fn read_raw(&self, pos: u64) -> [u8; 32] {
+        return get_block_cache(pos as usize / BLOCK_SZ, Arc::clone(&self.block_device))
+            .lock()
+            .read(pos as usize % BLOCK_SZ, |raw: &[u8; 32]| *raw);
+    }
+
+    /// Write the directory entry at byte position `pos` of the disk
+    pub(crate) fn write_raw(&self, pos: u64, raw: &[u8; 32]) {
+        get_block_cache(pos as usize / BLOCK_SZ, Arc::clone(&self.block_device))
+            .lock()
+            .modify(pos as usize % BLOCK_SZ, |entry: &mut [u8; 32]| {
+                *entry = *raw
+            });
+    }
+
+    /// The byte position on the disk of `offset` in a chain
+    pub(crate) fn disk_pos(&self, chain: &[u32], offset: usize) -> u64 {
+        let cluster = chain[offset / self.cluster_bytes];
+        let sector = self.cluster_sector(cluster) + offset % self.cluster_bytes / BLOCK_SZ;
+        return (sector * BLOCK_SZ + offset % BLOCK_SZ) as u64;
+    }
+
+    /// The shared node of the entry at `pos`, 没有打开时用`new`新建一个
+    pub(crate) fn node(&mut self, pos: u64, new: impl FnOnce() -> FatNode) -> Arc<Mutex<FatNode>> {
+        if let Some(node) = self.nodes.get(&pos).and_then(|node| node.upgrade()) {
+            return node;
+        }
+        self.nodes.retain(|_, node| node.strong_count() > 0);
+        let node = Arc::new(Mutex::new(new()));
+        self.nodes.insert(pos, Arc::downgrade(&node));
+        return node;
+    }
+}
@@ -0,0 +1,550 @@
+//! On-disk structures of FAT32
+//!
+//! 引导扇区和FSInfo里的字段很多没有对齐, 按字节偏移解析. 目录项是32字节, 短目录项的字段正好是对齐的
+
+use crate::BLOCK_SZ;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+/// Size of a directory entry
+pub const DIRENT_SZ: usize = 32;
+
+/// 只读
+pub const ATTR_READ_ONLY: u8 = 0x01;
+/// 隐藏
+pub const ATTR_HIDDEN: u8 = 0x02;
+/// 系统文件
+pub const ATTR_SYSTEM: u8 = 0x04;
+/// 卷标, 只能出现在根目录里
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+/// 目录
+pub const ATTR_DIRECTORY: u8 = 0x10;
+/// 修改过, 备份工具用
+pub const ATTR_ARCHIVE: u8 = 0x20;
+/// 长文件名目录项的属性
+pub const ATTR_LONG_NAME: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/// FAT32的表项只有低28位有效
+pub const FAT_ENTRY_MASK: u32 = 0x0fff_ffff;
+/// 空闲的簇
+pub const FAT_FREE: u32 = 0;
+/// 坏簇
+pub const FAT_BAD: u32 = 0x0fff_fff7;
+/// 写入簇链末尾的值, 读的时候 >= 0x0ffffff8 都是末尾
+pub const FAT_EOC: u32 = 0x0fff_ffff;
+/// The first cluster of the data area, 0和1号表项是保留的
+pub const FIRST_CLUSTER: u32 = 2;
+
+/// 目录项第一个字节: 这个和后面的目录项都是空闲的
+const DIRENT_END: u8 = 0x00;
+/// 目录项第一个字节: 空闲的目录项
+pub const DIRENT_FREE: u8 = 0xe5;
+/// 名字的第一个字节真的是0xe5时存成0x05
+const DIRENT_KANJI: u8 = 0x05;
+/// NTRes: 短文件名的主名是小写的
+const NTRES_LOWER_BASE: u8 = 0x08;
+/// NTRes: 短文件名的扩展名是小写的
+const NTRES_LOWER_EXT: u8 = 0x10;
+/// 长文件名的最后一个(也就是磁盘上第一个)目录项的序号带这个标记
+const LFN_LAST: u8 = 0x40;
+/// Characters (UCS-2) in a long name entry
+const LFN_CHARS: usize = 13;
+/// 长文件名字符在目录项里的字节偏移
+const LFN_CHAR_OFFSETS: [usize; LFN_CHARS] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+/// The max length of a long name
+pub const LFN_MAX_LEN: usize = 255;
+
+const BOOT_SIGNATURE: u16 = 0xaa55;
+const FSINFO_LEAD_SIG: u32 = 0x4161_5252;
+const FSINFO_STRUCT_SIG: u32 = 0x6141_7272;
+const FSINFO_TRAIL_SIG: u32 = 0xaa55_0000;
+
+fn get_u16(bytes: &[u8], offset: usize) -> u16 {
+    return u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+}
+
+fn get_u32(bytes: &[u8], offset: usize) -> u32 {
+    let mut word = [0u8; 4];
+    word.copy_from_slice(&bytes[offset..offset + 4]);
+    return u32::from_le_bytes(word);
+}
+
+fn put_u16(bytes: &mut [u8], offset: usize, value: u16) {
+    bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(bytes: &mut [u8], offset: usize, value: u32) {
+    bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// The BIOS parameter block of FAT32 in the boot sector, 只保留用得到的字段
+#[derive(Debug, Clone, Copy)]
+pub struct BootSector {
+    /// 每个簇的扇区数, 2的幂
+    pub sectors_per_cluster: u32,
+    /// 保留区的扇区数, 引导扇区和FSInfo都在保留区里
+    pub reserved_sectors: u32,
+    /// FAT表的份数, 一般是2
+    pub fat_count: u32,
+    /// 整个卷的扇区数
+    pub total_sectors: u32,
+    /// 每份FAT表的扇区数
+    pub fat_sectors: u32,
+    /// 第7位是1时只用第0-3位指定的那份FAT表, 否则所有FAT表互为镜像
+    pub ext_flags: u16,
+    /// 根目录的第一个簇
+    pub root_cluster: u32,
+    /// FSInfo所在的扇区
+    pub fsinfo_sector: u32,
+    /// 引导扇区备份所在的扇区
+    pub backup_boot_sector: u32,
+    /// 卷序列号
+    pub volume_id: u32,
+}
+
+impl BootSector {
+    /// Parse the boot sector, None if it is not FAT32 with 512-byte sectors
+    pub fn parse(sector: &[u8]) -> Option<Self> {
+        if get_u16(sector, 510) != BOOT_SIGNATURE || get_u16(sector, 11) as usize != BLOCK_SZ {
+            return None;
+        }
+        let sectors_per_cluster = sector[13] as u32;
+        if sectors_per_cluster == 0 || !sectors_per_cluster.is_power_of_two() {
+            return None;
+        }
+        // FAT12/16的根目录有固定的项数, FAT表的大小在16位的字段里
+        let root_entries = get_u16(sector, 17);
+        let fat_sectors16 = get_u16(sector, 22);
+        let fat_sectors = get_u32(sector, 36);
+        if root_entries != 0 || fat_sectors16 != 0 || fat_sectors == 0 {
+            return None;
+        }
+        let total_sectors = match get_u16(sector, 19) {
+            0 => get_u32(sector, 32),
+            sectors => sectors as u32,
+        };
+        let boot = Self {
+            sectors_per_cluster,
+            reserved_sectors: get_u16(sector, 14) as u32,
+            fat_count: sector[16] as u32,
+            total_sectors,
+            fat_sectors,
+            ext_flags: get_u16(sector, 40),
+            root_cluster: get_u32(sector, 44),
+            fsinfo_sector: get_u16(sector, 48) as u32,
+            backup_boot_sector: get_u16(sector, 50) as u32,
+            volume_id: get_u32(sector, 67),
+        };
+        if boot.reserved_sectors == 0 || boot.fat_count == 0 || boot.data_start() >= total_sectors {
+            return None;
+        }
+        return Some(boot);
+    }
+    /// Write the boot sector, 格式化时用
+    pub fn write(&self, sector: &mut [u8]) {
+        sector.fill(0);
+        // 跳转指令和OEM名字
+        sector[..3].copy_from_slice(&[0xeb, 0x58, 0x90]);
+        sector[3..11].copy_from_slice(b"tCore   ");
+        put_u16(sector, 11, BLOCK_SZ as u16);
+        sector[13] = self.sectors_per_cluster as u8;
+        put_u16(sector, 14, self.reserved_sectors as u16);
+        sector[16] = self.fat_count as u8;
+        // 硬盘
+        sector[21] = 0xf8;
+        put_u32(sector, 32, self.total_sectors);
+        put_u32(sector, 36, self.fat_sectors);
+        put_u16(sector, 40, self.ext_flags);
+        put_u32(sector, 44, self.root_cluster);
+        put_u16(sector, 48, self.fsinfo_sector as u16);
+        put_u16(sector, 50, self.backup_boot_sector as u16);
+        sector[64] = 0x80;
+        sector[66] = 0x29;
+        put_u32(sector, 67, self.volume_id);
+        sector[71..82].copy_from_slice(b"NO NAME    ");
+        sector[82..90].copy_from_slice(b"FAT32   ");
+        put_u16(sector, 510, BOOT_SIGNATURE);
+    }
+    /// The first sector of the data area
+    pub fn data_start(&self) -> u32 {
+        return self.reserved_sectors + self.fat_count * self.fat_sectors;
+    }
+    /// Number of clusters of the data area, 不能超过FAT表能记录的簇数
+    pub fn cluster_count(&self) -> u32 {
+        let clusters = (self.total_sectors - self.data_start()) / self.sectors_per_cluster;
+        let fat_entries = self.fat_sectors * (BLOCK_SZ / 4) as u32;
+        return clusters.min(fat_entries - FIRST_CLUSTER);
+    }
+    /// The FAT copies to update, 没有关闭镜像时每份都要写
+    pub fn active_fats(&self) -> Vec<u32> {
+        if self.ext_flags & 0x80 != 0 {
+            return vec![(self.ext_flags & 0xf) as u32];
+        }
+        return (0..self.fat_count).collect();
+    }
+}
+
+/// Read (free cluster count, next free cluster hint) from the FSInfo sector, None if it is invalid
+pub fn parse_fsinfo(sector: &[u8]) -> Option<(u32, u32)> {
+    if get_u32(sector, 0) != FSINFO_LEAD_SIG
+        || get_u32(sector, 484) != FSINFO_STRUCT_SIG
+        || get_u32(sector, 508) != FSINFO_TRAIL_SIG
+    {
+        return None;
+    }
+    return Some((get_u32(sector, 488), get_u32(sector, 492)));
+}
+
+/// Write the FSInfo sector
+pub fn write_fsinfo(sector: &mut [u8], free_count: u32, next_free: u32) {
+    put_u32(sector, 0, FSINFO_LEAD_SIG);
+    put_u32(sector, 484, FSINFO_STRUCT_SIG);
+    put_u32(sector, 488, free_count);
+    put_u32(sector, 492, next_free);
+    put_u32(sector, 508, FSINFO_TRAIL_SIG);
+}
+
+/// A short (8.3) directory entry
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShortDirEntry {
+    /// 8个字节的主名和3个字节的扩展名, 用空格补齐
+    pub name: [u8; 11],
+    pub attr: u8,
+    /// Windows NT 用来记录短文件名的大小写
+    pub nt_res: u8,
+    /// 创建时间的10毫秒数, 0-199
+    pub create_time_tenth: u8,
+    pub create_time: u16,
+    pub create_date: u16,
+    pub access_date: u16,
+    pub first_cluster_hi: u16,
+    pub write_time: u16,
+    pub write_date: u16,
+    pub first_cluster_lo: u16,
+    pub file_size: u32,
+}
+
+// 短目录项的字段刚好对齐, 大小就是一个目录项
+const _: () = assert!(core::mem::size_of::<ShortDirEntry>() == DIRENT_SZ);
+
+impl ShortDirEntry {
+    /// A new entry of `name` created now
+    pub fn new(name: [u8; 11], nt_res: u8, attr: u8, first_cluster: u32, now: u64) -> Self {
+        let (date, time, tenth) = encode_time(now);
+        let mut entry = Self {
+            name,
+            attr,
+            nt_res,
+            create_time_tenth: tenth,
+            create_time: time,
+            create_date: date,
+            access_date: date,
+            write_time: time,
+            write_date: date,
+            ..Default::default()
+        };
+        entry.set_first_cluster(first_cluster);
+        return entry;
+    }
+    /// "." or ".." of a directory
+    pub fn dot(name: &str, first_cluster: u32, now: u64) -> Self {
+        let mut short_name = [b' '; 11];
+        short_name[..name.len()].copy_from_slice(name.as_bytes());
+        return Self::new(short_name, 0, ATTR_DIRECTORY, first_cluster, now);
+    }
+    /// Read an entry from its bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        assert!(bytes.len() >= DIRENT_SZ);
+        unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Self) }
+    }
+    /// The bytes of the entry
+    pub fn as_bytes(&self) -> [u8; DIRENT_SZ] {
+        unsafe { core::mem::transmute_copy(self) }
+    }
+    pub fn is_dir(&self) -> bool {
+        return self.attr & ATTR_DIRECTORY != 0;
+    }
+    pub fn is_volume_label(&self) -> bool {
+        return self.attr & ATTR_VOLUME_ID != 0 && self.attr != ATTR_LONG_NAME;
+    }
+    pub fn first_cluster(&self) -> u32 {
+        return (self.first_cluster_hi as u32) << 16 | self.first_cluster_lo as u32;
+    }
+    pub fn set_first_cluster(&mut self, cluster: u32) {
+        self.first_cluster_hi = (cluster >> 16) as u16;
+        self.first_cluster_lo = cluster as u16;
+    }
+    /// The name shown to users, 按NTRes还原大小写
+    pub fn display_name(&self) -> String {
+        let mut name = self.name;
+        if name[0] == DIRENT_KANJI {
+            name[0] = DIRENT_FREE;
+        }
+        let to_string = |bytes: &[u8], lower: bool| -> String {
+            let trimmed = match bytes.iter().rposition(|b| *b != b' ') {
+                Some(last) => &bytes[..=last],
+                None => &bytes[..0],
+            };
+            // 非ASCII的字节按Latin-1解释
+            return trimmed
+                .iter()
+                .map(|b| {
+                    let c = *b as char;
+                    if lower {
+                        c.to_ascii_lowercase()
+                    } else {
+                        c
+                    }
+                })
+                .collect();
+        };
+        let mut display = to_string(&name[..8], self.nt_res & NTRES_LOWER_BASE != 0);
+        let ext = to_string(&name[8..], self.nt_res & NTRES_LOWER_EXT != 0);
+        if !ext.is_empty() {
+            display.push('.');
+            display.push_str(&ext);
+        }
+        return display;
+    }
+    /// Write time as milliseconds since 1980-01-01
+    pub fn mtime(&self) -> u64 {
+        return decode_time(self.write_date, self.write_time, 0);
+    }
+    /// Access date as milliseconds since 1980-01-01
+    pub fn atime(&self) -> u64 {
+        return decode_time(self.access_date, 0, 0);
+    }
+    /// Set the write time and the archive bit
+    pub fn touch_modified(&mut self, now: u64) {
+        let (date, time, _) = encode_time(now);
+        self.write_date = date;
+        self.write_time = time;
+        self.access_date = date;
+        self.attr |= ATTR_ARCHIVE;
+    }
+}
+
+/// State of a raw directory entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawDirent {
+    /// 这个和后面的目录项都是空闲的
+    End,
+    Free,
+    /// (序号, 是不是最后一个, 短文件名的校验和)
+    Long(u8, bool, u8),
+    Short,
+}
+
+/// Classify a raw directory entry
+pub fn classify(raw: &[u8]) -> RawDirent {
+    match raw[0] {
+        DIRENT_END => return RawDirent::End,
+        DIRENT_FREE => return RawDirent::Free,
+        _ => {}
+    }
+    if raw[11] & 0x3f == ATTR_LONG_NAME {
+        return RawDirent::Long(raw[0] & !LFN_LAST, raw[0] & LFN_LAST != 0, raw[13]);
+    }
+    return RawDirent::Short;
+}
+
+/// Mark a raw directory entry free
+pub fn mark_free(raw: &mut [u8]) {
+    raw[0] = DIRENT_FREE;
+}
+
+/// The characters of a long name entry, 名字的结尾是0, 后面用0xffff补齐
+pub fn long_name_chars(raw: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    return LFN_CHAR_OFFSETS
+        .iter()
+        .map(move |offset| get_u16(raw, *offset));
+}
+
+/// Checksum of a short name, 保存在它前面的每个长文件名目录项里
+pub fn lfn_checksum(short_name: &[u8; 11]) -> u8 {
+    let mut sum: u8 = 0;
+    for b in short_name.iter() {
+        sum = ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*b);
+    }
+    return sum;
+}
+
+/// The long name entries of `name` in the order on disk, 最后一段在最前面
+pub fn long_name_entries(name: &str, checksum: u8) -> Vec<[u8; DIRENT_SZ]> {
+    let chars: Vec<u16> = name.encode_utf16().collect();
+    let count = chars.len().div_ceil(LFN_CHARS);
+    let mut entries = Vec::new();
+    for ord in (1..=count).rev() {
+        let mut raw = [0u8; DIRENT_SZ];
+        raw[0] = ord as u8 | if ord == count { LFN_LAST } else { 0 };
+        raw[11] = ATTR_LONG_NAME;
+        raw[13] = checksum;
+        for (i, offset) in LFN_CHAR_OFFSETS.iter().enumerate() {
+            let index = (ord - 1) * LFN_CHARS + i;
+            let c = match index {
+                _ if index < chars.len() => chars[index],
+                _ if index == chars.len() => 0,
+                _ => 0xffff,
+            };
+            put_u16(&mut raw, *offset, c);
+        }
+        entries.push(raw);
+    }
+    return entries;
+}
+
+/// Decode the characters collected from long name entries, None if it is not valid UTF-16
+pub fn decode_long_name(chars: &[u16]) -> Option<String> {
+    let len = chars.iter().position(|c| *c == 0).unwrap_or(chars.len());
+    return core::char::decode_utf16(chars[..len].iter().copied())
+        .collect::<Result<String, _>>()
+        .ok();
+}
+
+/// Whether `c` can be used in a short name
+fn is_short_name_char(c: char) -> bool {
+    return c.is_ascii_alphanumeric() || "$%'-_@~`!(){}^#&".contains(c);
+}
+
+/// Whether `name` can be a name of FAT, 不能有 "*/:<>?\| 和控制字符, 也不能以空格或者点结尾
+pub fn is_valid_name(name: &str) -> bool {
+    if name.is_empty() || name == "." || name == ".." {
+        return false;
+    }
+    if name.encode_utf16().count() > LFN_MAX_LEN || name.ends_with(' ') || name.ends_with('.') {
+        return false;
+    }
+    return !name
+        .chars()
+        .any(|c| (c as u32) < 0x20 || "\"*/:<>?\\|".contains(c));
+}
+
+/// The short name and NTRes of `name` if it fits 8.3 exactly, 这样就不需要长文件名.
+/// 主名和扩展名各自只能全大写或者全小写
+pub fn exact_short_name(name: &str) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.find('.') {
+        Some(dot) => (&name[..dot], &name[dot + 1..]),
+        None => (name, ""),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || ext.contains('.') {
+        return None;
+    }
+    let mut nt_res = 0;
+    for (part, lower_flag) in [(base, NTRES_LOWER_BASE), (ext, NTRES_LOWER_EXT)] {
+        if !part.chars().all(is_short_name_char) {
+            return None;
+        }
+        let has_lower = part.chars().any(|c| c.is_ascii_lowercase());
+        let has_upper = part.chars().any(|c| c.is_ascii_uppercase());
+        match (has_lower, has_upper) {
+            (true, true) => return None,
+            (true, false) => nt_res |= lower_flag,
+            _ => {}
+        }
+    }
+    let mut short_name = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(base.to_ascii_uppercase().as_bytes());
+    short_name[8..8 + ext.len()].copy_from_slice(ext.to_ascii_uppercase().as_bytes());
+    return Some((short_name, nt_res));
+}
+
+/// The `n`th numeric-tail short name of a long `name`, 例如 "Long File Name.text" -> "LONGFI~1TEX"
+pub fn numbered_short_name(name: &str, n: usize) -> [u8; 11] {
+    let clean = |part: &str| -> Vec<u8> {
+        return part
+            .chars()
+            .filter(|c| *c != ' ' && *c != '.')
+            .map(|c| {
+                if is_short_name_char(c) {
+                    c.to_ascii_uppercase() as u8
+                } else {
+                    b'_'
+                }
+            })
+            .collect();
+    };
+    let trimmed = name.trim_start_matches('.');
+    let (base, ext) = match trimmed.rfind('.') {
+        Some(dot) => (clean(&trimmed[..dot]), clean(&trimmed[dot + 1..])),
+        None => (clean(trimmed), Vec::new()),
+    };
+    let tail = alloc::format!("~{}", n);
+    let base_len = base.len().min(8 - tail.len());
+    let mut short_name = [b' '; 11];
+    short_name[..base_len].copy_from_slice(&base[..base_len]);
+    short_name[base_len..base_len + tail.len()].copy_from_slice(tail.as_bytes());
+    let ext_len = ext.len().min(3);
+    short_name[8..8 + ext_len].copy_from_slice(&ext[..ext_len]);
+    return short_name;
+}
+
+/// Milliseconds in a day
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+/// FAT的日期只能表示到2107年
+const MAX_YEAR: i64 = 1980 + 127;
+
+/// Days since 1970-01-01 of a date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    // 从三月开始数月份, 闰日在一年的最后
+    let month_from_march = (month as i64 + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// The date of days since 1970-01-01, (year, month, day)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_from_march + 2) / 5 + 1) as u32;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    return (year, month, day);
+}
+
+/// Encode milliseconds since 1980-01-01 into (date, time, 10ms count) of a directory entry.
+/// 文件系统的时钟没有日期, 时间戳就当成从1980-01-01开始的偏移
+pub fn encode_time(ms: u64) -> (u16, u16, u8) {
+    let days = days_from_civil(1980, 1, 1) + (ms / DAY_MS) as i64;
+    let (year, month, day) = civil_from_days(days);
+    if year > MAX_YEAR {
+        return (0xff9f, 0xbf7d, 199);
+    }
+    let ms_of_day = ms % DAY_MS;
+    let seconds = ms_of_day / 1000;
+    let date = ((year - 1980) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((seconds / 3600) as u16) << 11
+        | ((seconds / 60 % 60) as u16) << 5
+        | (seconds % 60 / 2) as u16;
+    // 时间只精确到2秒, 剩下的放在10毫秒数里
+    let tenth = ((seconds % 2) * 100 + ms_of_day % 1000 / 10) as u8;
+    return (date, time, tenth);
+}
+
+/// Decode (date, time, 10ms count) of a directory entry into milliseconds since 1980-01-01, 日期是0时返回0
+pub fn decode_time(date: u16, time: u16, tenth: u8) -> u64 {
+    if date == 0 {
+        return 0;
+    }
+    let year = 1980 + (date >> 9) as i64;
+    let month = ((date >> 5) & 0xf).clamp(1, 12) as u32;
+    let day = (date & 0x1f).max(1) as u32;
+    let days = days_from_civil(year, month, day) - days_from_civil(1980, 1, 1);
+    let seconds =
+        (time >> 11) as u64 * 3600 + ((time >> 5) & 0x3f) as u64 * 60 + (time & 0x1f) as u64 * 2;
+    return days as u64 * DAY_MS + seconds * 1000 + tenth as u64 * 10;
+}
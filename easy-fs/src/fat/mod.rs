@@ -0,0 +1,12 @@
+//! FAT32 over the same [`crate::BlockDevice`] and block cache as easy-fs
+//!
+//! 可以用mkfs.vfat和mtools在宿主机上做镜像. 支持长文件名, 不支持硬链接和符号链接, 不记日志.
+//! FAT没有inode, 文件的簇号和大小在父目录的短目录项里, 打开的文件按短目录项的位置共用一个节点,
+//! 改名和删除时节点跟着变, 同一个文件的所有inode看到的都一样.
+
+mod fs;
+mod layout;
+mod vfs;
+
+pub use fs::FatFileSystem;
+pub use vfs::{FatDirEntry, FatInode};
@@ -0,0 +1,793 @@
+use super::fs::FatFileSystem;
+use super::layout::{
+    classify, decode_long_name, exact_short_name, is_valid_name, lfn_checksum, long_name_chars,
+    long_name_entries, mark_free, numbered_short_name, RawDirent, ShortDirEntry, ATTR_ARCHIVE,
+    ATTR_DIRECTORY, ATTR_READ_ONLY, DIRENT_SZ,
+};
+use crate::time::now;
+use crate::{InodeStat, BLOCK_SZ};
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter, Result};
+use spin::Mutex;
+
+/// Inode number of the root directory, 其他文件的编号是短目录项的磁盘位置除以32
+const ROOT_INO: u32 = 1;
+
+/// An open file or directory, 同一个文件的所有 [`FatInode`] 共用一个节点.
+/// FAT没有inode, 文件的元数据在父目录的短目录项里, 改名时目录项会移动
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FatNode {
+    /// 短目录项在磁盘上的字节位置, 根目录没有
+    entry_pos: Option<u64>,
+    ino: u32,
+    is_dir: bool,
+    first_cluster: u32,
+    /// 文件的大小, 目录总是0
+    size: u32,
+    /// 已经被删除, 簇也释放了. 和easy-fs一样, 打开的文件不会阻止删除
+    removed: bool,
+}
+
+impl FatNode {
+    pub(crate) fn root(root_cluster: u32) -> Self {
+        return Self {
+            entry_pos: None,
+            ino: ROOT_INO,
+            is_dir: true,
+            first_cluster: root_cluster,
+            size: 0,
+            removed: false,
+        };
+    }
+    fn new(pos: u64, entry: &ShortDirEntry) -> Self {
+        return Self {
+            entry_pos: Some(pos),
+            ino: (pos / DIRENT_SZ as u64) as u32,
+            is_dir: entry.is_dir(),
+            first_cluster: entry.first_cluster(),
+            size: if entry.is_dir() { 0 } else { entry.file_size },
+            removed: false,
+        };
+    }
+}
+
+/// A directory entry read by [`FatInode::read_dirent`]
+#[derive(Debug, Clone)]
+pub struct FatDirEntry {
+    /// Long name, or the short name if there is none
+    pub name: String,
+    /// Inode number
+    pub ino: u32,
+    /// Whether it is a directory
+    pub is_dir: bool,
+}
+
+/// An entry in a directory: a short entry and the long name entries before it
+struct DirSlot {
+    name: String,
+    entry: ShortDirEntry,
+    /// 第一个目录项(长文件名的或者短的)在目录里的偏移
+    offset: usize,
+    /// 目录项的个数, 包括长文件名的
+    count: usize,
+}
+
+impl DirSlot {
+    /// Offset of the short entry in the directory
+    fn short_offset(&self) -> usize {
+        return self.offset + (self.count - 1) * DIRENT_SZ;
+    }
+    fn is_dot(&self) -> bool {
+        return self.name == "." || self.name == "..";
+    }
+    /// FAT的名字不区分大小写, 长文件名和短文件名都可以用来查找
+    fn matches(&self, name: &str) -> bool {
+        return self.name.eq_ignore_ascii_case(name)
+            || self.entry.display_name().eq_ignore_ascii_case(name);
+    }
+}
+
+/// Parse all the entries in use of a directory, 不完整或者校验和不对的长文件名被忽略
+fn parse_dir(data: &[u8]) -> Vec<DirSlot> {
+    let mut slots = Vec::new();
+    // 正在收集的长文件名: (第一个目录项的下标, 下一个期望的序号, 校验和, 字符)
+    let mut long: Option<(usize, u8, u8, Vec<u16>)> = None;
+    for (i, raw) in data.chunks(DIRENT_SZ).enumerate() {
+        match classify(raw) {
+            RawDirent::End => break,
+            RawDirent::Free => long = None,
+            RawDirent::Long(ord, true, checksum) if ord > 0 => {
+                let mut chars = vec![0u16; ord as usize * 13];
+                for (j, c) in long_name_chars(raw).enumerate() {
+                    chars[(ord as usize - 1) * 13 + j] = c;
+                }
+                long = Some((i, ord - 1, checksum, chars));
+            }
+            RawDirent::Long(ord, false, checksum) => {
+                long = match long.take() {
+                    Some((start, next, sum, mut chars))
+                        if ord == next && ord > 0 && sum == checksum =>
+                    {
+                        for (j, c) in long_name_chars(raw).enumerate() {
+                            chars[(ord as usize - 1) * 13 + j] = c;
+                        }
+                        Some((start, ord - 1, sum, chars))
+                    }
+                    _ => None,
+                };
+            }
+            RawDirent::Long(..) => long = None,
+            RawDirent::Short => {
+                let entry = ShortDirEntry::from_bytes(raw);
+                if entry.is_volume_label() {
+                    long = None;
+                    continue;
+                }
+                let long_name = match long.take() {
+                    Some((start, 0, checksum, chars)) if checksum == lfn_checksum(&entry.name) => {
+                        decode_long_name(&chars).map(|name| (start, name))
+                    }
+                    _ => None,
+                };
+                let (start, name) = long_name.unwrap_or_else(|| (i, entry.display_name()));
+                slots.push(DirSlot {
+                    name,
+                    entry,
+                    offset: start * DIRENT_SZ,
+                    count: i - start + 1,
+                });
+            }
+        }
+    }
+    return slots;
+}
+
+/// Offset of the first run of `count` free entries in a directory.
+/// 结束标记后面的目录项都是空闲的, 找到的位置不会在结束标记之后, 否则读目录时看不到
+fn find_free_run(data: &[u8], count: usize) -> Option<usize> {
+    let total = data.len() / DIRENT_SZ;
+    let mut start = 0;
+    let mut len = 0;
+    for (i, raw) in data.chunks(DIRENT_SZ).enumerate() {
+        match classify(raw) {
+            RawDirent::End => {
+                if len == 0 {
+                    start = i;
+                }
+                return if len + total - i >= count {
+                    Some(start * DIRENT_SZ)
+                } else {
+                    None
+                };
+            }
+            RawDirent::Free => {
+                if len == 0 {
+                    start = i;
+                }
+                len += 1;
+                if len == count {
+                    return Some(start * DIRENT_SZ);
+                }
+            }
+            _ => len = 0,
+        }
+    }
+    return None;
+}
+
+/// The short name for `name` in a directory, (short name, NTRes, whether a long name is needed)
+fn short_name_for(name: &str, slots: &[DirSlot]) -> ([u8; 11], u8, bool) {
+    let used = |short_name: &[u8; 11]| slots.iter().any(|slot| &slot.entry.name == short_name);
+    if let Some((short_name, nt_res)) = exact_short_name(name) {
+        if !used(&short_name) {
+            return (short_name, nt_res, false);
+        }
+    }
+    let mut n = 1;
+    loop {
+        let short_name = numbered_short_name(name, n);
+        if !used(&short_name) {
+            return (short_name, 0, true);
+        }
+        n += 1;
+    }
+}
+
+/// The raw entries of `name`: long name entries and the short entry
+fn entries_of(name: &str, entry: &ShortDirEntry, long_name: bool) -> Vec<[u8; DIRENT_SZ]> {
+    let mut raws = if long_name {
+        long_name_entries(name, lfn_checksum(&entry.name))
+    } else {
+        Vec::new()
+    };
+    raws.push(entry.as_bytes());
+    return raws;
+}
+
+impl FatFileSystem {
+    pub(crate) fn root_cluster(&self) -> u32 {
+        return self.root.lock().first_cluster;
+    }
+
+    /// The chain and entries of the directory starting at `cluster`
+    fn read_dir(&self, cluster: u32) -> (Vec<u32>, Vec<DirSlot>) {
+        let chain = self.chain(cluster);
+        let mut data = vec![0u8; chain.len() * self.cluster_bytes()];
+        self.read_chain(&chain, 0, &mut data);
+        return (chain, parse_dir(&data));
+    }
+
+    /// Write `raws` into free entries of the directory starting at `cluster`,
+    /// 没有足够的连续空闲目录项时给目录加簇. Return the position of the last entry on the disk
+    fn add_entries(&mut self, cluster: u32, raws: &[[u8; DIRENT_SZ]]) -> Option<u64> {
+        loop {
+            let chain = self.chain(cluster);
+            let mut data = vec![0u8; chain.len() * self.cluster_bytes()];
+            self.read_chain(&chain, 0, &mut data);
+            if let Some(offset) = find_free_run(&data, raws.len()) {
+                let mut pos = 0;
+                for (i, raw) in raws.iter().enumerate() {
+                    pos = self.disk_pos(&chain, offset + i * DIRENT_SZ);
+                    self.write_raw(pos, raw);
+                }
+                return Some(pos);
+            }
+            self.alloc_cluster(chain.last().copied())?;
+        }
+    }
+
+    /// Mark the entries of a slot free
+    fn remove_entries(&self, chain: &[u32], slot: &DirSlot) {
+        for i in 0..slot.count {
+            let pos = self.disk_pos(chain, slot.offset + i * DIRENT_SZ);
+            let mut raw = self.read_raw(pos);
+            mark_free(&mut raw);
+            self.write_raw(pos, &raw);
+        }
+    }
+
+    /// Update the short entry at `pos`
+    fn update_entry(&self, pos: u64, f: impl FnOnce(&mut ShortDirEntry)) {
+        let mut entry = ShortDirEntry::from_bytes(&self.read_raw(pos));
+        f(&mut entry);
+        self.write_raw(pos, &entry.as_bytes());
+    }
+
+    /// Write the first cluster and size of a node into its entry, then update it by `f`
+    fn sync_node(&self, node: &FatNode, f: impl FnOnce(&mut ShortDirEntry)) {
+        if let Some(pos) = node.entry_pos {
+            self.update_entry(pos, |entry| {
+                entry.set_first_cluster(node.first_cluster);
+                entry.file_size = node.size;
+                f(entry);
+            });
+        }
+    }
+
+    /// The shared node of a slot of the directory `chain`
+    fn slot_node(&mut self, chain: &[u32], slot: &DirSlot) -> Arc<Mutex<FatNode>> {
+        let pos = self.disk_pos(chain, slot.short_offset());
+        return self.node(pos, || FatNode::new(pos, &slot.entry));
+    }
+
+    /// The first cluster of the parent of the directory starting at `cluster`, 根目录的父目录是自己
+    fn parent_cluster(&self, cluster: u32) -> u32 {
+        let root = self.root_cluster();
+        if cluster == root {
+            return root;
+        }
+        // 子目录的第二个目录项是 .., 指向根目录时簇号是0
+        let pos = self.disk_pos(&[cluster], DIRENT_SZ);
+        match ShortDirEntry::from_bytes(&self.read_raw(pos)).first_cluster() {
+            0 => return root,
+            parent => return parent,
+        }
+    }
+
+    /// The slot of the directory starting at `cluster` in its parent, None for the root directory
+    fn dir_slot(&self, cluster: u32) -> Option<(Vec<u32>, DirSlot)> {
+        if cluster == self.root_cluster() {
+            return None;
+        }
+        let (chain, slots) = self.read_dir(self.parent_cluster(cluster));
+        let slot = slots.into_iter().find(|slot| {
+            !slot.is_dot() && slot.entry.is_dir() && slot.entry.first_cluster() == cluster
+        })?;
+        return Some((chain, slot));
+    }
+
+    /// Whether the directory starting at `cluster` is `ancestor` or inside it
+    fn is_inside(&self, mut cluster: u32, ancestor: u32) -> bool {
+        let root = self.root_cluster();
+        // 目录树坏了的话 .. 可能成环, 最多走簇数那么多层
+        for _ in 0..=self.cluster_count() {
+            if cluster == ancestor {
+                return true;
+            }
+            if cluster == root {
+                return false;
+            }
+            cluster = self.parent_cluster(cluster);
+        }
+        return false;
+    }
+
+    /// Remove a slot and free its clusters, 打开着的节点标记为已删除
+    fn remove_slot(&mut self, chain: &[u32], slot: &DirSlot) {
+        let pos = self.disk_pos(chain, slot.short_offset());
+        self.remove_entries(chain, slot);
+        self.truncate_chain(slot.entry.first_cluster(), 0);
+        if let Some(node) = self.nodes.remove(&pos).and_then(|node| node.upgrade()) {
+            let mut node = node.lock();
+            node.entry_pos = None;
+            node.first_cluster = 0;
+            node.size = 0;
+            node.removed = true;
+        }
+    }
+}
+
+/// Virtual filesystem layer over FAT32
+pub struct FatInode {
+    fs: Arc<Mutex<FatFileSystem>>,
+    node: Arc<Mutex<FatNode>>,
+}
+
+impl Debug for FatInode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("FatInode")
+            .field("node", &*self.node.lock())
+            .finish()
+    }
+}
+
+impl FatInode {
+    pub(crate) fn new(fs: Arc<Mutex<FatFileSystem>>, node: Arc<Mutex<FatNode>>) -> Self {
+        return Self { fs, node };
+    }
+
+    fn wrap(&self, node: Arc<Mutex<FatNode>>) -> Arc<Self> {
+        return Arc::new(Self::new(Arc::clone(&self.fs), node));
+    }
+
+    /// A copy of the node, 不能同时锁两个节点, 它们可能是同一个
+    fn snapshot(&self) -> FatNode {
+        return *self.node.lock();
+    }
+
+    /// The directory node if this is a directory not removed
+    fn dir(&self) -> Option<FatNode> {
+        let node = self.snapshot();
+        if !node.is_dir || node.removed {
+            return None;
+        }
+        return Some(node);
+    }
+
+    /// Find `name` in current directory, . 和 .. 也可以. 根目录的 .. 是自己
+    pub fn lookup(&self, name: &str) -> Option<Arc<FatInode>> {
+        let mut fs = self.fs.lock();
+        let dir = self.dir()?;
+        match name {
+            "." => return Some(self.wrap(Arc::clone(&self.node))),
+            ".." => {
+                let parent = fs.parent_cluster(dir.first_cluster);
+                let node = match fs.dir_slot(parent) {
+                    Some((chain, slot)) => fs.slot_node(&chain, &slot),
+                    None => Arc::clone(&fs.root),
+                };
+                return Some(self.wrap(node));
+            }
+            _ => {}
+        }
+        let (chain, slots) = fs.read_dir(dir.first_cluster);
+        let slot = slots
+            .iter()
+            .find(|slot| !slot.is_dot() && slot.matches(name))?;
+        let node = fs.slot_node(&chain, slot);
+        return Some(self.wrap(node));
+    }
+
+    /// Find inode under current inode by path
+    pub fn find(&self, path: &str) -> Option<Arc<FatInode>> {
+        let mut inode = self.wrap(Arc::clone(&self.node));
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            inode = inode.lookup(name)?;
+        }
+        return Some(inode);
+    }
+
+    /// Create a regular file or a directory `name` under current directory
+    fn create_node(&self, name: &str, is_dir: bool) -> Option<Arc<FatInode>> {
+        if !is_valid_name(name) {
+            return None;
+        }
+        let mut fs = self.fs.lock();
+        let dir = self.dir()?;
+        let (_, slots) = fs.read_dir(dir.first_cluster);
+        if slots.iter().any(|slot| slot.matches(name)) {
+            return None;
+        }
+        let (short_name, nt_res, long_name) = short_name_for(name, &slots);
+        let time = now();
+        let first_cluster = if is_dir {
+            // 新目录里先写好 . 和 .., 指向根目录的 .. 簇号是0
+            let cluster = fs.alloc_cluster(None)?;
+            let parent = if dir.entry_pos.is_none() {
+                0
+            } else {
+                dir.first_cluster
+            };
+            fs.write_raw(
+                fs.disk_pos(&[cluster], 0),
+                &ShortDirEntry::dot(".", cluster, time).as_bytes(),
+            );
+            fs.write_raw(
+                fs.disk_pos(&[cluster], DIRENT_SZ),
+                &ShortDirEntry::dot("..", parent, time).as_bytes(),
+            );
+            cluster
+        } else {
+            0
+        };
+        let attr = if is_dir { ATTR_DIRECTORY } else { ATTR_ARCHIVE };
+        let entry = ShortDirEntry::new(short_name, nt_res, attr, first_cluster, time);
+        let pos = match fs.add_entries(dir.first_cluster, &entries_of(name, &entry, long_name)) {
+            Some(pos) => pos,
+            None => {
+                fs.truncate_chain(first_cluster, 0);
+                return None;
+            }
+        };
+        fs.sync_node(&dir, |entry| entry.touch_modified(time));
+        let node = fs.node(pos, || FatNode::new(pos, &entry));
+        return Some(self.wrap(node));
+    }
+
+    /// Create regular file under current inode
+    pub fn create(&self, name: &str) -> Option<Arc<FatInode>> {
+        return self.create_node(name, false);
+    }
+
+    /// Create directory under current inode
+    pub fn create_dir(&self, name: &str) -> Option<Arc<FatInode>> {
+        return self.create_node(name, true);
+    }
+
+    /// List names under current inode, 不包括 . 和 ..
+    pub fn ls(&self) -> Vec<String> {
+        let fs = self.fs.lock();
+        let dir = match self.dir() {
+            Some(dir) => dir,
+            None => return Vec::new(),
+        };
+        let (_, slots) = fs.read_dir(dir.first_cluster);
+        return slots
+            .into_iter()
+            .filter(|slot| !slot.is_dot())
+            .map(|slot| slot.name)
+            .collect();
+    }
+
+    /// Read the first directory entry at or after the byte position `pos`,
+    /// return it and the position of the next one.
+    /// 子目录有 . 和 .., 根目录没有
+    pub fn read_dirent(&self, pos: usize) -> Option<(FatDirEntry, usize)> {
+        let fs = self.fs.lock();
+        let dir = self.dir()?;
+        let (chain, slots) = fs.read_dir(dir.first_cluster);
+        let slot = slots.into_iter().find(|slot| slot.offset >= pos)?;
+        let ino = match slot.name.as_str() {
+            "." => dir.ino,
+            // .. 的编号要找到父目录的目录项才知道
+            ".." => match fs.dir_slot(fs.parent_cluster(dir.first_cluster)) {
+                Some((parent_chain, parent_slot)) => {
+                    (fs.disk_pos(&parent_chain, parent_slot.short_offset()) / DIRENT_SZ as u64)
+                        as u32
+                }
+                None => ROOT_INO,
+            },
+            _ => (fs.disk_pos(&chain, slot.short_offset()) / DIRENT_SZ as u64) as u32,
+        };
+        let next = slot.short_offset() + DIRENT_SZ;
+        let dirent = FatDirEntry {
+            ino,
+            is_dir: slot.entry.is_dir(),
+            name: slot.name,
+        };
+        return Some((dirent, next));
+    }
+
+    /// Read data from current inode
+    pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let fs = self.fs.lock();
+        let node = self.snapshot();
+        if node.is_dir || offset >= node.size as usize {
+            return 0;
+        }
+        let len = buf.len().min(node.size as usize - offset);
+        let chain = fs.chain(node.first_cluster);
+        fs.read_chain(&chain, offset, &mut buf[..len]);
+        return len;
+    }
+
+    /// Write data to current inode, 文件最大4GB-1. 磁盘满了只写进去一部分
+    pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut fs = self.fs.lock();
+        let mut node = self.snapshot();
+        if node.is_dir || node.removed {
+            return 0;
+        }
+        let end = (offset + buf.len()).min(u32::MAX as usize);
+        if offset >= end {
+            return 0;
+        }
+        let cluster_bytes = fs.cluster_bytes();
+        let mut chain = fs.chain(node.first_cluster);
+        let allocated = chain.len() * cluster_bytes;
+        while chain.len() * cluster_bytes < end {
+            match fs.alloc_cluster(chain.last().copied()) {
+                Some(cluster) => chain.push(cluster),
+                None => break,
+            }
+        }
+        if node.first_cluster == 0 {
+            node.first_cluster = chain.first().copied().unwrap_or(0);
+        }
+        let end = end.min(chain.len() * cluster_bytes);
+        let size = node.size as usize;
+        // 新分配的簇已经清零了, 原来的簇里文件末尾之后的内容不一定是0
+        if offset > size && allocated > size {
+            let zeros = vec![0u8; offset.min(allocated) - size];
+            fs.write_chain(&chain, size, &zeros);
+        }
+        let written = end.saturating_sub(offset);
+        fs.write_chain(&chain, offset, &buf[..written]);
+        node.size = node.size.max(end as u32);
+        fs.sync_node(&node, |entry| entry.touch_modified(now()));
+        *self.node.lock() = node;
+        return written;
+    }
+
+    /// Clear the data in current inode
+    pub fn clear(&self) {
+        self.truncate(0);
+    }
+
+    /// Set the size of a regular file to `size`, 变长的部分读出来是0, 变短时回收后面的簇.
+    /// 超过4GB-1, 不是普通文件或者磁盘满了返回false, 这时大小不变
+    pub fn truncate(&self, size: u64) -> bool {
+        let mut fs = self.fs.lock();
+        let mut node = self.snapshot();
+        if node.is_dir || node.removed || size > u32::MAX as u64 {
+            return false;
+        }
+        let size = size as usize;
+        let cluster_bytes = fs.cluster_bytes();
+        let old_size = node.size as usize;
+        let mut chain = fs.chain(node.first_cluster);
+        let allocated = chain.len();
+        if size <= old_size {
+            let keep = size.div_ceil(cluster_bytes);
+            node.first_cluster = fs.truncate_chain(node.first_cluster, keep);
+        } else {
+            while chain.len() * cluster_bytes < size {
+                match fs.alloc_cluster(chain.last().copied()) {
+                    Some(cluster) => chain.push(cluster),
+                    None => {
+                        // 这次分配的簇还回去
+                        if let Some(first) = chain.first() {
+                            fs.truncate_chain(*first, allocated);
+                        }
+                        return false;
+                    }
+                }
+            }
+            if node.first_cluster == 0 {
+                node.first_cluster = chain.first().copied().unwrap_or(0);
+            }
+            // 新分配的簇已经清零了, 原来的簇里文件末尾之后的内容不一定是0
+            let zeros = vec![0u8; size.min(allocated * cluster_bytes).saturating_sub(old_size)];
+            fs.write_chain(&chain, old_size, &zeros);
+        }
+        node.size = size as u32;
+        fs.sync_node(&node, |entry| entry.touch_modified(now()));
+        *self.node.lock() = node;
+        return true;
+    }
+
+    /// Remove the regular file `name` under current directory
+    pub fn unlink(&self, name: &str) -> bool {
+        return self.remove(name, false);
+    }
+
+    /// Remove the empty directory `name` under current directory
+    pub fn rmdir(&self, name: &str) -> bool {
+        return self.remove(name, true);
+    }
+
+    fn remove(&self, name: &str, is_dir: bool) -> bool {
+        if !is_valid_name(name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let dir = match self.dir() {
+            Some(dir) => dir,
+            None => return false,
+        };
+        let (chain, slots) = fs.read_dir(dir.first_cluster);
+        let slot = match slots
+            .iter()
+            .find(|slot| !slot.is_dot() && slot.matches(name))
+        {
+            Some(slot) => slot,
+            None => return false,
+        };
+        if slot.entry.is_dir() != is_dir || (is_dir && !Self::is_empty_dir(&fs, &slot.entry)) {
+            return false;
+        }
+        fs.remove_slot(&chain, slot);
+        fs.sync_node(&dir, |entry| entry.touch_modified(now()));
+        return true;
+    }
+
+    fn is_empty_dir(fs: &FatFileSystem, entry: &ShortDirEntry) -> bool {
+        let (_, slots) = fs.read_dir(entry.first_cluster());
+        return slots.iter().all(|slot| slot.is_dot());
+    }
+
+    /// Move `old_name` under current directory to `new_name` under `new_dir`.
+    /// 如果`new_name`已经存在, 它会被替换: 文件只能替换文件, 目录只能替换空目录
+    pub fn rename(&self, old_name: &str, new_dir: &FatInode, new_name: &str) -> bool {
+        if !is_valid_name(old_name) || !is_valid_name(new_name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let (old_dir, new_dir) = match (self.dir(), new_dir.dir()) {
+            (Some(old_dir), Some(new_dir)) => (old_dir, new_dir),
+            _ => return false,
+        };
+        let (old_chain, old_slots) = fs.read_dir(old_dir.first_cluster);
+        let old_slot = match old_slots
+            .into_iter()
+            .find(|slot| !slot.is_dot() && slot.matches(old_name))
+        {
+            Some(slot) => slot,
+            None => return false,
+        };
+        let old_pos = fs.disk_pos(&old_chain, old_slot.short_offset());
+        let is_dir = old_slot.entry.is_dir();
+        // 目录不能移动到它自己或者它的子目录下面
+        if is_dir && fs.is_inside(new_dir.first_cluster, old_slot.entry.first_cluster()) {
+            return false;
+        }
+        let (new_chain, new_slots) = fs.read_dir(new_dir.first_cluster);
+        if let Some(replaced) = new_slots
+            .iter()
+            .find(|slot| !slot.is_dot() && slot.matches(new_name))
+        {
+            // 只改大小写时找到的是自己
+            if fs.disk_pos(&new_chain, replaced.short_offset()) != old_pos {
+                if replaced.entry.is_dir() != is_dir
+                    || (is_dir && !Self::is_empty_dir(&fs, &replaced.entry))
+                {
+                    return false;
+                }
+                fs.remove_slot(&new_chain, replaced);
+            }
+        }
+        // 新目录项只有名字变了. 旧目录项还在, 不会和它的短文件名重复
+        let (_, new_slots) = fs.read_dir(new_dir.first_cluster);
+        let (short_name, nt_res, long_name) = short_name_for(new_name, &new_slots);
+        let mut entry = old_slot.entry;
+        entry.name = short_name;
+        entry.nt_res = nt_res;
+        let new_pos = match fs.add_entries(
+            new_dir.first_cluster,
+            &entries_of(new_name, &entry, long_name),
+        ) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        // 在同一个目录里时, 添加目录项只会用空闲的位置或者加簇, 旧目录项的偏移不变
+        fs.remove_entries(&old_chain, &old_slot);
+        if is_dir && old_dir.first_cluster != new_dir.first_cluster {
+            // 目录换了父目录, 它的 .. 也要指向新的父目录
+            let parent = if new_dir.entry_pos.is_none() {
+                0
+            } else {
+                new_dir.first_cluster
+            };
+            let dotdot = fs.disk_pos(&[entry.first_cluster()], DIRENT_SZ);
+            fs.update_entry(dotdot, |dotdot| dotdot.set_first_cluster(parent));
+        }
+        if let Some(node) = fs.nodes.remove(&old_pos) {
+            if let Some(node) = node.upgrade() {
+                let mut node = node.lock();
+                node.entry_pos = Some(new_pos);
+                node.ino = (new_pos / DIRENT_SZ as u64) as u32;
+            }
+            fs.nodes.insert(new_pos, node);
+        }
+        let time = now();
+        fs.sync_node(&old_dir, |entry| entry.touch_modified(time));
+        fs.sync_node(&new_dir, |entry| entry.touch_modified(time));
+        return true;
+    }
+
+    /// Get the absolute path of current directory inside the filesystem,
+    /// None if it is not a directory or has been removed
+    pub fn path(&self) -> Option<String> {
+        let fs = self.fs.lock();
+        let dir = self.dir()?;
+        let mut names: Vec<String> = Vec::new();
+        let mut cluster = dir.first_cluster;
+        while let Some((_, slot)) = fs.dir_slot(cluster) {
+            names.push(slot.name);
+            cluster = fs.parent_cluster(cluster);
+        }
+        if cluster != fs.root_cluster() {
+            return None;
+        }
+        if names.is_empty() {
+            return Some(String::from("/"));
+        }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        return Some(path);
+    }
+
+    /// Get the metadata of current inode.
+    /// 目录的链接数总是2, 时间戳是从1980-01-01开始的毫秒数, 没有状态改变时间, 用修改时间代替
+    pub fn stat(&self) -> InodeStat {
+        let fs = self.fs.lock();
+        let node = self.snapshot();
+        let entry = node
+            .entry_pos
+            .map(|pos| ShortDirEntry::from_bytes(&fs.read_raw(pos)))
+            .unwrap_or_default();
+        let clusters = fs.chain(node.first_cluster).len();
+        let size = if node.is_dir {
+            clusters * fs.cluster_bytes()
+        } else {
+            node.size as usize
+        };
+        let mut mode = if node.is_dir { 0o755 } else { 0o644 };
+        if entry.attr & ATTR_READ_ONLY != 0 {
+            mode &= !0o222;
+        }
+        let nlink = match node {
+            _ if node.removed => 0,
+            _ if node.is_dir => 2,
+            _ => 1,
+        };
+        return InodeStat {
+            ino: node.ino,
+            is_dir: node.is_dir,
+            is_symlink: false,
+            mode,
+            nlink,
+            size: size as u64,
+            blocks: (clusters * fs.cluster_bytes() / BLOCK_SZ) as u32,
+            atime: entry.atime(),
+            mtime: entry.mtime(),
+            ctime: entry.mtime(),
+        };
+    }
+
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        return self.snapshot().is_dir;
+    }
+
+    /// Size of a file in bytes, 目录是它占的簇的大小
+    pub fn size(&self) -> u64 {
+        return self.stat().size;
+    }
+}
@@ -0,0 +1,608 @@
+//! File system checker
+//!
+//! 从根目录出发遍历所有能访问到的inode, 再和两个位图对比, 找出重复使用的块, 位图里没有标记的块和inode,
+//! 没有被引用的块和inode(孤儿), 类型或标志无效的inode,
+//! 指向无效inode的目录项, 错误的链接数. 修复时以遍历的结果为准重建位图.
+
+use crate::{
+    block_cache::{block_cache_sync_all, get_block_cache},
+    layout::{
+        parse_dir_block, BlockPos, DataBlock, DirEntry, DirEntryHeader, DiskInode, IndirectBlock,
+        SuperBlock, DIRENT_HEADER_SZ, DISK_INODE_SZ, FT_DIR,
+    },
+    EasyFileSystem, BLOCK_SZ,
+};
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Result};
+
+/// A problem found by [`EasyFileSystem::check`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// The root inode is not an allocated directory, 无法检查也无法修复
+    BadRoot,
+    /// `inode` refers to `block_id` outside of the data area, 修复时把文件截断到这个块之前
+    BadBlockNumber {
+        /// The inode
+        inode: u32,
+        /// The bad block number
+        block_id: u32,
+    },
+    /// `block_id` is used by both `first` and `inode`, 修复时给`inode`复制一个新的数据块(索引块无法修复)
+    DoubleAllocatedBlock {
+        /// The block
+        block_id: u32,
+        /// The inode found first
+        first: u32,
+        /// The inode found later
+        inode: u32,
+    },
+    /// `block_id` is used by an inode but free in the data bitmap
+    UnmarkedBlock {
+        /// The block
+        block_id: u32,
+    },
+    /// `block_id` is allocated in the data bitmap but not used by any inode
+    LeakedBlock {
+        /// The block
+        block_id: u32,
+    },
+    /// `inode` has an invalid type or flags, 修复时删掉指向它的目录项并清空这个inode
+    BadInode {
+        /// The inode
+        inode: u32,
+    },
+    /// `inode` is reachable from the root but free in the inode bitmap
+    UnmarkedInode {
+        /// The inode
+        inode: u32,
+    },
+    /// `inode` is allocated in the inode bitmap but not reachable from the root
+    OrphanInode {
+        /// The inode
+        inode: u32,
+    },
+    /// The entry at `offset` of directory `dir` refers to a wrong `inode` or has a bad name.
+    /// 修复时删掉这个目录项, . 和 .. 则改成正确的inode
+    BadDirEntry {
+        /// The directory
+        dir: u32,
+        /// Offset of the entry in the directory
+        offset: usize,
+        /// The inode number in the entry
+        inode: u32,
+    },
+    /// The entry at `offset` of directory `dir` records a wrong file type of `inode`, 修复时改正
+    WrongFileType {
+        /// The directory
+        dir: u32,
+        /// Offset of the entry in the directory
+        offset: usize,
+        /// The inode number in the entry
+        inode: u32,
+    },
+    /// The entries of directory `dir` are broken from `offset` to the end of the block,
+    /// 修复时丢掉这部分内容
+    BadDirBlock {
+        /// The directory
+        dir: u32,
+        /// Offset of the broken part in the directory
+        offset: usize,
+    },
+    /// The link count of `inode` is `found` but `expected` entries refer to it
+    WrongLinkCount {
+        /// The inode
+        inode: u32,
+        /// Link count on disk
+        found: u16,
+        /// Number of entries referring to the inode
+        expected: u16,
+    },
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Self::BadRoot => write!(f, "root inode is not a directory"),
+            Self::BadBlockNumber { inode, block_id } => {
+                write!(f, "inode {} refers to bad block {}", inode, block_id)
+            }
+            Self::DoubleAllocatedBlock {
+                block_id,
+                first,
+                inode,
+            } => write!(
+                f,
+                "block {} is used by both inode {} and inode {}",
+                block_id, first, inode
+            ),
+            Self::UnmarkedBlock { block_id } => {
+                write!(
+                    f,
+                    "block {} is in use but free in the data bitmap",
+                    block_id
+                )
+            }
+            Self::LeakedBlock { block_id } => {
+                write!(f, "block {} is allocated but not in use", block_id)
+            }
+            Self::BadInode { inode } => write!(f, "inode {} has invalid type or flags", inode),
+            Self::UnmarkedInode { inode } => {
+                write!(f, "inode {} is in use but free in the inode bitmap", inode)
+            }
+            Self::OrphanInode { inode } => {
+                write!(f, "inode {} is allocated but not reachable", inode)
+            }
+            Self::BadDirEntry { dir, offset, inode } => write!(
+                f,
+                "entry at {} of directory {} refers to bad inode {}",
+                offset, dir, inode
+            ),
+            Self::WrongFileType { dir, offset, inode } => write!(
+                f,
+                "entry at {} of directory {} has wrong file type of inode {}",
+                offset, dir, inode
+            ),
+            Self::BadDirBlock { dir, offset } => write!(
+                f,
+                "directory {} is broken from {} to the end of the block",
+                dir, offset
+            ),
+            Self::WrongLinkCount {
+                inode,
+                found,
+                expected,
+            } => write!(
+                f,
+                "inode {} has link count {}, should be {}",
+                inode, found, expected
+            ),
+        }
+    }
+}
+
+/// Where an inode stores the number of a data block
+#[derive(Clone, Copy)]
+enum Slot {
+    /// `direct[i]` of the disk inode
+    Direct(usize),
+    /// The i-th entry of an indirect block
+    Indirect(u32, usize),
+}
+
+/// How to repair a bad directory entry
+#[derive(Clone, Copy)]
+enum DirFix {
+    /// 删除目录项
+    Remove,
+    /// . 和 .. 改成指向这个目录
+    Retarget(u32),
+    /// 改正文件类型
+    Retype(u8),
+    /// 丢掉从这里到块尾的内容
+    Truncate,
+}
+
+/// Result of walking the filesystem from the root
+#[derive(Default)]
+struct Scan {
+    problems: Vec<FsckProblem>,
+    /// 能访问到的inode -> 指向它的目录项个数
+    links: BTreeMap<u32, u16>,
+    /// 用到的块 -> 第一个用它的inode
+    owners: BTreeMap<u32, u32>,
+    /// 被第二个inode用到的数据块 (inode, 块号存放的位置, 块)
+    duplicates: Vec<(u32, Slot, u32)>,
+    /// 被多个inode用到的索引块
+    shared_index: BTreeSet<u32>,
+    /// 类型或标志无效的inode
+    bad_inodes: BTreeSet<u32>,
+    /// 要截断的inode -> 截断后的数据块数
+    truncated: Vec<(u32, usize)>,
+    /// 坏的目录项 (目录, 位置, 块里前一个目录项的位置, 怎么修)
+    bad_dirents: Vec<(u32, usize, Option<usize>, DirFix)>,
+}
+
+impl Scan {
+    fn bad_dirent(
+        &mut self,
+        dir: u32,
+        offset: usize,
+        prev: Option<usize>,
+        inode: u32,
+        fix: DirFix,
+    ) {
+        let problem = match fix {
+            DirFix::Retype(_) => FsckProblem::WrongFileType { dir, offset, inode },
+            _ => FsckProblem::BadDirEntry { dir, offset, inode },
+        };
+        self.problems.push(problem);
+        self.bad_dirents.push((dir, offset, prev, fix));
+    }
+
+    fn bad_inode(&mut self, inode: u32) {
+        if self.bad_inodes.insert(inode) {
+            self.problems.push(FsckProblem::BadInode { inode });
+        }
+    }
+}
+
+impl EasyFileSystem {
+    /// Check the filesystem and return the problems found, repair them if `repair`.
+    /// 修复之后应该再检查一次, 确认没有修不好的问题
+    pub fn check(&mut self, repair: bool) -> Vec<FsckProblem> {
+        let (inode_count, data_blocks) = get_block_cache(0, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |super_block: &SuperBlock| {
+                let inodes_per_block = (BLOCK_SZ / core::mem::size_of::<DiskInode>()) as u32;
+                let inode_count = (super_block.inode_area_blocks * inodes_per_block)
+                    .min(self.inode_bitmap.maximum() as u32);
+                (inode_count, super_block.data_area_blocks)
+            });
+        let root_is_dir = matches!(self.read_inode(0), Some(root) if root.is_dir());
+        if !self.inode_bitmap.get(&self.block_device, 0) || !root_is_dir {
+            return vec![FsckProblem::BadRoot];
+        }
+        let mut scan = Scan::default();
+        // 广度优先遍历, 队列里是 (inode, 父目录)
+        let mut queue = VecDeque::new();
+        queue.push_back((0u32, 0u32));
+        scan.links.insert(0, 0);
+        while let Some((inode_id, parent_id)) = queue.pop_front() {
+            // 进队列之前已经检查过类型
+            let disk_inode = self.read_inode(inode_id).unwrap();
+            let leaves = self.scan_blocks(inode_id, &disk_inode, data_blocks, &mut scan);
+            if !disk_inode.is_dir() {
+                continue;
+            }
+            let size = (disk_inode.size() as usize).min(leaves.len() * BLOCK_SZ);
+            for (i, (block_id, _)) in leaves.iter().enumerate().take(size / BLOCK_SZ) {
+                let data_block =
+                    get_block_cache(*block_id as usize, Arc::clone(&self.block_device))
+                        .lock()
+                        .read(0, |data_block: &DataBlock| *data_block);
+                let (records, broken) = parse_dir_block(&data_block, i * BLOCK_SZ);
+                if let Some(offset) = broken {
+                    scan.problems.push(FsckProblem::BadDirBlock {
+                        dir: inode_id,
+                        offset,
+                    });
+                    let prev = records.last().map(|record| record.offset);
+                    scan.bad_dirents
+                        .push((inode_id, offset, prev, DirFix::Truncate));
+                }
+                for record in records {
+                    let dirent = match record.entry {
+                        Some(dirent) => dirent,
+                        None => continue,
+                    };
+                    self.check_dirent(
+                        inode_id,
+                        parent_id,
+                        inode_count,
+                        record.offset,
+                        record.prev,
+                        &dirent,
+                        &mut scan,
+                        &mut queue,
+                    );
+                }
+            }
+        }
+        for inode_id in 0..inode_count {
+            let allocated = self.inode_bitmap.get(&self.block_device, inode_id as usize);
+            let reachable = scan.links.contains_key(&inode_id);
+            if reachable && !allocated {
+                scan.problems
+                    .push(FsckProblem::UnmarkedInode { inode: inode_id });
+            } else if allocated && !reachable {
+                scan.problems
+                    .push(FsckProblem::OrphanInode { inode: inode_id });
+                if self.read_inode(inode_id).is_none() {
+                    scan.bad_inode(inode_id);
+                }
+            }
+        }
+        for (inode_id, links) in scan.links.iter() {
+            let nlink = self.read_inode(*inode_id).unwrap().nlink;
+            if nlink != *links {
+                scan.problems.push(FsckProblem::WrongLinkCount {
+                    inode: *inode_id,
+                    found: nlink,
+                    expected: *links,
+                });
+            }
+        }
+        for i in 0..data_blocks {
+            let block_id = self.data_area_start_block + i;
+            let allocated = self.data_bitmap.get(&self.block_device, i as usize);
+            let used = scan.owners.contains_key(&block_id);
+            if used && !allocated {
+                scan.problems.push(FsckProblem::UnmarkedBlock { block_id });
+            } else if allocated && !used {
+                scan.problems.push(FsckProblem::LeakedBlock { block_id });
+            }
+        }
+        if repair && !scan.problems.is_empty() {
+            self.repair(&mut scan, inode_count, data_blocks);
+        }
+        return scan.problems;
+    }
+
+    /// Read a disk inode, return `None` if its type or flags are invalid
+    fn read_inode(&self, inode_id: u32) -> Option<DiskInode> {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, |bytes: &[u8; DISK_INODE_SZ]| {
+                DiskInode::from_bytes(bytes)
+            })
+    }
+
+    /// Zero a bad inode without reading it as a [`DiskInode`]
+    fn clear_inode(&self, inode_id: u32) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, |bytes: &mut [u8; DISK_INODE_SZ]| {
+                bytes.fill(0)
+            });
+    }
+
+    fn modify_inode(&self, inode_id: u32, f: impl FnOnce(&mut DiskInode)) {
+        let (block_id, block_offset) = self.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f);
+    }
+
+    fn read_index(&self, block_id: u32, index: usize) -> u32 {
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(0, |indirect: &IndirectBlock| indirect[index])
+    }
+
+    /// Check an entry at `offset` of directory `dir_id`, 第一次遇到的目录项指向的inode加入遍历队列
+    #[allow(clippy::too_many_arguments)]
+    fn check_dirent(
+        &self,
+        dir_id: u32,
+        parent_id: u32,
+        inode_count: u32,
+        offset: usize,
+        prev: Option<usize>,
+        dirent: &DirEntry,
+        scan: &mut Scan,
+        queue: &mut VecDeque<(u32, u32)>,
+    ) {
+        let target = dirent.inode_number();
+        let dot = match dirent.name() {
+            "." => Some(dir_id),
+            ".." => Some(parent_id),
+            name if !name.contains('/') => None,
+            _ => return scan.bad_dirent(dir_id, offset, prev, target, DirFix::Remove),
+        };
+        if let Some(dot) = dot {
+            if target != dot || !dirent.is_dir() {
+                scan.bad_dirent(dir_id, offset, prev, target, DirFix::Retarget(dot));
+            }
+            *scan.links.get_mut(&dot).unwrap() += 1;
+            return;
+        }
+        // 根目录不能出现在别的目录里; 已经释放并且没有链接的inode, 说明目录项是删除时残留的
+        if target == 0 || target >= inode_count {
+            return scan.bad_dirent(dir_id, offset, prev, target, DirFix::Remove);
+        }
+        let target_inode = self.read_inode(target);
+        if !self.inode_bitmap.get(&self.block_device, target as usize)
+            && !matches!(target_inode, Some(inode) if inode.nlink != 0)
+        {
+            return scan.bad_dirent(dir_id, offset, prev, target, DirFix::Remove);
+        }
+        // 不知道坏的inode原来是什么类型, 不能按它改目录项, 只能删掉目录项
+        let target_inode = match target_inode {
+            Some(target_inode) => target_inode,
+            None => {
+                scan.bad_inode(target);
+                return scan.bad_dirent(dir_id, offset, prev, target, DirFix::Remove);
+            }
+        };
+        let target_is_dir = target_inode.is_dir();
+        match scan.links.get_mut(&target) {
+            // 目录只能有一个父目录
+            Some(_) if target_is_dir => {
+                return scan.bad_dirent(dir_id, offset, prev, target, DirFix::Remove)
+            }
+            Some(links) => *links += 1,
+            None => {
+                scan.links.insert(target, 1);
+                queue.push_back((target, dir_id));
+            }
+        }
+        if dirent.file_type() != target_inode.file_type() {
+            let fix = DirFix::Retype(target_inode.file_type());
+            scan.bad_dirent(dir_id, offset, prev, target, fix);
+        }
+    }
+
+    /// Collect the blocks used by an inode and return its data blocks.
+    /// 遇到数据区以外的块号就停下, 之后的块都不算
+    fn scan_blocks(
+        &self,
+        inode_id: u32,
+        disk_inode: &DiskInode,
+        data_blocks: u32,
+        scan: &mut Scan,
+    ) -> Vec<(u32, Slot)> {
+        let start = self.data_area_start_block;
+        let valid = |block_id: u32| block_id >= start && block_id < start + data_blocks;
+        let mut leaves = Vec::new();
+        let mut bad = None;
+        let blocks = (disk_inode.data_blocks() as usize).min(disk_inode.max_blocks());
+        'blocks: for i in 0..blocks {
+            let (block_id, slot) = match disk_inode.locate(i) {
+                BlockPos::Direct(index) => (disk_inode.direct[index], Slot::Direct(index)),
+                BlockPos::Indirect { level, index } => {
+                    // 沿着索引块往下走到最后一层
+                    let path = DiskInode::index_path(level, index);
+                    let mut index_block = disk_inode.indirect(level);
+                    for (depth, index) in path[..level].iter().enumerate() {
+                        if !valid(index_block) {
+                            bad = Some(index_block);
+                            break 'blocks;
+                        }
+                        if depth + 1 < level {
+                            index_block = self.read_index(index_block, *index);
+                        }
+                    }
+                    let index = path[level - 1];
+                    (
+                        self.read_index(index_block, index),
+                        Slot::Indirect(index_block, index),
+                    )
+                }
+            };
+            if !valid(block_id) {
+                bad = Some(block_id);
+                break;
+            }
+            leaves.push((block_id, slot));
+        }
+        if let Some(block_id) = bad {
+            scan.problems.push(FsckProblem::BadBlockNumber {
+                inode: inode_id,
+                block_id,
+            });
+            scan.truncated.push((inode_id, leaves.len()));
+        }
+        // 截断之后还要用到的索引块
+        let index_blocks = disk_inode.index_blocks(0..leaves.len(), &self.block_device);
+        for block_id in index_blocks {
+            if !Self::claim(inode_id, block_id, scan) {
+                scan.shared_index.insert(block_id);
+            }
+        }
+        for (block_id, slot) in leaves.iter() {
+            if !Self::claim(inode_id, *block_id, scan) {
+                scan.duplicates.push((inode_id, *slot, *block_id));
+            }
+        }
+        return leaves;
+    }
+
+    /// Record that `inode_id` uses `block_id`, return false if another inode already uses it
+    fn claim(inode_id: u32, block_id: u32, scan: &mut Scan) -> bool {
+        match scan.owners.get(&block_id) {
+            None => {
+                scan.owners.insert(block_id, inode_id);
+                return true;
+            }
+            Some(first) => {
+                scan.problems.push(FsckProblem::DoubleAllocatedBlock {
+                    block_id,
+                    first: *first,
+                    inode: inode_id,
+                });
+                return false;
+            }
+        }
+    }
+
+    fn repair(&mut self, scan: &mut Scan, inode_count: u32, data_blocks: u32) {
+        // 1. 截断有坏块号的inode, 不再需要的索引块也不要了
+        for (inode_id, count) in scan.truncated.iter() {
+            let count = *count;
+            self.modify_inode(*inode_id, |disk_inode| {
+                disk_inode.set_size(disk_inode.size().min((count * BLOCK_SZ) as u64));
+                disk_inode.clear_indirect_from(count);
+            });
+        }
+        // 2. 修改或删除坏的目录项. 删除时并入前一个目录项, 前一个目录项可能也要删除, 所以从后往前删
+        scan.bad_dirents.sort_by_key(|dirent| core::cmp::Reverse((dirent.0, dirent.1)));
+        for (dir_id, offset, prev, fix) in scan.bad_dirents.iter() {
+            let offset = *offset;
+            self.modify_inode(*dir_id, |dir| {
+                let mut bytes = [0u8; DIRENT_HEADER_SZ];
+                dir.read_at(offset, &mut bytes, &self.block_device);
+                let mut header = DirEntryHeader::from_bytes(&bytes);
+                // 删除之后空出来的范围
+                let end = match fix {
+                    DirFix::Retarget(inode_id) => {
+                        header.inode = *inode_id;
+                        header.file_type = FT_DIR;
+                        dir.write_at(offset, &header.to_bytes(), &self.block_device);
+                        return;
+                    }
+                    DirFix::Retype(file_type) => {
+                        header.file_type = *file_type;
+                        dir.write_at(offset, &header.to_bytes(), &self.block_device);
+                        return;
+                    }
+                    DirFix::Remove => offset + header.rec_len as usize,
+                    DirFix::Truncate => (offset / BLOCK_SZ + 1) * BLOCK_SZ,
+                };
+                match prev {
+                    Some(prev) => {
+                        dir.read_at(*prev, &mut bytes, &self.block_device);
+                        let mut header = DirEntryHeader::from_bytes(&bytes);
+                        header.rec_len = (end - prev) as u16;
+                        dir.write_at(*prev, &header.to_bytes(), &self.block_device);
+                    }
+                    None => {
+                        let header = DirEntryHeader::free(end - offset);
+                        dir.write_at(offset, &header.to_bytes(), &self.block_device);
+                    }
+                }
+            });
+        }
+        // 3. 以遍历的结果为准重建位图, 孤儿inode的链接数清零, 坏的inode整个清零
+        for inode_id in 0..inode_count {
+            let reachable = scan.links.contains_key(&inode_id);
+            if scan.bad_inodes.contains(&inode_id) {
+                self.clear_inode(inode_id);
+            } else if !reachable && self.inode_bitmap.get(&self.block_device, inode_id as usize) {
+                self.modify_inode(inode_id, |disk_inode| disk_inode.nlink = 0);
+            }
+            self.inode_bitmap
+                .set(&self.block_device, inode_id as usize, reachable);
+        }
+        for i in 0..data_blocks {
+            let used = scan.owners.contains_key(&(self.data_area_start_block + i));
+            self.data_bitmap.set(&self.block_device, i as usize, used);
+        }
+        // 4. 重复使用的数据块, 复制一份给后来的inode
+        for (inode_id, slot, block_id) in scan.duplicates.iter() {
+            if let Slot::Indirect(index_block, _) = slot {
+                if scan.shared_index.contains(index_block) {
+                    continue;
+                }
+            }
+            let data = get_block_cache(*block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .read(0, |data_block: &DataBlock| *data_block);
+            let new_block_id = self.alloc_data();
+            get_block_cache(new_block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify(0, |data_block: &mut DataBlock| *data_block = data);
+            match slot {
+                Slot::Direct(i) => {
+                    self.modify_inode(*inode_id, |disk_inode| disk_inode.direct[*i] = new_block_id)
+                }
+                Slot::Indirect(index_block, i) => {
+                    get_block_cache(*index_block as usize, Arc::clone(&self.block_device))
+                        .lock()
+                        .modify(0, |indirect: &mut IndirectBlock| {
+                            indirect[*i] = new_block_id
+                        });
+                }
+            }
+        }
+        // 5. 链接数
+        for (inode_id, links) in scan.links.iter() {
+            self.modify_inode(*inode_id, |disk_inode| disk_inode.nlink = *links);
+        }
+        block_cache_sync_all();
+    }
+}
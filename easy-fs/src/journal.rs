@@ -0,0 +1,177 @@
+//! Write-ahead journal of metadata blocks
+//!
+//! 一个修改文件系统的操作(创建/写/删除/改名...)是一个事务. 事务进行中, 通过 `BlockCache::modify`
+//! 修改的块(超级块/位图/inode/索引块/目录内容)只留在内存里, 不会写回原来的位置. 提交的顺序是:
+//! 1. 把事务修改过的块写进日志区
+//! 2. 写日志头, 记下这些块原来的块号. 日志头写完, 事务就提交了
+//! 3. 把这些块写回原来的位置
+//! 4. 清空日志头
+//!
+//! 在第2步之前崩溃, 事务的修改全部丢失; 之后崩溃, 下次 `EasyFileSystem::open` 重放日志.
+//! 普通文件的内容不记日志, 只保证在事务提交之前写回.
+//! 事务属于开始它的设备, 块缓存是所有设备共用的, 其他设备上的块不会记进这个事务.
+
+use super::{BlockDevice, BLOCK_SZ};
+use crate::block_cache::device_id;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+use spin::Mutex;
+
+/// Magic number of the journal header
+const JOURNAL_MAGIC: u32 = 0x4a4e4c31;
+/// The max number of blocks in a transaction, 日志头正好一个块
+pub const JOURNAL_MAX_BLOCKS: usize = BLOCK_SZ / 4 - 2;
+/// Number of blocks of the journal area: a header and the logged blocks
+pub const JOURNAL_BLOCKS: u32 = 64;
+
+/// Header of the journal, the first block of the journal area
+#[repr(C)]
+struct JournalHeader {
+    magic: u32,
+    /// 已经提交但还没写回原位置的块数, 0表示日志是空的
+    count: u32,
+    /// 每个日志块原来的块号
+    blocks: [u32; JOURNAL_MAX_BLOCKS],
+}
+
+// 日志头正好一个块
+const _: () = assert!(core::mem::size_of::<JournalHeader>() == BLOCK_SZ);
+
+impl JournalHeader {
+    fn new(blocks: &[u32]) -> Self {
+        let mut header = Self {
+            magic: JOURNAL_MAGIC,
+            count: blocks.len() as u32,
+            blocks: [0; JOURNAL_MAX_BLOCKS],
+        };
+        header.blocks[..blocks.len()].copy_from_slice(blocks);
+        return header;
+    }
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self as *const _ as *const u8, BLOCK_SZ) }
+    }
+    fn read(block_device: &Arc<dyn BlockDevice>, block_id: usize) -> Self {
+        let mut header = Self::new(&[]);
+        let bytes =
+            unsafe { core::slice::from_raw_parts_mut(&mut header as *mut _ as *mut u8, BLOCK_SZ) };
+        block_device.read_block(block_id, bytes);
+        return header;
+    }
+}
+
+/// The running transaction
+struct Transaction {
+    /// 事务所在设备的id
+    device: usize,
+    /// 块号 -> 块的最新内容
+    blocks: BTreeMap<usize, Vec<u8>>,
+    capacity: usize,
+}
+
+lazy_static! {
+    static ref TRANSACTION: Mutex<Option<Transaction>> = Mutex::new(None);
+}
+
+/// Start a transaction on `block_device` if none is running, a transaction logs at most `capacity` blocks
+pub fn begin_transaction(block_device: &Arc<dyn BlockDevice>, capacity: usize) {
+    let mut transaction = TRANSACTION.lock();
+    if transaction.is_none() {
+        *transaction = Some(Transaction {
+            device: device_id(block_device),
+            blocks: BTreeMap::new(),
+            capacity,
+        });
+    }
+}
+
+/// Whether a transaction is running
+pub fn in_transaction() -> bool {
+    return TRANSACTION.lock().is_some();
+}
+
+/// Drop the running transaction without writing anything, 用于重新挂载
+pub fn abort_transaction() {
+    *TRANSACTION.lock() = None;
+}
+
+/// Record the new content of a block of `device` modified in the running transaction.
+/// Return false if no transaction is running on the device
+pub fn log_block(device: usize, block_id: usize, data: &[u8]) -> bool {
+    let mut transaction = TRANSACTION.lock();
+    let transaction = match transaction.as_mut() {
+        Some(transaction) if transaction.device == device => transaction,
+        _ => return false,
+    };
+    if let Some(block) = transaction.blocks.get_mut(&block_id) {
+        block.copy_from_slice(data);
+        return true;
+    }
+    assert!(
+        transaction.blocks.len() < transaction.capacity,
+        "Transaction too big for the journal!"
+    );
+    transaction.blocks.insert(block_id, data.to_vec());
+    return true;
+}
+
+/// Whether the block of `device` has been modified in the running transaction
+pub fn is_logged(device: usize, block_id: usize) -> bool {
+    return TRANSACTION.lock().as_ref().is_some_and(|transaction| {
+        transaction.device == device && transaction.blocks.contains_key(&block_id)
+    });
+}
+
+/// The content of a block of `device` modified in the running transaction, 缓存换出之后再读回来时用
+pub fn logged_block(device: usize, block_id: usize) -> Option<Vec<u8>> {
+    return TRANSACTION.lock().as_ref().and_then(|transaction| {
+        if transaction.device != device {
+            return None;
+        }
+        return transaction.blocks.get(&block_id).cloned();
+    });
+}
+
+/// Commit the running transaction to the journal starting at `journal_start`,
+/// then write the blocks back to their home locations
+pub fn commit_transaction(block_device: &Arc<dyn BlockDevice>, journal_start: usize) {
+    let transaction = match TRANSACTION.lock().take() {
+        Some(transaction) if !transaction.blocks.is_empty() => transaction,
+        _ => return,
+    };
+    assert_eq!(transaction.device, device_id(block_device));
+    let homes: Vec<u32> = transaction.blocks.keys().map(|id| *id as u32).collect();
+    // 日志块是连续的, 一次写完
+    let logged: Vec<u8> = transaction.blocks.values().flatten().copied().collect();
+    block_device.write_blocks(journal_start + 1, &logged);
+    // 提交点
+    block_device.write_block(journal_start, JournalHeader::new(&homes).as_bytes());
+    for (block_id, data) in transaction.blocks.iter() {
+        block_device.write_block(*block_id, data);
+    }
+    block_device.write_block(journal_start, JournalHeader::new(&[]).as_bytes());
+}
+
+/// Write an empty journal header, 格式化的时候用
+pub fn init_journal(block_device: &Arc<dyn BlockDevice>, journal_start: usize) {
+    block_device.write_block(journal_start, JournalHeader::new(&[]).as_bytes());
+}
+
+/// Replay the committed transaction left in the journal by a crash, return the number of blocks replayed.
+/// 重放要在块缓存里没有这些块的时候做
+pub fn replay_journal(block_device: &Arc<dyn BlockDevice>, journal_start: usize) -> usize {
+    let header = JournalHeader::read(block_device, journal_start);
+    if header.magic != JOURNAL_MAGIC || header.count == 0 {
+        return 0;
+    }
+    let count = (header.count as usize).min(JOURNAL_MAX_BLOCKS);
+    let mut data = vec![0u8; BLOCK_SZ];
+    for (i, block_id) in header.blocks[..count].iter().enumerate() {
+        block_device.read_block(journal_start + 1 + i, &mut data);
+        block_device.write_block(*block_id as usize, &data);
+    }
+    block_device.write_block(journal_start, JournalHeader::new(&[]).as_bytes());
+    return count;
+}
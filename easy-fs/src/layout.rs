@@ -1,27 +1,46 @@
-use crate::{block_cache::get_block_cache, block_dev::BlockDevice, BLOCK_SZ};
+use crate::{
+    block_cache::{block_cache_prefetch, get_block_cache},
+    block_dev::BlockDevice,
+    journal::JOURNAL_BLOCKS,
+    time::now,
+    BLOCK_SZ,
+};
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::fmt::{Debug, Formatter, Result};
+use core::ops::Range;
 
 /// Magic number for sanity check
 const EFS_MAGIC: u32 = 0x3b800001;
+/// Feature of the super block: new inodes use the large file layout, see [`DiskInode::is_large`].
+/// 旧镜像没有这个特性, 新建的inode还是旧格式
+pub const FEATURE_LARGE_FILE: u32 = 1;
+/// Features known by this implementation, 有不认识的特性的镜像不能挂载
+const SUPPORTED_FEATURES: u32 = FEATURE_LARGE_FILE;
 /// The max number of direct inodes
-const INODE_DIRECT_COUNT: usize = 28;
+/// 给时间戳和权限腾出位置, 保证DiskInode还是128字节
+const INODE_DIRECT_COUNT: usize = 21;
+/// 大文件格式把最后两个直接索引让给size的高32位和三级索引块
+const LARGE_DIRECT_COUNT: usize = INODE_DIRECT_COUNT - 2;
+/// Slot in `direct` of the high 32 bits of size of a large inode
+const SIZE_HI_SLOT: usize = INODE_DIRECT_COUNT - 2;
+/// Slot in `direct` of the indirect3 block of a large inode
+const INDIRECT3_SLOT: usize = INODE_DIRECT_COUNT - 1;
+/// Flag of a disk inode using the large file layout
+const INODE_FLAG_LARGE: u8 = 1;
+/// Offsets of `type_` and `flags` in a disk inode, 后面两个字节是填充
+const INODE_TYPE_OFFSET: usize = 124;
+const INODE_FLAGS_OFFSET: usize = 125;
+/// The max levels of indirect blocks
+const MAX_INDIRECT_LEVEL: usize = 3;
 /// The max length of inode name
-const NAME_LENGTH_LIMIT: usize = 27;
+pub const NAME_LENGTH_LIMIT: usize = 255;
 /// The max number of indirect1 inodes
 const INODE_INDIRECT1_COUNT: usize = BLOCK_SZ / 4;
-/// The max number of indirect2 indoes
-const INODE_INDIRECT2_COUNT: usize = INODE_INDIRECT1_COUNT * INODE_INDIRECT1_COUNT;
-/// The upper bound of direct inode index
-const DIRECT_BOUND: usize = INODE_DIRECT_COUNT;
-///The upper bound of indirect1 inode index
-const INDIRECT1_BOUND: usize = DIRECT_BOUND + INODE_INDIRECT1_COUNT;
-///The upper bound of indirect2 inode indexes
-#[allow(unused)]
-const INDIRECT2_BOUND: usize = INDIRECT1_BOUND + INODE_INDIRECT2_COUNT;
 /// Super block of a filesystem
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub struct SuperBlock {
     pub magic: u32,
     pub total_blocks: u32,
@@ -29,6 +48,10 @@ pub struct SuperBlock {
     pub inode_area_blocks: u32,
     pub data_bitmap_blocks: u32,
     pub data_area_blocks: u32,
+    /// 日志区紧跟在超级块后面
+    pub journal_blocks: u32,
+    /// 特性位, 旧镜像的超级块这里是0
+    pub features: u32,
 }
 
 impl Debug for SuperBlock {
@@ -40,30 +63,14 @@ impl Debug for SuperBlock {
             .field("inode_area_blocks", &self.inode_area_blocks)
             .field("data_bitmap_blocks", &self.data_bitmap_blocks)
             .field("data_area_blocks", &self.data_area_blocks)
+            .field("journal_blocks", &self.journal_blocks)
+            .field("features", &self.features)
             .finish()
     }
 }
 
 impl SuperBlock {
-    /// A new SuperBlock
-    pub fn new(
-        magic: u32,
-        total_blocks: u32,
-        inode_bitmap_blocks: u32,
-        inode_area_blocks: u32,
-        data_bitmap_blocks: u32,
-        data_area_blocks: u32,
-    ) -> Self {
-        Self {
-            magic,
-            total_blocks,
-            inode_bitmap_blocks,
-            inode_area_blocks,
-            data_bitmap_blocks,
-            data_area_blocks,
-        }
-    }
-    /// Initialize a super block
+    /// Initialize a super block, 新建的镜像总是带日志区和大文件特性
     pub fn initialize(
         &mut self,
         total_blocks: u32,
@@ -79,51 +86,198 @@ impl SuperBlock {
             inode_area_blocks,
             data_bitmap_blocks,
             data_area_blocks,
+            journal_blocks: JOURNAL_BLOCKS,
+            features: FEATURE_LARGE_FILE,
         }
     }
 
-    /// Check if a super block is valid using efs magic
+    /// Check if a super block is valid using efs magic and the features
     pub fn is_valid(&self) -> bool {
-        return self.magic == EFS_MAGIC;
+        return self.magic == EFS_MAGIC && self.features & !SUPPORTED_FEATURES == 0;
     }
 }
 
 /// Type of a disk inode
+#[repr(u8)]
 #[derive(PartialEq, Copy, Clone, Debug)]
 pub enum DiskInodeType {
     File,
     Directory,
+    /// 内容是链接指向的路径
+    Symlink,
+}
+
+impl DiskInodeType {
+    /// The file type stored in directory entries
+    pub(crate) fn file_type(&self) -> u8 {
+        match self {
+            Self::File => FT_REG_FILE,
+            Self::Directory => FT_DIR,
+            Self::Symlink => FT_SYMLINK,
+        }
+    }
 }
 
 /// A indirect block
-type IndirectBlock = [u32; BLOCK_SZ / 4];
+pub(crate) type IndirectBlock = [u32; BLOCK_SZ / 4];
 /// A data block
-type DataBlock = [u8; BLOCK_SZ];
+pub(crate) type DataBlock = [u8; BLOCK_SZ];
+/// Where the block number of a data block is stored
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum BlockPos {
+    /// `direct[i]`
+    Direct(usize),
+    /// The `index`th data block under the indirect block of `level`
+    Indirect { level: usize, index: usize },
+}
 ///A disk inode
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct DiskInode {
-    pub size: u32,
+    /// 大小的低32位, 用 [`DiskInode::size`] 读
+    size: u32,
+    /// 大文件格式只有前19个是直接索引, 最后两个是size的高32位和三级索引块
     pub direct: [u32; INODE_DIRECT_COUNT],
     pub indirect1: u32,
     pub indirect2: u32,
+    /// 最后访问时间
+    pub atime: u64,
+    /// 最后修改内容的时间
+    pub mtime: u64,
+    /// 最后修改inode(链接数, 权限...)的时间
+    pub ctime: u64,
+    /// 权限位, 例如 0o644
+    pub mode: u16,
+    /// 硬链接数, 指向这个inode的目录项个数
+    pub nlink: u16,
     type_: DiskInodeType,
+    /// 旧镜像里这个字节是结构体的填充, 总是0
+    flags: u8,
 }
 
+/// Size of a disk inode, 每个块正好放4个DiskInode
+pub(crate) const DISK_INODE_SZ: usize = 128;
+const _: () = assert!(core::mem::size_of::<DiskInode>() == DISK_INODE_SZ);
+
 impl DiskInode {
     /// Initialize a disk inode, as well as all direct inodes under it
-    /// indirect1 and indirect2 block are allocated only when they are needed
-    pub fn initialize(&mut self, type_: DiskInodeType) {
+    /// indirect1 and indirect2 block are allocated only when they are needed.
+    /// `large` 表示使用大文件格式, 见 [`DiskInode::is_large`]
+    pub fn initialize(&mut self, type_: DiskInodeType, large: bool) {
         self.size = 0;
         self.direct.iter_mut().for_each(|v| *v = 0);
         self.indirect1 = 0;
         self.indirect2 = 0;
+        let time = now();
+        self.atime = time;
+        self.mtime = time;
+        self.ctime = time;
+        self.mode = match type_ {
+            DiskInodeType::File => 0o644,
+            DiskInodeType::Directory => 0o755,
+            DiskInodeType::Symlink => 0o777,
+        };
+        self.nlink = 1;
         self.type_ = type_;
+        self.flags = if large { INODE_FLAG_LARGE } else { 0 };
+    }
+    /// Whether this inode uses the large file layout: 19个直接索引, 一二三级索引块, 64位的size.
+    /// 旧格式是21个直接索引, 一二级索引块, 32位的size
+    pub fn is_large(&self) -> bool {
+        self.flags & INODE_FLAG_LARGE != 0
+    }
+    /// Read a disk inode from raw bytes, return `None` if the type or flags are invalid.
+    /// 磁盘上的类型字节可能是坏的, 不检查就当作`DiskInodeType`读是未定义行为
+    pub(crate) fn from_bytes(bytes: &[u8; DISK_INODE_SZ]) -> Option<Self> {
+        let type_ = bytes[INODE_TYPE_OFFSET];
+        let flags = bytes[INODE_FLAGS_OFFSET];
+        if type_ > DiskInodeType::Symlink as u8 || flags & !INODE_FLAG_LARGE != 0 {
+            return None;
+        }
+        return Some(unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Self) });
+    }
+    /// Size of the content in bytes
+    pub fn size(&self) -> u64 {
+        if self.is_large() {
+            return (self.direct[SIZE_HI_SLOT] as u64) << 32 | self.size as u64;
+        }
+        return self.size as u64;
+    }
+    /// Set the size without touching the blocks, 调用者负责分配和回收块
+    pub(crate) fn set_size(&mut self, size: u64) {
+        assert!(size <= self.max_size(), "File too large!");
+        self.size = size as u32;
+        if self.is_large() {
+            self.direct[SIZE_HI_SLOT] = (size >> 32) as u32;
+        }
+    }
+    /// The max size of the content
+    pub fn max_size(&self) -> u64 {
+        return (self.max_blocks() * BLOCK_SZ) as u64;
+    }
+    /// The max number of data blocks
+    pub(crate) fn max_blocks(&self) -> usize {
+        let mut blocks = self.direct_count();
+        let mut count = INODE_INDIRECT1_COUNT;
+        for _ in 0..self.indirect_levels() {
+            blocks += count;
+            count *= INODE_INDIRECT1_COUNT;
+        }
+        return blocks;
+    }
+    fn direct_count(&self) -> usize {
+        if self.is_large() {
+            return LARGE_DIRECT_COUNT;
+        }
+        return INODE_DIRECT_COUNT;
+    }
+    fn indirect_levels(&self) -> usize {
+        if self.is_large() {
+            return 3;
+        }
+        return 2;
+    }
+    /// The top indirect block of `level`, 0 if not allocated
+    pub(crate) fn indirect(&self, level: usize) -> u32 {
+        match level {
+            1 => self.indirect1,
+            2 => self.indirect2,
+            _ => self.direct[INDIRECT3_SLOT],
+        }
+    }
+    fn set_indirect(&mut self, level: usize, block_id: u32) {
+        match level {
+            1 => self.indirect1 = block_id,
+            2 => self.indirect2 = block_id,
+            _ => self.direct[INDIRECT3_SLOT] = block_id,
+        }
+    }
+    /// Update mtime and ctime after the content is modified
+    pub fn touch_modified(&mut self) {
+        let time = now();
+        self.mtime = time;
+        self.ctime = time;
+    }
+    /// Update ctime after the inode itself is modified
+    pub fn touch_changed(&mut self) {
+        self.ctime = now();
+    }
+    /// Update atime after the content is read
+    pub fn touch_accessed(&mut self) {
+        self.atime = now();
     }
     /// Whether this inode is a directory
     pub fn is_dir(&self) -> bool {
         self.type_ == DiskInodeType::Directory
     }
+    /// The file type stored in directory entries
+    pub(crate) fn file_type(&self) -> u8 {
+        return self.type_.file_type();
+    }
+    /// Whether this inode is a symlink
+    pub fn is_symlink(&self) -> bool {
+        self.type_ == DiskInodeType::Symlink
+    }
     /// Whether this inode is a file
     #[allow(unused)]
     pub fn is_file(&self) -> bool {
@@ -131,219 +285,199 @@ impl DiskInode {
     }
     /// Return block number correspond to size
     pub fn data_blocks(&self) -> u32 {
-        Self::_data_blocks(self.size)
+        Self::_data_blocks(self.size())
     }
-    fn _data_blocks(size: u32) -> u32 {
-        (size + BLOCK_SZ as u32 - 1) / BLOCK_SZ as u32
+    fn _data_blocks(size: u64) -> u32 {
+        size.div_ceil(BLOCK_SZ as u64) as u32
     }
-    /// Return number of blocks needed include indirect1/2
-    pub fn total_blocks(size: u32) -> u32 {
+    /// Return number of blocks needed include indirect blocks, if the size of this inode is `size`
+    pub fn total_blocks(&self, size: u64) -> u32 {
         let data_blocks = Self::_data_blocks(size) as usize;
-        let mut total = data_blocks as usize;
-        // indirect1
-        if data_blocks > INODE_DIRECT_COUNT {
-            total += 1;
-        }
-        if data_blocks > INDIRECT1_BOUND {
-            total += 1;
-            //sub indirect1
-            total +=
-                (data_blocks - INDIRECT1_BOUND + INODE_INDIRECT1_COUNT - 1) / INODE_INDIRECT1_COUNT;
+        let mut total = data_blocks;
+        let mut rest = data_blocks.saturating_sub(self.direct_count());
+        // 每一级能放的数据块数
+        let mut count = INODE_INDIRECT1_COUNT;
+        for _ in 0..self.indirect_levels() {
+            if rest == 0 {
+                break;
+            }
+            let blocks = rest.min(count);
+            // 从这一级的顶层索引块往下, 每层一个索引块管span个数据块
+            let mut span = count;
+            while span >= INODE_INDIRECT1_COUNT {
+                total += blocks.div_ceil(span);
+                span /= INODE_INDIRECT1_COUNT;
+            }
+            rest -= blocks;
+            count *= INODE_INDIRECT1_COUNT;
         }
         return total as u32;
     }
     /// Get the number of data blocks that have to be allocated given the new size of data
-    pub fn blocks_num_needed(&self, new_size: u32) -> u32 {
-        assert!(new_size >= self.size);
-        return Self::total_blocks(new_size) - Self::total_blocks(self.size);
+    pub fn blocks_num_needed(&self, new_size: u64) -> u32 {
+        assert!(new_size >= self.size());
+        return self.total_blocks(new_size) - self.total_blocks(self.size());
+    }
+    /// Find where the block number of the `inner_id`th data block is stored
+    pub(crate) fn locate(&self, inner_id: usize) -> BlockPos {
+        let direct_count = self.direct_count();
+        if inner_id < direct_count {
+            return BlockPos::Direct(inner_id);
+        }
+        let mut index = inner_id - direct_count;
+        let mut count = INODE_INDIRECT1_COUNT;
+        for level in 1..=self.indirect_levels() {
+            if index < count {
+                return BlockPos::Indirect { level, index };
+            }
+            index -= count;
+            count *= INODE_INDIRECT1_COUNT;
+        }
+        panic!("File too large!");
+    }
+    /// The slots to follow in the index blocks from the indirect block of `level` down to its
+    /// `index`th data block, 只有前`level`个有用
+    pub(crate) fn index_path(level: usize, index: usize) -> [usize; MAX_INDIRECT_LEVEL] {
+        let mut path = [0; MAX_INDIRECT_LEVEL];
+        let mut index = index;
+        for slot in path[..level].iter_mut().rev() {
+            *slot = index % INODE_INDIRECT1_COUNT;
+            index /= INODE_INDIRECT1_COUNT;
+        }
+        return path;
     }
 
     /// Get id of block given inner id
     pub fn get_block_id(&self, inner_id: u32, block_device: &Arc<dyn BlockDevice>) -> u32 {
-        let inner_id = inner_id as usize;
-        if inner_id < INODE_DIRECT_COUNT {
-            self.direct[inner_id]
-        } else if inner_id < INDIRECT1_BOUND {
-            get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect_block: &IndirectBlock| {
-                    indirect_block[inner_id - INODE_DIRECT_COUNT]
-                })
-        } else {
-            let last = inner_id - INDIRECT1_BOUND;
-            let indirect1 = get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect2: &IndirectBlock| {
-                    indirect2[last / INODE_INDIRECT1_COUNT]
-                });
-            get_block_cache(indirect1 as usize, Arc::clone(block_device))
-                .lock()
-                .read(0, |indirect1: &IndirectBlock| {
-                    indirect1[last % INODE_INDIRECT1_COUNT]
-                })
+        match self.locate(inner_id as usize) {
+            BlockPos::Direct(i) => self.direct[i],
+            BlockPos::Indirect { level, index } => {
+                let mut block_id = self.indirect(level);
+                for slot in Self::index_path(level, index)[..level].iter() {
+                    block_id = get_block_cache(block_id as usize, Arc::clone(block_device))
+                        .lock()
+                        .read(0, |indirect_block: &IndirectBlock| indirect_block[*slot]);
+                }
+                block_id
+            }
+        }
+    }
+    /// The index blocks whose first data block is in `range`, 数据块从前往后用, 这些索引块只被`range`里的块用到
+    pub(crate) fn index_blocks(
+        &self,
+        range: Range<usize>,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        let mut v = Vec::new();
+        for inner_id in range {
+            let (level, index) = match self.locate(inner_id) {
+                BlockPos::Indirect { level, index } if index % INODE_INDIRECT1_COUNT == 0 => {
+                    (level, index)
+                }
+                _ => continue,
+            };
+            let path = Self::index_path(level, index);
+            let mut block_id = self.indirect(level);
+            for depth in 0..level {
+                // 这个索引块下面的第一个数据块就是 inner_id
+                if path[depth..level].iter().all(|slot| *slot == 0) {
+                    v.push(block_id);
+                }
+                if depth + 1 < level {
+                    block_id = get_block_cache(block_id as usize, Arc::clone(block_device))
+                        .lock()
+                        .read(0, |indirect_block: &IndirectBlock| {
+                            indirect_block[path[depth]]
+                        });
+                }
+            }
         }
+        return v;
     }
     /// Increase the size of current disk inode
     pub fn increase_size(
         &mut self,
-        new_size: u32,        //扩容后文件大小
+        new_size: u64,        //扩容后文件大小
         new_blocks: Vec<u32>, //本次扩容所增加的块编号的数组(向量)
         block_device: &Arc<dyn BlockDevice>,
     ) {
-        let mut current_blocks = self.data_blocks();
-        self.size = new_size;
-        let mut total_blocks = self.data_blocks();
+        let current_blocks = self.data_blocks() as usize;
+        self.set_size(new_size);
+        let total_blocks = self.data_blocks() as usize;
         let mut new_blocks = new_blocks.into_iter();
-        //fill direct
-        while current_blocks < total_blocks.min(INODE_DIRECT_COUNT as u32) {
-            self.direct[current_blocks as usize] = new_blocks.next().unwrap();
-            current_blocks += 1;
-        }
-        //alloc indirect1 如果28个direct已经慢了
-        if total_blocks > INODE_DIRECT_COUNT as u32 {
-            if current_blocks == INODE_DIRECT_COUNT as u32 {
-                //如果是全新文件, 而且现在已经大于28个直接索引, 需要加入一级索引块
-                self.indirect1 = new_blocks.next().unwrap();
-            } //如果没走上面的if, 说明是老文件, 早有一级索引块了 无需新增了
-            current_blocks -= INODE_DIRECT_COUNT as u32;
-            total_blocks -= INODE_DIRECT_COUNT as u32;
-        } else {
-            return; //此处说明文件小于28个数据块, 无需一级索引 直接退出.
-        }
-        // fill indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                while current_blocks < total_blocks.min(INODE_INDIRECT1_COUNT as u32) {
-                    //blocks from 29 to 128
-                    indirect1[current_blocks as usize] = new_blocks.next().unwrap(); //把扩容的块编号, 写入一级索引块中
-                    current_blocks += 1;
+        for inner_id in current_blocks..total_blocks {
+            let (level, index) = match self.locate(inner_id) {
+                BlockPos::Direct(i) => {
+                    self.direct[i] = new_blocks.next().unwrap();
+                    continue;
                 }
-            });
-        //alloc indirect2
-        if total_blocks > INODE_INDIRECT1_COUNT as u32 {
-            if current_blocks == INODE_INDIRECT1_COUNT as u32 {
-                //如果是全新文件, 而且现在已经大于128个直接索引, 需要加入二级索引块. 从数据块数组里取一块,作为二级索引块
-                self.indirect2 = new_blocks.next().unwrap();
+                BlockPos::Indirect { level, index } => (level, index),
+            };
+            // 这一级的第一个数据块, 顶层的索引块也是新的
+            if index == 0 {
+                self.set_indirect(level, new_blocks.next().unwrap());
             }
-            current_blocks -= INODE_INDIRECT1_COUNT as u32; // from 128
-            total_blocks -= INODE_INDIRECT1_COUNT as u32;
-        } else {
-            return; //走到此处 说明无需二级索引
-        }
-        // fill indirect2 from (a0,b0)->(a1,b1) 目的是 需要把a0,b0 一步步走到(索引块内容填上) a1,b1
-        // 可以理解为坐标, (a0,b0)就是字符Y所在位置, 需要走到字符Z所在位置 (a1,b1)
-        // ###############    每行128个格子, 填上一个索引值就从.-->#
-        // ######Y########
-        // ###############
-        // ##########Z....
-        let mut a0 = current_blocks as usize / INODE_INDIRECT1_COUNT; //a0 表示第几个 一级缓存块
-        let mut b0 = current_blocks as usize % INODE_INDIRECT1_COUNT; //b0 表示这个一级缓存块 存的数组里具体第几个
-        let a1 = total_blocks as usize / INODE_INDIRECT1_COUNT; //a1 同上
-        let b1 = total_blocks as usize % INODE_INDIRECT1_COUNT; //b1同上
-                                                                //alloc low-level indirect1
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                while (a0 < a1) || (a0 == a1 && b0 < b1) {
-                    //坐标(a0,b0) 当还没走到 (a1,b1)时
-                    if b0 == 0 {
-                        //全新的一行
-                        indirect2[a0] = new_blocks.next().unwrap(); //增加一个一级索引块, 并把值写入二级索引块
-                    }
-                    get_block_cache(indirect2[a0] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            indirect1[b0] = new_blocks.next().unwrap(); //扩容, 然后把扩容的块编号, 写入一级索引块中
-                        });
-                    //move to next
-                    b0 += 1; //格子走一步
-                    if b0 == INODE_INDIRECT1_COUNT {
-                        //格子走到128格后 换一行,继续走
-                        b0 = 0;
-                        a0 += 1;
-                    }
+            // 从顶层索引块往下走. 下一层的块第一次用到时(它下面只有这一个数据块)分配新块,
+            // 最后一层分配的就是数据块
+            let path = Self::index_path(level, index);
+            let mut block_id = self.indirect(level);
+            for depth in 0..level {
+                let slot = path[depth];
+                let block_cache = get_block_cache(block_id as usize, Arc::clone(block_device));
+                let mut block_cache = block_cache.lock();
+                if path[depth + 1..level].iter().all(|slot| *slot == 0) {
+                    let new_block = new_blocks.next().unwrap();
+                    block_cache.modify(0, |indirect_block: &mut IndirectBlock| {
+                        indirect_block[slot] = new_block;
+                    });
                 }
-            });
+                block_id =
+                    block_cache.read(0, |indirect_block: &IndirectBlock| indirect_block[slot]);
+            }
+        }
     }
 
     /// Clear size to zero and return blocks that should be deallocated.
-    /// We will clear the block contents to zero later. 回收的所有块编号 作为Vec<u32>类型返回
+    /// The blocks are cleared to zero when they are allocated again. 回收的所有块编号 作为Vec<u32>类型返回
     pub fn clear_size(&mut self, block_device: &Arc<dyn BlockDevice>) -> Vec<u32> {
-        let mut v: Vec<u32> = Vec::new();
-        let mut data_blocks = self.data_blocks() as usize;
-        self.size = 0; //清空后文件大小自然是0
-        let mut current_blocks = 0usize;
-        // direct, 清空直接索引数组, 里面全部置零
-        while current_blocks < data_blocks.min(INODE_DIRECT_COUNT) {
-            v.push(self.direct[current_blocks]); //直接索引的block_id放入已回收数组
-            self.direct[current_blocks] = 0;
-            current_blocks += 1;
-        }
-        // indirect1 block , 如果大于28, 继续清空一级索引块
-        if data_blocks > INODE_DIRECT_COUNT {
-            v.push(self.indirect1); //数据块放入回收数组
-            data_blocks -= INODE_DIRECT_COUNT; //从第29块开始
-            current_blocks = 0;
-        } else {
-            return v;
+        return self.decrease_size(0, block_device);
+    }
+    /// Decrease size to `new_size` and return blocks that should be deallocated,
+    /// including indirect blocks that are no longer needed.
+    pub fn decrease_size(
+        &mut self,
+        new_size: u64,
+        block_device: &Arc<dyn BlockDevice>,
+    ) -> Vec<u32> {
+        assert!(new_size <= self.size());
+        let old_blocks = self.data_blocks() as usize;
+        let new_blocks = Self::_data_blocks(new_size) as usize;
+        // 先找出要回收的索引块和数据块, 这时索引块都还在
+        let mut v = self.index_blocks(new_blocks..old_blocks, block_device);
+        for inner_id in new_blocks..old_blocks {
+            v.push(self.get_block_id(inner_id as u32, block_device));
         }
-        // indirect1
-        get_block_cache(self.indirect1 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect1: &mut IndirectBlock| {
-                //一级索引块保存的u32数组中数值就是数据块编号,遍历并放入回收数组
-                while current_blocks < data_blocks.min(INODE_INDIRECT1_COUNT) {
-                    v.push(indirect1[current_blocks]); //数据块放入回收数组
-                    current_blocks += 1;
-                }
-            });
-        self.indirect1 = 0; //一级索引块的编号 置零
-                            // indirect 2 block
-        if data_blocks > INODE_INDIRECT1_COUNT {
-            v.push(self.indirect2); //回收二级索引块的块编号
-            data_blocks -= INODE_INDIRECT1_COUNT;
-        } else {
-            return v; // 如果不大于128块, 说明无需二级索引块 直接退出
+        for inner_id in new_blocks..old_blocks {
+            if let BlockPos::Direct(i) = self.locate(inner_id) {
+                self.direct[i] = 0;
+            }
         }
-        // indirect2
-        assert!(data_blocks <= INODE_INDIRECT2_COUNT);
-        // 可以理解为坐标, 从左上角的原点一路清空到(a1,b1)的位置Z
-        // ###############    每行128个格子, 清空一个索引值就从 #-->.
-        // ###############
-        // ###############
-        // ##########Z....
-        let a1 = data_blocks / INODE_INDIRECT1_COUNT;
-        let b1 = data_blocks % INODE_INDIRECT1_COUNT;
-        get_block_cache(self.indirect2 as usize, Arc::clone(block_device))
-            .lock()
-            .modify(0, |indirect2: &mut IndirectBlock| {
-                // full indirect1 blocks
-                for entry in indirect2.iter_mut().take(a1) {
-                    v.push(*entry);
-                    get_block_cache(*entry as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter() {
-                                v.push(*entry); //循环 清空整行 回收所有数据块编号
-                            }
-                        })
-                }
-                // last indirect1 block
-                if b1 > 0 {
-                    v.push(indirect2[a1]);
-                    get_block_cache(indirect2[a1] as usize, Arc::clone(block_device))
-                        .lock()
-                        .modify(0, |indirect1: &mut IndirectBlock| {
-                            for entry in indirect1.iter().take(b1) {
-                                v.push(*entry); //清空最后一行, 清到b1的位置. 回收数据块编号
-                            }
-                        })
-                }
-            });
-        self.indirect2 = 0; //二级索引块的编号 置零
+        self.clear_indirect_from(new_blocks);
+        self.set_size(new_size);
         return v;
     }
+    /// Forget the indirect blocks of the levels starting at or after the `blocks`th data block
+    pub(crate) fn clear_indirect_from(&mut self, blocks: usize) {
+        let mut start = self.direct_count();
+        let mut count = INODE_INDIRECT1_COUNT;
+        for level in 1..=self.indirect_levels() {
+            if start >= blocks {
+                self.set_indirect(level, 0);
+            }
+            start += count;
+            count *= INODE_INDIRECT1_COUNT;
+        }
+    }
     /// Read data from current disk inode, 读数据块的内容 读入到buf中
     pub fn read_at(
         &self,
@@ -352,12 +486,20 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize); //终止位置要么是buf全满的位置, 要么是文件终点.
+        let end = (offset + buf.len()).min(self.size() as usize); //终止位置要么是buf全满的位置, 要么是文件终点.
         if start >= end {
             return 0;
         }
         let mut start_block = start / BLOCK_SZ; //起始数据块index
         let mut read_size = 0usize;
+        // 一次读多个块时, 先把没有缓存的块合并成大请求读进来
+        let end_block = end.div_ceil(BLOCK_SZ);
+        if end_block - start_block > 1 {
+            let block_ids: Vec<usize> = (start_block..end_block)
+                .map(|inner_id| self.get_block_id(inner_id as u32, block_device) as usize)
+                .collect();
+            block_cache_prefetch(&block_ids, block_device);
+        }
         loop {
             //每轮循环读一个数据块
             // calculate end of current block 终止数据块index
@@ -394,7 +536,7 @@ impl DiskInode {
         block_device: &Arc<dyn BlockDevice>,
     ) -> usize {
         let mut start = offset;
-        let end = (offset + buf.len()).min(self.size as usize);
+        let end = (offset + buf.len()).min(self.size() as usize);
         assert!(start <= end);
         let mut start_block = start / BLOCK_SZ;
         let mut write_size = 0usize;
@@ -404,16 +546,21 @@ impl DiskInode {
             end_current_block = end_current_block.min(end);
             // write and update write size
             let block_write_size = end_current_block - start;
-            get_block_cache(
-                self.get_block_id(start_block as u32, block_device) as usize,
-                Arc::clone(block_device),
-            )
-            .lock()
-            .modify(0, |data_block: &mut DataBlock| {
+            let copy = |data_block: &mut DataBlock| {
                 let src = &buf[write_size..write_size + block_write_size];
                 let dst = &mut data_block[start % BLOCK_SZ..start % BLOCK_SZ + block_write_size];
                 dst.copy_from_slice(src);
-            });
+            };
+            let block_cache = get_block_cache(
+                self.get_block_id(start_block as u32, block_device) as usize,
+                Arc::clone(block_device),
+            );
+            // 目录和符号链接的内容是元数据, 要记日志; 普通文件的内容不记
+            if !self.is_file() {
+                block_cache.lock().modify(0, copy);
+            } else {
+                block_cache.lock().modify_data(0, copy);
+            }
             write_size += block_write_size;
             //move to next block
             if end_current_block == end {
@@ -426,47 +573,163 @@ impl DiskInode {
     }
 }
 
-/// A directory entry
-#[repr(C)]
-#[derive(Debug)]
-pub struct DirEntry {
-    name: [u8; NAME_LENGTH_LIMIT + 1],
-    inode_number: u32,
+/// File type stored in a directory entry, 列目录的时候不用读inode就知道是不是目录
+pub const FT_UNKNOWN: u8 = 0;
+/// Regular file
+pub const FT_REG_FILE: u8 = 1;
+/// Directory
+pub const FT_DIR: u8 = 2;
+/// Symbolic link, 和ext2一样是7
+pub const FT_SYMLINK: u8 = 7;
+
+/// Header of a directory entry on disk, the name follows it.
+/// 目录项长度可变, 4字节对齐, 不跨块. 块里最后一个目录项的`rec_len`一直延伸到块尾, 多出来的地方留给以后插入的目录项.
+/// `name_len`为0的目录项是空闲的 (inode 0 是根目录, 不能用来表示空闲)
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DirEntryHeader {
+    pub inode: u32,
+    /// 整个目录项的长度, 到下一个目录项为止
+    pub rec_len: u16,
+    pub name_len: u8,
+    pub file_type: u8,
 }
-/// Size of a directory entry
-pub const DIRENT_SZ: usize = 32;
+/// Size of the header of a directory entry
+pub(crate) const DIRENT_HEADER_SZ: usize = 8;
 
-impl DirEntry {
-    pub fn empty() -> Self {
+impl DirEntryHeader {
+    /// A free entry of `rec_len` bytes
+    pub fn free(rec_len: usize) -> Self {
         Self {
-            name: [0u8; NAME_LENGTH_LIMIT + 1],
-            inode_number: 0,
+            inode: 0,
+            rec_len: rec_len as u16,
+            name_len: 0,
+            file_type: FT_UNKNOWN,
         }
     }
-
-    pub fn new(name: &str, inode_number: u32) -> Self {
-        let mut bytes = [0u8; NAME_LENGTH_LIMIT + 1];
-        bytes[..name.len()].copy_from_slice(name.as_bytes()); //把&str copy到[u8]数组去
+    /// Deserialize from the first bytes of `bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Self {
         Self {
-            name: bytes,
-            inode_number,
+            inode: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            rec_len: u16::from_le_bytes([bytes[4], bytes[5]]),
+            name_len: bytes[6],
+            file_type: bytes[7],
         }
     }
     /// Serialize into bytes
-    pub fn as_bytes(&self) -> &[u8] {
-        unsafe { core::slice::from_raw_parts(self as *const _ as usize as *const u8, DIRENT_SZ) }
+    pub fn to_bytes(self) -> [u8; DIRENT_HEADER_SZ] {
+        let mut bytes = [0u8; DIRENT_HEADER_SZ];
+        bytes[0..4].copy_from_slice(&self.inode.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.rec_len.to_le_bytes());
+        bytes[6] = self.name_len;
+        bytes[7] = self.file_type;
+        return bytes;
     }
-    /// Serialize into mutable bytes
-    pub fn as_bytes_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self as *mut _ as usize as *mut u8, DIRENT_SZ) }
+}
+
+/// A directory entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    name: String,
+    inode_number: u32,
+    file_type: u8,
+}
+
+impl DirEntry {
+    /// Create an entry of `name` referring to `inode_number` of `file_type`
+    pub fn new(name: &str, inode_number: u32, file_type: u8) -> Self {
+        Self {
+            name: String::from(name),
+            inode_number,
+            file_type,
+        }
     }
     /// Get name of the entry
     pub fn name(&self) -> &str {
-        let len = (0usize..).find(|i| self.name[*i] == 0).unwrap(); //找到name这个数组里面元素0的位置. 此位置即是字符串结束位
-        return core::str::from_utf8(&self.name[..len]).unwrap(); //byte[] ==> &str
+        return &self.name;
     }
     /// Get inode number of the entry
     pub fn inode_number(&self) -> u32 {
         return self.inode_number;
     }
+    /// Get file type of the entry
+    pub fn file_type(&self) -> u8 {
+        return self.file_type;
+    }
+    /// Whether the entry refers to a directory
+    pub fn is_dir(&self) -> bool {
+        return self.file_type == FT_DIR;
+    }
+    /// Size on disk of an entry whose name is `name_len` bytes long
+    pub(crate) fn rec_len_of(name_len: usize) -> usize {
+        return (DIRENT_HEADER_SZ + name_len + 3) & !3;
+    }
+    /// Size on disk of this entry without spare space
+    pub(crate) fn rec_len(&self) -> usize {
+        return Self::rec_len_of(self.name.len());
+    }
+    /// Serialize the header and the name into bytes, 目录项占`rec_len`字节
+    pub(crate) fn to_bytes(&self, rec_len: usize) -> Vec<u8> {
+        let header = DirEntryHeader {
+            inode: self.inode_number,
+            rec_len: rec_len as u16,
+            name_len: self.name.len() as u8,
+            file_type: self.file_type,
+        };
+        let mut bytes = header.to_bytes().to_vec();
+        bytes.extend_from_slice(self.name.as_bytes());
+        return bytes;
+    }
+}
+
+/// A directory entry and where it is
+#[derive(Debug, Clone)]
+pub(crate) struct DirRecord {
+    /// Offset of the entry in the directory
+    pub offset: usize,
+    pub rec_len: usize,
+    /// Offset of the previous entry in the same block, None if it is the first one
+    pub prev: Option<usize>,
+    /// None if the entry is free
+    pub entry: Option<DirEntry>,
+}
+
+/// Parse the entries of a directory block which starts at `base` in the directory.
+/// 同时返回损坏的位置(长度不对, 名字不是UTF-8), 从那里到块尾的内容都不能用
+pub(crate) fn parse_dir_block(block: &DataBlock, base: usize) -> (Vec<DirRecord>, Option<usize>) {
+    let mut records = Vec::new();
+    let mut start = 0;
+    let mut prev = None;
+    while start < BLOCK_SZ {
+        if start + DIRENT_HEADER_SZ > BLOCK_SZ {
+            return (records, Some(base + start));
+        }
+        let header = DirEntryHeader::from_bytes(&block[start..]);
+        let rec_len = header.rec_len as usize;
+        let name_len = header.name_len as usize;
+        if !rec_len.is_multiple_of(4)
+            || rec_len < DirEntry::rec_len_of(name_len)
+            || start + rec_len > BLOCK_SZ
+        {
+            return (records, Some(base + start));
+        }
+        let entry = if name_len == 0 {
+            None
+        } else {
+            let name = &block[start + DIRENT_HEADER_SZ..start + DIRENT_HEADER_SZ + name_len];
+            let name = match core::str::from_utf8(name) {
+                Ok(name) => name,
+                Err(_) => return (records, Some(base + start)),
+            };
+            Some(DirEntry::new(name, header.inode, header.file_type))
+        };
+        records.push(DirRecord {
+            offset: base + start,
+            rec_len,
+            prev: prev.map(|prev| base + prev),
+            entry,
+        });
+        prev = Some(start);
+        start += rec_len;
+    }
+    return (records, None);
 }
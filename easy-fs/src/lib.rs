@@ -7,14 +7,25 @@ mod bitmap;
 mod block_dev;
 mod block_cache;
 mod efs;
+mod fat;
+mod fsck;
+mod journal;
 mod layout;
+mod time;
 mod vfs;
 /// Use a block size of 512 bytes
 pub const BLOCK_SZ: usize = 512;
 use bitmap::Bitmap;
-use block_cache::{block_cache_sync_all, get_block_cache};
+use block_cache::get_block_cache;
+pub use block_cache::{
+    block_cache_stats, block_cache_sync_all, set_block_cache_size, BlockCacheStats,
+};
 pub use block_dev::BlockDevice;
 pub use efs::EasyFileSystem;
+pub use fat::{FatDirEntry, FatFileSystem, FatInode};
+pub use fsck::FsckProblem;
+pub use time::set_clock;
 
 use layout::*;
-pub use vfs::Inode;
+pub use layout::{DirEntry, FT_DIR, FT_REG_FILE, FT_SYMLINK, FT_UNKNOWN};
+pub use vfs::{Inode, InodeStat};
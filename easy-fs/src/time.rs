@@ -0,0 +1,21 @@
+//! Clock used for the timestamps of inodes
+
+use spin::Mutex;
+
+fn zero_clock() -> u64 {
+    0
+}
+
+/// 文件系统本身不知道时间, 由使用者(内核)提供一个时钟, 没有设置时所有时间戳都是0
+static CLOCK: Mutex<fn() -> u64> = Mutex::new(zero_clock);
+
+/// Set the clock of timestamps, e.g. milliseconds since boot
+pub fn set_clock(clock: fn() -> u64) {
+    *CLOCK.lock() = clock;
+}
+
+/// Current time of the clock
+pub(crate) fn now() -> u64 {
+    let clock = *CLOCK.lock();
+    clock()
+}
@@ -1,12 +1,65 @@
 use crate::{
-    block_cache::{block_cache_sync_all, get_block_cache},
-    layout::{DirEntry, DiskInode, DiskInodeType, DIRENT_SZ},
+    block_cache::{device_id, get_block_cache},
+    layout::{
+        parse_dir_block, DirEntry, DirEntryHeader, DirRecord, DiskInode, DiskInodeType,
+        DIRENT_HEADER_SZ, FT_DIR, NAME_LENGTH_LIMIT,
+    },
+    BLOCK_SZ,
 };
 
 use super::{BlockDevice, EasyFileSystem};
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec, vec::Vec};
+use core::fmt::{Debug, Formatter, Result};
+use lazy_static::*;
 use spin::{Mutex, MutexGuard};
 
+/// 一次写文件的事务最多写这么多字节
+const WRITE_CHUNK_SIZE: usize = 32 * BLOCK_SZ;
+/// 解析一个路径时最多跟随这么多次符号链接, 和Linux一样
+const MAX_SYMLINK_DEPTH: usize = 40;
+/// The max length of a symlink target
+const SYMLINK_MAX_LEN: usize = 4096;
+
+/// A disk inode: (device, block id, offset in the block)
+type InodeKey = (usize, usize, usize);
+
+/// How many [`Inode`]s refer to a disk inode
+struct InodeRefs {
+    count: usize,
+    /// 链接数已经是0, 最后一个 [`Inode`] 释放时回收
+    orphan: bool,
+}
+
+lazy_static! {
+    /// 打开的文件, 映射和当前目录都拿着 [`Inode`], 有 [`Inode`] 指向的inode链接数变成0时先不回收
+    static ref INODE_REFS: Mutex<BTreeMap<InodeKey, InodeRefs>> = Mutex::new(BTreeMap::new());
+}
+
+/// Metadata of an inode
+#[derive(Debug, Clone, Copy)]
+pub struct InodeStat {
+    /// Inode number
+    pub ino: u32,
+    /// Whether it is a directory
+    pub is_dir: bool,
+    /// Whether it is a symlink
+    pub is_symlink: bool,
+    /// Permission bits
+    pub mode: u16,
+    /// Number of hard links
+    pub nlink: u16,
+    /// Size in bytes
+    pub size: u64,
+    /// Number of blocks used, including index blocks
+    pub blocks: u32,
+    /// Last access time
+    pub atime: u64,
+    /// Last modification time
+    pub mtime: u64,
+    /// Last status change time
+    pub ctime: u64,
+}
+
 /// Virtual filesystem layer over easy-fs
 pub struct Inode {
     block_id: usize,
@@ -15,6 +68,15 @@ pub struct Inode {
     block_device: Arc<dyn BlockDevice>,
 }
 
+impl Debug for Inode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.debug_struct("Inode")
+            .field("block_id", &self.block_id)
+            .field("block_offset", &self.block_offset)
+            .finish()
+    }
+}
+
 impl Inode {
     /// Create a vfs inode
     pub fn new(
@@ -23,12 +85,29 @@ impl Inode {
         fs: Arc<Mutex<EasyFileSystem>>,
         block_device: Arc<dyn BlockDevice>,
     ) -> Self {
-        Self {
+        let inode = Self {
             block_id: block_id as usize,
             block_offset,
             fs,
             block_device,
-        }
+        };
+        INODE_REFS
+            .lock()
+            .entry(inode.key())
+            .or_insert(InodeRefs {
+                count: 0,
+                orphan: false,
+            })
+            .count += 1;
+        return inode;
+    }
+
+    fn key(&self) -> InodeKey {
+        return (
+            device_id(&self.block_device),
+            self.block_id,
+            self.block_offset,
+        );
     }
 
     /// Call a function over a disk inode to read it
@@ -46,44 +125,247 @@ impl Inode {
     /// Find inode under a disk inode by name
     /// 现在只是在根目录 (唯一的文件夹) 根据文件名寻找
     fn find_inode_id(&self, name: &str, disk_inode: &DiskInode) -> Option<u32> {
+        return self
+            .find_dirent(name, disk_inode)
+            .map(|record| record.entry.unwrap().inode_number());
+    }
+    /// Find a dirent under a disk inode by name
+    fn find_dirent(&self, name: &str, disk_inode: &DiskInode) -> Option<DirRecord> {
         // assert it is a directory
         assert!(disk_inode.is_dir());
-        let file_count = (disk_inode.size as usize) / DIRENT_SZ;
-        let mut dirent = DirEntry::empty();
-        for i in 0..file_count {
-            assert_eq!(
-                //读一个32位的磁盘块内容(是文件夹格式内容) 放到32位的buffer里去
-                disk_inode.read_at(DIRENT_SZ * i, dirent.as_bytes_mut(), &self.block_device),
-                DIRENT_SZ //确定长度就是32位
+        return self.dir_records(disk_inode).into_iter().find(|record| {
+            record
+                .entry
+                .as_ref()
+                .is_some_and(|dirent| dirent.name() == name)
+        });
+    }
+    /// All dirents of a directory, including the free ones.
+    /// 目录的大小总是块的整数倍, 一个块一个块地解析, 块里损坏的部分跳过
+    fn dir_records(&self, disk_inode: &DiskInode) -> Vec<DirRecord> {
+        let mut records = Vec::new();
+        let mut block = [0u8; BLOCK_SZ];
+        for base in (0..disk_inode.size() as usize).step_by(BLOCK_SZ) {
+            disk_inode.read_at(base, &mut block, &self.block_device);
+            records.extend(parse_dir_block(&block, base).0);
+        }
+        return records;
+    }
+    /// Dirents in use of a directory
+    fn dir_entries(&self, disk_inode: &DiskInode) -> Vec<DirEntry> {
+        return self
+            .dir_records(disk_inode)
+            .into_iter()
+            .filter_map(|record| record.entry)
+            .collect();
+    }
+    /// Whether a directory has nothing but . and ..
+    fn is_empty_dir(&self, disk_inode: &DiskInode) -> bool {
+        return self
+            .dir_entries(disk_inode)
+            .iter()
+            .all(|dirent| dirent.name() == "." || dirent.name() == "..");
+    }
+    /// Write `dirent` of `rec_len` bytes at `offset` of a directory, None writes a free dirent
+    fn write_dirent(
+        &self,
+        offset: usize,
+        rec_len: usize,
+        dirent: Option<&DirEntry>,
+        disk_inode: &mut DiskInode,
+    ) {
+        let bytes = match dirent {
+            Some(dirent) => dirent.to_bytes(rec_len),
+            None => DirEntryHeader::free(rec_len).to_bytes().to_vec(),
+        };
+        disk_inode.write_at(offset, &bytes, &self.block_device);
+    }
+    /// Change the length of the dirent at `offset` of a directory, 只改头部
+    fn set_rec_len(&self, offset: usize, rec_len: usize, disk_inode: &mut DiskInode) {
+        let mut bytes = [0u8; DIRENT_HEADER_SZ];
+        disk_inode.read_at(offset, &mut bytes, &self.block_device);
+        let mut header = DirEntryHeader::from_bytes(&bytes);
+        header.rec_len = rec_len as u16;
+        disk_inode.write_at(offset, &header.to_bytes(), &self.block_device);
+    }
+    /// Add a dirent to a directory.
+    /// 先找空闲的或者后面有富余空间的目录项, 都没有再给目录加一个块
+    fn add_dirent(
+        &self,
+        dirent: &DirEntry,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let needed = dirent.rec_len();
+        for record in self.dir_records(disk_inode) {
+            let used = record.entry.as_ref().map_or(0, |used| used.rec_len());
+            if record.rec_len < used + needed {
+                continue;
+            }
+            if used > 0 {
+                // 把富余的空间分出来
+                self.set_rec_len(record.offset, used, disk_inode);
+            }
+            self.write_dirent(
+                record.offset + used,
+                record.rec_len - used,
+                Some(dirent),
+                disk_inode,
             );
-            //文件Metadata内容 含有数组里面是二级制的文件名
-            if dirent.name() == name {
-                return Some(dirent.inode_number() as u32);
+            disk_inode.touch_modified();
+            return;
+        }
+        let offset = disk_inode.size() as usize;
+        self.increase_size((offset + BLOCK_SZ) as u64, disk_inode, fs);
+        self.write_dirent(offset, BLOCK_SZ, Some(dirent), disk_inode);
+        disk_inode.touch_modified();
+    }
+    /// Remove a dirent of a directory.
+    /// 并入同一个块里的前一个目录项, 它是块里的第一个就标记为空闲. 目录末尾空出来的块会被回收
+    fn remove_dirent(
+        &self,
+        record: &DirRecord,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        match record.prev {
+            Some(prev) => self.set_rec_len(prev, record.offset + record.rec_len - prev, disk_inode),
+            None => self.write_dirent(record.offset, record.rec_len, None, disk_inode),
+        }
+        // 第一个块里有 . 和 .., 不会被回收
+        let mut bytes = [0u8; DIRENT_HEADER_SZ];
+        while disk_inode.size() as usize > BLOCK_SZ {
+            let last = disk_inode.size() as usize - BLOCK_SZ;
+            disk_inode.read_at(last, &mut bytes, &self.block_device);
+            let header = DirEntryHeader::from_bytes(&bytes);
+            if header.name_len != 0 || header.rec_len as usize != BLOCK_SZ {
+                break;
+            }
+            for data_block in disk_inode.decrease_size(last as u64, &self.block_device) {
+                fs.dealloc_data(data_block);
             }
         }
-        return None;
+        disk_inode.touch_modified();
     }
-    /// Find inode under current inode by name
-    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
-        let fs = self.fs.lock();
-        let mut block_id = self.block_id as u32;
-        let mut block_offset = self.block_offset;
-        for name in path.split('/').filter(|s| !s.is_empty()) {
-            let inode_id = get_block_cache(block_id as usize, self.block_device.clone())
-                .lock()
-                .read(block_offset, |disk_inode: &DiskInode| {
-                    if disk_inode.is_file() {
-                        return None;
-                    }
-                    return self.find_inode_id(name, disk_inode);
-                });
-            if inode_id.is_none() {
+    /// Write `.` and `..` into a new directory, 它的链接数是2: 父目录里的目录项和自己的 .
+    fn init_dots(
+        &self,
+        inode_id: u32,
+        parent_id: u32,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        self.add_dirent(&DirEntry::new(".", inode_id, FT_DIR), disk_inode, fs);
+        self.add_dirent(&DirEntry::new("..", parent_id, FT_DIR), disk_inode, fs);
+        disk_inode.nlink = 2;
+    }
+    /// Write `.` and `..` of the root directory, both refer to itself
+    pub(crate) fn init_root_dots(&self) {
+        let mut fs = self.fs.lock();
+        let root_id = self.inode_id(&fs);
+        self.modify_disk_inode(|root_inode| self.init_dots(root_id, root_id, root_inode, &mut fs));
+    }
+    /// Whether `name` can be used as a dirent name, . 和 .. 不能被创建, 删除或者改名
+    fn is_valid_name(name: &str) -> bool {
+        return !name.is_empty()
+            && name.len() <= NAME_LENGTH_LIMIT
+            && !name.contains('/')
+            && name != "."
+            && name != "..";
+    }
+    /// Get the inode id of current inode
+    fn inode_id(&self, fs: &MutexGuard<EasyFileSystem>) -> u32 {
+        return fs.get_inode_id(self.block_id as u32, self.block_offset);
+    }
+    /// Call a function over the disk inode of `inode_id` to read it
+    fn read_disk_inode_by_id<V>(
+        &self,
+        inode_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+        f: impl FnOnce(&DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .read(block_offset, f)
+    }
+    /// Call a function over the disk inode of `inode_id` to modify it
+    fn modify_disk_inode_by_id<V>(
+        &self,
+        inode_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+        f: impl FnOnce(&mut DiskInode) -> V,
+    ) -> V {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+            .lock()
+            .modify(block_offset, f)
+    }
+    /// Decrease the link count of `inode_id` by `links`, free its data blocks and the inode when it drops to zero.
+    /// 还有 [`Inode`] 指向的等到最后一个 [`Inode`] 释放时再回收, 被删除的目录里不能再创建文件
+    fn drop_links(&self, inode_id: u32, links: u16, fs: &mut MutexGuard<EasyFileSystem>) {
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+        let key = (
+            device_id(&self.block_device),
+            block_id as usize,
+            block_offset,
+        );
+        let data_blocks_dealloc = self.modify_disk_inode_by_id(inode_id, fs, |disk_inode| {
+            disk_inode.nlink -= links;
+            disk_inode.touch_changed();
+            if disk_inode.nlink > 0 {
+                return None;
+            }
+            if let Some(refs) = INODE_REFS.lock().get_mut(&key) {
+                refs.orphan = true;
                 return None;
             }
-            //进入下一层文件夹 循环修改block_id与block_offset
-            (block_id, block_offset) = fs.get_disk_inode_pos(inode_id.unwrap());
+            return Some(disk_inode.clear_size(&self.block_device));
+        });
+        if let Some(data_blocks_dealloc) = data_blocks_dealloc {
+            for data_block in data_blocks_dealloc.into_iter() {
+                fs.dealloc_data(data_block);
+            }
+            fs.dealloc_inode(inode_id);
         }
-
+    }
+    /// Whether the directory `dir_id` contains `inode_id` in its subtree
+    fn subtree_contains(
+        &self,
+        dir_id: u32,
+        inode_id: u32,
+        fs: &MutexGuard<EasyFileSystem>,
+    ) -> bool {
+        let children =
+            self.read_disk_inode_by_id(dir_id, fs, |disk_inode| self.dir_entries(disk_inode));
+        for child in children {
+            if child.name() == "." || child.name() == ".." {
+                continue;
+            }
+            if child.inode_number() == inode_id {
+                return true;
+            }
+            // 目录项里记着文件类型, 不用再读inode
+            if child.is_dir() && self.subtree_contains(child.inode_number(), inode_id, fs) {
+                return true;
+            }
+        }
+        return false;
+    }
+    /// Find inode under current inode by path, 路径里的符号链接都会被跟随.
+    /// None if it does not exist or there are too many levels of symlinks
+    pub fn find(&self, path: &str) -> Option<Arc<Inode>> {
+        return self.lookup(path, true);
+    }
+    /// Find inode under current inode by path, 最后一个名字是符号链接的话返回链接本身
+    pub fn find_nofollow(&self, path: &str) -> Option<Arc<Inode>> {
+        return self.lookup(path, false);
+    }
+    fn lookup(&self, path: &str, follow: bool) -> Option<Arc<Inode>> {
+        let fs = self.fs.lock();
+        let mut depth = 0;
+        let inode_id = self.walk(self.inode_id(&fs), path, follow, &mut depth, &fs)?;
+        let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
         return Some(Arc::new(Self::new(
             block_id,
             block_offset,
@@ -91,14 +373,61 @@ impl Inode {
             self.block_device.clone(),
         )));
     }
+    /// Walk `path` from the directory `dir_id` and return the inode id it refers to.
+    /// 链接的相对路径从链接所在的目录开始, 绝对路径从根目录开始. `depth`是已经跟随过的链接数
+    fn walk(
+        &self,
+        dir_id: u32,
+        path: &str,
+        follow: bool,
+        depth: &mut usize,
+        fs: &MutexGuard<EasyFileSystem>,
+    ) -> Option<u32> {
+        let names: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut inode_id = dir_id;
+        for (i, name) in names.iter().enumerate() {
+            let next = self.read_disk_inode_by_id(inode_id, fs, |disk_inode| {
+                if !disk_inode.is_dir() {
+                    return None;
+                }
+                return self.find_inode_id(name, disk_inode);
+            })?;
+            let target = self.read_disk_inode_by_id(next, fs, |disk_inode| {
+                if !disk_inode.is_symlink() || (i + 1 == names.len() && !follow) {
+                    return None;
+                }
+                return Some(self.read_target(disk_inode));
+            });
+            //进入下一层文件夹
+            inode_id = match target {
+                Some(target) => {
+                    *depth += 1;
+                    if *depth > MAX_SYMLINK_DEPTH {
+                        return None;
+                    }
+                    // 根目录的inode是0
+                    let start = if target.starts_with('/') { 0 } else { inode_id };
+                    self.walk(start, &target, true, depth, fs)?
+                }
+                None => next,
+            };
+        }
+        return Some(inode_id);
+    }
+    /// Read the target path of a symlink
+    fn read_target(&self, disk_inode: &DiskInode) -> String {
+        let mut buf = vec![0u8; disk_inode.size() as usize];
+        disk_inode.read_at(0, &mut buf, &self.block_device);
+        return String::from_utf8_lossy(&buf).into_owned();
+    }
     /// Increase the size of a disk inode
     fn increase_size(
         &self,
-        new_size: u32,
+        new_size: u64,
         disk_inode: &mut DiskInode,
         fs: &mut MutexGuard<EasyFileSystem>,
     ) {
-        if new_size < disk_inode.size {
+        if new_size < disk_inode.size() {
             return;
         }
         let blocks_needed = disk_inode.blocks_num_needed(new_size);
@@ -108,19 +437,79 @@ impl Inode {
         }
         disk_inode.increase_size(new_size, v, &self.block_device);
     }
+    /// Increase the size of a disk inode, 磁盘满了返回false, 大小不变
+    fn try_increase_size(
+        &self,
+        new_size: u64,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) -> bool {
+        let mut v: Vec<u32> = Vec::new();
+        for _ in 0..disk_inode.blocks_num_needed(new_size) {
+            match fs.try_alloc_data() {
+                Some(block_id) => v.push(block_id),
+                None => {
+                    for block_id in v {
+                        fs.dealloc_data(block_id);
+                    }
+                    return false;
+                }
+            }
+        }
+        disk_inode.increase_size(new_size, v, &self.block_device);
+        return true;
+    }
+    /// Decrease the size of a regular file, 回收后面的数据块和不再用到的索引块.
+    /// 最后一个块里新的文件末尾之后清零, 以后再变长时这部分读出来是0
+    fn decrease_size(
+        &self,
+        new_size: u64,
+        disk_inode: &mut DiskInode,
+        fs: &mut MutexGuard<EasyFileSystem>,
+    ) {
+        let new_end = new_size as usize;
+        if !new_end.is_multiple_of(BLOCK_SZ) {
+            let block_end = (new_end / BLOCK_SZ + 1) * BLOCK_SZ;
+            let tail = block_end.min(disk_inode.size() as usize) - new_end;
+            disk_inode.write_at(new_end, &[0u8; BLOCK_SZ][..tail], &self.block_device);
+        }
+        let total_blocks =
+            disk_inode.total_blocks(disk_inode.size()) - disk_inode.total_blocks(new_size);
+        let data_blocks_dealloc = disk_inode.decrease_size(new_size, &self.block_device); //回收的块的编号Vec
+        assert!(data_blocks_dealloc.len() == total_blocks as usize);
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.dealloc_data(data_block);
+        }
+    }
     /// Create inode under current inode by name
     pub fn create_inode(&self, name: &str, inode_type: DiskInodeType) -> Option<Arc<Inode>> {
+        return self.create_child(name, inode_type, &[]);
+    }
+
+    /// Create inode under current inode by name, `content` is written into it in the same transaction
+    fn create_child(
+        &self,
+        name: &str,
+        inode_type: DiskInodeType,
+        content: &[u8],
+    ) -> Option<Arc<Inode>> {
+        if !Self::is_valid_name(name) {
+            return None;
+        }
         let mut fs = self.fs.lock();
         let op = |root_inode: &DiskInode| {
-            // assert it is a directory
-            assert!(root_inode.is_dir());
+            // 已经被删除的目录(链接数为0)里不能再创建文件
+            if !root_inode.is_dir() || root_inode.nlink == 0 {
+                return false;
+            }
             // has the file been created?
-            self.find_inode_id(name, root_inode)
+            self.find_inode_id(name, root_inode).is_none()
         };
-        if self.read_disk_inode(op).is_some() {
+        if !self.read_disk_inode(op) {
             //找到名字就不用创建了
             return None;
         }
+        fs.begin();
         // create a new file
         // alloc a inode with an indirect block
         let new_inode_id = fs.alloc_inode();
@@ -129,27 +518,34 @@ impl Inode {
         get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
             .lock()
             .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
-                new_inode.initialize(inode_type); //给待创建新文件 分配一个新的Inode
+                new_inode.initialize(inode_type, fs.large_file()); //给待创建新文件 分配一个新的Inode
+                if !content.is_empty() {
+                    self.increase_size(content.len() as u64, new_inode, &mut fs);
+                    new_inode.write_at(0, content, &self.block_device);
+                }
             });
+        let is_dir = inode_type == DiskInodeType::Directory;
+        if is_dir {
+            let parent_id = self.inode_id(&fs);
+            get_block_cache(new_inode_block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify(new_inode_block_offset, |new_inode: &mut DiskInode| {
+                    self.init_dots(new_inode_id, parent_id, new_inode, &mut fs);
+                });
+        }
         self.modify_disk_inode(|root_inode| {
             // append file in the dirent
-            let file_count = (root_inode.size as usize) / DIRENT_SZ;
-            let new_size = (file_count + 1) * DIRENT_SZ;
-            // increase size
-            self.increase_size(new_size as u32, root_inode, &mut fs);
-            // write dirent
-            let dirent = DirEntry::new(name, new_inode_id);
-            root_inode.write_at(
-                //将待创建文件的目录项插入到根目录的内容中，使得之后可以索引到。
-                file_count * DIRENT_SZ,
-                dirent.as_bytes(),
-                &self.block_device,
-            );
+            //将待创建文件的目录项插入到根目录的内容中，使得之后可以索引到。
+            let dirent = DirEntry::new(name, new_inode_id, inode_type.file_type());
+            self.add_dirent(&dirent, root_inode, &mut fs);
+            if is_dir {
+                root_inode.nlink += 1; //子目录的 .. 指向这里
+            }
         });
 
         let (block_id, block_offset) = fs.get_disk_inode_pos(new_inode_id);
-        block_cache_sync_all(); //写回真正的磁盘
-                                // return inode
+        fs.commit(); //提交事务, 写回真正的磁盘
+                     // return inode
         return Some(Arc::new(Self::new(
             block_id,
             block_offset,
@@ -169,64 +565,411 @@ impl Inode {
         return self.create_inode(name, DiskInodeType::Directory);
     }
 
+    /// Create symlink `name` to `target` under current inode, `target`可以不存在
+    pub fn symlink(&self, name: &str, target: &str) -> Option<Arc<Inode>> {
+        if target.is_empty() || target.len() > SYMLINK_MAX_LEN {
+            return None;
+        }
+        return self.create_child(name, DiskInodeType::Symlink, target.as_bytes());
+    }
+
+    /// Read the target path of current inode, None if it is not a symlink
+    pub fn read_link(&self) -> Option<String> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_symlink() {
+                return None;
+            }
+            return Some(self.read_target(disk_inode));
+        })
+    }
+
     /// List inodes under current inode
     pub fn ls(&self) -> Vec<String> {
         let _fs = self.fs.lock();
         self.read_disk_inode(|disk_inode| {
-            let file_count = (disk_inode.size as usize) / DIRENT_SZ; //通过读磁盘块里内容的大小, 得知里面有几个文件. 每个文件Metadata大小固定是32
-            let mut v: Vec<String> = Vec::new();
-            if disk_inode.is_file() {
+            if !disk_inode.is_dir() {
                 //如果是常规文件(不是文件夹) 就直接返回一个空Vec. 因为文件还是文件夹都可以调用ls方法.
-                return v;
+                return Vec::new();
             }
-
-            for i in 0..file_count {
-                let mut dirent = DirEntry::empty();
-                assert_eq!(
-                    disk_inode.read_at(i * DIRENT_SZ, dirent.as_bytes_mut(), &self.block_device), //读入到dirent的buf中
-                    DIRENT_SZ
-                );
-                v.push(String::from(dirent.name())); // dirent的buf 来得到文件名的数据u8数组, 再转换成String
+            return self
+                .dir_entries(disk_inode)
+                .into_iter()
+                .filter(|dirent| dirent.name() != "." && dirent.name() != "..") // . 和 .. 不算目录里的文件
+                .map(|dirent| String::from(dirent.name()))
+                .collect();
+        })
+    }
+    /// Read the first directory entry (including . and ..) at or after the byte position `pos`,
+    /// return it and the position of the next one.
+    /// Return None if current inode is not a directory or there are no more entries
+    pub fn read_dirent(&self, pos: usize) -> Option<(DirEntry, usize)> {
+        let _fs = self.fs.lock();
+        self.read_disk_inode(|disk_inode| {
+            if !disk_inode.is_dir() {
+                return None;
+            }
+            let mut block = [0u8; BLOCK_SZ];
+            let mut base = pos / BLOCK_SZ * BLOCK_SZ;
+            while base < disk_inode.size() as usize {
+                disk_inode.read_at(base, &mut block, &self.block_device);
+                let (records, _) = parse_dir_block(&block, base);
+                for record in records.into_iter().filter(|record| record.offset >= pos) {
+                    if let Some(dirent) = record.entry {
+                        return Some((dirent, record.offset + record.rec_len));
+                    }
+                }
+                base += BLOCK_SZ;
             }
-            return v;
+            return None;
         })
     }
     /// Read data from current inode
     pub fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
         let _fs = self.fs.lock();
-        self.read_disk_inode(|disk_inode| disk_inode.read_at(offset, buf, &self.block_device))
+        self.modify_disk_inode(|disk_inode| {
+            disk_inode.touch_accessed();
+            disk_inode.read_at(offset, buf, &self.block_device)
+        })
     }
-    /// Write data to current inode
+    /// Write data to current inode.
+    /// 每次最多写WRITE_CHUNK_SIZE字节作为一个事务, 这样一个事务修改的索引块和位图不会超过日志区的大小
     pub fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
-        let mut fs = self.fs.lock();
-        let size = self.modify_disk_inode(|disk_inode| {
-            assert!(disk_inode.is_file());
+        // 超过文件最大长度的部分写不进去
+        let max_size = self.read_disk_inode(|disk_inode| disk_inode.max_size()) as usize;
+        let buf = &buf[..buf.len().min(max_size.saturating_sub(offset))];
+        let mut size = 0;
+        for chunk in buf.chunks(WRITE_CHUNK_SIZE) {
+            let mut fs = self.fs.lock();
+            fs.begin();
+            let chunk_offset = offset + size;
+            size += self.modify_disk_inode(|disk_inode| {
+                assert!(disk_inode.is_file());
 
-            self.increase_size((offset + buf.len()) as u32, disk_inode, &mut fs); //由于是写回磁盘, 很有可能文件会变大.
-            disk_inode.write_at(offset, buf, &self.block_device)
-        });
-        block_cache_sync_all();
+                self.increase_size((chunk_offset + chunk.len()) as u64, disk_inode, &mut fs); //由于是写回磁盘, 很有可能文件会变大.
+                disk_inode.touch_modified();
+                disk_inode.write_at(chunk_offset, chunk, &self.block_device)
+            });
+            fs.commit();
+        }
         return size;
     }
 
     /// Clear the data in current inode
     pub fn clear(&self) {
+        assert!(self.truncate(0));
+    }
+
+    /// Set the size of a regular file to `new_size`, 变长的部分读出来是0.
+    /// 超过文件最大长度, 不是普通文件或者磁盘满了返回false, 这时大小不变
+    pub fn truncate(&self, new_size: u64) -> bool {
+        let (is_file, old_size, max_size) = self.read_disk_inode(|disk_inode| {
+            (
+                disk_inode.is_file(),
+                disk_inode.size(),
+                disk_inode.max_size(),
+            )
+        });
+        if !is_file || new_size > max_size {
+            return false;
+        }
+        if new_size <= old_size {
+            let mut fs = self.fs.lock();
+            fs.begin();
+            self.modify_disk_inode(|disk_inode| {
+                self.decrease_size(new_size, disk_inode, &mut fs);
+                disk_inode.touch_modified();
+            });
+            fs.commit(); //提交事务, 写回真正的磁盘
+            return true;
+        }
+        // 和write_at一样, 每次最多长WRITE_CHUNK_SIZE字节作为一个事务
+        let mut size = old_size;
+        while size < new_size {
+            let chunk_end = (size + WRITE_CHUNK_SIZE as u64).min(new_size);
+            let mut fs = self.fs.lock();
+            fs.begin();
+            let grown = self.modify_disk_inode(|disk_inode| {
+                disk_inode.touch_modified();
+                self.try_increase_size(chunk_end, disk_inode, &mut fs)
+            });
+            fs.commit();
+            if !grown {
+                // 前面的事务已经分配的块也还回去
+                drop(fs);
+                self.truncate(old_size);
+                return false;
+            }
+            size = chunk_end;
+        }
+        return true;
+    }
+
+    /// Create a hard link `name` under current directory to `target`, which is not a directory
+    pub fn link(&self, name: &str, target: &Inode) -> bool {
+        if !Self::is_valid_name(name) {
+            return false;
+        }
         let mut fs = self.fs.lock();
-        self.modify_disk_inode(|disk_inode| {
-            assert!(disk_inode.is_file());
+        // 目录不能建硬链接, 否则目录树里会出现环
+        let file_type = match target.read_disk_inode(|disk_inode| {
+            if disk_inode.is_dir() {
+                return None;
+            }
+            return Some(disk_inode.file_type());
+        }) {
+            Some(file_type) => file_type,
+            None => return false,
+        };
+        if !self.read_disk_inode(|dir| {
+            dir.is_dir() && dir.nlink > 0 && self.find_inode_id(name, dir).is_none()
+        }) {
+            return false;
+        }
+        let target_id = target.inode_id(&fs);
+        fs.begin();
+        target.modify_disk_inode(|disk_inode| {
+            disk_inode.nlink += 1;
+            disk_inode.touch_changed();
+        });
+        let dirent = DirEntry::new(name, target_id, file_type);
+        self.modify_disk_inode(|dir| self.add_dirent(&dirent, dir, &mut fs));
+        fs.commit();
+        return true;
+    }
 
-            let size = disk_inode.size;
-            let data_blocks_dealloc = disk_inode.clear_size(&self.block_device); //回收所有的块的编号Vec
-            assert!(data_blocks_dealloc.len() == DiskInode::total_blocks(size) as usize);
-            for data_block in data_blocks_dealloc.into_iter() {
-                fs.dealloc_data(data_block);
+    /// Remove the regular file or symlink `name` under current directory.
+    /// 链接数减到0时回收文件的数据块和inode, 打开着的文件等到关闭之后再回收
+    pub fn unlink(&self, name: &str) -> bool {
+        return self.remove(name, false);
+    }
+
+    /// Remove the empty directory `name` under current directory
+    pub fn rmdir(&self, name: &str) -> bool {
+        return self.remove(name, true);
+    }
+
+    fn remove(&self, name: &str, is_dir: bool) -> bool {
+        if !Self::is_valid_name(name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let record = match self.read_disk_inode(|dir| {
+            if !dir.is_dir() {
+                return None;
             }
+            return self.find_dirent(name, dir);
+        }) {
+            Some(record) => record,
+            None => return false,
+        };
+        let inode_id = record.entry.as_ref().unwrap().inode_number();
+        // rmdir只删除空目录, unlink只删除普通文件
+        let removable = self.read_disk_inode_by_id(inode_id, &fs, |disk_inode| {
+            return disk_inode.is_dir() == is_dir && (!is_dir || self.is_empty_dir(disk_inode));
         });
-        block_cache_sync_all(); //写回真正的磁盘
+        if !removable {
+            return false;
+        }
+        fs.begin();
+        self.modify_disk_inode(|dir| {
+            self.remove_dirent(&record, dir, &mut fs);
+            if is_dir {
+                dir.nlink -= 1; //子目录的 .. 没有了
+            }
+        });
+        // 目录还要去掉自己的 . 这个链接
+        self.drop_links(inode_id, if is_dir { 2 } else { 1 }, &mut fs);
+        fs.commit();
+        return true;
+    }
+
+    /// Move `old_name` under current directory to `new_name` under `new_dir`.
+    /// 如果`new_name`已经存在, 它会被替换: 文件只能替换文件, 目录只能替换空目录
+    pub fn rename(&self, old_name: &str, new_dir: &Inode, new_name: &str) -> bool {
+        if !Self::is_valid_name(old_name) || !Self::is_valid_name(new_name) {
+            return false;
+        }
+        let mut fs = self.fs.lock();
+        let old_record = match self.read_disk_inode(|dir| {
+            if !dir.is_dir() {
+                return None;
+            }
+            return self.find_dirent(old_name, dir);
+        }) {
+            Some(record) => record,
+            None => return false,
+        };
+        let old_dirent = old_record.entry.as_ref().unwrap();
+        let inode_id = old_dirent.inode_number();
+        if !new_dir.read_disk_inode(|dir| dir.is_dir() && dir.nlink > 0) {
+            return false;
+        }
+        let is_dir = self.read_disk_inode_by_id(inode_id, &fs, |disk_inode| disk_inode.is_dir());
+        // 目录不能移动到它自己或者它的子目录下面
+        let old_dir_id = self.inode_id(&fs);
+        let new_dir_id = new_dir.inode_id(&fs);
+        if is_dir && (new_dir_id == inode_id || self.subtree_contains(inode_id, new_dir_id, &fs)) {
+            return false;
+        }
+        let dirent = DirEntry::new(new_name, inode_id, old_dirent.file_type());
+        match new_dir.read_disk_inode(|dir| new_dir.find_dirent(new_name, dir)) {
+            Some(record) if record.entry.as_ref().unwrap().inode_number() == inode_id => {
+                // 新旧名字是同一个文件的硬链接, 什么都不做
+                return true;
+            }
+            Some(new_record) => {
+                let replaced_id = new_record.entry.as_ref().unwrap().inode_number();
+                let replaceable = self.read_disk_inode_by_id(replaced_id, &fs, |disk_inode| {
+                    return disk_inode.is_dir() == is_dir
+                        && (!is_dir || self.is_empty_dir(disk_inode));
+                });
+                if !replaceable {
+                    return false;
+                }
+                fs.begin();
+                // 先原地改写新目录项再删除旧目录项, 名字一样长, 不会影响旧目录项的位置
+                new_dir.modify_disk_inode(|dir| {
+                    new_dir.write_dirent(new_record.offset, new_record.rec_len, Some(&dirent), dir)
+                });
+                self.modify_disk_inode(|dir| self.remove_dirent(&old_record, dir, &mut fs));
+                if is_dir {
+                    // 被替换的空目录的 .. 没有了
+                    new_dir.modify_disk_inode(|dir| dir.nlink -= 1);
+                }
+                self.drop_links(replaced_id, if is_dir { 2 } else { 1 }, &mut fs);
+            }
+            None => {
+                fs.begin();
+                // 先删除旧目录项再插入新的, 同一个目录里插入可能会拆分旧目录项
+                self.modify_disk_inode(|dir| self.remove_dirent(&old_record, dir, &mut fs));
+                new_dir.modify_disk_inode(|dir| new_dir.add_dirent(&dirent, dir, &mut fs));
+            }
+        }
+        if is_dir && old_dir_id != new_dir_id {
+            // 目录换了父目录, 它的 .. 也要指向新的父目录
+            let (block_id, block_offset) = fs.get_disk_inode_pos(inode_id);
+            get_block_cache(block_id as usize, Arc::clone(&self.block_device))
+                .lock()
+                .modify(block_offset, |disk_inode: &mut DiskInode| {
+                    let record = self.find_dirent("..", disk_inode).unwrap();
+                    let dirent = DirEntry::new("..", new_dir_id, FT_DIR);
+                    self.write_dirent(record.offset, record.rec_len, Some(&dirent), disk_inode);
+                });
+            self.modify_disk_inode(|dir| dir.nlink -= 1);
+            new_dir.modify_disk_inode(|dir| dir.nlink += 1);
+        }
+        self.modify_disk_inode_by_id(inode_id, &fs, |disk_inode| disk_inode.touch_changed());
+        fs.commit();
+        return true;
+    }
+
+    /// Get the absolute path of current directory by walking up through `..`,
+    /// None if it is not a directory or has been removed
+    pub fn path(&self) -> Option<String> {
+        let fs = self.fs.lock();
+        let mut inode_id = self.inode_id(&fs);
+        let mut names: Vec<String> = Vec::new();
+        // 根目录的 .. 指向自己
+        loop {
+            let parent_id = self.read_disk_inode_by_id(inode_id, &fs, |disk_inode| {
+                if !disk_inode.is_dir() || disk_inode.nlink == 0 {
+                    return None;
+                }
+                return self.find_inode_id("..", disk_inode);
+            })?;
+            if parent_id == inode_id {
+                break;
+            }
+            // 在父目录里找到指向自己的目录项, 就是自己的名字
+            let name = self.read_disk_inode_by_id(parent_id, &fs, |disk_inode| {
+                if !disk_inode.is_dir() {
+                    return None;
+                }
+                return self
+                    .dir_entries(disk_inode)
+                    .into_iter()
+                    .find(|dirent| {
+                        dirent.inode_number() == inode_id
+                            && dirent.name() != "."
+                            && dirent.name() != ".."
+                    })
+                    .map(|dirent| String::from(dirent.name()));
+            })?;
+            names.push(name);
+            inode_id = parent_id;
+        }
+        if names.is_empty() {
+            return Some(String::from("/"));
+        }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        return Some(path);
+    }
+
+    /// Get the metadata of current inode
+    pub fn stat(&self) -> InodeStat {
+        let fs = self.fs.lock();
+        let ino = self.inode_id(&fs);
+        self.read_disk_inode(|disk_inode| InodeStat {
+            ino,
+            is_dir: disk_inode.is_dir(),
+            is_symlink: disk_inode.is_symlink(),
+            mode: disk_inode.mode,
+            nlink: disk_inode.nlink,
+            size: disk_inode.size(),
+            blocks: disk_inode.total_blocks(disk_inode.size()),
+            atime: disk_inode.atime,
+            mtime: disk_inode.mtime,
+            ctime: disk_inode.ctime,
+        })
+    }
+
+    /// Whether current inode is a directory
+    pub fn is_dir(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_dir())
+    }
+
+    /// Whether current inode is a symlink
+    pub fn is_symlink(&self) -> bool {
+        self.read_disk_inode(|disk_inode| disk_inode.is_symlink())
     }
 
     /// Show inode size
-    pub fn get_inode_size(&self) -> u32 {
-        self.modify_disk_inode(|disk_inode| return disk_inode.size)
+    pub fn get_inode_size(&self) -> u64 {
+        self.modify_disk_inode(|disk_inode| return disk_inode.size())
+    }
+}
+
+impl Drop for Inode {
+    /// 链接数已经是0的文件, 最后一个 [`Inode`] 释放时回收数据块和inode
+    fn drop(&mut self) {
+        let mut refs = INODE_REFS.lock();
+        let key = self.key();
+        let entry = refs.get_mut(&key).unwrap();
+        entry.count -= 1;
+        if entry.count > 0 {
+            return;
+        }
+        let orphan = entry.orphan;
+        refs.remove(&key);
+        drop(refs);
+        if !orphan {
+            return;
+        }
+        let mut fs = self.fs.lock();
+        fs.begin();
+        let inode_id = self.inode_id(&fs);
+        let data_blocks_dealloc = self.modify_disk_inode(|disk_inode| {
+            disk_inode.clear_size(&self.block_device)
+        });
+        for data_block in data_blocks_dealloc.into_iter() {
+            fs.dealloc_data(data_block);
+        }
+        fs.dealloc_inode(inode_id);
+        fs.commit();
     }
 }
@@ -17,7 +17,8 @@ pub const VIRT_UART: usize = 0x1000_0000;
 #[macro_use]
 use crate::drivers::chardev::{CharDevice, UART};
 use crate::drivers::plic::{IntrTargetPriority, PLIC};
-use crate::drivers::{BLOCK_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use crate::drivers::{BLOCK_DEVICE, BLOCK_DEVICE2, KEYBOARD_DEVICE, MOUSE_DEVICE};
+use easy_fs::BlockDevice;
 use crate::log;
 
 pub fn device_init() {
@@ -28,8 +29,8 @@ pub fn device_init() {
     let machine = IntrTargetPriority::Machine;
     plic.set_threshold(hart_id, supervisor, 0);
     plic.set_threshold(hart_id, machine, 1); // machine模式 阈值为1, 优先级<=1的中断源不会触发中断
-                                             //irq num:5 keyboard, 6 mouse , 8 block , 10 uart
-    for intr_src_id in [5usize, 6, 8, 10] {
+                                             //irq num:4 block2, 5 keyboard, 6 mouse , 8 block , 10 uart
+    for intr_src_id in [4usize, 5, 6, 8, 10] {
         plic.enable(hart_id, supervisor, intr_src_id); //这些中断源打开中断
         plic.set_priority(intr_src_id, 1); //中断源设置优先级
     }
@@ -42,6 +43,12 @@ pub fn irq_handler() {
     let mut plic = unsafe { PLIC::new(VIRT_PLIC) };
     let intr_src_id = plic.claim(0, IntrTargetPriority::Supervisor); //第0个CPU (反正我们单CPU) 操作系统的特权:Supervisor
     match intr_src_id {
+        4 => {
+            // 第二个磁盘, 没有接的话不会有这个中断
+            if let Some(block_device) = BLOCK_DEVICE2.as_ref() {
+                block_device.handle_irq()
+            }
+        }
         5 => {
             log!( "\x1b[35m[irq_handler] 设备 鼠标 发出中断请求  \x1b[0m");
             MOUSE_DEVICE.handle_irq()
@@ -56,7 +63,7 @@ pub fn irq_handler() {
         }
         10 => UART.handle_irq(),
         _ => panic!(
-            "unsupported IRQ {}  现在只接受5/6/8/10 对应就是鼠标|键盘|磁盘|串口",
+            "unsupported IRQ {}  现在只接受4/5/6/8/10 对应就是磁盘2|鼠标|键盘|磁盘|串口",
             intr_src_id
         ),
     }
@@ -10,5 +10,17 @@ pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT_BASE: usize = TRAMPOLINE - PAGE_SIZE;
 
+/// mmap 没有指定地址时, 从这里开始往上找空闲的虚拟地址 (显存映射在0x10000000)
+pub const MMAP_BASE: usize = 0x2000_0000;
+/// mmap 可以使用的最高地址(不含)
+pub const MMAP_END: usize = 0x20_0000_0000;
+
+/// 交换区的块数 (4MiB), 交换区紧跟在根设备上的easy-fs之后
+pub const SWAP_BLOCK_COUNT: usize = 8192;
+/// 空闲物理页帧少于这个数时, 开始把用户页面换出到交换区
+pub const FRAME_LOW_WATERMARK: usize = 64;
+/// easy-fs 块缓存最多缓存的块数 (64KiB)
+pub const BLOCK_CACHE_SIZE: usize = 128;
+
 pub const LOG_FLAG: bool = true;
 pub use crate::board::{CLOCK_FREQ, MMIO};
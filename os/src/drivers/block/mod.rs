@@ -4,9 +4,31 @@ pub use virtio_blk::VirtIOBlock;
 
 use crate::board::BlockDeviceImpl;
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use easy_fs::BlockDevice;
 use lazy_static::*;
+use virtio_blk::{VIRTIO0, VIRTIO4};
 
 lazy_static!{
-    pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice>=Arc::new(BlockDeviceImpl::new());
-}
\ No newline at end of file
+    /// The first disk, easy-fs和交换区在上面
+    pub static ref BLOCK_DEVICE: Arc<BlockDeviceImpl> = Arc::new(BlockDeviceImpl::new(VIRTIO0));
+    /// The second disk, None if qemu is started without it
+    pub static ref BLOCK_DEVICE2: Option<Arc<BlockDeviceImpl>> = BlockDeviceImpl::probe(VIRTIO4).map(Arc::new);
+}
+
+/// 大于0时块设备请求不能休眠, 驱动自己轮询完成的请求
+static NO_SLEEP: AtomicUsize = AtomicUsize::new(0);
+
+/// Run `f` with block requests polled instead of sleeping.
+/// 调用者可能持有进程的inner(比如分配页帧时换出脏页), 这时候休眠会让别的线程借用inner时panic
+pub fn without_sleep<T>(f: impl FnOnce() -> T) -> T {
+    NO_SLEEP.fetch_add(1, Ordering::Relaxed);
+    let ret = f();
+    NO_SLEEP.fetch_sub(1, Ordering::Relaxed);
+    return ret;
+}
+
+/// If the block requests of the current context can sleep
+fn can_sleep() -> bool {
+    return NO_SLEEP.load(Ordering::Relaxed) == 0;
+}
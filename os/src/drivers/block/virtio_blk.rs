@@ -1,94 +1,229 @@
-use super::BlockDevice;
+use super::{can_sleep, BlockDevice};
 use crate::drivers::bus::virtio::VirtioHal;
 use crate::sync::{Condvar, UPIntrFreeCell};
-use crate::task::schedule;
+use crate::task::{schedule, suspend_current_and_run_next};
 use crate::DEV_NON_BLOCKING_ACCESS;
 use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use easy_fs::BLOCK_SZ;
 use virtio_drivers::{BlkResp, Hal, RespStatus, VirtIOBlk, VirtIOHeader};
 
-const VIRTIO0: usize = 0x10008000;
+/// 第一个virtio-blk设备, 根文件系统和交换区在上面
+pub const VIRTIO0: usize = 0x10008000;
+/// 第二个virtio-blk设备, qemu按命令行的顺序从高地址往低分配, 它在鼠标后面
+pub const VIRTIO4: usize = 0x10004000;
+/// virtio-mmio的魔数 "virt"
+const VIRTIO_MAGIC: u32 = 0x74726976;
+/// virtio-mmio里块设备的设备号
+const VIRTIO_DEVICE_BLOCK: u32 = 2;
 
 pub struct VirtIOBlock {
-    virtio_blk: UPIntrFreeCell<VirtIOBlk<'static, VirtioHal>>,
+    /// virtio-mmio的地址
+    base: usize,
+    inner: UPIntrFreeCell<VirtIOBlockInner>,
     condvars: BTreeMap<u16, Condvar>,
 }
 
+pub struct VirtIOBlockInner {
+    virtio_blk: VirtIOBlk<'static, VirtioHal>,
+}
+
+/// A request of consecutive blocks, 每个块是一个virtio请求, 队列放得下的请求同时提交
+enum BlockRequest<'a> {
+    Read(&'a mut [u8]),
+    Write(&'a [u8]),
+}
+
 impl BlockDevice for VirtIOBlock {
     fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access(); //全局开关 : 是否阻塞
-        if nb {
-            // 非阻塞模式 (Aka 中断方式)
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.read_block_nb(block_id, buf, &mut resp).unwrap() }; // 这里的token 就是Descriptor链的头元素id
-                self.condvars.get(&token).unwrap().wait_no_sched() //将当前线程/进程 加入条件变量的等待队列
-            });
-            // log!("\x1b[38;5;208m[BLOCK DRIVE: read_block] block id: [{}] , sleep current thread/process  \x1b[0m",block_id);
-            schedule(task_cx_ptr); // 此线程/进程 进入休眠. 直到驱动取出数据 通过条件变量唤醒此线程/进程
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when reading VirtIOBlk"
-            );
-        } else {
-            // 阻塞模式 (Aka 轮询模式)
-            self.virtio_blk
-                .exclusive_access()
-                .read_block(block_id, buf)
-                .expect("Error when reading VirtIOBlk");
-        }
+        self.read_blocks(block_id, buf);
     }
 
     fn write_block(&self, block_id: usize, buf: &[u8]) {
-        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access(); //全局开关 : 是否阻塞
-        if nb {
-            // 非阻塞模式 (Aka 中断方式)
-            let mut resp = BlkResp::default();
-            let task_cx_ptr = self.virtio_blk.exclusive_session(|blk| {
-                let token = unsafe { blk.write_block_nb(block_id, buf, &mut resp).unwrap() }; // 这里的token 就是Descriptor链的头元素id
-                self.condvars.get(&token).unwrap().wait_no_sched()
-            });
-            schedule(task_cx_ptr); // 此线程/进程 进入休眠. 直到驱动取出数据 通过条件变量唤醒此线程/进程
-            assert_eq!(
-                resp.status(),
-                RespStatus::Ok,
-                "Error when writing VirtIOBlk"
-            );
-        } else {
-            self.virtio_blk
-                .exclusive_access()
-                .write_block(block_id, buf)
-                .expect("Error when writing VirtIOBlk");
-        }
+        self.write_blocks(block_id, buf);
+    }
+
+    fn read_blocks(&self, block_id: usize, buf: &mut [u8]) {
+        self.submit(block_id, BlockRequest::Read(buf));
+    }
+
+    fn write_blocks(&self, block_id: usize, buf: &[u8]) {
+        self.submit(block_id, BlockRequest::Write(buf));
     }
 
     fn handle_irq(&self) {
-        self.virtio_blk.exclusive_session(|blk| {
-            while let Ok(token) = blk.pop_used() {
-                // 唤醒等待该块设备I/O完成的线程/进程
-                // log!( "\x1b[35m[BLOCK DRIVE: handle_irq] token [{}]  \x1b[0m", token);
-                self.condvars.get(&token).unwrap().signal();
-            }
-        });
+        self.inner.exclusive_session(|inner| self.pop_used(inner));
     }
 }
 
 impl VirtIOBlock {
-    pub fn new() -> Self {
+    /// The block device at the virtio-mmio address `base`
+    pub fn new(base: usize) -> Self {
         let virtio_blk = unsafe {
-            UPIntrFreeCell::new(
-                VirtIOBlk::<VirtioHal>::new(&mut *(VIRTIO0 as *mut VirtIOHeader)).unwrap(),
-            )
+            VirtIOBlk::<VirtioHal>::new(&mut *(base as *mut VirtIOHeader)).unwrap()
         };
         let mut condvars = BTreeMap::new();
-        let channels = virtio_blk.exclusive_access().virt_queue_size();
+        let channels = virtio_blk.virt_queue_size();
         for i in 0..channels {
             let condvar = Condvar::new();
             condvars.insert(i, condvar);
         }
         return Self {
-            virtio_blk,
+            base,
+            inner: unsafe {
+                UPIntrFreeCell::new(VirtIOBlockInner { virtio_blk })
+            },
             condvars,
         };
     }
+
+    /// The block device at `base` if there is one.
+    /// qemu没有接设备的virtio-mmio位置设备号是0, 直接初始化会失败
+    pub fn probe(base: usize) -> Option<Self> {
+        let (magic, device_id) = unsafe {
+            (
+                (base as *const u32).read_volatile(),
+                ((base + 8) as *const u32).read_volatile(),
+            )
+        };
+        if magic != VIRTIO_MAGIC || device_id != VIRTIO_DEVICE_BLOCK {
+            return None;
+        }
+        return Some(Self::new(base));
+    }
+
+    /// Size of the device in bytes, 在virtio-blk的配置空间里, 单位是512字节的扇区
+    pub fn capacity(&self) -> usize {
+        let sectors = unsafe { ((self.base + 0x100) as *const u64).read_volatile() };
+        return sectors as usize * BLOCK_SZ;
+    }
+
+    /// Read or write consecutive blocks starting from `block_id`
+    fn submit(&self, block_id: usize, mut request: BlockRequest) {
+        let count = match &request {
+            BlockRequest::Read(buf) => buf.len() / BLOCK_SZ,
+            BlockRequest::Write(buf) => buf.len() / BLOCK_SZ,
+        };
+        let nb = *DEV_NON_BLOCKING_ACCESS.exclusive_access(); //全局开关 : 是否阻塞
+        if !nb {
+            // 阻塞模式 (Aka 轮询模式)
+            let mut inner = self.inner.exclusive_access();
+            for i in 0..count {
+                match &mut request {
+                    BlockRequest::Read(buf) => inner
+                        .virtio_blk
+                        .read_block(block_id + i, &mut buf[i * BLOCK_SZ..(i + 1) * BLOCK_SZ])
+                        .expect("Error when reading VirtIOBlk"),
+                    BlockRequest::Write(buf) => inner
+                        .virtio_blk
+                        .write_block(block_id + i, &buf[i * BLOCK_SZ..(i + 1) * BLOCK_SZ])
+                        .expect("Error when writing VirtIOBlk"),
+                }
+            }
+            return;
+        }
+        // 非阻塞模式 (Aka 中断方式)
+        // 设备完成请求之前 resp 不能释放, 每个块一个
+        let mut resps: Vec<BlkResp> = (0..count).map(|_| BlkResp::default()).collect();
+        let mut next = 0;
+        while next < count {
+            // 把队列塞满, 然后等这一批全部完成
+            let first = next;
+            let tokens = self.inner.exclusive_session(|inner| {
+                let mut tokens = Vec::new();
+                while next < count {
+                    let range = next * BLOCK_SZ..(next + 1) * BLOCK_SZ;
+                    let resp = &mut resps[next];
+                    // 这里的token 就是Descriptor链的头元素id, 队列满了返回错误
+                    let token = match &mut request {
+                        BlockRequest::Read(buf) => unsafe {
+                            inner
+                                .virtio_blk
+                                .read_block_nb(block_id + next, &mut buf[range], resp)
+                        },
+                        BlockRequest::Write(buf) => unsafe {
+                            inner
+                                .virtio_blk
+                                .write_block_nb(block_id + next, &buf[range], resp)
+                        },
+                    };
+                    match token {
+                        Ok(token) => tokens.push(token),
+                        Err(_) => break,
+                    }
+                    next += 1;
+                }
+                return tokens;
+            });
+            if tokens.is_empty() {
+                // 队列被别的线程的请求占满了, 等它们完成
+                if can_sleep() {
+                    suspend_current_and_run_next();
+                } else {
+                    self.inner.exclusive_session(|inner| self.pop_used(inner));
+                }
+                continue;
+            }
+            for (token, resp) in tokens.into_iter().zip(resps[first..].iter()) {
+                if can_sleep() {
+                    self.wait(token, resp);
+                } else {
+                    self.poll(resp);
+                }
+            }
+        }
+        for resp in resps.iter() {
+            assert_eq!(resp.status(), RespStatus::Ok, "Error when accessing VirtIOBlk");
+        }
+    }
+
+    /// Take the completed requests from the device and wake up their waiters.
+    /// 取出之后token就可以分给新的请求了, 所以请求是否完成要看它自己的 [`BlkResp`]
+    fn pop_used(&self, inner: &mut VirtIOBlockInner) {
+        while let Ok(token) = inner.virtio_blk.pop_used() {
+            // 唤醒等待该块设备I/O完成的线程/进程
+            // log!( "\x1b[35m[BLOCK DRIVE: handle_irq] token [{}]  \x1b[0m", token);
+            self.condvars.get(&token).unwrap().signal();
+        }
+    }
+
+    /// Spin until the request of `resp` is completed, 不能休眠的时候用
+    fn poll(&self, resp: &BlkResp) {
+        loop {
+            let completed = self.inner.exclusive_session(|inner| {
+                self.pop_used(inner);
+                return is_completed(resp);
+            });
+            if completed {
+                return;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    /// Sleep until the request `token` whose response is `resp` is completed.
+    /// 被唤醒不代表自己的请求完成了(token可能被复用过), 醒来之后再检查一次
+    fn wait(&self, token: u16, resp: &BlkResp) {
+        loop {
+            let task_cx_ptr = self.inner.exclusive_session(|_| {
+                if is_completed(resp) {
+                    return None;
+                }
+                //将当前线程/进程 加入条件变量的等待队列
+                return Some(self.condvars.get(&token).unwrap().wait_no_sched());
+            });
+            match task_cx_ptr {
+                // 此线程/进程 进入休眠. 直到驱动取出数据 通过条件变量唤醒此线程/进程
+                Some(task_cx_ptr) => schedule(task_cx_ptr),
+                None => return,
+            }
+        }
+    }
+}
+
+/// If the device has written the status of the request, 设备写完状态之后才会把请求放进used ring
+fn is_completed(resp: &BlkResp) -> bool {
+    // 状态是设备用DMA写的, 每次都要从内存里读
+    let resp = unsafe { core::ptr::read_volatile(resp) };
+    return resp.status() != RespStatus::_NotReady;
 }
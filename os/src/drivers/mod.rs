@@ -5,7 +5,7 @@ pub mod plic;
 pub mod gpu;
 pub mod input;
 
-pub use block::BLOCK_DEVICE;
+pub use block::{BLOCK_DEVICE, BLOCK_DEVICE2};
 pub use bus::*;
 pub use chardev::UART;
 pub use gpu::*;
@@ -0,0 +1,204 @@
+//! devfs: 设备驱动注册的设备文件
+//!
+//! 驱动用 [`register_device`] 把设备注册到一个相对于 `/dev` 的路径上, 例如 "input/event0",
+//! 中间的目录由注册的路径得到, 不能创建或者删除文件.
+//! 打开设备文件得到的是驱动提供的 [`File`], 每次打开都是一个新的实例.
+
+use super::vfs::{Dirent, Inode, SuperBlock};
+use super::{File, Stat, DT_BLK, DT_CHR, DT_DIR, S_IFBLK, S_IFCHR, S_IFDIR};
+use crate::sync::UPSafeCell;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::any::Any;
+use lazy_static::*;
+
+/// Kind of a device file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// Character device, 按字节流读写
+    Char,
+    /// Block device, 可以随机读写
+    Block,
+}
+
+#[derive(Clone, Copy)]
+struct Device {
+    kind: DeviceKind,
+    open: fn() -> Arc<dyn File + Send + Sync>,
+}
+
+lazy_static! {
+    /// Registered devices by the path relative to /dev
+    static ref DEVICES: UPSafeCell<BTreeMap<String, Device>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Register a device at `path` relative to /dev, `open` creates the [`File`] each time it is opened.
+/// 同一个路径重复注册会替换掉原来的设备
+pub fn register_device(path: &str, kind: DeviceKind, open: fn() -> Arc<dyn File + Send + Sync>) {
+    DEVICES
+        .exclusive_access()
+        .insert(String::from(path), Device { kind, open });
+}
+
+fn find_device(path: &str) -> Option<Device> {
+    return DEVICES.exclusive_access().get(path).copied();
+}
+
+/// Paths of all the directories and devices, 根目录是""
+fn all_nodes() -> BTreeSet<String> {
+    let mut nodes = BTreeSet::new();
+    nodes.insert(String::new());
+    for path in DEVICES.exclusive_access().keys() {
+        for (i, _) in path.match_indices('/') {
+            nodes.insert(String::from(&path[..i]));
+        }
+        nodes.insert(path.clone());
+    }
+    return nodes;
+}
+
+/// A mounted devfs, 所有挂载看到的都是同一组设备
+pub struct DevSuperBlock;
+
+impl SuperBlock for DevSuperBlock {
+    fn fs_type(&self) -> &str {
+        return "devfs";
+    }
+    fn root_inode(&self) -> Arc<dyn Inode> {
+        return Arc::new(DevInode {
+            path: String::new(),
+        });
+    }
+}
+
+/// Open a devfs for mounting, 没有设备, `source`被忽略
+pub fn open_devfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    return Some(Arc::new(DevSuperBlock));
+}
+
+/// A directory or a device of devfs
+pub struct DevInode {
+    /// 相对于devfs根目录的路径, 根目录是""
+    path: String,
+}
+
+impl DevInode {
+    fn child_path(&self, name: &str) -> String {
+        if self.path.is_empty() {
+            return String::from(name);
+        }
+        return format!("{}/{}", self.path, name);
+    }
+    fn parent_path(&self) -> String {
+        match self.path.rfind('/') {
+            Some(i) => return String::from(&self.path[..i]),
+            None => return String::new(),
+        }
+    }
+    fn ino(path: &str) -> u64 {
+        // 节点的编号就是它在所有路径里的序号, 根目录是1
+        let index = all_nodes()
+            .iter()
+            .position(|node| node == path)
+            .unwrap_or(0);
+        return index as u64 + 1;
+    }
+    fn is_dir_path(path: &str) -> bool {
+        return path.is_empty() || (find_device(path).is_none() && all_nodes().contains(path));
+    }
+    fn d_type(path: &str) -> u8 {
+        match find_device(path) {
+            Some(device) if device.kind == DeviceKind::Block => return DT_BLK,
+            Some(_) => return DT_CHR,
+            None => return DT_DIR,
+        }
+    }
+    /// Entries of this directory including . and .., (name, path)
+    fn entries(&self) -> Vec<(String, String)> {
+        let mut entries = Vec::new();
+        entries.push((String::from("."), self.path.clone()));
+        entries.push((String::from(".."), self.parent_path()));
+        let prefix = self.child_path("");
+        for path in DEVICES.exclusive_access().keys() {
+            if let Some(rest) = path.strip_prefix(prefix.as_str()) {
+                // 只取第一层, 更深的设备变成一个子目录
+                let name = rest.split('/').next().unwrap();
+                if entries.last().map_or(true, |(last, _)| last != name) {
+                    entries.push((String::from(name), self.child_path(name)));
+                }
+            }
+        }
+        return entries;
+    }
+}
+
+impl Inode for DevInode {
+    fn stat(&self) -> Stat {
+        let (mode, nlink) = match find_device(&self.path) {
+            Some(device) => {
+                let file_type = match device.kind {
+                    DeviceKind::Char => S_IFCHR,
+                    DeviceKind::Block => S_IFBLK,
+                };
+                // 权限位按驱动支持的读写方式给出
+                let file = (device.open)();
+                let read = if file.readable() { 0o444 } else { 0 };
+                let write = if file.writable() { 0o222 } else { 0 };
+                (file_type | read | write, 1)
+            }
+            None => (S_IFDIR | 0o555, 2),
+        };
+        return Stat {
+            ino: Self::ino(&self.path),
+            mode,
+            nlink,
+            ..Default::default()
+        };
+    }
+    fn is_dir(&self) -> bool {
+        return Self::is_dir_path(&self.path);
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        if !self.is_dir() {
+            return None;
+        }
+        let path = match name {
+            "." => self.path.clone(),
+            ".." => self.parent_path(),
+            _ => self.child_path(name),
+        };
+        if !all_nodes().contains(&path) {
+            return None;
+        }
+        return Some(Arc::new(DevInode { path }));
+    }
+    fn read_dirent(&self, pos: usize) -> Option<(Dirent, usize)> {
+        if !self.is_dir() {
+            return None;
+        }
+        // 目录里的位置就是第几个目录项
+        let (name, path) = self.entries().into_iter().nth(pos)?;
+        let dirent = Dirent {
+            ino: Self::ino(&path),
+            d_type: Self::d_type(&path),
+            name,
+        };
+        return Some((dirent, pos + 1));
+    }
+    fn path(&self) -> Option<String> {
+        if !self.is_dir() {
+            return None;
+        }
+        return Some(format!("/{}", self.path));
+    }
+    fn open_device(&self) -> Option<Arc<dyn File + Send + Sync>> {
+        return find_device(&self.path).map(|device| (device.open)());
+    }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
@@ -0,0 +1,254 @@
+//! Device files of the drivers, 由 [`register_devices`] 注册到devfs
+
+use super::devfs::{register_device, DeviceKind};
+use super::{File, Tty, SEEK_CUR, SEEK_END, SEEK_SET};
+use crate::drivers::{
+    InputDevice, BLOCK_DEVICE, BLOCK_DEVICE2, GPU_DEVICE, KEYBOARD_DEVICE, MOUSE_DEVICE,
+};
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::{block_cache_sync_all, BlockDevice, BLOCK_SZ};
+
+/// Register the devices of all the drivers, 在挂载devfs之前调用
+pub fn register_devices() {
+    register_device("null", DeviceKind::Char, || Arc::new(Null));
+    register_device("zero", DeviceKind::Char, || Arc::new(Zero));
+    register_device("tty", DeviceKind::Char, || Arc::new(Tty));
+    register_device("fb0", DeviceKind::Char, || Arc::new(FrameBuffer::new()));
+    register_device("input/event0", DeviceKind::Char, || {
+        Arc::new(InputEvents(KEYBOARD_DEVICE.clone()))
+    });
+    register_device("input/event1", DeviceKind::Char, || {
+        Arc::new(InputEvents(MOUSE_DEVICE.clone()))
+    });
+    register_device("vda", DeviceKind::Block, || {
+        Arc::new(Disk::new(BLOCK_DEVICE.clone(), BLOCK_DEVICE.capacity()))
+    });
+    // 第二个磁盘是可选的
+    if BLOCK_DEVICE2.is_some() {
+        register_device("vdb", DeviceKind::Block, || {
+            let device = BLOCK_DEVICE2.clone().unwrap();
+            let size = device.capacity();
+            Arc::new(Disk::new(device, size))
+        });
+    }
+}
+
+/// Copy `src` into `buf`, return the size copied
+fn copy_to_user(src: &[u8], buf: UserBuffer) -> usize {
+    let mut copied = 0;
+    for slice in buf.buffers {
+        let len = slice.len().min(src.len() - copied);
+        slice[..len].copy_from_slice(&src[copied..copied + len]);
+        copied += len;
+    }
+    return copied;
+}
+
+/// Copy `buf` into `dst`, return the size copied
+fn copy_from_user(buf: UserBuffer, dst: &mut [u8]) -> usize {
+    let mut copied = 0;
+    for slice in buf.buffers {
+        let len = slice.len().min(dst.len() - copied);
+        dst[copied..copied + len].copy_from_slice(&slice[..len]);
+        copied += len;
+    }
+    return copied;
+}
+
+/// lseek on a device of `size` bytes, 可以移动到设备末尾之后, 之后读写的长度是0
+fn seek_offset(offset: &mut usize, delta: isize, whence: usize, size: usize) -> isize {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => *offset as isize,
+        SEEK_END => size as isize,
+        _ => return -1,
+    };
+    match base.checked_add(delta) {
+        Some(new_offset) if new_offset >= 0 => {
+            *offset = new_offset as usize;
+            return new_offset;
+        }
+        _ => return -1,
+    }
+}
+
+/// /dev/null: 读总是到文件末尾, 写入的数据都丢掉
+pub struct Null;
+
+impl File for Null {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return true;
+    }
+    fn read(&self, _buf: UserBuffer) -> usize {
+        return 0;
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        return buf.len();
+    }
+}
+
+/// /dev/zero: 读出来全是0, 写入的数据都丢掉
+pub struct Zero;
+
+impl File for Zero {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return true;
+    }
+    fn read(&self, mut buf: UserBuffer) -> usize {
+        for slice in buf.buffers.iter_mut() {
+            slice.fill(0);
+        }
+        return buf.len();
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        return buf.len();
+    }
+}
+
+/// /dev/fb0: 显存, 可以读写也可以mmap
+pub struct FrameBuffer {
+    offset: UPSafeCell<usize>,
+}
+
+impl FrameBuffer {
+    pub fn new() -> Self {
+        return Self {
+            offset: unsafe { UPSafeCell::new(0) },
+        };
+    }
+}
+
+impl File for FrameBuffer {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return true;
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        let fb = GPU_DEVICE.get_framebuffer();
+        let mut offset = self.offset.exclusive_access();
+        let start = (*offset).min(fb.len());
+        let read_size = copy_to_user(&fb[start..], buf);
+        *offset += read_size;
+        return read_size;
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
+        let fb = GPU_DEVICE.get_framebuffer();
+        let mut offset = self.offset.exclusive_access();
+        let start = (*offset).min(fb.len());
+        let write_size = copy_from_user(buf, &mut fb[start..]);
+        *offset += write_size;
+        // 写完马上刷新到屏幕上
+        GPU_DEVICE.flush();
+        return write_size;
+    }
+    fn seek(&self, offset: isize, whence: usize) -> isize {
+        let size = GPU_DEVICE.get_framebuffer().len();
+        return seek_offset(&mut self.offset.exclusive_access(), offset, whence, size);
+    }
+    fn device_memory(&self) -> Option<(usize, usize)> {
+        let fb = GPU_DEVICE.get_framebuffer();
+        return Some((fb.as_ptr() as usize, fb.len()));
+    }
+}
+
+/// Size of an input event read from /dev/input/event*
+const INPUT_EVENT_SZ: usize = 8;
+
+/// /dev/input/event*: 输入设备的事件, 每个事件是一个u64, 编码和sys_event_get一样
+pub struct InputEvents(Arc<dyn InputDevice>);
+
+impl File for InputEvents {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return false;
+    }
+    /// 没有事件时等待第一个事件, 之后只读已经到达的事件. 缓冲区放不下一个事件时返回0
+    fn read(&self, buf: UserBuffer) -> usize {
+        let capacity = buf.len() / INPUT_EVENT_SZ;
+        if capacity == 0 {
+            return 0;
+        }
+        let mut events: Vec<u8> = Vec::new();
+        events.extend_from_slice(&self.0.read_event().to_le_bytes());
+        while events.len() / INPUT_EVENT_SZ < capacity && !self.0.is_empty() {
+            events.extend_from_slice(&self.0.read_event().to_le_bytes());
+        }
+        return copy_to_user(&events, buf);
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        return 0;
+    }
+}
+
+/// /dev/vda, /dev/vdb: 整个块设备, 只读.
+/// 上面可能挂载着文件系统, 直接写设备会和块缓存不一致, 所以不支持写
+pub struct Disk {
+    device: Arc<dyn BlockDevice>,
+    size: usize,
+    offset: UPSafeCell<usize>,
+}
+
+impl Disk {
+    pub fn new(device: Arc<dyn BlockDevice>, size: usize) -> Self {
+        return Self {
+            device,
+            size,
+            offset: unsafe { UPSafeCell::new(0) },
+        };
+    }
+}
+
+impl File for Disk {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return false;
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        // 块缓存里改过的块先写回, 读到的才是最新的内容
+        block_cache_sync_all();
+        let mut offset = self.offset.exclusive_access();
+        let mut block = [0u8; BLOCK_SZ];
+        let mut read_size = 0;
+        for slice in buf.buffers {
+            let mut done = 0;
+            while done < slice.len() && *offset < self.size {
+                let start = *offset % BLOCK_SZ;
+                let len = (BLOCK_SZ - start)
+                    .min(slice.len() - done)
+                    .min(self.size - *offset);
+                self.device.read_block(*offset / BLOCK_SZ, &mut block);
+                slice[done..done + len].copy_from_slice(&block[start..start + len]);
+                done += len;
+                *offset += len;
+            }
+            read_size += done;
+        }
+        return read_size;
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        return 0;
+    }
+    fn seek(&self, offset: isize, whence: usize) -> isize {
+        return seek_offset(
+            &mut self.offset.exclusive_access(),
+            offset,
+            whence,
+            self.size,
+        );
+    }
+}
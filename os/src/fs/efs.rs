@@ -0,0 +1,193 @@
+//! easy-fs behind the VFS traits
+
+use super::vfs::{Dirent, Inode, InodeType, SuperBlock};
+use super::{Stat, DT_DIR, DT_LNK, DT_REG, DT_UNKNOWN, S_IFDIR, S_IFLNK, S_IFREG};
+use crate::config::BLOCK_CACHE_SIZE;
+use crate::drivers::BLOCK_DEVICE;
+use crate::timer::get_time_ms;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use easy_fs::{EasyFileSystem, BLOCK_SZ, FT_DIR, FT_REG_FILE, FT_SYMLINK};
+use lazy_static::*;
+
+/// Name of the block device the root filesystem is on
+pub const ROOT_DEVICE: &str = "/dev/vda";
+
+/// A mounted easy-fs
+pub struct EfsSuperBlock {
+    root: Arc<easy_fs::Inode>,
+    /// 超级块里记录的总块数
+    total_blocks: usize,
+}
+
+lazy_static! {
+    /// The easy-fs on the block device
+    pub static ref ROOT_FS: Arc<EfsSuperBlock> = {
+        // inode的时间戳用开机后的毫秒数
+        easy_fs::set_clock(|| get_time_ms() as u64);
+        easy_fs::set_block_cache_size(BLOCK_CACHE_SIZE);
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        let total_blocks = efs.lock().total_blocks() as usize;
+        return Arc::new(EfsSuperBlock {
+            root: Arc::new(EasyFileSystem::root_inode(&efs)),
+            total_blocks,
+        });
+    };
+}
+
+/// Open the easy-fs on the device `source`.
+/// 根设备上只有一个easy-fs, 再次挂载它得到的是同一个文件系统
+pub fn open_efs(source: &str) -> Option<Arc<dyn SuperBlock>> {
+    if source != ROOT_DEVICE {
+        return None;
+    }
+    return Some(ROOT_FS.clone());
+}
+
+impl SuperBlock for EfsSuperBlock {
+    fn fs_type(&self) -> &str {
+        return "easyfs";
+    }
+    fn root_inode(&self) -> Arc<dyn Inode> {
+        return Arc::new(EfsInode(Arc::clone(&self.root)));
+    }
+    fn sync(&self) {
+        easy_fs::block_cache_sync_all();
+    }
+}
+
+/// Number of blocks of the easy-fs on the root device, 交换区从这里开始
+pub fn root_fs_blocks() -> usize {
+    return ROOT_FS.total_blocks;
+}
+
+/// Print the statistics of the block cache
+pub fn print_block_cache_stats() {
+    let stats = easy_fs::block_cache_stats();
+    println!(
+        "[kernel] block cache: {}/{} blocks, {} hits, {} misses, {} evictions, {} writebacks",
+        stats.cached, stats.capacity, stats.hits, stats.misses, stats.evictions, stats.writebacks
+    );
+}
+
+/// An inode of easy-fs
+pub struct EfsInode(Arc<easy_fs::Inode>);
+
+impl EfsInode {
+    fn wrap(inode: Arc<easy_fs::Inode>) -> Arc<dyn Inode> {
+        return Arc::new(Self(inode));
+    }
+    /// The easy-fs inode of `other`, None if it is on another filesystem
+    fn downcast(other: &Arc<dyn Inode>) -> Option<&easy_fs::Inode> {
+        return other
+            .as_any()
+            .downcast_ref::<EfsInode>()
+            .map(|inode| inode.0.as_ref());
+    }
+}
+
+impl Inode for EfsInode {
+    fn stat(&self) -> Stat {
+        let stat = self.0.stat();
+        let file_type = if stat.is_dir {
+            S_IFDIR
+        } else if stat.is_symlink {
+            S_IFLNK
+        } else {
+            S_IFREG
+        };
+        let ms_to_time = |ms: u64| ((ms / 1000) as i64, ((ms % 1000) * 1_000_000) as i64);
+        let (atime_sec, atime_nsec) = ms_to_time(stat.atime);
+        let (mtime_sec, mtime_nsec) = ms_to_time(stat.mtime);
+        let (ctime_sec, ctime_nsec) = ms_to_time(stat.ctime);
+        return Stat {
+            ino: stat.ino as u64,
+            mode: file_type | stat.mode as u32,
+            nlink: stat.nlink as u32,
+            size: stat.size as i64,
+            blksize: BLOCK_SZ as u32,
+            blocks: stat.blocks as u64,
+            atime_sec,
+            atime_nsec,
+            mtime_sec,
+            mtime_nsec,
+            ctime_sec,
+            ctime_nsec,
+            ..Default::default()
+        };
+    }
+    fn is_dir(&self) -> bool {
+        return self.0.is_dir();
+    }
+    fn is_symlink(&self) -> bool {
+        return self.0.is_symlink();
+    }
+    fn size(&self) -> usize {
+        return self.0.get_inode_size() as usize;
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        return self.0.read_at(offset, buf);
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        return self.0.write_at(offset, buf);
+    }
+    fn truncate(&self, size: usize) -> bool {
+        return self.0.truncate(size as u64);
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        return self.0.find_nofollow(name).map(Self::wrap);
+    }
+    fn create(&self, name: &str, type_: InodeType) -> Option<Arc<dyn Inode>> {
+        let inode = match type_ {
+            InodeType::File => self.0.create(name),
+            InodeType::Dir => self.0.create_dir(name),
+        };
+        return inode.map(Self::wrap);
+    }
+    fn symlink(&self, name: &str, target: &str) -> bool {
+        return self.0.symlink(name, target).is_some();
+    }
+    fn read_link(&self) -> Option<String> {
+        return self.0.read_link();
+    }
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> bool {
+        match Self::downcast(target) {
+            Some(target) => self.0.link(name, target),
+            None => false,
+        }
+    }
+    fn unlink(&self, name: &str) -> bool {
+        return self.0.unlink(name);
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        return self.0.rmdir(name);
+    }
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> bool {
+        match Self::downcast(new_dir) {
+            Some(new_dir) => self.0.rename(old_name, new_dir, new_name),
+            None => false,
+        }
+    }
+    fn read_dirent(&self, pos: usize) -> Option<(Dirent, usize)> {
+        let (dirent, next) = self.0.read_dirent(pos)?;
+        let d_type = match dirent.file_type() {
+            FT_DIR => DT_DIR,
+            FT_REG_FILE => DT_REG,
+            FT_SYMLINK => DT_LNK,
+            _ => DT_UNKNOWN,
+        };
+        let dirent = Dirent {
+            ino: dirent.inode_number() as u64,
+            d_type,
+            name: String::from(dirent.name()),
+        };
+        return Some((dirent, next));
+    }
+    fn path(&self) -> Option<String> {
+        return self.0.path();
+    }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
@@ -1,7 +1,7 @@
 use crate::mm::UserBuffer;
 use crate::sync::{Mutex, Semaphore, UPSafeCell};
 use crate::task::{block_current_and_run_next, current_task, wakeup_task, TaskControlBlock};
-use alloc::{collections::VecDeque, sync::Arc};
+use alloc::{collections::VecDeque, string::String, sync::Arc};
 use bitflags::*;
 
 use super::File;
@@ -147,4 +147,8 @@ impl File for Eventfd {
 
         return U32_BYTE_SIZE; // 4 bytes & unlock I
     }
+
+    fn fd_path(&self) -> String {
+        String::from("anon_inode:[eventfd]")
+    }
 }
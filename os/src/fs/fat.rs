@@ -0,0 +1,144 @@
+//! FAT32 behind the VFS traits, 挂载第二个磁盘上用mkfs.vfat做的镜像
+
+use super::vfs::{Dirent, Inode, InodeType, SuperBlock};
+use super::{Stat, DT_DIR, DT_REG, S_IFDIR, S_IFREG};
+use crate::drivers::BLOCK_DEVICE2;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use easy_fs::{BlockDevice, FatFileSystem, BLOCK_SZ};
+use lazy_static::*;
+
+/// Name of the block device FAT32 can be mounted from
+pub const FAT_DEVICE: &str = "/dev/vdb";
+
+/// A mounted FAT32
+pub struct FatSuperBlock {
+    root: Arc<easy_fs::FatInode>,
+}
+
+lazy_static! {
+    /// The FAT32 on the second disk, 没有这个磁盘或者上面不是FAT32时是None
+    static ref FAT_FS: Option<Arc<FatSuperBlock>> = {
+        let block_device: Arc<dyn BlockDevice> = BLOCK_DEVICE2.as_ref()?.clone();
+        let fs = FatFileSystem::open(block_device)?;
+        Some(Arc::new(FatSuperBlock {
+            root: Arc::new(FatFileSystem::root_inode(&fs)),
+        }))
+    };
+}
+
+/// Open the FAT32 on the device `source`.
+/// 和easy-fs一样, 再次挂载同一个设备得到的是同一个文件系统
+pub fn open_fat(source: &str) -> Option<Arc<dyn SuperBlock>> {
+    if source != FAT_DEVICE {
+        return None;
+    }
+    return FAT_FS.clone().map(|sb| sb as Arc<dyn SuperBlock>);
+}
+
+impl SuperBlock for FatSuperBlock {
+    fn fs_type(&self) -> &str {
+        return "vfat";
+    }
+    fn root_inode(&self) -> Arc<dyn Inode> {
+        return Arc::new(FatInode(Arc::clone(&self.root)));
+    }
+    fn sync(&self) {
+        easy_fs::block_cache_sync_all();
+    }
+}
+
+/// An inode of FAT32
+pub struct FatInode(Arc<easy_fs::FatInode>);
+
+impl FatInode {
+    fn wrap(inode: Arc<easy_fs::FatInode>) -> Arc<dyn Inode> {
+        return Arc::new(Self(inode));
+    }
+    /// The FAT inode of `other`, None if it is on another filesystem
+    fn downcast(other: &Arc<dyn Inode>) -> Option<&easy_fs::FatInode> {
+        return other
+            .as_any()
+            .downcast_ref::<FatInode>()
+            .map(|inode| inode.0.as_ref());
+    }
+}
+
+impl Inode for FatInode {
+    fn stat(&self) -> Stat {
+        let stat = self.0.stat();
+        let file_type = if stat.is_dir { S_IFDIR } else { S_IFREG };
+        let ms_to_time = |ms: u64| ((ms / 1000) as i64, ((ms % 1000) * 1_000_000) as i64);
+        let (atime_sec, atime_nsec) = ms_to_time(stat.atime);
+        let (mtime_sec, mtime_nsec) = ms_to_time(stat.mtime);
+        let (ctime_sec, ctime_nsec) = ms_to_time(stat.ctime);
+        return Stat {
+            ino: stat.ino as u64,
+            mode: file_type | stat.mode as u32,
+            nlink: stat.nlink as u32,
+            size: stat.size as i64,
+            blksize: BLOCK_SZ as u32,
+            blocks: stat.blocks as u64,
+            atime_sec,
+            atime_nsec,
+            mtime_sec,
+            mtime_nsec,
+            ctime_sec,
+            ctime_nsec,
+            ..Default::default()
+        };
+    }
+    fn is_dir(&self) -> bool {
+        return self.0.is_dir();
+    }
+    fn size(&self) -> usize {
+        return self.0.size() as usize;
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        return self.0.read_at(offset, buf);
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        return self.0.write_at(offset, buf);
+    }
+    fn truncate(&self, size: usize) -> bool {
+        return self.0.truncate(size as u64);
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        return self.0.lookup(name).map(Self::wrap);
+    }
+    fn create(&self, name: &str, type_: InodeType) -> Option<Arc<dyn Inode>> {
+        let inode = match type_ {
+            InodeType::File => self.0.create(name),
+            InodeType::Dir => self.0.create_dir(name),
+        };
+        return inode.map(Self::wrap);
+    }
+    fn unlink(&self, name: &str) -> bool {
+        return self.0.unlink(name);
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        return self.0.rmdir(name);
+    }
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> bool {
+        match Self::downcast(new_dir) {
+            Some(new_dir) => self.0.rename(old_name, new_dir, new_name),
+            None => false,
+        }
+    }
+    fn read_dirent(&self, pos: usize) -> Option<(Dirent, usize)> {
+        let (dirent, next) = self.0.read_dirent(pos)?;
+        let dirent = Dirent {
+            ino: dirent.ino as u64,
+            d_type: if dirent.is_dir { DT_DIR } else { DT_REG },
+            name: dirent.name,
+        };
+        return Some((dirent, next));
+    }
+    fn path(&self) -> Option<String> {
+        return self.0.path();
+    }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
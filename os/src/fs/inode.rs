@@ -1,79 +1,65 @@
-use crate::drivers::BLOCK_DEVICE;
-use crate::mm::UserBuffer;
+use crate::mm::{BackingFile, UserBuffer};
 use crate::sync::UPSafeCell;
-use alloc::{sync::Arc, borrow::ToOwned};
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::*;
 
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
-
-use super::File;
-
+use super::mount::{self, Dentry};
+use super::vfs::{Inode, InodeType};
+use super::{File, Stat, SEEK_CUR, SEEK_END, SEEK_SET};
 
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    append: bool, //O_APPEND, 每次写都写在文件末尾
+    /// 打开的文件让它所在的文件系统不能卸载
+    dentry: Dentry,
+    path: String, //打开时的绝对路径, 显示在/proc/<pid>/fd里
     inner: UPSafeCell<OSInodeInner>,
 }
 
 /// The OS inode inner in 'UPSafeCell'
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
 }
 
 impl OSInode {
     /// Construct an OS inode from a inode
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    pub fn new(readable: bool, writable: bool, append: bool, dentry: Dentry, path: String) -> Self {
         Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+            append,
+            dentry,
+            path,
+            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0 }) },
         }
     }
-    /// Read all data inside a inode into vector
-    pub fn read_all(&self) -> Vec<u8> {
-        let mut inner = self.inner.exclusive_access();
-        let mut buffer = [0u8; 512];
-        let mut v: Vec<u8> = Vec::new();
-        loop {
-            let len = inner.inode.read_at(inner.offset, &mut buffer);
-            if len == 0 {
-                break;
-            }
-            inner.offset += len;
-            v.extend_from_slice(&buffer[..len]);
-        }
-        return v;
+    fn inode(&self) -> &Arc<dyn Inode> {
+        return self.dentry.inode();
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        return Arc::new(EasyFileSystem::root_inode(&efs));
-    };
-}
-
-/// List all files in the file systems, sort by file name.
-pub fn list_files(path: &str) {
+/// List all files in the directory `dir`, sort by file name.
+pub fn list_files(dir: &Dentry) {
     println!("/**** Files ****");
-    let mut file_list=ROOT_INODE.find(path).unwrap().ls();
+    let dir = dir.inode();
+    let mut file_list: Vec<String> = Vec::new();
+    let mut pos = 0;
+    while let Some((dirent, next)) = dir.read_dirent(pos) {
+        // . 和 .. 不算目录里的文件
+        if dirent.name != "." && dirent.name != ".." {
+            file_list.push(dirent.name);
+        }
+        pos = next;
+    }
     file_list.sort();
-    for file_name in file_list{
-        let mut path_file=path.to_owned();
-        if path.ends_with('/'){
-            path_file.push_str(file_name.as_str());
-        }else{
-            path_file.push_str("/");
-            path_file.push_str(file_name.as_str());
-        }
-       
-        let file = ROOT_INODE.find(path_file.as_str()).unwrap();
-        println!("{}    [{} Bytes]", file_name, file.get_inode_size());
+    for file_name in file_list {
+        let file = dir.lookup(file_name.as_str()).unwrap();
+        println!("{}    [{} Bytes]", file_name, file.size());
     }
     println!("**************/");
 }
@@ -91,47 +77,272 @@ bitflags! {
         const CREATE =1<<9;
         // Clear file and return an empty one
         const TRUNC = 1<<10;
+        // Every write appends to the end of file
+        const APPEND = 1<<11;
+        // Fail if not a directory, 打开的目录可以用getdents64读目录项
+        const DIRECTORY = 1<<16;
+        // Fail if the last name of the path is a symlink
+        const NOFOLLOW = 1<<17;
     }
 }
 
 impl OpenFlags {
     /// Do not check validity for simplicity 简单起见, 假定标志位格式必须合法
     /// Return (readable, writable) 返回结构体 括号里面2个bool值, 标识是否可读, 是否可写
+    /// 只看访问模式的两位, DIRECTORY/NOFOLLOW 这些标志不影响读写
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
-            return (true, false);
-        } else if self.contains(Self::WRONLY) {
+        if self.contains(Self::WRONLY) {
             return (false, true);
-        } else {
+        } else if self.contains(Self::RDWR) {
             return (true, true);
+        } else {
+            return (true, false);
         }
     }
 }
-/// Open file with flags
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+/// Find the inode of `path`, 绝对路径从根目录开始找, 相对路径从当前目录`cwd`开始找.
+/// 路径里的符号链接都会被跟随
+pub fn find_inode(cwd: &Dentry, path: &str) -> Option<Dentry> {
+    return mount::lookup(cwd, path, true);
+}
+
+/// Open file at `path` with flags
+pub fn open_file(cwd: &Dentry, path: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
     let (readable, writable) = flags.read_write();
+    let append = flags.contains(OpenFlags::APPEND);
+    let open = |dentry: Dentry| {
+        let abs_path = opened_path(cwd, path, &dentry);
+        Arc::new(OSInode::new(readable, writable, append, dentry, abs_path))
+    };
+    // 不管是不是带着CREATE打开的, TRUNC只截断可写打开的文件. 目录不能截断
+    let truncate = |dentry: Dentry| {
+        if writable && flags.contains(OpenFlags::TRUNC) {
+            if dentry.inode().is_dir() {
+                return None;
+            }
+            dentry.inode().truncate(0);
+        }
+        return Some(dentry);
+    };
+    if flags.contains(OpenFlags::NOFOLLOW)
+        && mount::lookup(cwd, path, false).map_or(false, |dentry| dentry.inode().is_symlink())
+    {
+        return None;
+    }
     if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            //已经存在此文件名, 对应inode直接清空
-            // clear size
-            inode.clear();
-            return Some(Arc::new(OSInode::new(readable, writable, inode)));
+        if let Some(dentry) = find_inode(cwd, path) {
+            //已经存在此文件名, 带着TRUNC时清空
+            return truncate(dentry).map(open);
         } else {
             // Create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
+            let (parent, name) = find_parent(cwd, path)?;
+            parent.create(name, InodeType::File).map(open)
+        }
+    } else if flags.contains(OpenFlags::DIRECTORY) {
+        // 目录只能只读打开
+        if writable || flags.contains(OpenFlags::TRUNC) {
+            return None;
+        }
+        let dentry = find_inode(cwd, path)?;
+        if !dentry.inode().is_dir() {
+            return None;
         }
+        return Some(open(dentry));
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
+        find_inode(cwd, path).and_then(truncate).map(open)
+    }
+}
+
+/// Open `path` with flags, 设备文件打开的是驱动提供的 [`File`], 其他文件同 [`open_file`]
+pub fn open_path(
+    cwd: &Dentry,
+    path: &str,
+    flags: OpenFlags,
+) -> Option<Arc<dyn File + Send + Sync>> {
+    let (readable, writable) = flags.read_write();
+    // NOFOLLOW时找到的是符号链接本身, 不是设备, 由open_file报错
+    let follow = !flags.contains(OpenFlags::NOFOLLOW);
+    let found = mount::lookup(cwd, path, follow)
+        .and_then(|dentry| Some((dentry.inode().open_device()?, dentry)));
+    let (device, dentry) = match found {
+        Some(found) => found,
+        None => return open_file(cwd, path, flags).map(|file| file as Arc<dyn File + Send + Sync>),
+    };
+    // 设备没有内容可以截断, 和Linux一样忽略CREATE和TRUNC
+    if flags.contains(OpenFlags::DIRECTORY)
+        || (readable && !device.readable())
+        || (writable && !device.writable())
+    {
+        return None;
+    }
+    let path = opened_path(cwd, path, &dentry);
+    return Some(Arc::new(DeviceFile {
+        readable,
+        writable,
+        dentry,
+        path,
+        device,
+    }));
+}
+
+/// Absolute path of the file opened at `path`.
+/// 普通文件从inode找不回名字, 用父目录的路径加上最后一个名字
+fn opened_path(cwd: &Dentry, path: &str, dentry: &Dentry) -> String {
+    if let Some(dir_path) = dentry.path() {
+        return dir_path;
+    }
+    if let Some((parent, name)) = find_parent(cwd, path) {
+        if let Some(mut parent_path) = parent.path() {
+            if !parent_path.ends_with('/') {
+                parent_path.push('/');
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+            parent_path.push_str(name);
+            return parent_path;
+        }
+    }
+    return String::from(path);
+}
+
+/// Split a path into its parent directory and the last name
+fn find_parent<'a>(cwd: &Dentry, path: &'a str) -> Option<(Dentry, &'a str)> {
+    let trimmed = path.trim_end_matches('/');
+    let (parent, name) = match trimmed.rfind('/') {
+        Some(pos) => (&trimmed[..=pos], &trimmed[pos + 1..]),
+        None => (".", trimmed),
+    };
+    if name.is_empty() {
+        return None;
+    }
+    return find_inode(cwd, parent).map(|parent| (parent, name));
+}
+
+/// Create the directory `path`
+pub fn make_dir(cwd: &Dentry, path: &str) -> bool {
+    match find_parent(cwd, path) {
+        Some((parent, name)) => parent.create(name, InodeType::Dir).is_some(),
+        None => false,
+    }
+}
+
+/// Whether `path` is a mount point or a directory containing mount points
+fn is_busy(cwd: &Dentry, path: &str) -> bool {
+    return mount::lookup(cwd, path, false).map_or(false, |dentry| dentry.is_busy());
+}
+
+/// Remove a regular file, or an empty directory if `is_dir`. 挂载点不能删除
+pub fn unlink_file(cwd: &Dentry, path: &str, is_dir: bool) -> bool {
+    if is_busy(cwd, path) {
+        return false;
+    }
+    match find_parent(cwd, path) {
+        Some((parent, name)) if is_dir => parent.inode().rmdir(name),
+        Some((parent, name)) => parent.inode().unlink(name),
+        None => false,
+    }
+}
+
+/// Set the size of the regular file at `path` to `size`, 路径里的符号链接都会被跟随
+pub fn truncate_file(cwd: &Dentry, path: &str, size: usize) -> bool {
+    match find_inode(cwd, path) {
+        Some(dentry) => dentry.inode().truncate(size),
+        None => false,
     }
 }
 
+/// Metadata of the file at `path`, 最后一个名字是符号链接时, `follow`为false就返回链接本身的
+pub fn stat_file(cwd: &Dentry, path: &str, follow: bool) -> Option<Stat> {
+    return mount::lookup(cwd, path, follow).map(|dentry| dentry.stat());
+}
+
+/// Create a hard link `new_path` to the regular file `old_path`, 两者要在同一个挂载里
+pub fn link_file(cwd: &Dentry, old_path: &str, new_path: &str) -> bool {
+    let target = match find_inode(cwd, old_path) {
+        Some(dentry) => dentry,
+        None => return false,
+    };
+    match find_parent(cwd, new_path) {
+        Some((parent, name)) if parent.same_mount(&target) => {
+            parent.inode().link(name, target.inode())
+        }
+        _ => false,
+    }
+}
+
+/// Create a symlink `link_path` to `target`
+pub fn symlink_file(cwd: &Dentry, target: &str, link_path: &str) -> bool {
+    match find_parent(cwd, link_path) {
+        Some((parent, name)) => parent.inode().symlink(name, target),
+        None => false,
+    }
+}
+
+/// Read the target of the symlink at `path`
+pub fn read_link(cwd: &Dentry, path: &str) -> Option<String> {
+    return mount::lookup(cwd, path, false)?.inode().read_link();
+}
+
+/// Move `old_path` to `new_path`, 两者要在同一个挂载里, 挂载点不能移动也不能被覆盖
+pub fn rename_file(cwd: &Dentry, old_path: &str, new_path: &str) -> bool {
+    if is_busy(cwd, old_path) || is_busy(cwd, new_path) {
+        return false;
+    }
+    match (find_parent(cwd, old_path), find_parent(cwd, new_path)) {
+        (Some((old_parent, old_name)), Some((new_parent, new_name)))
+            if old_parent.same_mount(&new_parent) =>
+        {
+            old_parent
+                .inode()
+                .rename(old_name, new_parent.inode(), new_name)
+        }
+        _ => false,
+    }
+}
+
+impl BackingFile for OSInode {
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        // 不持有inner去读磁盘, 读的时候可能休眠, 同一个文件可能被多个进程按需加载
+        return self.inode().read_at(offset, buf);
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        return self.inode().write_at(offset, buf);
+    }
+    fn size(&self) -> usize {
+        return self.inode().size();
+    }
+}
+
+/// Size of the fixed part of `linux_dirent64` before d_name
+const DIRENT64_HEADER_SZ: usize = 19;
+
+/// Read from `offset` of the inode into `buf`, return the size read
+fn read_inode_at(inode: &Arc<dyn Inode>, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, *slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    return total_read_size;
+}
+
+/// Write `buf` to `offset` of the inode, return the size written.
+/// 文件达到最大长度或者文件系统不支持写(procfs)时写不完
+fn write_inode_at(inode: &Arc<dyn Inode>, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = inode.write_at(offset, *slice);
+        offset += write_size;
+        total_write_size += write_size;
+        if write_size < slice.len() {
+            break;
+        }
+    }
+    return total_write_size;
+}
+
 impl File for OSInode {
     fn readable(&self) -> bool {
         return self.readable;
@@ -140,28 +351,128 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         return self.writable;
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut inner = self.inner.exclusive_access(); //加锁后拿
+        let read_size = read_inode_at(self.inode(), inner.offset, buf);
+        inner.offset += read_size;
+        return read_size;
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access(); //加锁后拿
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
+        if self.append {
+            inner.offset = self.inode().size();
+        }
+        let write_size = write_inode_at(self.inode(), inner.offset, buf);
+        inner.offset += write_size;
+        return write_size;
+    }
+    fn seek(&self, offset: isize, whence: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        let base = match whence {
+            SEEK_SET => 0,
+            SEEK_CUR => inner.offset as isize,
+            SEEK_END => self.inode().size() as isize,
+            _ => return -1,
+        };
+        // 可以移动到文件末尾之后, 之后写入时中间的空洞读出来是0
+        match base.checked_add(offset) {
+            Some(new_offset) if new_offset >= 0 => {
+                inner.offset = new_offset as usize;
+                return new_offset;
+            }
+            _ => return -1,
+        }
+    }
+    fn getdents(&self, buf: UserBuffer) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        if !self.inode().is_dir() {
+            return None;
+        }
+        // 目录的offset是下一个要读的目录项在目录里的位置
+        let mut records: Vec<u8> = Vec::new();
+        while let Some((dirent, next)) = self.inode().read_dirent(inner.offset) {
+            let name = dirent.name.as_bytes();
+            // d_ino(8) d_off(8) d_reclen(2) d_type(1) d_name(以'\0'结尾), 整条记录8字节对齐
+            let reclen = (DIRENT64_HEADER_SZ + name.len() + 1 + 7) / 8 * 8;
+            if records.len() + reclen > buf.len() {
                 break;
             }
-            inner.offset += read_size;
-            total_read_size += read_size;
+            records.extend_from_slice(&dirent.ino.to_le_bytes());
+            records.extend_from_slice(&(next as i64).to_le_bytes());
+            records.extend_from_slice(&(reclen as u16).to_le_bytes());
+            records.push(dirent.d_type);
+            records.extend_from_slice(name);
+            records.resize(records.len() + reclen - DIRENT64_HEADER_SZ - name.len(), 0);
+            inner.offset = next;
+        }
+        if records.is_empty() && self.inode().read_dirent(inner.offset).is_some() {
+            return None; //缓冲区连一条目录项都放不下
+        }
+        for (byte, dst) in records.iter().zip(buf.into_iter()) {
+            unsafe {
+                *dst = *byte;
+            }
         }
-        return total_read_size;
+        return Some(records.len());
+    }
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        Some(read_inode_at(self.inode(), offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        Some(write_inode_at(self.inode(), offset, buf))
+    }
+    fn backing_file(self: Arc<Self>) -> Option<Arc<dyn BackingFile>> {
+        Some(self)
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.dentry.stat())
+    }
+    fn truncate(&self, size: usize) -> bool {
+        return self.writable && self.inode().truncate(size);
+    }
+    fn fd_path(&self) -> String {
+        self.path.clone()
+    }
+}
+
+/// An opened device file, 读写都交给驱动的 [`File`], 元数据来自devfs里的设备文件
+pub struct DeviceFile {
+    readable: bool,
+    writable: bool,
+    dentry: Dentry,
+    path: String,
+    device: Arc<dyn File + Send + Sync>,
+}
+
+impl File for DeviceFile {
+    fn readable(&self) -> bool {
+        return self.readable;
+    }
+    fn writable(&self) -> bool {
+        return self.writable;
+    }
+    fn read(&self, buf: UserBuffer) -> usize {
+        return self.device.read(buf);
     }
     fn write(&self, buf: UserBuffer) -> usize {
-        let mut inner = self.inner.exclusive_access(); //加锁后拿
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
-        }
-        return total_write_size;
+        return self.device.write(buf);
+    }
+    fn seek(&self, offset: isize, whence: usize) -> isize {
+        return self.device.seek(offset, whence);
+    }
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        return self.device.read_at(offset, buf);
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Option<usize> {
+        return self.device.write_at(offset, buf);
+    }
+    fn stat(&self) -> Option<Stat> {
+        Some(self.dentry.stat())
+    }
+    fn fd_path(&self) -> String {
+        self.path.clone()
+    }
+    fn device_memory(&self) -> Option<(usize, usize)> {
+        return self.device.device_memory();
     }
 }
@@ -1,10 +1,20 @@
 //! File system in os
+mod device;
+mod devfs;
+mod efs;
+mod fat;
 mod inode;
+mod mount;
+mod procfs;
+mod tmpfs;
+mod vfs;
 mod pipe;
 mod stdio;
 mod eventfd;
 
-use crate::mm::UserBuffer;
+use crate::mm::{BackingFile, UserBuffer};
+use alloc::string::String;
+use alloc::sync::Arc;
 use core::fmt::Debug;
 /// File trait
 pub trait File: Send + Sync {
@@ -16,11 +26,126 @@ pub trait File: Send + Sync {
     fn read(&self, buf: UserBuffer) -> usize;
     /// Write `UserBuffer` to file
     fn write(&self, buf: UserBuffer) -> usize;
+    /// The file that mmap can load pages from, None if the file can not be mapped (pipe/stdio)
+    fn backing_file(self: Arc<Self>) -> Option<Arc<dyn BackingFile>> {
+        None
+    }
+    /// Move the offset of the file and return the new offset, -1 if the file is not seekable (pipe/stdio)
+    fn seek(&self, _offset: isize, _whence: usize) -> isize {
+        -1
+    }
+    /// Read from `offset` without moving the file offset, None if the file is not seekable
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Write to `offset` without moving the file offset, None if the file is not seekable
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Read directory entries into `buf` as `linux_dirent64` records, return the size filled, 0 at the end.
+    /// None if the file is not a directory or `buf` is too small for the next entry
+    fn getdents(&self, _buf: UserBuffer) -> Option<usize> {
+        None
+    }
+    /// Metadata of the file, None if it is not on the filesystem (pipe/stdio)
+    fn stat(&self) -> Option<Stat> {
+        None
+    }
+    /// Set the size of the file to `size`, false if it is not a regular file opened for writing
+    fn truncate(&self, _size: usize) -> bool {
+        false
+    }
+    /// What /proc/<pid>/fd/<fd> links to, 文件系统里的文件是打开时的绝对路径
+    fn fd_path(&self) -> String {
+        String::from("anon_inode:[file]")
+    }
+    /// Physical memory of a device that mmap maps directly, (physical address, length), 例如显存
+    fn device_memory(&self) -> Option<(usize, usize)> {
+        None
+    }
+}
+
+/// Seek from the beginning of file
+pub const SEEK_SET: usize = 0;
+/// Seek from the current offset
+pub const SEEK_CUR: usize = 1;
+/// Seek from the end of file
+pub const SEEK_END: usize = 2;
+
+/// d_type of an unknown file type in `linux_dirent64`
+pub const DT_UNKNOWN: u8 = 0;
+/// d_type of a character device in `linux_dirent64`
+pub const DT_CHR: u8 = 2;
+/// d_type of a directory in `linux_dirent64`
+pub const DT_DIR: u8 = 4;
+/// d_type of a block device in `linux_dirent64`
+pub const DT_BLK: u8 = 6;
+/// d_type of a regular file in `linux_dirent64`
+pub const DT_REG: u8 = 8;
+/// d_type of a symlink in `linux_dirent64`
+pub const DT_LNK: u8 = 10;
+
+/// Bit mask of the file type in `mode`
+pub const S_IFMT: u32 = 0o170000;
+/// Character device
+pub const S_IFCHR: u32 = 0o020000;
+/// Directory
+pub const S_IFDIR: u32 = 0o040000;
+/// Block device
+pub const S_IFBLK: u32 = 0o060000;
+/// Regular file
+pub const S_IFREG: u32 = 0o100000;
+/// Symbolic link
+pub const S_IFLNK: u32 = 0o120000;
+
+/// File metadata returned by fstat, 和Linux riscv64的`struct stat`布局一致
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    /// ID of device containing file
+    pub dev: u64,
+    /// Inode number
+    pub ino: u64,
+    /// File type and permission bits
+    pub mode: u32,
+    /// Number of hard links
+    pub nlink: u32,
+    /// User ID of owner
+    pub uid: u32,
+    /// Group ID of owner
+    pub gid: u32,
+    /// Device ID (if special file)
+    pub rdev: u64,
+    pad: u64,
+    /// Total size in bytes
+    pub size: i64,
+    /// Block size for filesystem I/O
+    pub blksize: u32,
+    pad2: u32,
+    /// Number of 512B blocks allocated
+    pub blocks: u64,
+    /// Last access time, 内核的时间是开机后的毫秒数
+    pub atime_sec: i64,
+    pub atime_nsec: i64,
+    /// Last modification time
+    pub mtime_sec: i64,
+    pub mtime_nsec: i64,
+    /// Last status change time
+    pub ctime_sec: i64,
+    pub ctime_nsec: i64,
+    unused: [u32; 2],
 }
 
-pub use inode::{list_files, open_file, OSInode, OpenFlags};
+pub use devfs::{register_device, DeviceKind};
+pub use device::register_devices;
+pub use efs::{print_block_cache_stats, root_fs_blocks};
+pub use inode::{
+    find_inode, link_file, list_files, make_dir, open_file, open_path, read_link, rename_file,
+    stat_file, symlink_file, truncate_file, unlink_file, OSInode, OpenFlags,
+};
+pub use mount::{mount, mount_boot_filesystems, open_fs, root_dentry, sync_fs, umount, Dentry};
 pub use pipe::{make_pipe, Pipe};
-pub use stdio::{Stdin, Stdout};
+pub use stdio::{Stdin, Stdout, Tty};
 pub use eventfd::{eventfd_create,Eventfd, EventfdFlags};
 
 impl Debug for dyn File + Send + Sync {
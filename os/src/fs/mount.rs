@@ -0,0 +1,307 @@
+//! Mount table and path resolution
+//!
+//! 挂载表以挂载点的绝对路径为键. 解析路径时一个名字一个名字地往下找, 走到挂载点就换到挂载在上面的
+//! 文件系统的根目录; 在文件系统的根目录上, ".." 回到挂载点所在的目录.
+
+use super::devfs::open_devfs;
+use super::efs::{open_efs, ROOT_FS};
+use super::fat::open_fat;
+use super::procfs::open_procfs;
+use super::tmpfs::open_tmpfs;
+use super::vfs::{Inode, InodeType, SuperBlock};
+use super::Stat;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+use core::sync::atomic::{AtomicU64, Ordering};
+use lazy_static::*;
+
+/// The max number of symlinks followed when resolving a path
+const MAX_SYMLINK_DEPTH: usize = 40;
+
+/// A filesystem mounted on a directory
+pub struct Mount {
+    /// Absolute path of the mount point
+    path: String,
+    /// Device id reported by stat, 每次挂载一个新的
+    dev: u64,
+    sb: Arc<dyn SuperBlock>,
+    root: Arc<dyn Inode>,
+    /// The directory covered by this mount, 根文件系统没有
+    mountpoint: Option<Dentry>,
+}
+
+/// A resolved location in the directory tree: an inode and the mount it is reached through.
+/// 持有挂载的引用, 所以当前目录和打开的文件所在的文件系统不能卸载
+#[derive(Clone)]
+pub struct Dentry {
+    mount: Arc<Mount>,
+    inode: Arc<dyn Inode>,
+}
+
+impl Mount {
+    fn new(path: String, sb: Arc<dyn SuperBlock>, mountpoint: Option<Dentry>) -> Self {
+        static NEXT_DEV: AtomicU64 = AtomicU64::new(1);
+        Self {
+            path,
+            dev: NEXT_DEV.fetch_add(1, Ordering::Relaxed),
+            root: sb.root_inode(),
+            sb,
+            mountpoint,
+        }
+    }
+}
+
+lazy_static! {
+    /// 挂载点的绝对路径 -> 挂载在上面的文件系统
+    static ref MOUNTS: UPSafeCell<BTreeMap<String, Arc<Mount>>> = unsafe {
+        let mut mounts = BTreeMap::new();
+        let root = Mount::new(String::from("/"), ROOT_FS.clone(), None);
+        mounts.insert(String::from("/"), Arc::new(root));
+        UPSafeCell::new(mounts)
+    };
+}
+
+/// The root directory of the whole directory tree
+pub fn root_dentry() -> Dentry {
+    let mount = Arc::clone(MOUNTS.exclusive_access().get("/").unwrap());
+    return Dentry {
+        inode: Arc::clone(&mount.root),
+        mount,
+    };
+}
+
+impl Dentry {
+    /// The inode
+    pub fn inode(&self) -> &Arc<dyn Inode> {
+        return &self.inode;
+    }
+    /// Metadata of the inode, `dev`是挂载的编号
+    pub fn stat(&self) -> Stat {
+        let mut stat = self.inode.stat();
+        stat.dev = self.mount.dev;
+        return stat;
+    }
+    /// Absolute path in the directory tree, None if it has been removed
+    pub fn path(&self) -> Option<String> {
+        let path = self.inode.path()?;
+        if self.mount.path == "/" {
+            return Some(path);
+        } else if path == "/" {
+            return Some(self.mount.path.clone());
+        }
+        let mut full = self.mount.path.clone();
+        full.push_str(&path);
+        return Some(full);
+    }
+    /// Whether both are reached through the same mount, 硬链接和改名不能跨越挂载
+    pub fn same_mount(&self, other: &Dentry) -> bool {
+        return Arc::ptr_eq(&self.mount, &other.mount);
+    }
+    /// Whether it is a mount point or a directory containing mount points, 这样的目录不能删除和改名
+    pub fn is_busy(&self) -> bool {
+        let path = match self.path() {
+            Some(path) => path,
+            None => return false,
+        };
+        return MOUNTS.exclusive_access().keys().any(|mount_path| {
+            mount_path != "/"
+                && mount_path.starts_with(path.as_str())
+                && (path == "/"
+                    || mount_path.len() == path.len()
+                    || mount_path[path.len()..].starts_with('/'))
+        });
+    }
+    /// Create a regular file or a directory `name` in this directory
+    pub fn create(&self, name: &str, type_: InodeType) -> Option<Dentry> {
+        let inode = self.inode.create(name, type_)?;
+        return Some(Dentry {
+            mount: Arc::clone(&self.mount),
+            inode,
+        });
+    }
+    /// Whether it is the root directory of its mount
+    fn is_mount_root(&self) -> bool {
+        return self.inode.stat().ino == self.mount.root.stat().ino;
+    }
+    /// The parent directory. 文件系统的根目录的父目录是挂载点的父目录, 整个目录树的根目录的父目录是自己
+    fn parent(&self) -> Dentry {
+        let mut dentry = self.clone();
+        while dentry.is_mount_root() {
+            match &dentry.mount.mountpoint {
+                Some(mountpoint) => dentry = mountpoint.clone(),
+                None => return dentry,
+            }
+        }
+        match dentry.inode.lookup("..") {
+            Some(inode) => {
+                return Dentry {
+                    mount: dentry.mount,
+                    inode,
+                }
+            }
+            None => return dentry,
+        }
+    }
+    /// Find `name` in this directory, 被挂载的目录换成挂载在上面的文件系统的根目录
+    fn child(&self, name: &str) -> Option<Dentry> {
+        let child = Dentry {
+            mount: Arc::clone(&self.mount),
+            inode: self.inode.lookup(name)?,
+        };
+        let candidates: Vec<Arc<Mount>> = MOUNTS
+            .exclusive_access()
+            .values()
+            .filter(|mount| {
+                mount.mountpoint.as_ref().map_or(false, |mountpoint| {
+                    Arc::ptr_eq(&mountpoint.mount, &self.mount)
+                })
+            })
+            .cloned()
+            .collect();
+        if candidates.is_empty() {
+            return Some(child);
+        }
+        let ino = child.inode.stat().ino;
+        for mount in candidates {
+            if mount.mountpoint.as_ref().unwrap().inode.stat().ino == ino {
+                return Some(Dentry {
+                    inode: Arc::clone(&mount.root),
+                    mount,
+                });
+            }
+        }
+        return Some(child);
+    }
+}
+
+impl Debug for Dentry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Dentry -- Path : {:?}", self.path())
+    }
+}
+
+/// Resolve `path`: 绝对路径从根目录开始找, 相对路径从`cwd`开始找.
+/// 最后一个名字是符号链接时, `follow`为false就返回链接本身
+pub fn lookup(cwd: &Dentry, path: &str, follow: bool) -> Option<Dentry> {
+    let mut depth = 0;
+    return walk(cwd, path, follow, &mut depth);
+}
+
+fn walk(dir: &Dentry, path: &str, follow: bool, depth: &mut usize) -> Option<Dentry> {
+    if path.is_empty() {
+        return None;
+    }
+    let mut current = if path.starts_with('/') {
+        root_dentry()
+    } else {
+        dir.clone()
+    };
+    let names: Vec<&str> = path.split('/').filter(|name| !name.is_empty()).collect();
+    for (i, name) in names.iter().enumerate() {
+        if !current.inode.is_dir() {
+            return None;
+        }
+        let next = match *name {
+            "." => continue,
+            ".." => current.parent(),
+            _ => current.child(name)?,
+        };
+        if next.inode.is_symlink() && (follow || i + 1 < names.len()) {
+            *depth += 1;
+            if *depth > MAX_SYMLINK_DEPTH {
+                return None;
+            }
+            // 相对路径的链接从链接所在的目录开始找
+            let target = next.inode.read_link()?;
+            current = walk(&current, &target, true, depth)?;
+        } else {
+            current = next;
+        }
+    }
+    return Some(current);
+}
+
+/// Open the filesystem `fs_type` on `source` for mounting
+pub fn open_fs(fs_type: &str, source: &str) -> Option<Arc<dyn SuperBlock>> {
+    match fs_type {
+        "easyfs" => open_efs(source),
+        "proc" => open_procfs(source),
+        "devfs" => open_devfs(source),
+        "tmpfs" => open_tmpfs(source),
+        "vfat" => open_fat(source),
+        _ => None,
+    }
+}
+
+/// Filesystems mounted at boot: (mount point, fs_type, source)
+const BOOT_MOUNTS: &[(&str, &str, &str)] = &[
+    ("/proc", "proc", "proc"),
+    ("/dev", "devfs", "devfs"),
+    ("/tmp", "tmpfs", "tmpfs"),
+];
+
+/// Mount the filesystems in [`BOOT_MOUNTS`], 挂载点不存在就在根目录下创建
+pub fn mount_boot_filesystems() {
+    let root = root_dentry();
+    for (path, fs_type, source) in BOOT_MOUNTS {
+        if lookup(&root, path, true).is_none() {
+            root.create(path.trim_start_matches('/'), InodeType::Dir);
+        }
+        let mounted = match open_fs(fs_type, source) {
+            Some(sb) => mount(&root, path, sb),
+            None => false,
+        };
+        if !mounted {
+            println!("[kernel] failed to mount {} on {}", fs_type, path);
+        }
+    }
+}
+
+/// Mount `sb` on the directory `path`, 一个目录上只能挂载一个文件系统
+pub fn mount(cwd: &Dentry, path: &str, sb: Arc<dyn SuperBlock>) -> bool {
+    let mountpoint = match lookup(cwd, path, true) {
+        Some(dentry) if dentry.inode.is_dir() => dentry,
+        _ => return false,
+    };
+    let mount_path = match mountpoint.path() {
+        Some(path) => path,
+        None => return false,
+    };
+    let mut mounts = MOUNTS.exclusive_access();
+    if mounts.contains_key(&mount_path) {
+        return false;
+    }
+    let mount = Mount::new(mount_path.clone(), sb, Some(mountpoint));
+    mounts.insert(mount_path, Arc::new(mount));
+    return true;
+}
+
+/// Unmount the filesystem mounted on `path`. 还有文件系统挂载在它里面, 或者还有打开的文件,
+/// 当前目录在它里面时失败
+pub fn umount(cwd: &Dentry, path: &str) -> bool {
+    let mount = match lookup(cwd, path, true) {
+        Some(dentry) if dentry.is_mount_root() && dentry.mount.mountpoint.is_some() => dentry.mount,
+        _ => return false,
+    };
+    let mut mounts = MOUNTS.exclusive_access();
+    // 挂载表和这里各有一个引用
+    if Arc::strong_count(&mount) > 2 {
+        return false;
+    }
+    mounts.remove(&mount.path);
+    drop(mounts);
+    mount.sb.sync();
+    return true;
+}
+
+/// Write everything cached of all mounted filesystems back to the devices
+pub fn sync_fs() {
+    let mounts: Vec<Arc<Mount>> = MOUNTS.exclusive_access().values().cloned().collect();
+    for mount in mounts {
+        mount.sb.sync();
+    }
+}
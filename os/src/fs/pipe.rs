@@ -1,6 +1,8 @@
 use super::File;
 use crate::mm::UserBuffer;
 use crate::sync::UPSafeCell;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::{Arc, Weak};
 
 use crate::task::suspend_current_and_run_next;
@@ -225,4 +227,9 @@ impl File for Pipe {
             }
         }
     }
+
+    /// 两端共用一个缓冲区, 用缓冲区的地址区分不同的管道
+    fn fd_path(&self) -> String {
+        format!("pipe:[{}]", Arc::as_ptr(&self.buffer) as usize)
+    }
 }
@@ -0,0 +1,232 @@
+//! procfs: 进程和内核状态做成的只读文件系统
+//!
+//! 文件内容在读的时候才生成, 不占用存储. 目录结构:
+//! - `meminfo`: 物理页帧和交换区的使用情况
+//! - `uptime`: 开机后的秒数
+//! - `self`: 指向当前进程目录的符号链接
+//! - `<pid>/status`, `<pid>/maps`: 进程的状态和地址空间
+//! - `<pid>/fd/<fd>`: 指向打开的文件的符号链接
+
+use super::vfs::{Dirent, Inode, SuperBlock};
+use super::{Stat, DT_DIR, DT_LNK, DT_REG, S_IFDIR, S_IFLNK, S_IFREG};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_free_count, frame_total_count, swap_free_count, swap_total_count};
+use crate::task::{all_pids, current_process, pid2process};
+use crate::timer::get_time_ms;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::any::Any;
+
+/// A mounted procfs, 每次挂载一个, 内容都一样
+pub struct ProcSuperBlock;
+
+impl SuperBlock for ProcSuperBlock {
+    fn fs_type(&self) -> &str {
+        return "proc";
+    }
+    fn root_inode(&self) -> Arc<dyn Inode> {
+        return Arc::new(ProcInode(ProcNode::Root));
+    }
+}
+
+/// Open a procfs for mounting, 没有设备, `source`被忽略
+pub fn open_procfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    return Some(Arc::new(ProcSuperBlock));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProcNode {
+    Root,
+    MemInfo,
+    Uptime,
+    SelfLink,
+    PidDir(usize),
+    Status(usize),
+    Maps(usize),
+    FdDir(usize),
+    Fd(usize, usize),
+}
+
+/// An inode of procfs
+pub struct ProcInode(ProcNode);
+
+impl ProcNode {
+    /// 进程的文件从 (pid+1)<<16 开始编号, 不会和根目录下的文件重复
+    fn ino(&self) -> u64 {
+        let pid_base = |pid: usize| ((pid as u64) + 1) << 16;
+        match *self {
+            ProcNode::Root => 1,
+            ProcNode::MemInfo => 2,
+            ProcNode::Uptime => 3,
+            ProcNode::SelfLink => 4,
+            ProcNode::PidDir(pid) => pid_base(pid),
+            ProcNode::Status(pid) => pid_base(pid) + 1,
+            ProcNode::Maps(pid) => pid_base(pid) + 2,
+            ProcNode::FdDir(pid) => pid_base(pid) + 3,
+            ProcNode::Fd(pid, fd) => pid_base(pid) + 0x100 + fd as u64,
+        }
+    }
+    fn is_dir(&self) -> bool {
+        return matches!(
+            self,
+            ProcNode::Root | ProcNode::PidDir(_) | ProcNode::FdDir(_)
+        );
+    }
+    fn is_symlink(&self) -> bool {
+        return matches!(self, ProcNode::SelfLink | ProcNode::Fd(..));
+    }
+    /// Entries of a directory including . and .., None if the process has exited
+    fn entries(&self) -> Option<Vec<(String, ProcNode)>> {
+        let (parent, mut entries) = match *self {
+            ProcNode::Root => {
+                let mut entries = vec![
+                    (String::from("meminfo"), ProcNode::MemInfo),
+                    (String::from("uptime"), ProcNode::Uptime),
+                    (String::from("self"), ProcNode::SelfLink),
+                ];
+                for pid in all_pids() {
+                    entries.push((pid.to_string(), ProcNode::PidDir(pid)));
+                }
+                (ProcNode::Root, entries)
+            }
+            ProcNode::PidDir(pid) => {
+                pid2process(pid)?;
+                let entries = vec![
+                    (String::from("status"), ProcNode::Status(pid)),
+                    (String::from("maps"), ProcNode::Maps(pid)),
+                    (String::from("fd"), ProcNode::FdDir(pid)),
+                ];
+                (ProcNode::Root, entries)
+            }
+            ProcNode::FdDir(pid) => {
+                let entries = pid2process(pid)?
+                    .fd_paths()?
+                    .into_iter()
+                    .map(|(fd, _)| (fd.to_string(), ProcNode::Fd(pid, fd)))
+                    .collect();
+                (ProcNode::PidDir(pid), entries)
+            }
+            _ => return None,
+        };
+        entries.insert(0, (String::from("."), *self));
+        entries.insert(1, (String::from(".."), parent));
+        return Some(entries);
+    }
+    /// Content of a regular file, None if the process has exited
+    fn content(&self) -> Option<String> {
+        match *self {
+            ProcNode::MemInfo => {
+                let page_kb = PAGE_SIZE / 1024;
+                return Some(format!(
+                    "MemTotal:\t{} kB\nMemFree:\t{} kB\nSwapTotal:\t{} kB\nSwapFree:\t{} kB\n",
+                    frame_total_count() * page_kb,
+                    frame_free_count() * page_kb,
+                    swap_total_count() * page_kb,
+                    swap_free_count() * page_kb,
+                ));
+            }
+            ProcNode::Uptime => {
+                // 第二个数是空闲时间, 内核没有统计
+                let ms = get_time_ms();
+                return Some(format!("{}.{:02} 0.00\n", ms / 1000, ms % 1000 / 10));
+            }
+            ProcNode::Status(pid) => return pid2process(pid)?.status(),
+            ProcNode::Maps(pid) => return pid2process(pid)?.maps(),
+            _ => return None,
+        }
+    }
+}
+
+impl Inode for ProcInode {
+    fn stat(&self) -> Stat {
+        let (mode, nlink) = if self.0.is_dir() {
+            (S_IFDIR | 0o555, 2)
+        } else if self.0.is_symlink() {
+            (S_IFLNK | 0o777, 1)
+        } else {
+            (S_IFREG | 0o444, 1)
+        };
+        // 和Linux一样, 普通文件的大小是0, 内容要读了才知道
+        let size = self.read_link().map_or(0, |target| target.len());
+        return Stat {
+            ino: self.0.ino(),
+            mode,
+            nlink,
+            size: size as i64,
+            blksize: PAGE_SIZE as u32,
+            ..Default::default()
+        };
+    }
+    fn is_dir(&self) -> bool {
+        return self.0.is_dir();
+    }
+    fn is_symlink(&self) -> bool {
+        return self.0.is_symlink();
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let content = match self.0.content() {
+            Some(content) => content,
+            None => return 0,
+        };
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return 0;
+        }
+        let len = buf.len().min(bytes.len() - offset);
+        buf[..len].copy_from_slice(&bytes[offset..offset + len]);
+        return len;
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        let (_, node) = self
+            .0
+            .entries()?
+            .into_iter()
+            .find(|(entry, _)| entry == name)?;
+        return Some(Arc::new(ProcInode(node)));
+    }
+    fn read_link(&self) -> Option<String> {
+        match self.0 {
+            // 相对于 /proc 的路径
+            ProcNode::SelfLink => return Some(current_process().getpid().to_string()),
+            ProcNode::Fd(pid, fd) => {
+                let fds = pid2process(pid)?.fd_paths()?;
+                return fds
+                    .into_iter()
+                    .find(|(n, _)| *n == fd)
+                    .map(|(_, path)| path);
+            }
+            _ => return None,
+        }
+    }
+    fn read_dirent(&self, pos: usize) -> Option<(Dirent, usize)> {
+        // 目录里的位置就是第几个目录项
+        let (name, node) = self.0.entries()?.into_iter().nth(pos)?;
+        let d_type = if node.is_dir() {
+            DT_DIR
+        } else if node.is_symlink() {
+            DT_LNK
+        } else {
+            DT_REG
+        };
+        let dirent = Dirent {
+            ino: node.ino(),
+            d_type,
+            name,
+        };
+        return Some((dirent, pos + 1));
+    }
+    fn path(&self) -> Option<String> {
+        match self.0 {
+            ProcNode::Root => return Some(String::from("/")),
+            ProcNode::PidDir(pid) => return Some(format!("/{}", pid)),
+            ProcNode::FdDir(pid) => return Some(format!("/{}/fd", pid)),
+            _ => return None,
+        }
+    }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
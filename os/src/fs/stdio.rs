@@ -4,6 +4,7 @@ use crate::drivers::chardev::CharDevice;
 use crate::drivers::chardev::UART;
 use crate::mm::UserBuffer;
 use crate::task::suspend_current_and_run_next;
+use alloc::string::String;
 
 ///Standard input
 pub struct Stdin;
@@ -28,6 +29,9 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
+    fn fd_path(&self) -> String {
+        String::from("/dev/tty")
+    }
 }
 
 impl File for Stdout {
@@ -46,4 +50,41 @@ impl File for Stdout {
         }
         return user_buf.len();
     }
+    fn fd_path(&self) -> String {
+        String::from("/dev/tty")
+    }
+}
+
+/// /dev/tty: 串口终端, 可以读也可以写
+pub struct Tty;
+
+impl File for Tty {
+    fn readable(&self) -> bool {
+        return true;
+    }
+    fn writable(&self) -> bool {
+        return true;
+    }
+    /// 没有输入时等待第一个字符, 之后只读已经收到的字符
+    fn read(&self, user_buf: UserBuffer) -> usize {
+        let mut read_size = 0;
+        for byte in user_buf.into_iter() {
+            if read_size > 0 && UART.read_buffer_is_empty() {
+                break;
+            }
+            unsafe {
+                byte.write_volatile(UART.read());
+            }
+            read_size += 1;
+        }
+        return read_size;
+    }
+    fn write(&self, user_buf: UserBuffer) -> usize {
+        for buffer in user_buf.buffers.iter() {
+            for byte in buffer.iter() {
+                UART.write(*byte);
+            }
+        }
+        return user_buf.len();
+    }
 }
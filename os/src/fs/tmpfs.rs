@@ -0,0 +1,469 @@
+//! tmpfs: 内存里的文件系统
+//!
+//! 文件内容存放在 `frame_alloc` 分配的物理页帧里, 不经过块设备和块缓存.
+//! 每次挂载都是一个新的空文件系统, 卸载之后所有文件和它们的页帧都被释放.
+
+use super::vfs::{Dirent, Inode, InodeType, SuperBlock};
+use super::{Stat, DT_DIR, DT_LNK, DT_REG, S_IFDIR, S_IFLNK, S_IFREG};
+use crate::config::{FRAME_LOW_WATERMARK, PAGE_SIZE};
+use crate::mm::{frame_alloc, frame_free_count, FrameTracker};
+use crate::sync::UPSafeCell;
+use crate::timer::get_time_ms;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Longest name in a directory, 和easy-fs一样
+const NAME_LENGTH_LIMIT: usize = 255;
+
+/// 所有tmpfs共用的inode编号, 每个文件都不一样
+static NEXT_INO: AtomicU64 = AtomicU64::new(1);
+
+/// A mounted tmpfs
+pub struct TmpSuperBlock {
+    root: Arc<TmpInode>,
+}
+
+impl SuperBlock for TmpSuperBlock {
+    fn fs_type(&self) -> &str {
+        return "tmpfs";
+    }
+    fn root_inode(&self) -> Arc<dyn Inode> {
+        return self.root.clone();
+    }
+}
+
+/// Create an empty tmpfs for mounting, 没有设备, `source`被忽略
+pub fn open_tmpfs(_source: &str) -> Option<Arc<dyn SuperBlock>> {
+    return Some(Arc::new(TmpSuperBlock {
+        root: TmpInode::new(TmpKind::Dir, Weak::new()),
+    }));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TmpKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// A file, directory or symlink of tmpfs
+pub struct TmpInode {
+    ino: u64,
+    kind: TmpKind,
+    inner: UPSafeCell<TmpInodeInner>,
+}
+
+struct TmpInodeInner {
+    /// 指向自己, lookup(".")时返回
+    me: Weak<TmpInode>,
+    /// 目录的父目录, 根目录和被删除的目录没有
+    parent: Weak<TmpInode>,
+    /// 硬链接数, 目录被删除后是0
+    nlink: u32,
+    size: usize,
+    /// 文件内容的页帧, 没写过的页(空洞)读出来是0
+    pages: BTreeMap<usize, FrameTracker>,
+    entries: BTreeMap<String, Arc<TmpInode>>,
+    /// 符号链接指向的路径
+    target: String,
+    /// 时间戳, 开机后的毫秒数
+    atime: u64,
+    mtime: u64,
+    ctime: u64,
+}
+
+impl TmpInode {
+    fn new(kind: TmpKind, parent: Weak<TmpInode>) -> Arc<Self> {
+        let now = get_time_ms() as u64;
+        let inode = Arc::new(Self {
+            ino: NEXT_INO.fetch_add(1, Ordering::Relaxed),
+            kind,
+            inner: unsafe {
+                UPSafeCell::new(TmpInodeInner {
+                    me: Weak::new(),
+                    parent,
+                    nlink: if kind == TmpKind::Dir { 2 } else { 1 },
+                    size: 0,
+                    pages: BTreeMap::new(),
+                    entries: BTreeMap::new(),
+                    target: String::new(),
+                    atime: now,
+                    mtime: now,
+                    ctime: now,
+                })
+            },
+        });
+        inode.inner.exclusive_access().me = Arc::downgrade(&inode);
+        return inode;
+    }
+    /// The tmpfs inode of `other`, None if it is on another filesystem
+    fn downcast(other: &Arc<dyn Inode>) -> Option<Arc<TmpInode>> {
+        let inode = other.as_any().downcast_ref::<TmpInode>()?;
+        return inode.inner.exclusive_access().me.upgrade();
+    }
+    fn is_valid_name(name: &str) -> bool {
+        return !name.is_empty()
+            && name.len() <= NAME_LENGTH_LIMIT
+            && !name.contains('/')
+            && name != "."
+            && name != "..";
+    }
+    /// Whether this is a directory that has not been removed
+    fn is_live_dir(&self) -> bool {
+        return self.kind == TmpKind::Dir && self.inner.exclusive_access().nlink > 0;
+    }
+    /// Add a new inode `name` to this directory
+    fn add_entry(&self, name: &str, kind: TmpKind) -> Option<Arc<TmpInode>> {
+        if !self.is_live_dir() || !Self::is_valid_name(name) {
+            return None;
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.entries.contains_key(name) {
+            return None;
+        }
+        let inode = TmpInode::new(kind, inner.me.clone());
+        inner.entries.insert(String::from(name), inode.clone());
+        inner.mtime = get_time_ms() as u64;
+        return Some(inode);
+    }
+    /// Whether `ancestor` is this directory or one of its parents
+    fn is_under(self: &Arc<Self>, ancestor: &Arc<TmpInode>) -> bool {
+        let mut current = Some(self.clone());
+        while let Some(dir) = current {
+            if Arc::ptr_eq(&dir, ancestor) {
+                return true;
+            }
+            current = dir.inner.exclusive_access().parent.upgrade();
+        }
+        return false;
+    }
+    /// 被删除或者被替换掉的inode少一个链接, 页帧在最后一个引用(打开的文件)消失时释放
+    fn drop_link(&self) {
+        let mut inner = self.inner.exclusive_access();
+        if self.kind == TmpKind::Dir {
+            inner.nlink = 0;
+            inner.parent = Weak::new();
+        } else {
+            inner.nlink -= 1;
+        }
+        inner.ctime = get_time_ms() as u64;
+    }
+}
+
+impl Inode for TmpInode {
+    fn stat(&self) -> Stat {
+        let inner = self.inner.exclusive_access();
+        let (mode, size) = match self.kind {
+            TmpKind::File => (S_IFREG | 0o644, inner.size),
+            TmpKind::Dir => (S_IFDIR | 0o755, 0),
+            TmpKind::Symlink => (S_IFLNK | 0o777, inner.target.len()),
+        };
+        // 目录的链接数: 自己的名字, 自己的 . 和每个子目录的 ..
+        let nlink = match self.kind {
+            TmpKind::Dir if inner.nlink > 0 => {
+                let subdirs = inner
+                    .entries
+                    .values()
+                    .filter(|inode| inode.kind == TmpKind::Dir)
+                    .count();
+                2 + subdirs as u32
+            }
+            _ => inner.nlink,
+        };
+        let ms_to_time = |ms: u64| ((ms / 1000) as i64, ((ms % 1000) * 1_000_000) as i64);
+        let (atime_sec, atime_nsec) = ms_to_time(inner.atime);
+        let (mtime_sec, mtime_nsec) = ms_to_time(inner.mtime);
+        let (ctime_sec, ctime_nsec) = ms_to_time(inner.ctime);
+        return Stat {
+            ino: self.ino,
+            mode,
+            nlink,
+            size: size as i64,
+            blksize: PAGE_SIZE as u32,
+            blocks: (inner.pages.len() * PAGE_SIZE / 512) as u64,
+            atime_sec,
+            atime_nsec,
+            mtime_sec,
+            mtime_nsec,
+            ctime_sec,
+            ctime_nsec,
+            ..Default::default()
+        };
+    }
+    fn is_dir(&self) -> bool {
+        return self.kind == TmpKind::Dir;
+    }
+    fn is_symlink(&self) -> bool {
+        return self.kind == TmpKind::Symlink;
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        if self.kind != TmpKind::File || offset >= inner.size {
+            return 0;
+        }
+        let end = (offset + buf.len()).min(inner.size);
+        let mut pos = offset;
+        while pos < end {
+            let start = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - start).min(end - pos);
+            let dst = &mut buf[pos - offset..pos - offset + len];
+            match inner.pages.get(&(pos / PAGE_SIZE)) {
+                Some(frame) => {
+                    dst.copy_from_slice(&frame.ppn.get_bytes_array()[start..start + len])
+                }
+                None => dst.fill(0),
+            }
+            pos += len;
+        }
+        inner.atime = get_time_ms() as u64;
+        return end - offset;
+    }
+    /// 空闲页帧不多时不再分配, 留给用户程序的页面, 这时写不完
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        if self.kind != TmpKind::File {
+            return 0;
+        }
+        let end = offset + buf.len();
+        let mut pos = offset;
+        while pos < end {
+            let page = pos / PAGE_SIZE;
+            if !inner.pages.contains_key(&page) {
+                if frame_free_count() <= FRAME_LOW_WATERMARK {
+                    break;
+                }
+                match frame_alloc() {
+                    Some(frame) => inner.pages.insert(page, frame),
+                    None => break,
+                };
+            }
+            let start = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - start).min(end - pos);
+            inner.pages[&page].ppn.get_bytes_array()[start..start + len]
+                .copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        if pos > offset {
+            inner.size = inner.size.max(pos);
+            inner.mtime = get_time_ms() as u64;
+            inner.ctime = inner.mtime;
+        }
+        return pos - offset;
+    }
+    /// 变短时还回后面的页帧, 变长时不分配页帧, 和空洞一样读出来是0
+    fn truncate(&self, size: usize) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        if self.kind != TmpKind::File {
+            return false;
+        }
+        let keep = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+        drop(inner.pages.split_off(&keep));
+        // 留下的最后一页里文件末尾之后清零, 以后再变长时读出来是0
+        if size < inner.size && size % PAGE_SIZE != 0 {
+            if let Some(frame) = inner.pages.get(&(size / PAGE_SIZE)) {
+                frame.ppn.get_bytes_array()[size % PAGE_SIZE..].fill(0);
+            }
+        }
+        inner.size = size;
+        inner.mtime = get_time_ms() as u64;
+        inner.ctime = inner.mtime;
+        return true;
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn Inode>> {
+        if self.kind != TmpKind::Dir {
+            return None;
+        }
+        let inner = self.inner.exclusive_access();
+        let inode = match name {
+            "." => inner.me.upgrade()?,
+            ".." => inner.parent.upgrade().or_else(|| inner.me.upgrade())?,
+            _ => inner.entries.get(name)?.clone(),
+        };
+        return Some(inode);
+    }
+    fn create(&self, name: &str, type_: InodeType) -> Option<Arc<dyn Inode>> {
+        let kind = match type_ {
+            InodeType::File => TmpKind::File,
+            InodeType::Dir => TmpKind::Dir,
+        };
+        return self
+            .add_entry(name, kind)
+            .map(|inode| inode as Arc<dyn Inode>);
+    }
+    fn symlink(&self, name: &str, target: &str) -> bool {
+        match self.add_entry(name, TmpKind::Symlink) {
+            Some(inode) => {
+                inode.inner.exclusive_access().target = String::from(target);
+                return true;
+            }
+            None => return false,
+        }
+    }
+    fn read_link(&self) -> Option<String> {
+        if self.kind != TmpKind::Symlink {
+            return None;
+        }
+        return Some(self.inner.exclusive_access().target.clone());
+    }
+    fn link(&self, name: &str, target: &Arc<dyn Inode>) -> bool {
+        let target = match Self::downcast(target) {
+            Some(target) if target.kind != TmpKind::Dir => target,
+            _ => return false,
+        };
+        if !self.is_live_dir() || !Self::is_valid_name(name) {
+            return false;
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.entries.contains_key(name) {
+            return false;
+        }
+        inner.entries.insert(String::from(name), target.clone());
+        inner.mtime = get_time_ms() as u64;
+        let mut target_inner = target.inner.exclusive_access();
+        target_inner.nlink += 1;
+        target_inner.ctime = inner.mtime;
+        return true;
+    }
+    fn unlink(&self, name: &str) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.entries.get(name) {
+            Some(inode) if inode.kind != TmpKind::Dir => {}
+            _ => return false,
+        }
+        let inode = inner.entries.remove(name).unwrap();
+        inner.mtime = get_time_ms() as u64;
+        inode.drop_link();
+        return true;
+    }
+    fn rmdir(&self, name: &str) -> bool {
+        let mut inner = self.inner.exclusive_access();
+        match inner.entries.get(name) {
+            Some(inode)
+                if inode.kind == TmpKind::Dir
+                    && inode.inner.exclusive_access().entries.is_empty() => {}
+            _ => return false,
+        }
+        let inode = inner.entries.remove(name).unwrap();
+        inner.mtime = get_time_ms() as u64;
+        inode.drop_link();
+        return true;
+    }
+    /// 如果`new_name`已经存在, 它会被替换: 文件只能替换文件, 目录只能替换空目录
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn Inode>, new_name: &str) -> bool {
+        let new_dir = match Self::downcast(new_dir) {
+            Some(new_dir) if new_dir.is_live_dir() => new_dir,
+            _ => return false,
+        };
+        if !Self::is_valid_name(old_name) || !Self::is_valid_name(new_name) {
+            return false;
+        }
+        let inode = match self.inner.exclusive_access().entries.get(old_name) {
+            Some(inode) => inode.clone(),
+            None => return false,
+        };
+        // 目录不能移动到它自己或者它的子目录下面
+        if inode.kind == TmpKind::Dir && new_dir.is_under(&inode) {
+            return false;
+        }
+        let replaced = new_dir
+            .inner
+            .exclusive_access()
+            .entries
+            .get(new_name)
+            .cloned();
+        if let Some(replaced) = &replaced {
+            if Arc::ptr_eq(replaced, &inode) {
+                // 新旧名字是同一个文件的硬链接, 什么都不做
+                return true;
+            }
+            let is_dir = inode.kind == TmpKind::Dir;
+            if (replaced.kind == TmpKind::Dir) != is_dir
+                || (is_dir && !replaced.inner.exclusive_access().entries.is_empty())
+            {
+                return false;
+            }
+        }
+        let now = get_time_ms() as u64;
+        let mut inner = self.inner.exclusive_access();
+        inner.entries.remove(old_name);
+        inner.mtime = now;
+        drop(inner);
+        let mut new_inner = new_dir.inner.exclusive_access();
+        new_inner
+            .entries
+            .insert(String::from(new_name), inode.clone());
+        new_inner.mtime = now;
+        drop(new_inner);
+        if let Some(replaced) = replaced {
+            replaced.drop_link();
+        }
+        let mut inode_inner = inode.inner.exclusive_access();
+        if inode.kind == TmpKind::Dir {
+            inode_inner.parent = Arc::downgrade(&new_dir);
+        }
+        inode_inner.ctime = now;
+        return true;
+    }
+    fn read_dirent(&self, pos: usize) -> Option<(Dirent, usize)> {
+        if self.kind != TmpKind::Dir {
+            return None;
+        }
+        // 目录里的位置就是第几个目录项, 前两个是 . 和 ..
+        let inner = self.inner.exclusive_access();
+        let (name, ino, kind) = match pos {
+            0 => (String::from("."), self.ino, TmpKind::Dir),
+            1 => {
+                let parent_ino = inner.parent.upgrade().map_or(self.ino, |parent| parent.ino);
+                (String::from(".."), parent_ino, TmpKind::Dir)
+            }
+            _ => {
+                let (name, inode) = inner.entries.iter().nth(pos - 2)?;
+                (name.clone(), inode.ino, inode.kind)
+            }
+        };
+        let d_type = match kind {
+            TmpKind::File => DT_REG,
+            TmpKind::Dir => DT_DIR,
+            TmpKind::Symlink => DT_LNK,
+        };
+        return Some((Dirent { ino, d_type, name }, pos + 1));
+    }
+    fn path(&self) -> Option<String> {
+        if !self.is_live_dir() {
+            return None;
+        }
+        let mut names: Vec<String> = Vec::new();
+        let mut current = self.inner.exclusive_access().me.upgrade()?;
+        loop {
+            let parent = match current.inner.exclusive_access().parent.upgrade() {
+                Some(parent) => parent,
+                None => break,
+            };
+            let name = parent
+                .inner
+                .exclusive_access()
+                .entries
+                .iter()
+                .find(|(_, inode)| Arc::ptr_eq(inode, &current))
+                .map(|(name, _)| name.clone())?;
+            names.push(name);
+            current = parent;
+        }
+        if names.is_empty() {
+            return Some(String::from("/"));
+        }
+        let mut path = String::new();
+        for name in names.iter().rev() {
+            path.push('/');
+            path.push_str(name);
+        }
+        return Some(path);
+    }
+    fn as_any(&self) -> &dyn Any {
+        return self;
+    }
+}
@@ -0,0 +1,115 @@
+//! Virtual file system
+//!
+//! 每种文件系统实现 [`SuperBlock`] 和 [`Inode`], 内核只通过这两个trait访问文件系统.
+//! 路径解析, 符号链接和挂载点都在 [`super::mount`] 里处理, 文件系统只需要在一个目录里按名字查找.
+
+use super::{File, Stat, S_IFDIR, S_IFLNK, S_IFMT};
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+
+/// Type of an inode to create
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InodeType {
+    /// Regular file
+    File,
+    /// Directory
+    Dir,
+}
+
+/// A directory entry read by [`Inode::read_dirent`]
+#[derive(Debug, Clone)]
+pub struct Dirent {
+    /// Inode number
+    pub ino: u64,
+    /// d_type of `linux_dirent64`, 例如 DT_DIR
+    pub d_type: u8,
+    /// Name in the directory
+    pub name: String,
+}
+
+/// A mounted filesystem
+pub trait SuperBlock: Send + Sync {
+    /// Name of the filesystem type, 例如 "easyfs"
+    fn fs_type(&self) -> &str;
+    /// The root directory
+    fn root_inode(&self) -> Arc<dyn Inode>;
+    /// Write everything cached back to the device
+    fn sync(&self) {}
+}
+
+/// A file, directory or symlink of a filesystem.
+/// 目录操作的`name`都是一个名字, 不含'/'. 不支持的操作默认失败
+pub trait Inode: Send + Sync {
+    /// Metadata, `dev`由挂载表填写
+    fn stat(&self) -> Stat;
+    /// Whether it is a directory
+    fn is_dir(&self) -> bool {
+        return self.stat().mode & S_IFMT == S_IFDIR;
+    }
+    /// Whether it is a symlink
+    fn is_symlink(&self) -> bool {
+        return self.stat().mode & S_IFMT == S_IFLNK;
+    }
+    /// Size of the content in bytes
+    fn size(&self) -> usize {
+        return self.stat().size as usize;
+    }
+    /// Read from `offset` into `buf`, return the size read
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+    /// Write `buf` to `offset`, return the size written
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    /// Set the size of a regular file to `size`, 变长的部分读出来是0. 不是普通文件或者空间不够时失败
+    fn truncate(&self, _size: usize) -> bool {
+        false
+    }
+    /// Find `name` in this directory, 不跟随符号链接. ".." 在文件系统的根目录上返回自己
+    fn lookup(&self, _name: &str) -> Option<Arc<dyn Inode>> {
+        None
+    }
+    /// Create a regular file or a directory `name` in this directory
+    fn create(&self, _name: &str, _type_: InodeType) -> Option<Arc<dyn Inode>> {
+        None
+    }
+    /// Create a symlink `name` to `target` in this directory
+    fn symlink(&self, _name: &str, _target: &str) -> bool {
+        false
+    }
+    /// The target of a symlink
+    fn read_link(&self) -> Option<String> {
+        None
+    }
+    /// Create a hard link `name` to `target`, 调用者保证`target`在同一个文件系统里
+    fn link(&self, _name: &str, _target: &Arc<dyn Inode>) -> bool {
+        false
+    }
+    /// Remove the non-directory `name` from this directory
+    fn unlink(&self, _name: &str) -> bool {
+        false
+    }
+    /// Remove the empty directory `name` from this directory
+    fn rmdir(&self, _name: &str) -> bool {
+        false
+    }
+    /// Move `old_name` to `new_name` in `new_dir`, 调用者保证`new_dir`在同一个文件系统里
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn Inode>, _new_name: &str) -> bool {
+        false
+    }
+    /// Read the first entry at or after the position `pos` of this directory,
+    /// return it and the position of the next one
+    fn read_dirent(&self, _pos: usize) -> Option<(Dirent, usize)> {
+        None
+    }
+    /// Open the device of a device file, None if it is not a device
+    fn open_device(&self) -> Option<Arc<dyn File + Send + Sync>> {
+        None
+    }
+    /// Absolute path of this directory inside its filesystem, None if it is not a directory or has been removed
+    fn path(&self) -> Option<String>;
+    /// For downcasting the other inode of `link` and `rename`
+    fn as_any(&self) -> &dyn Any;
+}
@@ -102,7 +102,9 @@ pub fn rust_main() -> ! {
     println!(r" (__) (__) (__)  (__)      (__)   (__) (__) (__)  (__)      (__)            (__)    (__)      ");
     
 
-    fs::list_files("/");
+    fs::register_devices();
+    fs::mount_boot_filesystems();
+    fs::list_files(&fs::root_dentry());
     task::add_initproc();
     *DEV_NON_BLOCKING_ACCESS.exclusive_access() = true;
     println!("after initproc!");
@@ -3,12 +3,17 @@
 
 use super::{PhysAddr, PhysPageNum};
 use crate::config::MEMORY_END;
+use crate::drivers::block::without_sleep;
 use crate::sync::UPSafeCell;
+use crate::task::evict_one_page;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
 
 /// manage a frame which has the same lifecycle as the tracker
+///
+/// 写时复制(Copy On Write)的物理页会被多个地址空间共享, 所以 `MapArea` 里用 `Arc<FrameTracker>` 持有它,
+/// `Arc` 的引用计数就是这个物理页帧的引用计数, 最后一个持有者释放时才真正回收物理页帧
 pub struct FrameTracker {
     pub ppn: PhysPageNum,
     pub nodrop: bool, //此物理页 能否回收 (给显卡用的内存[显存] 不可回收)
@@ -47,10 +52,12 @@ trait FrameAllocator {
     fn new() -> Self;
     fn alloc(&mut self) -> Option<PhysPageNum>;
     fn dealloc(&mut self, ppn: PhysPageNum);
+    fn free_count(&self) -> usize;
 }
 
 /// an implementation for frame allocator
 pub struct StackFrameAllocator {
+    start: usize,
     current: usize,
     end: usize,
     recycled: Vec<usize>,
@@ -58,6 +65,7 @@ pub struct StackFrameAllocator {
 
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
 
@@ -71,6 +79,7 @@ impl StackFrameAllocator {
 impl FrameAllocator for StackFrameAllocator {
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
@@ -95,6 +104,9 @@ impl FrameAllocator for StackFrameAllocator {
         // recycle
         self.recycled.push(ppn);
     }
+    fn free_count(&self) -> usize {
+        self.end - self.current + self.recycled.len()
+    }
 }
 
 type FrameAllocatorImpl = StackFrameAllocator;
@@ -117,11 +129,30 @@ pub fn init_frame_allocator() {
 }
 
 /// allocate a frame
+///
+/// 没有空闲页帧时换出一个用户页面再重试, 什么都换不出来才返回None.
+/// 调用者可能持有进程的inner, 所以换出的时候不能休眠
 pub fn frame_alloc() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR
-        .exclusive_access()
-        .alloc()
-        .map(FrameTracker::new)
+    loop {
+        let ppn = FRAME_ALLOCATOR.exclusive_access().alloc();
+        if let Some(ppn) = ppn {
+            return Some(FrameTracker::new(ppn));
+        }
+        if !without_sleep(evict_one_page) {
+            return None;
+        }
+    }
+}
+
+/// number of frames that can still be allocated
+pub fn frame_free_count() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().free_count()
+}
+
+/// number of frames managed by the frame allocator
+pub fn frame_total_count() -> usize {
+    let allocator = FRAME_ALLOCATOR.exclusive_access();
+    return allocator.end - allocator.start;
 }
 
 /// deallocate a frame
@@ -1,15 +1,19 @@
 //! Implementation of [`MapArea`] and [`MemorySet`].
 
+use super::swap::{swap_alloc, swap_dup, swap_release};
 use super::{frame_alloc, FrameTracker};
 use super::{PTEFlags, PageTable, PageTableEntry};
 use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
 use super::{StepByOne, VPNRange,PPNRange};
-use crate::config::{MEMORY_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_STACK_SIZE};
+use crate::config::{MEMORY_END, MMAP_END, MMIO, PAGE_SIZE, TRAMPOLINE, USER_STACK_SIZE};
 use crate::sync::UPSafeCell;
 use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::fmt::{self, Debug, Formatter, Write};
 use lazy_static::*;
 use riscv::register::satp;
 
@@ -42,6 +46,9 @@ pub fn kernel_token() -> usize {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    heap_bottom: usize, //用户堆的起始地址, 紧跟在ELF各段之后
+    brk: usize,         //program break, 用户堆当前的结束地址
+    clock_hand: VirtPageNum, //换出页面时时钟算法的指针, 从这一页开始找
 }
 
 impl MemorySet {
@@ -50,6 +57,9 @@ impl MemorySet {
         Self {
             page_table: PageTable::new(),
             areas: Vec::new(),
+            heap_bottom: 0,
+            brk: 0,
+            clock_hand: VirtPageNum(0),
         }
     }
     ///Get pagetable `root_ppn`
@@ -74,6 +84,206 @@ impl MemorySet {
             None,
         );
     }
+    /// Insert a user area whose frames are allocated on first access (zero filled), e.g. user stack
+    pub fn insert_lazy_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        section_type: SectionType,
+    ) {
+        self.push_lazy(MapArea::new_lazy(
+            start_va,
+            end_va,
+            permission,
+            section_type,
+            None,
+        ));
+    }
+    /// Insert a lazily allocated area created by mmap, `shared` means changes are written back to `backing`
+    pub fn insert_mmap_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+        backing: Option<Backing>,
+        shared: bool,
+    ) {
+        let mut map_area =
+            MapArea::new_lazy(start_va, end_va, permission, SectionType::Mmap, backing);
+        map_area.shared = shared;
+        self.push_lazy(map_area);
+    }
+    /// Find `page_count` free pages for mmap, searching upwards from `hint`
+    pub fn find_free_area(&self, hint: VirtPageNum, page_count: usize) -> Option<VirtPageNum> {
+        let limit = VirtAddr::from(MMAP_END).floor();
+        let mut start = hint;
+        loop {
+            let end = VirtPageNum(start.0 + page_count);
+            if end > limit {
+                return None;
+            }
+            match self.areas.iter().find(|area| area.overlaps(start, end)) {
+                Some(area) => start = area.vpn_range.get_end(),
+                None => return Some(start),
+            }
+        }
+    }
+    /// If any area overlaps [start, end)
+    pub fn is_overlapped(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        self.areas.iter().any(|area| area.overlaps(start, end))
+    }
+    /// 把跨过 `vpn` 的按需分配区域一分为二, 这样munmap/mprotect只需要处理完整的区域
+    fn split_area_at(&mut self, vpn: VirtPageNum) {
+        if let Some(area) = self.areas.iter_mut().find(|area| {
+            area.lazy && area.vpn_range.get_start() < vpn && vpn < area.vpn_range.get_end()
+        }) {
+            let tail = area.split_off(vpn);
+            self.areas.push(tail);
+        }
+    }
+    /// Remove the mmap areas in [start, end), return None if the range touches other areas.
+    /// 设备内存的映射(显存)不能拆开, 必须整个在范围里.
+    /// 返回共享文件映射中被改过的页面, 由调用者在释放进程锁之后写回文件
+    pub fn munmap(&mut self, start: VirtPageNum, end: VirtPageNum) -> Option<Vec<WriteBack>> {
+        let removable = |area: &MapArea| match area.section_type {
+            SectionType::Mmap => true,
+            SectionType::Device => {
+                start <= area.vpn_range.get_start() && area.vpn_range.get_end() <= end
+            }
+            _ => false,
+        };
+        if self
+            .areas
+            .iter()
+            .any(|area| area.overlaps(start, end) && !removable(area))
+        {
+            return None;
+        }
+        self.split_area_at(start);
+        self.split_area_at(end);
+        let write_backs = self.shared_write_backs(start, end);
+        let starts: Vec<VirtPageNum> = self
+            .areas
+            .iter()
+            .filter(|area| area.overlaps(start, end))
+            .map(|area| area.vpn_range.get_start())
+            .collect();
+        for start_vpn in starts {
+            self.remove_area_with_start_vpn(start_vpn);
+        }
+        return Some(write_backs);
+    }
+    /// Change the permission of user pages in [start, end), return false if the range is not
+    /// completely mapped by user areas or a read-only shared file would become writable.
+    pub fn mprotect(&mut self, start: VirtPageNum, end: VirtPageNum, perm: MapPermission) -> bool {
+        let mut covered = 0;
+        for area in self.areas.iter().filter(|area| area.overlaps(start, end)) {
+            if !area.lazy {
+                return false;
+            }
+            if area.shared
+                && perm.contains(MapPermission::W)
+                && !area.backing.as_ref().map_or(true, |backing| backing.writable)
+            {
+                return false;
+            }
+            covered += area.vpn_range.get_end().0.min(end.0) - area.vpn_range.get_start().0.max(start.0);
+        }
+        if covered != end.0 - start.0 {
+            return false;
+        }
+        self.split_area_at(start);
+        self.split_area_at(end);
+        let pte_flags = PTEFlags::from_bits(perm.bits).unwrap();
+        for area in self.areas.iter_mut().filter(|area| area.overlaps(start, end)) {
+            area.map_perm = perm;
+            for vpn in area.data_frames.keys() {
+                self.page_table.set_flags(*vpn, pte_flags);
+            }
+        }
+        return true;
+    }
+    /// Collect the dirty pages of shared file mappings in [start, end)
+    pub fn shared_write_backs(&self, start: VirtPageNum, end: VirtPageNum) -> Vec<WriteBack> {
+        let mut write_backs = Vec::new();
+        for area in self.areas.iter().filter(|area| area.shared && area.overlaps(start, end)) {
+            let backing = match &area.backing {
+                Some(backing) => backing,
+                None => continue,
+            };
+            for (vpn, frame) in area.data_frames.range(start..end) {
+                let area_offset = (vpn.0 - area.vpn_range.get_start().0) * PAGE_SIZE;
+                if area_offset >= backing.len || !self.page_table.translate(*vpn).unwrap().is_dirty() {
+                    continue;
+                }
+                write_backs.push(WriteBack {
+                    file: Arc::clone(&backing.file),
+                    offset: backing.offset + area_offset,
+                    len: (backing.len - area_offset).min(PAGE_SIZE),
+                    frame: Arc::clone(frame),
+                });
+            }
+        }
+        return write_backs;
+    }
+    /// Current program break
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+    /// Move the program break to `new_brk`, return false if it is below the heap bottom
+    /// or the heap would run into another area.
+    /// 堆区域是按需分配的, 变大只需要改区域的结束页号, 变小时要释放超出部分的页帧
+    pub fn set_brk(&mut self, new_brk: usize) -> bool {
+        if new_brk < self.heap_bottom || new_brk > MMAP_END {
+            return false;
+        }
+        let old_end = VirtAddr::from(self.brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        if new_end > old_end {
+            if self.is_overlapped(old_end, new_end) {
+                return false;
+            }
+            // mprotect可能把堆分成了几段, 结尾的那一段一定结束在old_end
+            let area = self
+                .areas
+                .iter_mut()
+                .find(|area| {
+                    area.section_type == SectionType::Heap && area.vpn_range.get_end() == old_end
+                })
+                .unwrap();
+            area.vpn_range = VPNRange::new(area.vpn_range.get_start(), new_end);
+        } else if new_end < old_end {
+            self.split_area_at(new_end);
+            let starts: Vec<VirtPageNum> = self
+                .areas
+                .iter()
+                .filter(|area| {
+                    area.section_type == SectionType::Heap
+                        && area.vpn_range.get_start() >= new_end
+                        && area.vpn_range.get_start() < old_end
+                })
+                .map(|area| area.vpn_range.get_start())
+                .collect();
+            for start_vpn in starts {
+                self.remove_area_with_start_vpn(start_vpn);
+            }
+            if !self.areas.iter().any(|area| {
+                area.section_type == SectionType::Heap && area.vpn_range.get_end() == new_end
+            }) {
+                // 堆缩到底了, 留一个长度为0的区域以后继续增长
+                self.push_lazy(MapArea::new_lazy(
+                    new_end.into(),
+                    new_end.into(),
+                    MapPermission::R | MapPermission::W | MapPermission::U,
+                    SectionType::Heap,
+                    None,
+                ));
+            }
+        }
+        self.brk = new_brk;
+        return true;
+    }
     ///Remove `MapArea` that starts with `start_vpn`
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
@@ -94,6 +304,10 @@ impl MemorySet {
         }
         self.areas.push(map_area);
     }
+    /// 只登记这段区域, 不分配物理页帧, 等到缺页异常时再由 [`MemorySet::map_lazy_page`] 分配
+    pub fn push_lazy(&mut self, map_area: MapArea) {
+        self.areas.push(map_area);
+    }
     pub fn push_noalloc(&mut self, mut map_area: MapArea, ppn_range: PPNRange) {
         map_area.map_noalloc(&mut self.page_table, ppn_range);
         self.areas.push(map_area);
@@ -208,12 +422,16 @@ impl MemorySet {
     }
     /// Include sections in elf and trampoline and TrapContext and user stack,
     /// also returns user_sp and entry point.
-    pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+    ///
+    /// ELF的各个段都是按需加载的: 这里只读ELF头和程序头表, 建立MapArea, 记下段内容在 `elf_file` 里的位置,
+    /// 第一次访问某一页时才分配物理页帧并从文件读入这一页的内容(超出文件部分的.bss补0).
+    pub fn from_elf(elf_file: Arc<dyn BackingFile>) -> (Self, usize, usize) {
         let mut memory_set = Self::new_bare();
         // map trampoline
         memory_set.map_trampoline("USER APP");
         // map program headers of elf, with U flag
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+        let elf_data = read_elf_headers(&elf_file);
+        let elf = xmas_elf::ElfFile::new(&elf_data).unwrap();
         let elf_header = elf.header;
         let magic = elf_header.pt1.magic;
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
@@ -235,51 +453,236 @@ impl MemorySet {
                 if ph_flags.is_execute() {
                     map_perm |= MapPermission::X;
                 }
-                let map_area = MapArea::new(
+                let map_area = MapArea::new_lazy(
                     start_va,
                     end_va,
-                    MapType::Framed,
                     map_perm,
                     SectionType::Unknow,
+                    Some(Backing {
+                        file: Arc::clone(&elf_file),
+                        offset: ph.offset() as usize,
+                        len: ph.file_size() as usize,
+                        writable: false,
+                    }),
                 );
                 max_end_vpn = map_area.vpn_range.get_end();
-                memory_set.push(
-                    map_area,
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                memory_set.push_lazy(map_area);
             }
         }
-        // map user stack with U flags
+        // 用户堆紧跟在ELF各段之后, 一开始长度为0, 通过brk/sbrk向上增长
         let max_end_va: VirtAddr = max_end_vpn.into();
-        let mut user_stack_base: usize = max_end_va.into();
-        user_stack_base += PAGE_SIZE; //用户栈的栈底 就是ELF文件分配内存后, 再加上一个保护页之后的位置
+        memory_set.heap_bottom = max_end_va.into();
+        memory_set.brk = memory_set.heap_bottom;
+        memory_set.push_lazy(MapArea::new_lazy(
+            max_end_va,
+            max_end_va,
+            MapPermission::R | MapPermission::W | MapPermission::U,
+            SectionType::Heap,
+            None,
+        ));
+        // map user stack with U flags
+        // 用户栈放在mmap区域之上, 中间隔一个保护页, 这样堆往上长不会撞到栈
+        let user_stack_base: usize = MMAP_END + PAGE_SIZE;
         (
             memory_set,
             user_stack_base,
             elf.header.pt2.entry_point() as usize,
         )
     }
-    //Clone a same MemorySet
-    pub fn from_existed_user(user_space: &Self) -> Self {
+    /// Clone a same MemorySet for fork, sharing user pages copy-on-write.
+    ///
+    /// 用户可见的页面(带U标记)不再立即复制, 父子进程共享同一个物理页帧:
+    /// 可写的页面在父子两边都改成只读并打上COW标记, 谁先写谁触发缺页异常, 在 [`MemorySet::cow_page_fault`] 里才真正复制.
+    /// Trap上下文这种内核专用的页面, 内核是直接通过物理页帧去写的, 不会触发缺页异常, 所以仍然立即复制.
+    pub fn from_existed_user(user_space: &mut Self) -> Self {
         let mut memory_set = Self::new_bare();
+        memory_set.heap_bottom = user_space.heap_bottom;
+        memory_set.brk = user_space.brk;
         // map trampoline
         memory_set.map_trampoline("from_existed_user");
-        // copy data sections/trap_context/user_stack
         for area in user_space.areas.iter() {
-            let new_area = MapArea::from_another(area);
-            memory_set.push(new_area, None);
-            //copy data from another space
-            for vpn in area.vpn_range {
-                let src_ppn = user_space.translate(vpn).unwrap().ppn();
-                let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
-                dst_ppn
-                    .get_bytes_array()
-                    .copy_from_slice(src_ppn.get_bytes_array());
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type == MapType::Framed && !area.map_perm.contains(MapPermission::U) {
+                // copy trap_context eagerly
+                memory_set.push(new_area, None);
+                for vpn in area.vpn_range {
+                    let src_ppn = user_space.translate(vpn).unwrap().ppn();
+                    let dst_ppn = memory_set.translate(vpn).unwrap().ppn();
+                    dst_ppn
+                        .get_bytes_array()
+                        .copy_from_slice(src_ppn.get_bytes_array());
+                }
+                continue;
+            }
+            // share data sections/user_stack/framebuffer
+            // 只读的页面也要打上COW标记, 之后mprotect加上写权限时才不会写到别的进程的页帧;
+            // MAP_SHARED的映射本来就是要共享的, 不需要写时复制
+            let cow = area.map_type == MapType::Framed && !area.shared;
+            // 子进程里这些页面的内容不一定和文件一致, 标记为脏页, 换出时不能直接丢弃
+            let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap() | PTEFlags::D;
+            for (vpn, frame) in area.data_frames.iter() {
+                memory_set.page_table.map(*vpn, frame.ppn, pte_flags);
+                if cow {
+                    memory_set.page_table.set_cow(*vpn);
+                    user_space.page_table.set_cow(*vpn);
+                }
+                new_area.data_frames.insert(*vpn, Arc::clone(frame));
+            }
+            // 已经换出的页面, 父子进程共享交换区里的同一个槽
+            for (vpn, slot) in area.swapped.iter() {
+                swap_dup(*slot);
+                new_area.swapped.insert(*vpn, *slot);
             }
+            memory_set.areas.push(new_area);
         }
         return memory_set;
     }
 
+    /// Handle a store page fault on a copy-on-write page, return false if `vpn` is not copy-on-write.
+    /// 如果该物理页帧已经只剩自己在用(其他进程已经复制走或者退出了), 就不用复制, 直接恢复写权限
+    pub fn cow_page_fault(&mut self, vpn: VirtPageNum) -> bool {
+        match self.page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.is_cow() => {}
+            _ => return false,
+        }
+        let area = match self.areas.iter_mut().find(|area| area.contains(vpn)) {
+            Some(area) => area,
+            None => return false,
+        };
+        if !area.map_perm.contains(MapPermission::W) {
+            return false;
+        }
+        let pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+        let frame = area.data_frames.get(&vpn).unwrap();
+        if Arc::strong_count(frame) == 1 {
+            let ppn = frame.ppn;
+            self.page_table.remap_cow(vpn, ppn, pte_flags);
+        } else {
+            let new_frame = frame_alloc().unwrap();
+            new_frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(frame.ppn.get_bytes_array());
+            let ppn = new_frame.ppn;
+            area.data_frames.insert(vpn, Arc::new(new_frame));
+            self.page_table.remap_cow(vpn, ppn, pte_flags);
+        }
+        return true;
+    }
+
+    /// Check whether a page fault at `vpn` hits a lazily mapped page that is not loaded yet,
+    /// and return what the page should be filled with.
+    /// 从文件读数据可能会让当前线程休眠, 所以真正的读取由调用者在释放进程锁之后完成
+    pub fn lazy_page(&self, vpn: VirtPageNum) -> Option<LazyPage> {
+        let area = self
+            .areas
+            .iter()
+            .find(|area| area.lazy && area.contains(vpn))?;
+        if area.data_frames.contains_key(&vpn) {
+            return None;
+        }
+        let mut page = LazyPage {
+            file: None,
+            offset: 0,
+            len: 0,
+            swap_slot: area.swapped.get(&vpn).copied(),
+        };
+        if page.swap_slot.is_some() {
+            return Some(page);
+        }
+        if let Some(backing) = &area.backing {
+            // backing从区域起始处开始对齐, 和copy_data的假设一样
+            let area_offset = (vpn.0 - area.vpn_range.get_start().0) * PAGE_SIZE;
+            if area_offset < backing.len {
+                page.file = Some(Arc::clone(&backing.file));
+                page.offset = backing.offset + area_offset;
+                page.len = (backing.len - area_offset).min(PAGE_SIZE);
+            }
+        }
+        return Some(page);
+    }
+
+    /// Map a filled `frame` at `vpn` of a lazy area, return false if the page was mapped meanwhile.
+    /// `dirty`: 从交换区换入的页面内容和文件不一致, 要标记为脏页
+    pub fn map_lazy_page(&mut self, vpn: VirtPageNum, frame: FrameTracker, dirty: bool) -> bool {
+        let area = match self
+            .areas
+            .iter_mut()
+            .find(|area| area.lazy && area.contains(vpn))
+        {
+            Some(area) => area,
+            None => return false,
+        };
+        if area.data_frames.contains_key(&vpn) {
+            return false;
+        }
+        let mut pte_flags = PTEFlags::from_bits(area.map_perm.bits).unwrap();
+        if dirty {
+            pte_flags |= PTEFlags::D;
+        }
+        self.page_table.map(vpn, frame.ppn, pte_flags);
+        area.data_frames.insert(vpn, Arc::new(frame));
+        if let Some(slot) = area.swapped.remove(&vpn) {
+            swap_release(slot);
+        }
+        return true;
+    }
+
+    /// Pick a victim user page with the clock (second chance) algorithm and evict it,
+    /// return None if no page can be evicted.
+    ///
+    /// 访问位A为1的页面清掉A, 再给一次机会; 没被写过(D为0)的页面内容可以从文件或者全0重新得到, 直接丢弃;
+    /// 否则分配交换区的槽, 由调用者在释放进程锁之后调用 [`super::swap_write`] 写入磁盘.
+    /// 和其他进程共享的页面(写时复制)不换出.
+    pub fn evict_one(&mut self) -> Option<Evicted> {
+        let mut candidates: Vec<VirtPageNum> = Vec::new();
+        for area in self.areas.iter().filter(|area| area.lazy && !area.shared) {
+            for (vpn, frame) in area.data_frames.iter() {
+                if Arc::strong_count(frame) == 1 {
+                    candidates.push(*vpn);
+                }
+            }
+        }
+        if candidates.is_empty() {
+            return None;
+        }
+        candidates.sort();
+        let start = candidates
+            .iter()
+            .position(|vpn| *vpn >= self.clock_hand)
+            .unwrap_or(0);
+        // 转两圈: 第一圈清掉的访问位, 第二圈就可以换出了
+        for i in 0..candidates.len() * 2 {
+            let vpn = candidates[(start + i) % candidates.len()];
+            let pte = self.page_table.translate(vpn).unwrap();
+            if pte.is_accessed() {
+                self.page_table.clear_accessed(vpn);
+                continue;
+            }
+            let area = self
+                .areas
+                .iter_mut()
+                .find(|area| area.contains(vpn))
+                .unwrap();
+            let evicted = if pte.is_dirty() {
+                match swap_alloc(Arc::clone(area.data_frames.get(&vpn).unwrap())) {
+                    Some(slot) => {
+                        area.swapped.insert(vpn, slot);
+                        Evicted::Swapped(slot)
+                    }
+                    None => continue, //交换区满了
+                }
+            } else {
+                Evicted::Clean
+            };
+            area.data_frames.remove(&vpn);
+            self.page_table.unmap(vpn);
+            self.clock_hand = VirtPageNum(vpn.0 + 1);
+            return Some(evicted);
+        }
+        return None;
+    }
+
     pub fn activate(&self) {
         let satp = self.page_table.token();
         unsafe {
@@ -290,20 +693,142 @@ impl MemorySet {
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
+    /// Number of pages of all areas: (mapped, in memory, swapped out)
+    pub fn page_counts(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for area in self.areas.iter() {
+            counts.0 += area.vpn_range.get_end().0 - area.vpn_range.get_start().0;
+            counts.1 += area.data_frames.len();
+            counts.2 += area.swapped.len();
+        }
+        return counts;
+    }
+    /// One line for each area like /proc/<pid>/maps: 地址范围, 权限, 映射的文件偏移, 区域类型
+    pub fn maps(&self) -> String {
+        let mut areas: Vec<&MapArea> = self.areas.iter().collect();
+        areas.sort_by_key(|area| area.vpn_range.get_start());
+        let mut maps = String::new();
+        for area in areas {
+            let start: VirtAddr = area.vpn_range.get_start().into();
+            let end: VirtAddr = area.vpn_range.get_end().into();
+            let perm =
+                |flag: MapPermission, c: char| if area.map_perm.contains(flag) { c } else { '-' };
+            let offset = area.backing.as_ref().map_or(0, |backing| backing.offset);
+            let _ = writeln!(
+                maps,
+                "{:016x}-{:016x} {}{}{}{} {:08x} {:?}",
+                start.0,
+                end.0,
+                perm(MapPermission::R, 'r'),
+                perm(MapPermission::W, 'w'),
+                perm(MapPermission::X, 'x'),
+                if area.shared { 's' } else { 'p' },
+                offset,
+                area.section_type
+            );
+        }
+        return maps;
+    }
     ///Remove all `MapArea`
     pub fn recycle_data_pages(&mut self) {
         self.areas.clear();
     }
 }
 
+/// Size of the ELF header of a 64-bit ELF
+const ELF64_HEADER_SIZE: usize = 64;
+
+/// Read the ELF header and the program header table of `elf_file`.
+/// 程序头表一般紧跟在ELF头后面, 加起来只有几百字节, 不用把整个文件读进内核
+fn read_elf_headers(elf_file: &Arc<dyn BackingFile>) -> Vec<u8> {
+    let mut data = vec![0u8; ELF64_HEADER_SIZE];
+    let len = elf_file.read_at(0, &mut data);
+    data.truncate(len);
+    let header = xmas_elf::header::parse_header(&data).unwrap();
+    let ph_end = header.pt2.ph_offset() as usize
+        + header.pt2.ph_count() as usize * header.pt2.ph_entry_size() as usize;
+    if ph_end > data.len() {
+        let start = data.len();
+        data.resize(ph_end, 0);
+        let len = elf_file.read_at(start, &mut data[start..]);
+        assert_eq!(start + len, ph_end, "truncated elf!");
+    }
+    return data;
+}
+
+/// A file that lazily mapped pages can be loaded from
+pub trait BackingFile: Send + Sync {
+    /// Read bytes starting at `offset` of the file into `buf`, return the number of bytes read
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Write `buf` to the file starting at `offset`, return the number of bytes written
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize;
+    /// Size of the file in bytes
+    fn size(&self) -> usize;
+}
+
+/// The part of a file backing a lazy area: `len` bytes from `offset`, placed at the start of the area
+#[derive(Clone)]
+pub struct Backing {
+    pub file: Arc<dyn BackingFile>,
+    pub offset: usize,
+    pub len: usize,
+    pub writable: bool, //文件是否以可写方式打开, 决定共享映射能不能有写权限
+}
+
+impl Debug for Backing {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!(
+            "Backing: offset={:#x}, len={:#x}, writable={}",
+            self.offset, self.len, self.writable
+        ))
+    }
+}
+
+/// A dirty page of a shared file mapping that should be written back to the file
+pub struct WriteBack {
+    file: Arc<dyn BackingFile>,
+    offset: usize,
+    len: usize,
+    frame: Arc<FrameTracker>,
+}
+
+impl WriteBack {
+    /// 写回文件, 可能会休眠, 不能在持有进程锁的时候调用
+    pub fn write(&self) {
+        self.file
+            .write_at(self.offset, &self.frame.ppn.get_bytes_array()[..self.len]);
+    }
+}
+
+/// What a lazily mapped page should be filled with: the page in `swap_slot` if it was swapped out,
+/// otherwise zeros, then `len` bytes of `file` from `offset`
+pub struct LazyPage {
+    pub file: Option<Arc<dyn BackingFile>>,
+    pub offset: usize,
+    pub len: usize,
+    pub swap_slot: Option<usize>,
+}
+
+/// A page evicted by [`MemorySet::evict_one`]
+pub enum Evicted {
+    /// 页面没被写过, 直接丢弃
+    Clean,
+    /// 页面放进了交换区的这个槽, 还需要写入磁盘
+    Swapped(usize),
+}
+
 /// map area structure, controls a contiguous piece of virtual memory
 #[derive(Debug)]
 pub struct MapArea {
     vpn_range: VPNRange,
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>,
+    data_frames: BTreeMap<VirtPageNum, Arc<FrameTracker>>, //fork之后可能和其他进程共享(写时复制)
     map_type: MapType,
     map_perm: MapPermission,
     section_type: SectionType,
+    lazy: bool,               //按需分配: 只有被访问过的页面才在data_frames里
+    backing: Option<Backing>, //按需加载时页面内容的来源, 没有的话就是全0
+    shared: bool,             //MAP_SHARED: fork之后父子进程共享, 修改要写回文件
+    swapped: BTreeMap<VirtPageNum, usize>, //换出到交换区的页面 -> 交换区的槽号
 }
 
 impl MapArea {
@@ -322,8 +847,25 @@ impl MapArea {
             map_type,
             map_perm,
             section_type,
+            lazy: false,
+            backing: None,
+            shared: false,
+            swapped: BTreeMap::new(),
         }
     }
+    /// Create a framed area whose pages are allocated (and loaded from `backing`) on first access
+    pub fn new_lazy(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_perm: MapPermission,
+        section_type: SectionType,
+        backing: Option<Backing>,
+    ) -> Self {
+        let mut area = Self::new(start_va, end_va, MapType::Framed, map_perm, section_type);
+        area.lazy = true;
+        area.backing = backing;
+        return area;
+    }
     pub fn from_another(another: &Self) -> Self {
         Self {
             vpn_range: VPNRange::new(another.vpn_range.get_start(), another.vpn_range.get_end()),
@@ -331,8 +873,40 @@ impl MapArea {
             map_type: another.map_type,
             map_perm: another.map_perm,
             section_type: another.section_type,
+            lazy: another.lazy,
+            backing: another.backing.clone(),
+            shared: another.shared,
+            swapped: BTreeMap::new(),
         }
     }
+    /// Split a lazy area at `vpn`: self keeps [start, vpn) and the returned area gets [vpn, end)
+    pub fn split_off(&mut self, vpn: VirtPageNum) -> Self {
+        assert!(self.lazy && self.contains(vpn));
+        let start = self.vpn_range.get_start();
+        let mut tail = Self::from_another(self);
+        tail.vpn_range = VPNRange::new(vpn, self.vpn_range.get_end());
+        tail.data_frames = self.data_frames.split_off(&vpn);
+        tail.swapped = self.swapped.split_off(&vpn);
+        self.vpn_range = VPNRange::new(start, vpn);
+        let head_len = (vpn.0 - start.0) * PAGE_SIZE;
+        if let Some(backing) = &mut tail.backing {
+            backing.offset += head_len;
+            backing.len = backing.len.saturating_sub(head_len);
+        }
+        if let Some(backing) = &mut self.backing {
+            backing.len = backing.len.min(head_len);
+        }
+        return tail;
+    }
+    /// If `vpn` is inside this area
+    pub fn contains(&self, vpn: VirtPageNum) -> bool {
+        self.vpn_range.get_start() <= vpn && vpn < self.vpn_range.get_end()
+    }
+    /// If this area overlaps [start, end), an empty area (heap before brk grows) overlaps nothing
+    pub fn overlaps(&self, start: VirtPageNum, end: VirtPageNum) -> bool {
+        let (area_start, area_end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        area_start < area_end && area_start < end && start < area_end
+    }
     pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
         let ppn: PhysPageNum;
         match self.map_type {
@@ -342,7 +916,7 @@ impl MapArea {
             MapType::Framed => {
                 let frame = frame_alloc().unwrap();
                 ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+                self.data_frames.insert(vpn, Arc::new(frame));
             }
             MapType::Noalloc => {
                 panic!("Noalloc should not be mapped");
@@ -353,8 +927,9 @@ impl MapArea {
     }
     #[allow(unused)]
     pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        if self.map_type == MapType::Framed {
-            self.data_frames.remove(&vpn);
+        if self.map_type == MapType::Framed && self.data_frames.remove(&vpn).is_none() {
+            // 按需分配的页面从来没被访问过, 也就没有映射
+            return;
         }
         page_table.unmap(vpn);
     }
@@ -365,7 +940,7 @@ impl MapArea {
     }
     pub fn map_noalloc(&mut self, page_table: &mut PageTable, ppn_range: PPNRange) {
         for (vpn, ppn) in core::iter::zip(self.vpn_range, ppn_range) {
-            self.data_frames.insert(vpn, FrameTracker::new_noalloc(ppn));
+            self.data_frames.insert(vpn, Arc::new(FrameTracker::new_noalloc(ppn)));
             let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
             page_table.map(vpn, ppn, pte_flags);
         }
@@ -401,6 +976,14 @@ impl MapArea {
     }
 }
 
+impl Drop for MapArea {
+    fn drop(&mut self) {
+        for slot in self.swapped.values() {
+            swap_release(*slot);
+        }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 /// map type for memory set: identical or framed
 pub enum MapType {
@@ -421,6 +1004,7 @@ pub enum SectionType {
     TrapContext,
     Kernel_Stack,
     Device,
+    Mmap,
 }
 
 bitflags! {
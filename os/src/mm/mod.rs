@@ -11,14 +11,18 @@ mod frame_allocator;
 mod heap_allocator;
 mod memory_set;
 mod page_table;
+mod swap;
 
 pub use address::{ VPNRange,PPNRange};
 pub use address::{PhysAddr, PhysPageNum,StepByOne, VirtAddr, VirtPageNum};
-pub use frame_allocator::{frame_alloc, frame_dealloc,FrameTracker};
+pub use frame_allocator::{
+    frame_alloc, frame_dealloc, frame_free_count, frame_total_count, FrameTracker,
+};
 pub use memory_set::remap_test;
-pub use memory_set::{kernel_token,MapArea,MapPermission,MapType,SectionType, MemorySet, KERNEL_SPACE};
+pub use memory_set::{kernel_token,Backing,BackingFile,Evicted,LazyPage,WriteBack,MapArea,MapPermission,MapType,SectionType, MemorySet, KERNEL_SPACE};
 use page_table::PTEFlags;
-pub use page_table::{translated_byte_buffer,translated_ref, translated_refmut, translated_str,PageTable, PageTableEntry,UserBuffer};
+pub use swap::{swap_free_count, swap_in, swap_total_count, swap_write};
+pub use page_table::{copy_to_user, translated_byte_buffer,translated_ref, translated_refmut, translated_str,PageTable, PageTableEntry,UserBuffer};
 
 /// initiate heap allocator, frame allocator and kernel space
 pub fn init() {
@@ -19,6 +19,10 @@ bitflags! {
     }
 }
 
+/// PTE 的第8位属于 RSW (Reserved for Software), 硬件不会使用.
+/// 这里用来标记写时复制(Copy On Write)的页面: 该页面本身可写, 只是暂时和其他进程共享同一个物理页帧
+const PTE_COW: usize = 1 << 8;
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 /// page table entry structure
@@ -54,6 +58,18 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    /// If the page has been accessed since the A bit was cleared
+    pub fn is_accessed(&self) -> bool {
+        (self.flags() & PTEFlags::A) != PTEFlags::empty()
+    }
+    /// If the page has been written since it was mapped
+    pub fn is_dirty(&self) -> bool {
+        (self.flags() & PTEFlags::D) != PTEFlags::empty()
+    }
+    /// If the page is shared copy-on-write
+    pub fn is_cow(&self) -> bool {
+        (self.bits & PTE_COW) != 0
+    }
 }
 
 /// page table structure
@@ -128,6 +144,42 @@ impl PageTable {
         assert!(pte.is_valid(), "vpn {:?} is invalid before unmapping", vpn);
         *pte = PageTableEntry::empty();
     }
+    /// 把一个已经映射的页面变成写时复制页面: 去掉写权限, 打上COW标记
+    pub fn set_cow(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before set cow", vpn);
+        pte.bits = (pte.bits & !(PTEFlags::W.bits as usize)) | PTE_COW;
+    }
+    /// 写时复制页面 复制完成后, 指向新的物理页帧(也可能还是原来那个), 并恢复原本的权限
+    pub fn remap_cow(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_cow(), "vpn {:?} is not copy-on-write", vpn);
+        // 复制出来的内容和文件里的不一样了, 标记为脏页, 换出时不能直接丢弃
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V | PTEFlags::D);
+    }
+    /// 时钟算法给页面第二次机会: 清掉访问位
+    pub fn clear_accessed(&mut self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        pte.bits &= !(PTEFlags::A.bits as usize);
+    }
+    /// 内核通过物理地址写用户页面时, 硬件不会设置脏位, 需要手动设置
+    pub fn set_dirty(&self, vpn: VirtPageNum) {
+        let pte = self.find_pte(vpn).unwrap();
+        pte.bits |= (PTEFlags::A | PTEFlags::D).bits as usize;
+    }
+    /// mprotect 修改已映射页面的权限, 写时复制的页面继续保持只读, 等写的时候再复制
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) {
+        let pte = self.find_pte(vpn).unwrap();
+        assert!(pte.is_valid(), "vpn {:?} is invalid before set flags", vpn);
+        // 访问位/脏位要保留, 换出页面时还要用
+        let flags = flags | PTEFlags::V | (pte.flags() & (PTEFlags::A | PTEFlags::D));
+        if pte.is_cow() {
+            *pte = PageTableEntry::new(pte.ppn(), flags - PTEFlags::W);
+            pte.bits |= PTE_COW;
+        } else {
+            *pte = PageTableEntry::new(pte.ppn(), flags);
+        }
+    }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.find_pte(vpn).map(|pte| *pte)
     }
@@ -146,8 +198,34 @@ impl PageTable {
     }
 }
 
-/// translate a pointer to a mutable u8 Vec through page table
-pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&'static mut [u8]> {
+/// 内核直接通过物理地址读写用户内存, 不会触发缺页异常.
+/// 所以遇到还没加载的按需分配页面, 或者要写写时复制的共享页面, 要在这里替当前进程先处理缺页,
+/// 否则会读到别处的物理内存, 或者改到其他进程的数据
+fn translate_user_page(page_table: &PageTable, vpn: VirtPageNum, write: bool) -> PhysPageNum {
+    match page_table.translate(vpn) {
+        Some(pte) if pte.is_valid() && !(write && pte.is_cow()) => {
+            if write {
+                page_table.set_dirty(vpn);
+            }
+            return pte.ppn();
+        }
+        _ => {}
+    }
+    crate::task::current_process().handle_page_fault(vpn);
+    if write {
+        page_table.set_dirty(vpn);
+    }
+    return page_table.translate(vpn).unwrap().ppn();
+}
+
+/// translate a pointer to a mutable u8 Vec through page table.
+/// 内核只读这块用户内存时`write`为false, 不会打破写时复制的共享, 也不会把页面标记为脏
+pub fn translated_byte_buffer(
+    token: usize,
+    ptr: *const u8,
+    len: usize,
+    write: bool,
+) -> Vec<&'static mut [u8]> {
     let page_table = PageTable::from_token(token);
     let mut start = ptr as usize;
     let end = start + len;
@@ -155,7 +233,7 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = translate_user_page(&page_table, vpn, write);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -175,10 +253,9 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let mut string = String::new();
     let mut va = ptr as usize;
     loop {
-        let ch: u8 = *(page_table
-            .translate_va(VirtAddr::from(va))
-            .unwrap()
-            .get_mut());
+        let va_ = VirtAddr::from(va);
+        let pa: PhysAddr = translate_user_page(&page_table, va_.floor(), false).into();
+        let ch: u8 = *PhysAddr::from(pa.0 + va_.page_offset()).get_mut::<u8>();
         if ch == 0 {
             break;
         }
@@ -192,10 +269,9 @@ pub fn translated_str(token: usize, ptr: *const u8) -> String {
 /// 传入用户内存空间的token, 和对应用户APP的指针(指向的地址当然是虚拟地址), 作为操作系统必然需要把指针指向的虚拟的位置, 解释成物理地址, 并变成对应的T type类型的实例(只读)
 pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
     let page_table = PageTable::from_token(token);
-    page_table
-        .translate_va(VirtAddr::from(ptr as usize))
-        .unwrap()
-        .get_ref()
+    let va = VirtAddr::from(ptr as usize);
+    let pa: PhysAddr = translate_user_page(&page_table, va.floor(), false).into();
+    PhysAddr::from(pa.0 + va.page_offset()).get_ref()
 }
 
 ///translate a generic through page table and return a mutable reference
@@ -203,12 +279,24 @@ pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
 pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
     //log!("into translated_refmut!");
     let page_table = PageTable::from_token(token);
-    let va = ptr as usize;
+    let va = VirtAddr::from(ptr as usize);
     //log!("translated_refmut: before translate_va");
-    page_table
-        .translate_va(VirtAddr::from(va))
-        .unwrap()
-        .get_mut()
+    let ppn = translate_user_page(&page_table, va.floor(), true);
+    let pa: PhysAddr = ppn.into();
+    PhysAddr::from(pa.0 + va.page_offset()).get_mut()
+}
+
+/// Copy `value` to user space at `ptr`, 和translated_refmut不同, 这里的值可以跨过页面边界
+pub fn copy_to_user<T>(token: usize, ptr: *mut T, value: &T) {
+    let src = unsafe {
+        core::slice::from_raw_parts(value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    let mut start = 0;
+    let buffers = translated_byte_buffer(token, ptr as *const u8, src.len(), true);
+    for buffer in buffers {
+        buffer.copy_from_slice(&src[start..start + buffer.len()]);
+        start += buffer.len();
+    }
 }
 
 /// Array of u8 slice that user communicate with os
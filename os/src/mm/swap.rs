@@ -0,0 +1,145 @@
+//! Swap space on the block device.
+//!
+//! 交换区是块设备上紧跟在文件系统之后的一段保留区域, 每个槽(slot)存放一个被换出的页面.
+//! 文件系统的大小是做镜像时定的, 所以交换区的起始块号从挂载的超级块里读.
+//! fork之后父子进程可能引用同一个槽, 所以槽有引用计数, 计数为0时才回收.
+
+use super::{frame_alloc, FrameTracker};
+use crate::config::{PAGE_SIZE, SWAP_BLOCK_COUNT};
+use crate::drivers::BLOCK_DEVICE;
+use crate::fs::root_fs_blocks;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use easy_fs::{BlockDevice, BLOCK_SZ};
+use lazy_static::*;
+
+const BLOCKS_PER_SLOT: usize = PAGE_SIZE / BLOCK_SZ;
+
+/// Location of the swap area on the block device
+struct SwapArea {
+    start_block: usize,
+    slot_count: usize,
+}
+
+lazy_static! {
+    /// 镜像的大小可以用 --size 改, 交换区总是从文件系统的最后一块之后开始.
+    /// 磁盘在文件系统后面放不下整个交换区时只用放得下的部分, 一个槽都放不下就不换出
+    static ref SWAP_AREA: SwapArea = {
+        let start_block = root_fs_blocks();
+        let free_blocks = (BLOCK_DEVICE.capacity() / BLOCK_SZ).saturating_sub(start_block);
+        let blocks = free_blocks.min(SWAP_BLOCK_COUNT);
+        if blocks < SWAP_BLOCK_COUNT {
+            log!("[KERNEL] only {} blocks after easy-fs for the swap area", free_blocks);
+        }
+        SwapArea {
+            start_block,
+            slot_count: blocks / BLOCKS_PER_SLOT,
+        }
+    };
+}
+
+/// Allocator and reference counts of swap slots
+pub struct SwapManager {
+    current: usize,
+    recycled: Vec<usize>,
+    refs: BTreeMap<usize, usize>, //槽号 -> 引用计数
+    writing: BTreeMap<usize, Arc<FrameTracker>>, //正在写入磁盘的槽, 写完之前换入直接从页帧复制
+}
+
+impl SwapManager {
+    pub fn new() -> Self {
+        Self {
+            current: 0,
+            recycled: Vec::new(),
+            refs: BTreeMap::new(),
+            writing: BTreeMap::new(),
+        }
+    }
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.recycled.pop() {
+            Some(slot)
+        } else if self.current == SWAP_AREA.slot_count {
+            None
+        } else {
+            self.current += 1;
+            Some(self.current - 1)
+        }
+    }
+    fn release(&mut self, slot: usize) {
+        let count = self.refs.get_mut(&slot).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.refs.remove(&slot);
+            self.recycled.push(slot);
+        }
+    }
+}
+
+lazy_static! {
+    pub static ref SWAP_MANAGER: UPSafeCell<SwapManager> =
+        unsafe { UPSafeCell::new(SwapManager::new()) };
+}
+
+fn slot_block(slot: usize, i: usize) -> usize {
+    SWAP_AREA.start_block + slot * BLOCKS_PER_SLOT + i
+}
+
+/// Allocate a slot for `frame` that is being swapped out, return None if the swap area is full.
+/// 写入磁盘由 [`swap_write`] 完成, 在那之前写入方也持有一个引用, 防止槽被回收后又分配给别人
+pub fn swap_alloc(frame: Arc<FrameTracker>) -> Option<usize> {
+    let mut manager = SWAP_MANAGER.exclusive_access();
+    let slot = manager.alloc()?;
+    manager.refs.insert(slot, 2);
+    manager.writing.insert(slot, frame);
+    return Some(slot);
+}
+
+/// Write the frame of a slot allocated by [`swap_alloc`] to disk, may sleep
+pub fn swap_write(slot: usize) {
+    let frame = Arc::clone(SWAP_MANAGER.exclusive_access().writing.get(&slot).unwrap());
+    let bytes = frame.ppn.get_bytes_array();
+    BLOCK_DEVICE.write_blocks(slot_block(slot, 0), bytes);
+    let mut manager = SWAP_MANAGER.exclusive_access();
+    manager.writing.remove(&slot);
+    manager.release(slot);
+}
+
+/// Read a swapped out page into a new frame, may sleep. The slot is not released.
+pub fn swap_in(slot: usize) -> FrameTracker {
+    let frame = frame_alloc().unwrap();
+    let writing = SWAP_MANAGER
+        .exclusive_access()
+        .writing
+        .get(&slot)
+        .map(Arc::clone);
+    let bytes = frame.ppn.get_bytes_array();
+    if let Some(old_frame) = writing {
+        bytes.copy_from_slice(old_frame.ppn.get_bytes_array());
+    } else {
+        BLOCK_DEVICE.read_blocks(slot_block(slot, 0), bytes);
+    }
+    return frame;
+}
+
+/// Number of slots in the swap area
+pub fn swap_total_count() -> usize {
+    SWAP_AREA.slot_count
+}
+
+/// Number of slots that can still be allocated
+pub fn swap_free_count() -> usize {
+    let manager = SWAP_MANAGER.exclusive_access();
+    SWAP_AREA.slot_count - manager.current + manager.recycled.len()
+}
+
+/// Another area refers to the slot (fork)
+pub fn swap_dup(slot: usize) {
+    *SWAP_MANAGER.exclusive_access().refs.get_mut(&slot).unwrap() += 1;
+}
+
+/// An area no longer refers to the slot
+pub fn swap_release(slot: usize) {
+    SWAP_MANAGER.exclusive_access().release(slot);
+}
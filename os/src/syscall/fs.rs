@@ -1,7 +1,11 @@
 //! File and filesystem-related syscalls
 
-use crate::fs::{eventfd_create, make_pipe, open_file, Eventfd, EventfdFlags, OpenFlags};
-use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
+use crate::fs::{
+    eventfd_create, link_file, make_dir, make_pipe, mount, open_fs, open_path, read_link,
+    rename_file, stat_file, symlink_file, sync_fs, truncate_file, umount, unlink_file, Eventfd,
+    EventfdFlags, OpenFlags, Stat,
+};
+use crate::mm::{copy_to_user, translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
 use crate::task::{
     current_process, current_task, current_user_token, suspend_current_and_run_next,
 };
@@ -22,7 +26,7 @@ pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
         let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len, false))) as isize
     } else {
         return -1;
     }
@@ -42,7 +46,91 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
         }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        file.read(UserBuffer::new(translated_byte_buffer(token, buf, len, true))) as isize
+    } else {
+        return -1;
+    }
+}
+
+/// Read the entries of directory `fd` into `buf` as `linux_dirent64` records.
+/// Return the size filled, 0 at the end of directory, or -1 on error
+pub fn sys_getdents64(fd: usize, buf: *mut u8, len: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        drop(inner);
+        match file.getdents(UserBuffer::new(translated_byte_buffer(token, buf, len, true))) {
+            Some(size) => return size as isize,
+            None => return -1,
+        }
+    } else {
+        return -1;
+    }
+}
+
+/// Move the offset of file `fd`, return the new offset
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        drop(inner);
+        return file.seek(offset, whence);
+    } else {
+        return -1;
+    }
+}
+
+/// Read `len` bytes from `offset` of file `fd` into `buf`, the file offset is not changed
+pub fn sys_pread64(fd: usize, buf: *mut u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        if !file.readable() {
+            return -1;
+        }
+        drop(inner);
+        match file.read_at(offset, UserBuffer::new(translated_byte_buffer(token, buf, len, true))) {
+            Some(size) => return size as isize,
+            None => return -1,
+        }
+    } else {
+        return -1;
+    }
+}
+
+/// Write `len` bytes of `buf` to `offset` of file `fd`, the file offset is not changed
+pub fn sys_pwrite64(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    if let Some(file) = &inner.fd_table[fd] {
+        let file = file.clone();
+        if !file.writable() {
+            return -1;
+        }
+        drop(inner);
+        let buf = UserBuffer::new(translated_byte_buffer(token, buf, len, false));
+        match file.write_at(offset, buf) {
+            Some(size) => return size as isize,
+            None => return -1,
+        }
     } else {
         return -1;
     }
@@ -52,10 +140,11 @@ pub fn sys_open(path: *const u8, flags: u32) -> isize {
     let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
+    let cwd = process.cwd();
+    if let Some(file) = open_path(&cwd, path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
         let mut inner = process.inner_exclusive_access();
         let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
+        inner.fd_table[fd] = Some(file);
         fd as isize
     } else {
         return -1;
@@ -90,6 +179,8 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
     // pipe[0] = read_fd;
     // pipe[1] = write_fd;
     log!("\x1b[38;5;208m[SYSCALL : pipe] Create Pipe, User Pipe Point [{:?}] Read fd [{}] and Write fd [{}]  \x1b[0m",pipe, read_fd, write_fd);
+    // 写用户内存时可能要处理写时复制, 会再次访问PCB, 所以先释放
+    drop(inner);
     *translated_refmut(token, pipe) = read_fd;
     *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
     return 0;
@@ -134,3 +225,220 @@ pub fn sys_eventfd(initval: u32, flags: u32) -> isize {
     //flag 不合法 返回 -1
     //创建的文件描述符数量超过进程限制  返回 -1
 }
+
+/// 目前还没有目录的文件描述符, dirfd只能是AT_FDCWD, 相对路径从当前目录开始
+const AT_FDCWD: isize = -100;
+/// unlinkat删除的是空目录(相当于rmdir)
+const AT_REMOVEDIR: u32 = 0x200;
+/// fstatat不跟随最后一个符号链接(相当于lstat)
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+
+/// Create the directory `path`, 没有权限检查, `mode`被忽略
+pub fn sys_mkdirat(dirfd: isize, path: *const u8, _mode: u32) -> isize {
+    if dirfd != AT_FDCWD {
+        return -1;
+    }
+    let path = translated_str(current_user_token(), path);
+    if make_dir(&current_process().cwd(), path.as_str()) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Remove the file at `path`, or the empty directory if `flags` contains AT_REMOVEDIR
+pub fn sys_unlinkat(dirfd: isize, path: *const u8, flags: u32) -> isize {
+    if dirfd != AT_FDCWD || flags & !AT_REMOVEDIR != 0 {
+        return -1;
+    }
+    let path = translated_str(current_user_token(), path);
+    if unlink_file(&current_process().cwd(), path.as_str(), flags & AT_REMOVEDIR != 0) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Create a hard link `new_path` to the regular file `old_path`
+pub fn sys_linkat(
+    old_dirfd: isize,
+    old_path: *const u8,
+    new_dirfd: isize,
+    new_path: *const u8,
+    flags: u32,
+) -> isize {
+    if old_dirfd != AT_FDCWD || new_dirfd != AT_FDCWD || flags != 0 {
+        return -1;
+    }
+    let token = current_user_token();
+    let old_path = translated_str(token, old_path);
+    let new_path = translated_str(token, new_path);
+    if link_file(&current_process().cwd(), old_path.as_str(), new_path.as_str()) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Create a symlink `link_path` to `target`, `target`可以不存在
+pub fn sys_symlinkat(target: *const u8, new_dirfd: isize, link_path: *const u8) -> isize {
+    if new_dirfd != AT_FDCWD {
+        return -1;
+    }
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let link_path = translated_str(token, link_path);
+    if symlink_file(&current_process().cwd(), target.as_str(), link_path.as_str()) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Read the target of the symlink `path` into `buf`, 不以'\0'结尾, 放不下的部分被截掉.
+/// Return the size read, or -1 if `path` is not a symlink
+pub fn sys_readlinkat(dirfd: isize, path: *const u8, buf: *mut u8, size: usize) -> isize {
+    if dirfd != AT_FDCWD {
+        return -1;
+    }
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let target = match read_link(&current_process().cwd(), path.as_str()) {
+        Some(target) => target,
+        None => return -1,
+    };
+    let len = target.len().min(size);
+    let mut start = 0;
+    for buffer in translated_byte_buffer(token, buf, len, true) {
+        buffer.copy_from_slice(&target.as_bytes()[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    return len as isize;
+}
+
+/// Move `old_path` to `new_path`, replacing `new_path` if it exists
+pub fn sys_renameat(
+    old_dirfd: isize,
+    old_path: *const u8,
+    new_dirfd: isize,
+    new_path: *const u8,
+) -> isize {
+    if old_dirfd != AT_FDCWD || new_dirfd != AT_FDCWD {
+        return -1;
+    }
+    let token = current_user_token();
+    let old_path = translated_str(token, old_path);
+    let new_path = translated_str(token, new_path);
+    if rename_file(&current_process().cwd(), old_path.as_str(), new_path.as_str()) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Get the metadata of the file `fd` into `st`
+pub fn sys_fstat(fd: usize, st: *mut Stat) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(inner);
+    match file.stat() {
+        Some(stat) => {
+            copy_to_user(current_user_token(), st, &stat);
+            return 0;
+        }
+        None => return -1,
+    }
+}
+
+/// Get the metadata of the file at `path` into `st` (newfstatat)
+pub fn sys_statat(dirfd: isize, path: *const u8, st: *mut Stat, flags: u32) -> isize {
+    if dirfd != AT_FDCWD || flags & !AT_SYMLINK_NOFOLLOW != 0 {
+        return -1;
+    }
+    let token = current_user_token();
+    let path = translated_str(token, path);
+    let follow = flags & AT_SYMLINK_NOFOLLOW == 0;
+    match stat_file(&current_process().cwd(), path.as_str(), follow) {
+        Some(stat) => {
+            copy_to_user(token, st, &stat);
+            return 0;
+        }
+        None => return -1,
+    }
+}
+
+/// Set the size of the regular file at `path` to `length`, 变长的部分读出来是0
+pub fn sys_truncate(path: *const u8, length: isize) -> isize {
+    if length < 0 {
+        return -1;
+    }
+    let path = translated_str(current_user_token(), path);
+    if truncate_file(&current_process().cwd(), path.as_str(), length as usize) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Set the size of the file `fd` to `length`, 文件要可写打开, 文件偏移不变
+pub fn sys_ftruncate(fd: usize, length: isize) -> isize {
+    let process = current_process();
+    let inner = process.inner_exclusive_access();
+    if fd >= inner.fd_table.len() || length < 0 {
+        return -1;
+    }
+    let file = match &inner.fd_table[fd] {
+        Some(file) => file.clone(),
+        None => return -1,
+    };
+    drop(inner);
+    if file.truncate(length as usize) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Write all cached dirty blocks back to the disk
+pub fn sys_sync() -> isize {
+    sync_fs();
+    return 0;
+}
+
+/// Mount the filesystem `fs_type` on the device `source` at the directory `target`.
+/// 不支持挂载选项, `flags`必须是0, `data`被忽略
+pub fn sys_mount(
+    source: *const u8,
+    target: *const u8,
+    fs_type: *const u8,
+    flags: usize,
+    _data: *const u8,
+) -> isize {
+    if flags != 0 {
+        return -1;
+    }
+    let token = current_user_token();
+    let source = translated_str(token, source);
+    let target = translated_str(token, target);
+    let fs_type = translated_str(token, fs_type);
+    let sb = match open_fs(fs_type.as_str(), source.as_str()) {
+        Some(sb) => sb,
+        None => return -1,
+    };
+    if mount(&current_process().cwd(), target.as_str(), sb) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Unmount the filesystem mounted at `target`, 文件系统还在使用时失败. `flags`必须是0
+pub fn sys_umount2(target: *const u8, flags: u32) -> isize {
+    if flags != 0 {
+        return -1;
+    }
+    let target = translated_str(current_user_token(), target);
+    if umount(&current_process().cwd(), target.as_str()) {
+        return 0;
+    }
+    return -1;
+}
@@ -0,0 +1,222 @@
+//! Memory mapping syscalls: mmap / munmap / mprotect / brk / sbrk
+
+use crate::config::{MMAP_BASE, MMAP_END, PAGE_SIZE};
+use crate::mm::{
+    Backing, MapArea, MapPermission, MapType, PPNRange, PhysAddr, SectionType, VirtAddr,
+    VirtPageNum,
+};
+use crate::task::current_process;
+
+const PROT_READ: usize = 1 << 0;
+const PROT_WRITE: usize = 1 << 1;
+const PROT_EXEC: usize = 1 << 2;
+
+const MAP_SHARED: usize = 0x01;
+const MAP_PRIVATE: usize = 0x02;
+const MAP_FIXED: usize = 0x10;
+const MAP_ANONYMOUS: usize = 0x20;
+
+/// PROT_READ/WRITE/EXEC 正好对应 MapPermission 的 R/W/X 右移一位
+fn prot_to_permission(prot: usize) -> Option<MapPermission> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return None;
+    }
+    return Some(MapPermission::from_bits((prot << 1) as u8).unwrap() | MapPermission::U);
+}
+
+/// 检查 [addr, addr+len) 是否页对齐并且长度不为0, 返回对应的页号范围
+fn page_range(addr: usize, len: usize) -> Option<(VirtPageNum, VirtPageNum)> {
+    if addr % PAGE_SIZE != 0 || len == 0 || addr.checked_add(len).is_none() {
+        return None;
+    }
+    return Some((VirtAddr::from(addr).floor(), VirtAddr::from(addr + len).ceil()));
+}
+
+/// Map `len` bytes at `addr` (only a hint unless MAP_FIXED), anonymous or backed by file `fd` from `offset`.
+/// Return the start address of the mapping, or -1 on error.
+///
+/// 页面都是按需分配的, 只有被访问的时候才分配物理页帧/读文件.
+/// MAP_SHARED 只支持文件映射, 修改的页面在munmap或者进程退出时写回文件.
+/// 设备文件(/dev/fb0)的设备内存直接映射到物理地址, 只能MAP_SHARED
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: usize,
+    flags: usize,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    let perm = match prot_to_permission(prot) {
+        Some(perm) => perm,
+        None => return -1,
+    };
+    let shared = match flags & (MAP_SHARED | MAP_PRIVATE) {
+        MAP_SHARED => true,
+        MAP_PRIVATE => false,
+        _ => return -1,
+    };
+    if len == 0 || offset % PAGE_SIZE != 0 || (flags & MAP_FIXED != 0 && addr % PAGE_SIZE != 0) {
+        return -1;
+    }
+    let page_count = match len.checked_add(PAGE_SIZE - 1) {
+        Some(len) => len / PAGE_SIZE,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let mut device = None;
+    let backing = if flags & MAP_ANONYMOUS != 0 {
+        if shared {
+            return -1;
+        }
+        None
+    } else {
+        if fd >= inner.fd_table.len() {
+            return -1;
+        }
+        let file = match &inner.fd_table[fd] {
+            Some(file) => file.clone(),
+            None => return -1,
+        };
+        // 映射文件总是要读文件的; 共享映射可写的话, 文件也必须可写
+        if !file.readable() || (shared && prot & PROT_WRITE != 0 && !file.writable()) {
+            return -1;
+        }
+        if let Some((paddr, size)) = file.device_memory() {
+            // 设备内存不是按需加载的, 也不会写回, 只能共享映射
+            match offset.checked_add(len) {
+                Some(end) if shared && end <= size => {
+                    let start_ppn = PhysAddr::from(paddr + offset).floor();
+                    let end_ppn = PhysAddr::from(paddr + end).ceil();
+                    device = Some(PPNRange::new(start_ppn, end_ppn));
+                }
+                _ => return -1,
+            }
+            None
+        } else {
+            let writable = file.writable();
+            let file = match file.backing_file() {
+                Some(file) => file,
+                None => return -1,
+            };
+            // 超过文件末尾的部分补0, 也不会写回
+            let file_len = file.size().saturating_sub(offset).min(len);
+            Some(Backing {
+                file,
+                offset,
+                len: file_len,
+                writable,
+            })
+        }
+    };
+    let start_vpn = if flags & MAP_FIXED != 0 {
+        // MAP_FIXED也只能映射在mmap区域里, 不能盖住跳板页, Trap上下文和用户栈
+        match addr.checked_add(len) {
+            Some(end) if addr >= MMAP_BASE && end <= MMAP_END => {}
+            _ => return -1,
+        }
+        let start_vpn = VirtAddr::from(addr).floor();
+        if inner
+            .memory_set
+            .is_overlapped(start_vpn, VirtPageNum(start_vpn.0 + page_count))
+        {
+            return -1;
+        }
+        start_vpn
+    } else {
+        let hint = VirtAddr::from(addr.max(MMAP_BASE)).ceil();
+        match inner.memory_set.find_free_area(hint, page_count) {
+            Some(start_vpn) => start_vpn,
+            None => return -1,
+        }
+    };
+    let start_va: VirtAddr = start_vpn.into();
+    let end_va: VirtAddr = VirtPageNum(start_vpn.0 + page_count).into();
+    match device {
+        Some(ppn_range) => inner.memory_set.push_noalloc(
+            MapArea::new(
+                start_va,
+                end_va,
+                MapType::Noalloc,
+                perm,
+                SectionType::Device,
+            ),
+            ppn_range,
+        ),
+        None => inner
+            .memory_set
+            .insert_mmap_area(start_va, end_va, perm, backing, shared),
+    }
+    log!(
+        "\x1b[32m[SYSCALL : mmap] [{:#x},{:#x}) prot {:#x} flags {:#x}\x1b[0m",
+        start_va.0,
+        end_va.0,
+        prot,
+        flags
+    );
+    return start_va.0 as isize;
+}
+
+/// Unmap the mmap areas in [addr, addr+len), dirty pages of shared mappings are written back first.
+/// 设备内存的映射只能整个解除
+pub fn sys_munmap(addr: usize, len: usize) -> isize {
+    let (start_vpn, end_vpn) = match page_range(addr, len) {
+        Some(range) => range,
+        None => return -1,
+    };
+    let process = current_process();
+    let write_backs = match process
+        .inner_exclusive_access()
+        .memory_set
+        .munmap(start_vpn, end_vpn)
+    {
+        Some(write_backs) => write_backs,
+        None => return -1,
+    };
+    // 写回文件可能休眠, 这时已经释放了进程锁
+    for write_back in write_backs {
+        write_back.write();
+    }
+    return 0;
+}
+
+/// Change the permission of the mapped pages in [addr, addr+len)
+pub fn sys_mprotect(addr: usize, len: usize, prot: usize) -> isize {
+    let (start_vpn, end_vpn) = match page_range(addr, len) {
+        Some(range) => range,
+        None => return -1,
+    };
+    let perm = match prot_to_permission(prot) {
+        Some(perm) => perm,
+        None => return -1,
+    };
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if inner.memory_set.mprotect(start_vpn, end_vpn, perm) {
+        return 0;
+    }
+    return -1;
+}
+
+/// Set the program break to `addr` and return the new break.
+/// 和Linux一样, addr为0或者设置失败时返回当前的program break
+pub fn sys_brk(addr: usize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    if addr != 0 {
+        inner.memory_set.set_brk(addr);
+    }
+    return inner.memory_set.brk() as isize;
+}
+
+/// Grow (or shrink) the user heap by `increment` bytes, return the old program break or -1
+pub fn sys_sbrk(increment: isize) -> isize {
+    let process = current_process();
+    let mut inner = process.inner_exclusive_access();
+    let old_brk = inner.memory_set.brk();
+    let new_brk = old_brk as isize + increment;
+    if new_brk < 0 || !inner.memory_set.set_brk(new_brk as usize) {
+        return -1;
+    }
+    return old_brk as isize;
+}
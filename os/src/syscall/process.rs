@@ -1,6 +1,6 @@
 //! Process management syscalls
-use crate::fs::{list_files, open_file, OpenFlags};
-use crate::mm::{translated_ref, translated_refmut, translated_str};
+use crate::fs::{find_inode, list_files, open_file, print_block_cache_stats, sync_fs, OpenFlags};
+use crate::mm::{translated_byte_buffer, translated_ref, translated_refmut, translated_str};
 use crate::sbi::shutdown;
 use crate::task::{
     add_task, current_process, current_task, current_user_token, exit_current_and_run_next,
@@ -32,18 +32,38 @@ pub fn sys_getpid() -> isize {
     current_task().unwrap().process.upgrade().unwrap().getpid() as isize
 }
 
-pub fn sys_getcwd() -> isize {
-    let current_process = current_process();
-    println!("{}", current_process.get_working_dir());
-    return 0;
+/// Copy the absolute path of the current directory (ending with '\0') into `buf`.
+/// 和POSIX一样, 成功时返回`buf`, `size`放不下或者当前目录已经被删除时返回-1
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
+    let path = match current_process().cwd().path() {
+        Some(path) => path,
+        None => return -1,
+    };
+    if path.len() + 1 > size {
+        return -1;
+    }
+    let mut bytes = path.into_bytes();
+    bytes.push(0);
+    let mut start = 0;
+    for buffer in translated_byte_buffer(current_user_token(), buf, bytes.len(), true) {
+        buffer.copy_from_slice(&bytes[start..start + buffer.len()]);
+        start += buffer.len();
+    }
+    return buf as isize;
 }
 
+/// Change the current directory, `path` is resolved from the current directory and must be a directory
 pub fn sys_chdir(path: *const u8) -> isize {
-    let current_process = current_process();
+    let process = current_process();
     let token = current_user_token();
     let path = translated_str(token, path);
-    current_process.inner_exclusive_access().working_dir = path;
-    return 0;
+    match find_inode(&process.cwd(), path.as_str()) {
+        Some(dentry) if dentry.inode().is_dir() => {
+            process.inner_exclusive_access().cwd = dentry;
+            return 0;
+        }
+        _ => return -1,
+    }
 }
 
 pub fn sys_fork() -> isize {
@@ -82,11 +102,11 @@ pub fn sys_exec(path: *const u8, mut args: *const usize) -> isize {
         }
     }
 
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
-        let all_data = app_inode.read_all();
+    let cwd = current_process().cwd();
+    if let Some(app_inode) = open_file(&cwd, path.as_str(), OpenFlags::RDONLY) {
         let process = current_process();
         let argc = args_vec.len();
-        process.exec(path.as_str(), all_data.as_slice(), args_vec);
+        process.exec(path.as_str(), app_inode, args_vec);
         return argc as isize;
     } else {
         return -1;
@@ -122,7 +142,10 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         // ++++ temporarily access child PCB exclusively
         let exit_code = child.inner_exclusive_access().exit_code;
         // ++++ release child PCB
-        *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
+        let token = inner.memory_set.token();
+        // 写用户内存时可能要处理写时复制, 会再次访问PCB, 所以先释放
+        drop(inner);
+        *translated_refmut(token, exit_code_ptr) = exit_code;
         return found_pid as isize;
     } else {
         return -2;
@@ -131,12 +154,14 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
 }
 
 pub fn sys_list_apps() -> isize {
-    let path = current_process().get_working_dir();
-    list_files(path.as_str());
+    list_files(&current_process().cwd());
     return 0;
 }
 
 pub fn sys_shutdown() -> isize {
+    // 关机前把块缓存里的脏块写回磁盘
+    sync_fs();
+    print_block_cache_stats();
     shutdown(false);
     return 0;
 }
@@ -232,7 +257,6 @@ pub fn sys_sigaction(
     let token = current_user_token();
     let task = current_task().unwrap();
     let process = task.process.upgrade().unwrap();
-    let mut process_inner = process.inner_exclusive_access();
     if signum as usize > MAX_SIG {
         return -1;
     }
@@ -241,9 +265,13 @@ pub fn sys_sigaction(
         if check_sigaction_error(flag, action as usize, old_action as usize) {
             return -1;
         }
+        // 读写用户内存时可能要处理缺页(按需加载/写时复制), 会再次访问PCB, 所以只在中间短暂持有
+        let new_action = *translated_ref(token, action); //这也是跨虚拟内存, 本质就是把action函数指针赋值到PCB的信号对应callback函数表.
+        let mut process_inner = process.inner_exclusive_access();
         let prev_action = process_inner.signal_actions.table[signum as usize];
+        process_inner.signal_actions.table[signum as usize] = new_action;
+        drop(process_inner);
         *translated_refmut(token, old_action) = prev_action; //prev_action函数指针 赋值给old_action. 由于是跨虚拟内存空间操作, 需要用translated_refmut
-        process_inner.signal_actions.table[signum as usize] = *translated_ref(token, action); //这也是跨虚拟内存, 本质就是把action函数指针赋值到PCB的信号对应callback函数表.
         log!("\x1b[38;5;208m[SYSCALL : sigaction] 程序 [{}]  信号 [{}] mapping 回调函数 [{:?}]   \x1b[0m",process.getpid(),signum,action);
         return 0;
     } else {
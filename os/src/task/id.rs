@@ -1,6 +1,6 @@
 use super::ProcessControlBlock;
 use crate::config::{KERNEL_STACK_SIZE, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT_BASE, USER_STACK_SIZE};
-use crate::mm::{MapPermission, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use crate::mm::{MapPermission, PhysPageNum, SectionType, VirtAddr, KERNEL_SPACE};
 use crate::sync::UPSafeCell;
 use alloc::{
     sync::{Arc, Weak},
@@ -145,10 +145,12 @@ impl TaskUserRes {
         // 分配用户栈  分配一块内存 [ustack_bottom,ustack_top) , 权限为 可读可写且为用户模式 (给用户程序用的)
         let ustack_bottom = ustack_bottom_from_tid(self.ustack_base, self.tid);
         let ustack_top = ustack_bottom + USER_STACK_SIZE;
-        process_inner.memory_set.insert_framed_area(
+        // 用户栈按需分配, 用到哪一页才分配哪一页
+        process_inner.memory_set.insert_lazy_area(
             ustack_bottom.into(),
             ustack_top.into(),
             MapPermission::R | MapPermission::W | MapPermission::U,
+            SectionType::Stack,
         );
         // 分配Trap上下文  分配一块内存 [trap_cx_bottom, trap_cx_top] 权限为 可读可写 内核模式 (trap 都是在内核完成)
         let trap_cx_bottom = trap_cx_bottom_from_tid(self.tid);
@@ -2,9 +2,12 @@
 use super::process::ProcessControlBlock;
 use super::task::TaskStatus;
 use super::TaskControlBlock;
+use crate::config::FRAME_LOW_WATERMARK;
+use crate::mm::{frame_free_count, swap_write, Evicted};
 use crate::sync::UPSafeCell;
 use alloc::collections::{BTreeMap, VecDeque};
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
 ///A array of `TaskControlBlock` that is thread-safe
 pub struct TaskManager {
@@ -43,6 +46,8 @@ lazy_static! {
         unsafe { UPSafeCell::new(TaskManager::new()) };
     pub static ref PID2PCB: UPSafeCell<BTreeMap<usize, Arc<ProcessControlBlock>>> =
         unsafe { UPSafeCell::new(BTreeMap::new()) };
+    /// 上一次换出页面的进程, 下一次从它后面的进程开始找
+    static ref CLOCK_PID: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
 }
 
 pub fn add_task(task: Arc<TaskControlBlock>) {
@@ -69,6 +74,11 @@ pub fn pid2process(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     return map.get(&pid).map(Arc::clone);
 }
 
+/// Pids of all processes in ascending order
+pub fn all_pids() -> Vec<usize> {
+    return PID2PCB.exclusive_access().keys().cloned().collect();
+}
+
 pub fn insert_into_pid2process(pid: usize, process: Arc<ProcessControlBlock>) {
     PID2PCB.exclusive_access().insert(pid, process);
 }
@@ -79,3 +89,46 @@ pub fn remove_from_pid2process(pid: usize) {
         panic!("cannot find pid {} in pid2task!", pid);
     }
 }
+
+/// Swap out user pages until there are enough free frames or nothing can be evicted.
+/// 可能要写磁盘而休眠, 调用时不能持有任何进程/线程的inner
+pub fn reclaim_frames() {
+    while frame_free_count() < FRAME_LOW_WATERMARK {
+        if !evict_one_page() {
+            break;
+        }
+    }
+}
+
+/// Evict one user page, return false if nothing can be evicted.
+/// 在进程之间轮转, 每个进程内部用时钟算法选出要换出的页面
+pub fn evict_one_page() -> bool {
+    let processes: Vec<Arc<ProcessControlBlock>> =
+        PID2PCB.exclusive_access().values().cloned().collect();
+    if processes.is_empty() {
+        return false;
+    }
+    let last_pid = *CLOCK_PID.exclusive_access();
+    let start = processes
+        .iter()
+        .position(|process| process.getpid() > last_pid)
+        .unwrap_or(0);
+    for i in 0..processes.len() {
+        let process = &processes[(start + i) % processes.len()];
+        // 正在被借用的进程(比如正在fork)和正在执行系统调用的进程都跳过
+        let evicted = match process.try_inner_exclusive_access() {
+            Some(mut inner) if !inner.is_zombie && !inner.in_syscall() => {
+                inner.memory_set.evict_one()
+            }
+            _ => None,
+        };
+        if let Some(evicted) = evicted {
+            *CLOCK_PID.exclusive_access() = process.getpid();
+            if let Evicted::Swapped(slot) = evicted {
+                swap_write(slot);
+            }
+            return true;
+        }
+    }
+    return false;
+}
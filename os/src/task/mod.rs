@@ -21,7 +21,7 @@ mod switch;
 mod task;
 
 use self::id::TaskUserRes;
-use crate::fs::{open_file, OpenFlags};
+use crate::fs::{open_file, print_block_cache_stats, root_dentry, sync_fs, OpenFlags};
 use crate::sbi::shutdown;
 use crate::sync::UPSafeCell;
 use crate::timer::remove_timer;
@@ -35,7 +35,10 @@ use switch::__switch;
 pub use action::{SignalAction, SignalActions};
 pub use context::TaskContext;
 pub use id::{kstack_alloc, pid_alloc, KernelStack, PidHandle, IDLE_PID};
-pub use manager::{add_task, pid2process, remove_from_pid2process, remove_task, wakeup_task};
+pub use manager::{
+    add_task, all_pids, evict_one_page, pid2process, remove_from_pid2process, remove_task,
+    wakeup_task,
+};
 pub use processor::{
     current_kstack_top, current_process, current_task, current_trap_cx, current_trap_cx_user_va,
     current_user_token, run_tasks, schedule, take_current_task, Processor,
@@ -79,6 +82,13 @@ pub fn block_current_and_run_next() {
 
 /// Exit the current 'Running' task and run the next task in task list.
 pub fn exit_current_and_run_next(exit_code: i32) {
+    // 主线程退出前先把共享文件映射改过的页面写回文件.
+    // 写磁盘可能会休眠, 必须趁当前线程还在处理器上的时候做
+    let task = current_task().unwrap();
+    if task.inner_exclusive_access().res.as_ref().unwrap().tid == 0 {
+        task.process.upgrade().unwrap().sync_shared_mappings();
+    }
+    drop(task);
     let task = take_current_task().unwrap();
     let mut task_inner = task.inner_exclusive_access();
     let process = task.process.upgrade().unwrap();
@@ -99,6 +109,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
                 "[kernel] Idle process exit with exit_code {} ...",
                 exit_code
             );
+            sync_fs();
+            print_block_cache_stats();
             if exit_code != 0 {
                 println!("[kernel] user app exit with exit_code {} ...", exit_code);
                 shutdown(true);
@@ -165,9 +177,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
 lazy_static! {
     //Global process that init user shell
     pub static ref INITPROC: Arc<ProcessControlBlock> = {
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
-        let v = inode.read_all();
-        ProcessControlBlock::new(v.as_slice())
+        let inode = open_file(&root_dentry(), "initproc", OpenFlags::RDONLY).unwrap();
+        ProcessControlBlock::new(inode)
     };
 }
 ///Add init process to the manager
@@ -1,17 +1,22 @@
-use crate::fs::{File, Stdin, Stdout};
-use crate::mm::{translated_refmut, translated_str, MemorySet, KERNEL_SPACE};
+use crate::fs::{root_dentry, Dentry, File, OSInode, Stdin, Stdout};
+use crate::mm::{
+    frame_alloc, swap_in, translated_refmut, translated_str, MemorySet, VirtAddr, VirtPageNum,
+    KERNEL_SPACE,
+};
+use crate::config::{MMAP_END, PAGE_SIZE};
 use crate::sync::{UPSafeCell, Mutex, Semaphore, Condvar};
 use crate::trap::{trap_handler, TrapContext};
 
 use super::id::{PidHandle, RecycleAllocator};
-use super::manager::insert_into_pid2process;
-use super::task::TaskControlBlock;
+use super::manager::{insert_into_pid2process, reclaim_frames};
+use super::task::{TaskControlBlock, TaskStatus};
 use super::{add_task, pid_alloc, SignalActions, SignalFlags};
 use alloc::string::{String, ToString};
 use alloc::sync::{Arc, Weak};
 use alloc::vec;
 use alloc::vec::Vec;
 use core::cell::RefMut;
+use core::fmt::Write;
 
 #[derive(Debug)]
 pub struct ProcessControlBlock {
@@ -22,7 +27,7 @@ pub struct ProcessControlBlock {
 #[derive(Debug)]
 pub struct ProcessControlBlockInner {
     pub app_name: String,
-    pub working_dir: String,
+    pub cwd: Dentry, //当前目录
     pub is_zombie: bool,
     pub memory_set: MemorySet,                              //应用地址空间
     pub parent: Option<Weak<ProcessControlBlock>>,          //父进程
@@ -67,6 +72,16 @@ impl ProcessControlBlockInner {
     pub fn get_task(&self, tid: usize) -> Arc<TaskControlBlock> {
         return self.tasks[tid].as_ref().unwrap().clone();
     }
+
+    /// If any thread is inside a syscall (or can not be checked right now)
+    pub fn in_syscall(&self) -> bool {
+        return self.tasks.iter().flatten().any(|task| {
+            match task.try_inner_exclusive_access() {
+                Some(task_inner) => task_inner.res.is_some() && task_inner.in_syscall,
+                None => true,
+            }
+        });
+    }
 }
 
 impl ProcessControlBlock {
@@ -74,9 +89,13 @@ impl ProcessControlBlock {
         self.inner.exclusive_access()
     }
 
-    pub fn new(elf_data: &[u8]) -> Arc<Self> {
+    pub fn try_inner_exclusive_access(&self) -> Option<RefMut<'_, ProcessControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
+    pub fn new(elf_file: Arc<OSInode>) -> Arc<Self> {
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_file);
         //alloc a pid and a kernel stack in kernel space
         let pid_handle = pid_alloc();
         // push a task context which goes to trap_return to the top of kernel stack
@@ -85,7 +104,7 @@ impl ProcessControlBlock {
             inner: unsafe {
                 UPSafeCell::new(ProcessControlBlockInner {
                     app_name: String::new(),
-                    working_dir: String::new(),
+                    cwd: root_dentry(),
                     is_zombie: false,
                     memory_set,
                     parent: None,
@@ -145,11 +164,60 @@ impl ProcessControlBlock {
         return process;
     }
 
+    /// Handle a page fault at `vpn` of this process, return false if it is an invalid access.
+    ///
+    /// 写时复制的页面直接在地址空间里复制; 按需加载的页面要先从文件读内容,
+    /// 读磁盘时当前线程可能会休眠, 所以读的过程中不能持有进程的inner(别的线程/父进程waitpid都会借用它)
+    pub fn handle_page_fault(&self, vpn: VirtPageNum) -> bool {
+        // 空闲物理页帧不多了, 先换出一些页面
+        reclaim_frames();
+        let mut inner = self.inner_exclusive_access();
+        if inner.memory_set.cow_page_fault(vpn) {
+            return true;
+        }
+        let page = match inner.memory_set.lazy_page(vpn) {
+            Some(page) => page,
+            None => return false,
+        };
+        drop(inner);
+        let frame = if let Some(slot) = page.swap_slot {
+            swap_in(slot)
+        } else {
+            let frame = frame_alloc().unwrap();
+            if let Some(file) = page.file {
+                file.read_at(page.offset, &mut frame.ppn.get_bytes_array()[..page.len]);
+            }
+            frame
+        };
+        // 读文件期间别的线程可能已经把这一页映射好了, 那样的话新页帧直接丢掉就行
+        self.inner_exclusive_access()
+            .memory_set
+            .map_lazy_page(vpn, frame, page.swap_slot.is_some());
+        return true;
+    }
+
+    /// Write the dirty pages of shared file mappings back to their files, used when the process exits.
+    /// 写回的时候可能休眠, 所以先在锁内收集好, 释放锁之后再写
+    pub fn sync_shared_mappings(&self) {
+        let write_backs = self
+            .inner_exclusive_access()
+            .memory_set
+            .shared_write_backs(VirtPageNum(0), VirtAddr::from(MMAP_END).floor());
+        for write_back in write_backs {
+            write_back.write();
+        }
+    }
+
     /// Only support processes with a single thread.
-    pub fn exec(self: &Arc<Self>, app_name: &str, elf_data: &[u8], args: Vec<String>) {
+    pub fn exec(
+        self: &Arc<Self>,
+        app_name: &str,
+        elf_file: Arc<OSInode>,
+        args: Vec<String>,
+    ) {
         assert_eq!(self.inner_exclusive_access().thread_count(), 1);
         // memory_set with elf program headers/trampoline/trap context/user stack
-        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_data);
+        let (memory_set, ustack_base, entry_point) = MemorySet::from_elf(elf_file);
         let new_token = memory_set.token();
         // substitute memory_set
         self.inner_exclusive_access().memory_set = memory_set;
@@ -172,6 +240,8 @@ impl ProcessControlBlock {
         // 1 usize == 8 bytes
 
         let mut user_sp = task_inner.res.as_mut().unwrap().ustack_top();
+        // 往用户栈里写参数可能触发缺页, 处理缺页时可能要读写磁盘而休眠, 休眠时会访问当前线程的inner
+        drop(task_inner);
         log!(
             "\x1b[32m[SYSCALL : exec] original user_sp : [{}] \x1b[0m",
             user_sp
@@ -224,7 +294,7 @@ impl ProcessControlBlock {
         );
         trap_cx.x[10] = args.len(); // x10(a0)寄存器, 函数入参1寄存器 传入命令行参数个数
         trap_cx.x[11] = argv_base; // x11(a1)寄存器, 函数入参2寄存器 传如argv_base 具体位置看上面注释图 读取argv[0]/argv[1]/argv[2]
-        *task_inner.get_trap_cx() = trap_cx;
+        *task.inner_exclusive_access().get_trap_cx() = trap_cx;
 
         log!(
             "\x1b[32m[SYSCALL : exec] now user_sp : [{}] \x1b[0m",
@@ -237,8 +307,8 @@ impl ProcessControlBlock {
     pub fn fork(self: &Arc<Self>) -> Arc<Self> {
         let mut parent = self.inner_exclusive_access();
         assert_eq!(parent.thread_count(), 1);
-        // copy user space(include trap context)
-        let memory_set = MemorySet::from_existed_user(&parent.memory_set);
+        // copy user space(include trap context), user pages are shared copy-on-write
+        let memory_set = MemorySet::from_existed_user(&mut parent.memory_set);
         // alloc a pid and a kernel stack in kernel space
         let pid = pid_alloc();
         // copy fd table
@@ -250,7 +320,7 @@ impl ProcessControlBlock {
                 new_fd_table.push(None);
             }
         }
-        let working_dir = parent.working_dir.clone();
+        let cwd = parent.cwd.clone();
         let child = Arc::new(Self {
             pid,
             inner: unsafe {
@@ -262,8 +332,8 @@ impl ProcessControlBlock {
                     exit_code: 0,
                     fd_table: new_fd_table,
                     signals: SignalFlags::empty(),
-                    app_name: String::new(),
-                    working_dir,
+                    app_name: parent.app_name.clone(),
+                    cwd,
                     // inherit the signal_mask and signal_action
                     signal_mask: parent.signal_mask,
                     handling_sig: -1,
@@ -319,7 +389,92 @@ impl ProcessControlBlock {
         self.pid.0
     }
 
-    pub fn get_working_dir(&self) -> String {
-        self.inner.exclusive_access().working_dir.clone()
+    pub fn cwd(&self) -> Dentry {
+        self.inner.exclusive_access().cwd.clone()
+    }
+
+    /// Content of /proc/<pid>/status, None if the process is being modified (fork/exec)
+    pub fn status(&self) -> Option<String> {
+        let inner = self.try_inner_exclusive_access()?;
+        // tasks的下标就是tid, 借用不到的线程当作正在运行
+        let threads: Vec<(usize, Option<TaskStatus>)> = inner
+            .tasks
+            .iter()
+            .enumerate()
+            .filter_map(|(tid, task)| task.as_ref().map(|task| (tid, task)))
+            .map(|(tid, task)| {
+                let status = task
+                    .try_inner_exclusive_access()
+                    .map(|task_inner| task_inner.task_status);
+                (tid, status)
+            })
+            .collect();
+        // 有线程在运行或者就绪就算运行, 线程都阻塞了才算睡眠
+        let state = if inner.is_zombie {
+            "Z (zombie)"
+        } else if inner.frozen {
+            "T (stopped)"
+        } else if threads
+            .iter()
+            .any(|(_, status)| !matches!(status, Some(TaskStatus::Blocked)))
+        {
+            "R (running)"
+        } else {
+            "S (sleeping)"
+        };
+        let ppid = inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.upgrade())
+            .map_or(0, |parent| parent.getpid());
+        let (vm_pages, rss_pages, swap_pages) = inner.memory_set.page_counts();
+        let page_kb = PAGE_SIZE / 1024;
+        let mut status = String::new();
+        let _ = writeln!(status, "Name:\t{}", inner.app_name);
+        let _ = writeln!(status, "State:\t{}", state);
+        let _ = writeln!(status, "Pid:\t{}", self.getpid());
+        let _ = writeln!(status, "PPid:\t{}", ppid);
+        let _ = writeln!(status, "Cwd:\t{}", inner.cwd.path().unwrap_or_default());
+        let _ = writeln!(status, "FDSize:\t{}", inner.fd_table.len());
+        let _ = writeln!(status, "VmSize:\t{} kB", vm_pages * page_kb);
+        let _ = writeln!(status, "VmRSS:\t{} kB", rss_pages * page_kb);
+        let _ = writeln!(status, "VmSwap:\t{} kB", swap_pages * page_kb);
+        let _ = write!(status, "Children:\t");
+        for (i, child) in inner.children.iter().enumerate() {
+            let _ = write!(
+                status,
+                "{}{}",
+                if i == 0 { "" } else { " " },
+                child.getpid()
+            );
+        }
+        let _ = writeln!(status);
+        let _ = writeln!(status, "Threads:\t{}", threads.len());
+        for (tid, task_status) in threads {
+            let task_state = match task_status {
+                Some(TaskStatus::Ready) => "R (ready)",
+                Some(TaskStatus::Blocked) => "S (blocked)",
+                _ => "R (running)",
+            };
+            let _ = writeln!(status, "Tid:\t{}\t{}", tid, task_state);
+        }
+        return Some(status);
+    }
+
+    /// Content of /proc/<pid>/maps
+    pub fn maps(&self) -> Option<String> {
+        return Some(self.try_inner_exclusive_access()?.memory_set.maps());
+    }
+
+    /// The open files: (fd, what /proc/<pid>/fd/<fd> links to)
+    pub fn fd_paths(&self) -> Option<Vec<(usize, String)>> {
+        let inner = self.try_inner_exclusive_access()?;
+        let fds = inner
+            .fd_table
+            .iter()
+            .enumerate()
+            .filter_map(|(fd, file)| file.as_ref().map(|file| (fd, file.fd_path())))
+            .collect();
+        return Some(fds);
     }
 }
@@ -23,6 +23,7 @@ pub struct TaskControlBlockInner {
     pub task_status: TaskStatus,  //执行状态
     pub exit_code: Option<i32>,   //当进程主动调用exit 或者执行出错被内核杀死, 它的退出码会不同
     pub trap_ctx_backup: Option<TrapContext>,
+    pub in_syscall: bool, //正在执行系统调用, 内核可能持有用户页面的引用, 这时不能换出该进程的页面
 }
 
 impl TaskControlBlockInner {
@@ -40,6 +41,10 @@ impl TaskControlBlock {
         self.inner.exclusive_access()
     }
 
+    pub fn try_inner_exclusive_access(&self) -> Option<RefMut<'_, TaskControlBlockInner>> {
+        self.inner.try_exclusive_access()
+    }
+
     pub fn get_user_token(&self)-> usize {
         let process=self.process.upgrade().unwrap();
         let inner=process.inner_exclusive_access();
@@ -66,6 +71,7 @@ impl TaskControlBlock {
                     task_status: TaskStatus::Ready,
                     exit_code: None,
                     trap_ctx_backup: None,
+                    in_syscall: false,
                 })
             },
         }
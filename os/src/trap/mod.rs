@@ -15,9 +15,10 @@
 mod context;
 
 use crate::config::TRAMPOLINE;
+use crate::mm::VirtAddr;
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_error_of_current, current_add_signal, current_task, current_trap_cx,
+    check_signals_error_of_current, current_add_signal, current_process, current_task, current_trap_cx,
     current_trap_cx_user_va, current_user_token, exit_current_and_run_next, handle_signals,
     suspend_current_and_run_next, SignalFlags,
 };
@@ -89,11 +90,23 @@ pub fn trap_handler() -> ! {
             cx.sepc += 4;
             enable_supervisor_interrupt();
             // get system call return value
-            let result = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]);
+            current_task().unwrap().inner_exclusive_access().in_syscall = true;
+            let result = syscall(
+                cx.x[17],
+                [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+            );
+            current_task().unwrap().inner_exclusive_access().in_syscall = false;
             // cx is changed during sys_exec, so we have to call it again
             cx = current_trap_cx();
             cx.x[10] = result as usize;
         }
+        Trap::Exception(Exception::StorePageFault)
+        | Trap::Exception(Exception::InstructionPageFault)
+        | Trap::Exception(Exception::LoadPageFault)
+            if current_process().handle_page_fault(VirtAddr::from(stval).floor()) =>
+        {
+            // 写时复制的页面已经复制好了, 或者按需加载的页面已经分配好了, 回到用户态重新执行这条指令
+        }
         Trap::Exception(Exception::StoreFault)
         | Trap::Exception(Exception::StorePageFault)
         | Trap::Exception(Exception::InstructionFault)
@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, close, getcwd, open, unlink, OpenFlags};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let mut buf = [0u8; 64];
+    assert_eq!(getcwd(&mut buf), Some("/"));
+    // 缓冲区放不下路径和结尾的'\0'
+    assert_eq!(getcwd(&mut buf[..1]), None);
+
+    // 根目录的 .. 还是根目录
+    assert_eq!(chdir("..\0"), 0);
+    assert_eq!(chdir("./.\0"), 0);
+    assert_eq!(getcwd(&mut buf), Some("/"));
+
+    // 只能进入目录
+    let fd = open(
+        "cwdfile\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(chdir("cwdfile\0"), -1);
+    assert_eq!(chdir("nonexist\0"), -1);
+    assert_eq!(getcwd(&mut buf), Some("/"));
+
+    // 相对路径和绝对路径找到的是同一个文件
+    let fd = open("./cwdfile\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    let fd = open("/cwdfile\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    close(fd as usize);
+    assert_eq!(unlink("cwdfile\0"), 0);
+    println!("cwd_simple passed!");
+    0
+}
@@ -0,0 +1,93 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, dirents, fstat, getdents, lseek, mmap, munmap, open, read, stat, unlink, write,
+    MapFlags, OpenFlags, ProtFlags, Stat, DT_CHR, SEEK_SET, S_IFBLK, S_IFCHR, S_IFMT,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // /dev/null: 写入的都丢掉, 读到的是文件末尾
+    let fd = open("/dev/null\0", OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"discarded"), 9);
+    let mut buf = [0xffu8; 64];
+    assert_eq!(read(fd, &mut buf), 0);
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd, &mut st), 0);
+    assert_eq!(st.mode & S_IFMT, S_IFCHR);
+    close(fd);
+
+    // /dev/zero: 读出来全是0
+    let fd = open("/dev/zero\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    assert_eq!(read(fd as usize, &mut buf), buf.len() as isize);
+    assert!(buf.iter().all(|b| *b == 0));
+    close(fd as usize);
+
+    let fd = open("/dev/tty\0", OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    assert_eq!(
+        write(fd as usize, b"devfs_simple: hello from /dev/tty\n"),
+        34
+    );
+    close(fd as usize);
+
+    // 输入设备在 /dev/input 目录下
+    let fd = open("/dev/input\0", OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    assert!(fd >= 0);
+    let mut dir_buf = [0u8; 256];
+    let len = getdents(fd as usize, &mut dir_buf);
+    assert!(len > 0);
+    let mut events = 0;
+    for dirent in dirents(&dir_buf, len as usize) {
+        if dirent.name == "event0" || dirent.name == "event1" {
+            assert_eq!(dirent.d_type, DT_CHR);
+            events += 1;
+        }
+    }
+    assert_eq!(events, 2);
+    close(fd as usize);
+
+    // 块设备只读, 开头是easy-fs的超级块
+    assert_eq!(stat("/dev/vda\0", &mut st), 0);
+    assert_eq!(st.mode & S_IFMT, S_IFBLK);
+    assert_eq!(open("/dev/vda\0", OpenFlags::WRONLY), -1);
+    let fd = open("/dev/vda\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let mut block = [0u8; 512];
+    assert_eq!(read(fd as usize, &mut block), 512);
+    assert_eq!(lseek(fd as usize, 0, SEEK_SET), 0);
+    let mut again = [0u8; 512];
+    assert_eq!(read(fd as usize, &mut again), 512);
+    assert_eq!(block, again);
+    close(fd as usize);
+
+    // 显存可以映射, 只能共享映射, 映射只能整个解除
+    let fd = open("/dev/fb0\0", OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let prot = ProtFlags::READ | ProtFlags::WRITE;
+    assert_eq!(mmap(0, 4096, prot, MapFlags::PRIVATE, fd as usize, 0), -1);
+    let addr = mmap(0, 8192, prot, MapFlags::SHARED, fd as usize, 0);
+    assert!(addr > 0);
+    let pixel = addr as *mut u32;
+    unsafe {
+        let old = pixel.read_volatile();
+        pixel.write_volatile(old);
+    }
+    assert_eq!(munmap(addr as usize, 4096), -1);
+    assert_eq!(munmap(addr as usize, 8192), 0);
+    close(fd as usize);
+
+    // 不能在devfs里删除或者创建文件
+    assert_eq!(unlink("/dev/null\0"), -1);
+    assert!(open("/dev/newfile\0", OpenFlags::CREATE | OpenFlags::WRONLY) < 0);
+    assert_eq!(stat("/dev/null\0", &mut st), 0);
+    println!("devfs_simple passed!");
+    0
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, dirents, fstat, getdents, lseek, open, unlink, OpenFlags, Stat, SEEK_SET};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fd = open(
+        "direntfile\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd as usize, &mut st), 0);
+    close(fd as usize);
+
+    // O_DIRECTORY只能只读打开目录, 普通文件不能getdents
+    assert_eq!(open("direntfile\0", OpenFlags::DIRECTORY), -1);
+    assert_eq!(open("/\0", OpenFlags::DIRECTORY | OpenFlags::RDWR), -1);
+    let fd = open("direntfile\0", OpenFlags::RDONLY);
+    let mut buf = [0u8; 512];
+    assert_eq!(getdents(fd as usize, &mut buf), -1);
+    close(fd as usize);
+
+    let fd = open("/\0", OpenFlags::DIRECTORY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    // 缓冲区连一条目录项都放不下
+    assert_eq!(getdents(fd, &mut buf[..8]), -1);
+
+    // 一次只能放下一条, 最前面是 . 和 .., 每次读完偏移前进一条
+    let len = getdents(fd, &mut buf[..24]);
+    assert_eq!(len, 24);
+    let dot = dirents(&buf, len as usize).next().unwrap();
+    assert!(dot.name == "." && dot.is_dir());
+    let len = getdents(fd, &mut buf[..24]);
+    assert_eq!(dirents(&buf, len as usize).next().unwrap().name, "..");
+
+    // 读完整个目录, 能找到刚刚创建的文件
+    let mut found = false;
+    loop {
+        let len = getdents(fd, &mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        for dirent in dirents(&buf, len as usize) {
+            if dirent.name == "direntfile" {
+                assert!(!dirent.is_dir());
+                assert_eq!(dirent.ino, st.ino);
+                found = true;
+            }
+        }
+    }
+    assert!(found);
+
+    // 回到开头重新读
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let len = getdents(fd, &mut buf);
+    assert_eq!(dirents(&buf, len as usize).next().unwrap().name, ".");
+    close(fd);
+    assert_eq!(unlink("direntfile\0"), 0);
+    println!("dirent_simple passed!");
+    0
+}
@@ -0,0 +1,90 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use user_lib::{
+    close, dirents, getdents, lseek, mkdir, mount, open, read, rename, rmdir, stat, umount, unlink,
+    write, OpenFlags, Stat, SEEK_SET,
+};
+
+/// Whether the directory `path` has an entry called `name`
+fn has_entry(path: &str, name: &str) -> bool {
+    let fd = open(path, OpenFlags::RDONLY | OpenFlags::DIRECTORY);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 512];
+    let mut found = false;
+    loop {
+        let len = getdents(fd as usize, &mut buf);
+        assert!(len >= 0);
+        if len == 0 {
+            break;
+        }
+        found |= dirents(&buf, len as usize).any(|dirent| dirent.name == name);
+    }
+    close(fd as usize);
+    return found;
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 第二个磁盘是Makefile里用mkfs.vfat做的FAT32
+    assert_eq!(mkdir("/fat\0"), 0);
+    assert_eq!(mount("/dev/vdb\0", "/fat\0", "vfat\0"), 0);
+    assert_eq!(mount("/dev/vda\0", "/fat\0", "vfat\0"), -1);
+
+    // mtools放进去的文件, 名字不区分大小写
+    let fd = open("/fat/hello.txt\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 64];
+    let len = read(fd as usize, &mut buf);
+    assert_eq!(&buf[..len as usize], b"hello from mtools\n");
+    close(fd as usize);
+    assert!(has_entry("/fat\0", "HELLO.TXT"));
+
+    // 长文件名, 跨簇读写
+    let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+    let fd = open(
+        "/fat/A Long File Name.data\0",
+        OpenFlags::CREATE | OpenFlags::RDWR,
+    );
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &data), data.len() as isize);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut read_back = vec![0u8; data.len()];
+    assert_eq!(read(fd, &mut read_back), data.len() as isize);
+    assert_eq!(read_back, data);
+    close(fd);
+    let mut st = Stat::default();
+    assert_eq!(stat("/fat/A Long File Name.data\0", &mut st), 0);
+    assert_eq!(st.size, data.len() as i64);
+    assert!(has_entry("/fat\0", "A Long File Name.data"));
+
+    // 目录和改名
+    assert_eq!(mkdir("/fat/dir\0"), 0);
+    assert!(has_entry("/fat/dir\0", ".."));
+    assert_eq!(
+        rename("/fat/A Long File Name.data\0", "/fat/dir/moved.data\0"),
+        0
+    );
+    assert_eq!(stat("/fat/A Long File Name.data\0", &mut st), -1);
+    assert_eq!(stat("/fat/dir/moved.data\0", &mut st), 0);
+    assert_eq!(st.size, data.len() as i64);
+    // 不同的文件系统之间不能改名
+    assert_eq!(rename("/fat/dir/moved.data\0", "/moved.data\0"), -1);
+    assert_eq!(rmdir("/fat/dir\0"), -1);
+    assert_eq!(unlink("/fat/dir/moved.data\0"), 0);
+    assert_eq!(rmdir("/fat/dir\0"), 0);
+    assert!(!has_entry("/fat\0", "dir"));
+
+    assert_eq!(umount("/fat\0"), 0);
+    assert_eq!(stat("/fat/hello.txt\0", &mut st), -1);
+    assert_eq!(rmdir("/fat\0"), 0);
+    println!("fat_simple passed!");
+    0
+}
@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use user_lib::{brk, sbrk};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // sbrk直接使用: 新增的页面可读可写, 缩回去之后program break恢复
+    let old_brk = sbrk(0);
+    assert!(old_brk > 0);
+    assert_eq!(sbrk(8192), old_brk);
+    let p = old_brk as *mut u8;
+    unsafe {
+        *p = 42;
+        *p.add(8191) = 24;
+        assert_eq!(*p + *p.add(8191), 66);
+    }
+    assert_eq!(brk(0), old_brk + 8192);
+    assert_eq!(sbrk(-8192), old_brk + 8192);
+    assert_eq!(brk(0), old_brk);
+
+    // 分配远超过初始静态堆(32KiB)的内存, 分配器会自己通过sbrk扩展堆
+    let len = 64 * 1024;
+    let mut v: Vec<usize> = Vec::with_capacity(len);
+    for i in 0..len {
+        v.push(i);
+    }
+    assert_eq!(v.iter().sum::<usize>(), len * (len - 1) / 2);
+    println!("heap_grow passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, link, open, read, rename, unlink, write, OpenFlags};
+
+fn read_file(path: &str, buffer: &mut [u8]) -> isize {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return fd;
+    }
+    let len = read(fd as usize, buffer);
+    close(fd as usize);
+    return len;
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, link!";
+    let fd = open(
+        "linka\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    // 硬链接: 删除原来的名字之后还能通过新名字读到内容
+    let mut buffer = [0u8; 32];
+    assert_eq!(link("linka\0", "linkb\0"), 0);
+    assert_eq!(link("linka\0", "linkb\0"), -1);
+    assert_eq!(unlink("linka\0"), 0);
+    assert_eq!(read_file("linka\0", &mut buffer), -1);
+    let len = read_file("linkb\0", &mut buffer) as usize;
+    assert_eq!(&buffer[..len], test_str.as_bytes());
+
+    // 改名之后旧名字就找不到了
+    assert_eq!(rename("linkb\0", "linkc\0"), 0);
+    assert_eq!(read_file("linkb\0", &mut buffer), -1);
+    let len = read_file("linkc\0", &mut buffer) as usize;
+    assert_eq!(&buffer[..len], test_str.as_bytes());
+    assert_eq!(unlink("linkc\0"), 0);
+    assert_eq!(unlink("linkc\0"), -1);
+    println!("link_simple passed!");
+    0
+}
@@ -1,12 +1,136 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
 extern crate user_lib;
+extern crate alloc;
 
-use user_lib::list_apps;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{
+    close, dirents, getdents, lstat, open, readlink, stat, OpenFlags, Stat, S_IFBLK, S_IFCHR,
+    S_IFDIR, S_IFLNK, S_IFMT,
+};
 
+/// 把权限位转换成 drwxr-xr-x 这样的格式
+fn print_mode(st: &Stat) {
+    let kind = match st.mode & S_IFMT {
+        S_IFDIR => 'd',
+        S_IFLNK => 'l',
+        S_IFCHR => 'c',
+        S_IFBLK => 'b',
+        _ => '-',
+    };
+    print!("{}", kind);
+    for i in (0..9).rev() {
+        if st.mode & (1 << i) == 0 {
+            print!("-");
+        } else {
+            print!("{}", ['x', 'w', 'r'][i % 3]);
+        }
+    }
+}
+
+/// 一行显示一个文件, -l 时显示 权限 链接数 inode号 大小 修改时间, 符号链接还显示指向的路径
+fn print_file(path: &str, name: &str, long: bool) -> bool {
+    if !long {
+        println!("{}", name);
+        return true;
+    }
+    let mut st = Stat::default();
+    if lstat(path, &mut st) != 0 {
+        println!("ls: cannot access {}", path);
+        return false;
+    }
+    let mut target = String::new();
+    if st.is_symlink() {
+        let mut buf = [0u8; 256];
+        let len = readlink(path, &mut buf);
+        if len >= 0 {
+            target = format!(" -> {}", String::from_utf8_lossy(&buf[..len as usize]));
+        }
+    }
+    print_mode(&st);
+    println!(
+        " {} {:>4} {:>8} {:>6}.{:03} {}{}",
+        st.nlink,
+        st.ino,
+        st.size,
+        st.mtime_sec,
+        st.mtime_nsec / 1_000_000,
+        name,
+        target
+    );
+    return true;
+}
+
+/// 用getdents读出目录里的文件名(不含 . 开头的), 按名字排序
+fn read_dir(path: &str) -> Option<Vec<String>> {
+    let fd = open(format!("{}\0", path).as_str(), OpenFlags::DIRECTORY);
+    if fd < 0 {
+        return None;
+    }
+    let mut buf = [0u8; 512];
+    let mut names = Vec::new();
+    loop {
+        let len = getdents(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        for dirent in dirents(&buf, len as usize) {
+            if !dirent.name.starts_with('.') {
+                names.push(String::from(dirent.name));
+            }
+        }
+    }
+    close(fd as usize);
+    names.sort();
+    return Some(names);
+}
+
+/// ls [-l] [path...] 列出目录里的文件, 默认是当前目录
 #[no_mangle]
-pub fn main() -> i32 {
-    list_apps();
-    return 0;
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let long = argc >= 2 && argv[1] == "-l";
+    let mut paths: Vec<&str> = argv[1..argc].iter().copied().filter(|arg| *arg != "-l").collect();
+    if paths.is_empty() {
+        paths.push(".");
+    }
+    let mut exit_code = 0;
+    for (i, path) in paths.iter().enumerate() {
+        let mut st = Stat::default();
+        if stat(format!("{}\0", path).as_str(), &mut st) != 0 {
+            println!("ls: cannot access {}", path);
+            exit_code = -1;
+            continue;
+        }
+        if !st.is_dir() {
+            if !print_file(format!("{}\0", path).as_str(), path, long) {
+                exit_code = -1;
+            }
+            continue;
+        }
+        if paths.len() > 1 {
+            if i > 0 {
+                println!("");
+            }
+            println!("{}:", path);
+        }
+        let names = match read_dir(path) {
+            Some(names) => names,
+            None => {
+                println!("ls: cannot open directory {}", path);
+                exit_code = -1;
+                continue;
+            }
+        };
+        for name in names {
+            let file_path = format!("{}/{}\0", path.trim_end_matches('/'), name);
+            if !print_file(file_path.as_str(), name.as_str(), long) {
+                exit_code = -1;
+            }
+        }
+    }
+    return exit_code;
 }
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, mmap, mprotect, munmap, open, read, write, MapFlags, OpenFlags, ProtFlags};
+
+const PAGE_SIZE: usize = 4096;
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // 匿名映射: 页面一开始全是0, 可以读写
+    let len = PAGE_SIZE * 4;
+    let addr = mmap(
+        0,
+        len,
+        ProtFlags::READ | ProtFlags::WRITE,
+        MapFlags::PRIVATE | MapFlags::ANONYMOUS,
+        0,
+        0,
+    );
+    assert!(addr > 0);
+    let buf = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) };
+    assert!(buf.iter().all(|b| *b == 0));
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    assert_eq!(buf[PAGE_SIZE * 3 + 7], (PAGE_SIZE * 3 + 7) as u8);
+    assert_eq!(mprotect(addr as usize, PAGE_SIZE, ProtFlags::READ), 0);
+    assert_eq!(munmap(addr as usize, len), 0);
+
+    // MAP_FIXED只能映射在mmap区域里, 长度溢出也要失败
+    let anon = MapFlags::PRIVATE | MapFlags::ANONYMOUS;
+    let fixed = anon | MapFlags::FIXED;
+    let (prot, addr) = (ProtFlags::READ, addr as usize);
+    assert_eq!(mmap(addr, PAGE_SIZE, prot, fixed, 0, 0), addr as isize);
+    assert_eq!(munmap(addr, PAGE_SIZE), 0);
+    let trampoline = usize::MAX - PAGE_SIZE + 1;
+    assert_eq!(mmap(trampoline, PAGE_SIZE, prot, fixed, 0, 0), -1);
+    assert_eq!(mmap(PAGE_SIZE, PAGE_SIZE, prot, fixed, 0, 0), -1);
+    assert_eq!(mmap(addr, usize::MAX, prot, fixed, 0, 0), -1);
+    assert_eq!(mmap(0, usize::MAX, prot, anon, 0, 0), -1);
+
+    // 文件映射: 只读打开的文件不能共享可写映射
+    let test_str = "Hello, mmap!";
+    let fname = "mmapfile\0";
+    let fd = open(
+        fname,
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let shared_rw = mmap(
+        0,
+        PAGE_SIZE,
+        ProtFlags::READ | ProtFlags::WRITE,
+        MapFlags::SHARED,
+        fd,
+        0,
+    );
+    assert_eq!(shared_rw, -1);
+    let addr = mmap(0, PAGE_SIZE, ProtFlags::READ, MapFlags::PRIVATE, fd, 0);
+    assert!(addr > 0);
+    close(fd);
+    let mapped = unsafe { core::slice::from_raw_parts(addr as *const u8, test_str.len()) };
+    assert_eq!(mapped, test_str.as_bytes());
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+
+    // 共享映射的修改在munmap时写回文件
+    let fd = open(fname, OpenFlags::RDWR);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    let addr = mmap(
+        0,
+        PAGE_SIZE,
+        ProtFlags::READ | ProtFlags::WRITE,
+        MapFlags::SHARED,
+        fd,
+        0,
+    );
+    assert!(addr > 0);
+    unsafe {
+        *(addr as *mut u8) = b'J';
+    }
+    assert_eq!(munmap(addr as usize, PAGE_SIZE), 0);
+    close(fd);
+
+    let fd = open(fname, OpenFlags::RDONLY);
+    assert!(fd > 0);
+    let mut buffer = [0u8; 32];
+    let read_len = read(fd as usize, &mut buffer) as usize;
+    close(fd as usize);
+    assert_eq!(&buffer[..read_len], b"Jello, mmap!");
+    println!("mmap_simple passed!");
+    0
+}
@@ -0,0 +1,48 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{chdir, close, getcwd, mkdir, mount, open, rmdir, stat, umount, OpenFlags, Stat};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    assert_eq!(mkdir("mnt\0"), 0);
+    assert_eq!(mkdir("mnt\0"), -1);
+    // 不认识的文件系统和设备挂载失败
+    assert_eq!(mount("/dev/vda\0", "mnt\0", "nofs\0"), -1);
+    assert_eq!(mount("/dev/nodev\0", "mnt\0", "easyfs\0"), -1);
+    assert_eq!(mount("/dev/vda\0", "nonexist\0", "easyfs\0"), -1);
+
+    // 把根文件系统再挂载到 /mnt, 里面能看到同样的文件, 但设备号不同
+    assert_eq!(mount("/dev/vda\0", "mnt\0", "easyfs\0"), 0);
+    assert_eq!(mount("/dev/vda\0", "/mnt\0", "easyfs\0"), -1);
+    let mut root_st = Stat::default();
+    let mut mnt_st = Stat::default();
+    assert_eq!(stat("/initproc\0", &mut root_st), 0);
+    assert_eq!(stat("/mnt/initproc\0", &mut mnt_st), 0);
+    assert_eq!(root_st.ino, mnt_st.ino);
+    assert_ne!(root_st.dev, mnt_st.dev);
+
+    // 挂载点的 .. 回到挂载点所在的目录
+    let mut buf = [0u8; 64];
+    assert_eq!(chdir("/mnt\0"), 0);
+    assert_eq!(getcwd(&mut buf), Some("/mnt"));
+    assert_eq!(umount("/mnt\0"), -1);
+    assert_eq!(chdir("..\0"), 0);
+    assert_eq!(getcwd(&mut buf), Some("/"));
+
+    // 挂载点不能删除, 打开的文件让文件系统不能卸载
+    assert_eq!(rmdir("mnt\0"), -1);
+    let fd = open("mnt/initproc\0", OpenFlags::RDONLY);
+    assert!(fd > 0);
+    assert_eq!(umount("mnt\0"), -1);
+    close(fd as usize);
+    assert_eq!(umount("mnt\0"), 0);
+    assert_eq!(umount("mnt\0"), -1);
+    assert_eq!(stat("/mnt/initproc\0", &mut mnt_st), -1);
+    assert_eq!(rmdir("mnt\0"), 0);
+    println!("mount_simple passed!");
+    0
+}
@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::rename;
+
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    if argc != 3 {
+        println!("usage: mv source dest");
+        return -1;
+    }
+    if rename(argv[1], argv[2]) != 0 {
+        println!("mv: cannot move {} to {}", argv[1], argv[2]);
+        return -1;
+    }
+    return 0;
+}
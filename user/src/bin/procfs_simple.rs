@@ -0,0 +1,108 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use user_lib::{
+    close, dirents, getdents, getpid, open, read, readlink, stat, unlink, write, OpenFlags, Stat,
+};
+
+/// 读出整个文件, procfs的文件大小是0, 只能读到结束为止
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut content = String::new();
+    let mut buf = [0u8; 128];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        content.push_str(core::str::from_utf8(&buf[..len as usize]).unwrap());
+    }
+    close(fd as usize);
+    return Some(content);
+}
+
+fn read_link(path: &str) -> Option<String> {
+    let mut buf = [0u8; 64];
+    let len = readlink(path, &mut buf);
+    if len < 0 {
+        return None;
+    }
+    return Some(String::from(
+        core::str::from_utf8(&buf[..len as usize]).unwrap(),
+    ));
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let pid = getpid();
+    assert_eq!(read_link("/proc/self\0"), Some(format!("{}", pid)));
+
+    // 自己的状态: 名字, pid, 只有一个线程
+    let status = read_file("/proc/self/status\0").unwrap();
+    assert!(status.contains("Name:\tprocfs_simple\n"));
+    assert!(status.contains(format!("Pid:\t{}\n", pid).as_str()));
+    assert!(status.contains("State:\tR (running)\n"));
+    assert!(status.contains("Threads:\t1\n"));
+    assert!(read_file(format!("/proc/{}/maps\0", pid).as_str())
+        .unwrap()
+        .contains("Stack"));
+
+    // fd目录里是指向打开的文件的符号链接
+    let fd = open(
+        "procfsfile\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    let fd_link = format!("/proc/self/fd/{}\0", fd);
+    assert_eq!(
+        read_link(fd_link.as_str()),
+        Some(String::from("/procfsfile"))
+    );
+    assert_eq!(
+        read_link("/proc/self/fd/1\0"),
+        Some(String::from("/dev/tty"))
+    );
+    close(fd as usize);
+    assert_eq!(read_link(fd_link.as_str()), None);
+    assert_eq!(unlink("procfsfile\0"), 0);
+
+    // 根目录下能看到自己的进程目录
+    let dir = open("/proc\0", OpenFlags::DIRECTORY);
+    assert!(dir > 0);
+    let mut buf = [0u8; 512];
+    let mut found = false;
+    loop {
+        let len = getdents(dir as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        let pid_name = format!("{}", pid);
+        found |=
+            dirents(&buf, len as usize).any(|dirent| dirent.is_dir() && dirent.name == pid_name);
+    }
+    close(dir as usize);
+    assert!(found);
+
+    assert!(read_file("/proc/meminfo\0")
+        .unwrap()
+        .starts_with("MemTotal:"));
+    assert!(!read_file("/proc/uptime\0").unwrap().is_empty());
+    // procfs是只读的, 不存在的进程找不到
+    let fd = open("/proc/meminfo\0", OpenFlags::WRONLY);
+    assert!(fd > 0);
+    assert_eq!(write(fd as usize, b"0"), 0);
+    close(fd as usize);
+    let mut st = Stat::default();
+    assert_eq!(stat("/proc/99999/status\0", &mut st), -1);
+    println!("procfs_simple passed!");
+    0
+}
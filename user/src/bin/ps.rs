@@ -0,0 +1,80 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use user_lib::{close, dirents, getdents, open, read, OpenFlags};
+
+fn read_file(path: &str) -> Option<String> {
+    let fd = open(path, OpenFlags::RDONLY);
+    if fd < 0 {
+        return None;
+    }
+    let mut content = String::new();
+    let mut buf = [0u8; 128];
+    loop {
+        let len = read(fd as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        content.push_str(core::str::from_utf8(&buf[..len as usize]).unwrap_or(""));
+    }
+    close(fd as usize);
+    return Some(content);
+}
+
+/// /proc/<pid>/status 里`key`那一行的值
+fn field<'a>(status: &'a str, key: &str) -> &'a str {
+    return status
+        .lines()
+        .find_map(|line| line.strip_prefix(key)?.strip_prefix(":\t"))
+        .unwrap_or("?");
+}
+
+/// ps 列出所有进程, 信息来自 /proc/<pid>/status
+#[no_mangle]
+pub fn main() -> i32 {
+    let dir = open("/proc\0", OpenFlags::DIRECTORY);
+    if dir < 0 {
+        println!("ps: /proc is not mounted");
+        return -1;
+    }
+    let mut pids: Vec<usize> = Vec::new();
+    let mut buf = [0u8; 512];
+    loop {
+        let len = getdents(dir as usize, &mut buf);
+        if len <= 0 {
+            break;
+        }
+        pids.extend(
+            dirents(&buf, len as usize).filter_map(|dirent| dirent.name.parse::<usize>().ok()),
+        );
+    }
+    close(dir as usize);
+    println!(
+        "{:>5} {:>5} {:>4} {:>8}  {}",
+        "PID", "PPID", "THR", "RSS", "STAT NAME"
+    );
+    for pid in pids {
+        // 读目录之后进程可能已经退出了
+        let status = match read_file(format!("/proc/{}/status\0", pid).as_str()) {
+            Some(status) => status,
+            None => continue,
+        };
+        println!(
+            "{:>5} {:>5} {:>4} {:>8}  {}    {}",
+            pid,
+            field(&status, "PPid"),
+            field(&status, "Threads"),
+            field(&status, "VmRSS"),
+            field(&status, "State").chars().next().unwrap_or('?'),
+            field(&status, "Name")
+        );
+    }
+    return 0;
+}
@@ -1,12 +1,22 @@
 #![no_std]
 #![no_main]
 
+#[macro_use]
 extern crate user_lib;
 
-use user_lib::get_cwd;
+use user_lib::getcwd;
 
 #[no_mangle]
 pub fn main() -> i32 {
-    get_cwd();
-    return 0;
+    let mut buf = [0u8; 256];
+    match getcwd(&mut buf) {
+        Some(path) => {
+            println!("{}", path);
+            return 0;
+        }
+        None => {
+            println!("pwd: cannot get current directory");
+            return -1;
+        }
+    }
 }
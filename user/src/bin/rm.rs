@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{rmdir, unlink};
+
+/// rm [-d] path... , -d 表示删除空目录
+#[no_mangle]
+pub fn main(argc: usize, argv: &[&str]) -> i32 {
+    let remove_dir = argc > 1 && argv[1] == "-d";
+    let start = if remove_dir { 2 } else { 1 };
+    if argc <= start {
+        println!("usage: rm [-d] path...");
+        return -1;
+    }
+    let mut exit_code = 0;
+    for path in &argv[start..argc] {
+        let ret = if remove_dir { rmdir(path) } else { unlink(path) };
+        if ret != 0 {
+            println!("rm: cannot remove {}", path);
+            exit_code = -1;
+        }
+    }
+    return exit_code;
+}
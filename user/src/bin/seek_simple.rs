@@ -0,0 +1,72 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    close, lseek, open, pipe, pread, pwrite, read, unlink, write, OpenFlags, SEEK_CUR, SEEK_END,
+    SEEK_SET,
+};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let fname = "seekfile\0";
+    let fd = open(
+        fname,
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, b"Hello, world!"), 13);
+
+    // lseek: 三种起点, 移到负数位置失败并且不改变偏移
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 13);
+    assert_eq!(lseek(fd, 7, SEEK_SET), 7);
+    let mut buffer = [0u8; 16];
+    assert_eq!(read(fd, &mut buffer[..5]), 5);
+    assert_eq!(&buffer[..5], b"world");
+    assert_eq!(lseek(fd, -6, SEEK_END), 7);
+    assert_eq!(lseek(fd, -100, SEEK_CUR), -1);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 7);
+    assert_eq!(lseek(fd, 0, 3), -1);
+
+    // pread/pwrite 不移动文件偏移
+    assert_eq!(pwrite(fd, b"W", 7), 1);
+    assert_eq!(pread(fd, &mut buffer, 0), 13);
+    assert_eq!(&buffer[..13], b"Hello, World!");
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 7);
+
+    // 移到文件末尾之后再写, 中间的空洞读出来是0
+    assert_eq!(lseek(fd, 16, SEEK_SET), 16);
+    assert_eq!(write(fd, b"!"), 1);
+    assert_eq!(pread(fd, &mut buffer, 13), 4);
+    assert_eq!(&buffer[..4], b"\0\0\0!");
+    close(fd);
+
+    // O_APPEND: 不管偏移在哪里, 写入总是在文件末尾
+    let fd = open(fname, OpenFlags::WRONLY | OpenFlags::APPEND);
+    assert!(fd > 0);
+    let fd = fd as usize;
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    assert_eq!(write(fd, b"??"), 2);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), 19);
+    close(fd);
+    let fd = open(fname, OpenFlags::RDONLY) as usize;
+    assert_eq!(read(fd, &mut buffer), 16);
+    assert_eq!(&buffer[13..16], b"\0\0!");
+    assert_eq!(pread(fd, &mut buffer[..2], 17), 2);
+    assert_eq!(&buffer[..2], b"??");
+    close(fd);
+    assert_eq!(unlink(fname), 0);
+
+    // 管道不能lseek
+    let mut pipe_fd = [0usize; 2];
+    pipe(&mut pipe_fd);
+    assert_eq!(lseek(pipe_fd[0], 0, SEEK_SET), -1);
+    assert_eq!(pwrite(pipe_fd[1], b"x", 0), -1);
+    close(pipe_fd[0]);
+    close(pipe_fd[1]);
+    println!("seek_simple passed!");
+    0
+}
@@ -0,0 +1,46 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, fstat, link, open, stat, unlink, write, OpenFlags, Stat};
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, stat!";
+    let fd = open(
+        "stata\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    let fd = fd as usize;
+    write(fd, test_str.as_bytes());
+
+    // fstat和stat看到的是同一个inode
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd, &mut st), 0);
+    close(fd);
+    assert!(!st.is_dir());
+    assert_eq!(st.size as usize, test_str.len());
+    assert_eq!((st.nlink, st.mode & 0o777), (1, 0o644));
+    let mut st2 = Stat::default();
+    assert_eq!(stat("stata\0", &mut st2), 0);
+    assert_eq!(st2.ino, st.ino);
+    assert!(st2.mtime_sec * 1000 + st2.mtime_nsec / 1_000_000 > 0);
+
+    // 硬链接数
+    assert_eq!(link("stata\0", "statb\0"), 0);
+    assert_eq!(stat("statb\0", &mut st2), 0);
+    assert_eq!((st2.ino, st2.nlink), (st.ino, 2));
+    assert_eq!(unlink("stata\0"), 0);
+    assert_eq!(unlink("statb\0"), 0);
+    assert_eq!(stat("stata\0", &mut st2), -1);
+
+    // 根目录
+    assert_eq!(stat("/\0", &mut st2), 0);
+    assert!(st2.is_dir());
+    assert_eq!(st2.ino, 0);
+    println!("stat_simple passed!");
+    0
+}
@@ -0,0 +1,68 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{close, lstat, open, read, readlink, stat, symlink, unlink, write, OpenFlags, Stat};
+
+fn read_file(path: &str, flags: OpenFlags, buffer: &mut [u8]) -> isize {
+    let fd = open(path, flags);
+    if fd < 0 {
+        return fd;
+    }
+    let len = read(fd as usize, buffer);
+    close(fd as usize);
+    return len;
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    let test_str = "Hello, symlink!";
+    let fd = open(
+        "symlinka\0",
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
+    );
+    assert!(fd > 0);
+    write(fd as usize, test_str.as_bytes());
+    close(fd as usize);
+
+    // 打开链接读到的是目标的内容, O_NOFOLLOW 打不开链接
+    let mut buffer = [0u8; 32];
+    assert_eq!(symlink("symlinka\0", "symlinkb\0"), 0);
+    assert_eq!(symlink("symlinka\0", "symlinkb\0"), -1);
+    let len = read_file("symlinkb\0", OpenFlags::RDONLY, &mut buffer) as usize;
+    assert_eq!(&buffer[..len], test_str.as_bytes());
+    assert_eq!(
+        read_file("symlinkb\0", OpenFlags::NOFOLLOW, &mut buffer),
+        -1
+    );
+    assert!(read_file("symlinka\0", OpenFlags::NOFOLLOW, &mut buffer) > 0);
+
+    // readlink 读出链接指向的路径, 放不下就截断
+    let len = readlink("symlinkb\0", &mut buffer) as usize;
+    assert_eq!(&buffer[..len], b"symlinka");
+    assert_eq!(readlink("symlinkb\0", &mut buffer[..4]), 4);
+    assert_eq!(readlink("symlinka\0", &mut buffer), -1);
+
+    // stat 看到的是目标, lstat 看到的是链接本身
+    let (mut st, mut lst) = (Stat::default(), Stat::default());
+    assert_eq!(stat("symlinkb\0", &mut st), 0);
+    assert_eq!(lstat("symlinkb\0", &mut lst), 0);
+    assert!(!st.is_symlink() && lst.is_symlink());
+    assert_eq!(st.size as usize, test_str.len());
+    assert_eq!(lst.size as usize, "symlinka".len());
+    assert_ne!(st.ino, lst.ino);
+
+    // 目标删除之后链接就悬空了, 链接成环也打不开
+    assert_eq!(unlink("symlinka\0"), 0);
+    assert_eq!(read_file("symlinkb\0", OpenFlags::RDONLY, &mut buffer), -1);
+    assert_eq!(symlink("symlinkd\0", "symlinkc\0"), 0);
+    assert_eq!(symlink("symlinkc\0", "symlinkd\0"), 0);
+    assert_eq!(read_file("symlinkc\0", OpenFlags::RDONLY, &mut buffer), -1);
+    for path in ["symlinkb\0", "symlinkc\0", "symlinkd\0"] {
+        assert_eq!(unlink(path), 0);
+    }
+    println!("symlink_simple passed!");
+    0
+}
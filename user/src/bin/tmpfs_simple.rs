@@ -0,0 +1,120 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use user_lib::{
+    close, link, lseek, mkdir, mount, open, read, readlink, rename, rmdir, stat, symlink, umount,
+    unlink, write, OpenFlags, Stat, SEEK_SET,
+};
+
+const PAGE_SIZE: usize = 4096;
+
+/// MemFree of /proc/meminfo in kB
+fn mem_free() -> usize {
+    let fd = open("/proc/meminfo\0", OpenFlags::RDONLY);
+    assert!(fd >= 0);
+    let mut buf = [0u8; 256];
+    let len = read(fd as usize, &mut buf);
+    close(fd as usize);
+    let meminfo = core::str::from_utf8(&buf[..len as usize]).unwrap();
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("MemFree:"))
+        .unwrap();
+    return line
+        .trim_start_matches("MemFree:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse::<usize>()
+        .unwrap();
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    // /tmp 是单独挂载的文件系统
+    let mut root_st = Stat::default();
+    let mut tmp_st = Stat::default();
+    assert_eq!(stat("/\0", &mut root_st), 0);
+    assert_eq!(stat("/tmp\0", &mut tmp_st), 0);
+    assert!(tmp_st.is_dir());
+    assert_ne!(root_st.dev, tmp_st.dev);
+
+    // 跨页读写
+    let data: Vec<u8> = (0..PAGE_SIZE * 3 + 100).map(|i| (i % 251) as u8).collect();
+    let fd = open("/tmp/a\0", OpenFlags::CREATE | OpenFlags::RDWR);
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &data), data.len() as isize);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut buf = vec![0u8; data.len()];
+    assert_eq!(read(fd, &mut buf), data.len() as isize);
+    assert_eq!(buf, data);
+    // 文件中间的空洞读出来是0, 不占页帧
+    let hole_end = PAGE_SIZE * 8;
+    assert_eq!(lseek(fd, hole_end as isize, SEEK_SET), hole_end as isize);
+    assert_eq!(write(fd, b"end"), 3);
+    assert_eq!(
+        lseek(fd, data.len() as isize, SEEK_SET),
+        data.len() as isize
+    );
+    let mut hole = vec![0xffu8; hole_end - data.len()];
+    assert_eq!(read(fd, &mut hole), hole.len() as isize);
+    assert!(hole.iter().all(|b| *b == 0));
+    close(fd);
+    let mut st = Stat::default();
+    assert_eq!(stat("/tmp/a\0", &mut st), 0);
+    assert_eq!(st.size, (hole_end + 3) as i64);
+    assert_eq!(st.blocks, (5 * PAGE_SIZE / 512) as u64);
+
+    // 目录, 硬链接, 符号链接和改名
+    assert_eq!(mkdir("/tmp/d\0"), 0);
+    assert_eq!(rename("/tmp/a\0", "/tmp/d/b\0"), 0);
+    assert_eq!(link("/tmp/d/b\0", "/tmp/c\0"), 0);
+    assert_eq!(stat("/tmp/c\0", &mut st), 0);
+    assert_eq!(st.nlink, 2);
+    assert_eq!(symlink("d/b\0", "/tmp/l\0"), 0);
+    let mut target = [0u8; 16];
+    let len = readlink("/tmp/l\0", &mut target);
+    assert_eq!(&target[..len as usize], b"d/b");
+    assert_eq!(stat("/tmp/l\0", &mut st), 0);
+    assert_eq!(st.size, (hole_end + 3) as i64);
+    assert_eq!(rename("/tmp/d\0", "/tmp/d/e\0"), -1);
+    assert_eq!(rmdir("/tmp/d\0"), -1);
+    assert_eq!(unlink("/tmp/d/b\0"), 0);
+    assert_eq!(stat("/tmp/c\0", &mut st), 0);
+    assert_eq!(st.nlink, 1);
+    assert_eq!(rmdir("/tmp/d\0"), 0);
+    assert_eq!(stat("/tmp/l\0", &mut st), -1);
+    assert_eq!(unlink("/tmp/l\0"), 0);
+    assert_eq!(unlink("/tmp/c\0"), 0);
+
+    // 另外挂载一个tmpfs, 卸载之后页帧都还回去了, 再挂载是空的
+    assert_eq!(mkdir("/tmp/m\0"), 0);
+    assert_eq!(mount("tmpfs\0", "/tmp/m\0", "tmpfs\0"), 0);
+    let pages = 64;
+    let before = mem_free();
+    let fd = open("/tmp/m/big\0", OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    let page = [0x5au8; PAGE_SIZE];
+    for _ in 0..pages {
+        assert_eq!(write(fd as usize, &page), PAGE_SIZE as isize);
+    }
+    assert_eq!(umount("/tmp/m\0"), -1);
+    close(fd as usize);
+    assert!(mem_free() + pages * PAGE_SIZE / 1024 <= before);
+    assert_eq!(umount("/tmp/m\0"), 0);
+    // 允许其他地方(页表, 内核栈)用掉少量页帧
+    assert!(mem_free() + 16 * PAGE_SIZE / 1024 >= before);
+    assert_eq!(mount("tmpfs\0", "/tmp/m\0", "tmpfs\0"), 0);
+    assert!(open("/tmp/m/big\0", OpenFlags::RDONLY) < 0);
+    assert_eq!(umount("/tmp/m\0"), 0);
+    assert_eq!(rmdir("/tmp/m\0"), 0);
+    println!("tmpfs_simple passed!");
+    0
+}
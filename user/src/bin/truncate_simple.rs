@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use user_lib::{
+    close, fstat, ftruncate, lseek, open, read, stat, truncate, unlink, write, OpenFlags, Stat,
+    SEEK_CUR, SEEK_SET,
+};
+
+/// Size of the file `fd`
+fn size_of(fd: usize) -> i64 {
+    let mut st = Stat::default();
+    assert_eq!(fstat(fd, &mut st), 0);
+    return st.size;
+}
+
+/// 在目录`dir`里测试截断, easy-fs和tmpfs的行为应该一样
+fn test_in(dir: &str) {
+    let path = format!("{}/truncfile\0", dir);
+    let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+    let fd = open(
+        path.as_str(),
+        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::RDWR,
+    );
+    assert!(fd >= 0);
+    let fd = fd as usize;
+    assert_eq!(write(fd, &data), data.len() as isize);
+
+    // 变短, 文件偏移不变
+    assert_eq!(ftruncate(fd, 1000), 0);
+    assert_eq!(size_of(fd), 1000);
+    assert_eq!(lseek(fd, 0, SEEK_CUR), data.len() as isize);
+    // 再变长, 中间读出来是0
+    assert_eq!(ftruncate(fd, 5000), 0);
+    assert_eq!(size_of(fd), 5000);
+    assert_eq!(lseek(fd, 0, SEEK_SET), 0);
+    let mut buf = vec![0xffu8; 6000];
+    assert_eq!(read(fd, &mut buf), 5000);
+    assert!(buf[..1000] == data[..1000]);
+    assert!(buf[1000..5000].iter().all(|b| *b == 0));
+    assert_eq!(ftruncate(fd, -1), -1);
+    close(fd);
+
+    // 按路径截断
+    let mut st = Stat::default();
+    assert_eq!(truncate(path.as_str(), 10), 0);
+    assert_eq!(stat(path.as_str(), &mut st), 0);
+    assert_eq!(st.size, 10);
+    assert_eq!(truncate(format!("{}\0", dir).as_str(), 0), -1);
+
+    // 只读打开的不能截断, 也不会被TRUNC清空
+    let fd = open(path.as_str(), OpenFlags::RDONLY | OpenFlags::TRUNC);
+    assert!(fd >= 0);
+    assert_eq!(ftruncate(fd as usize, 0), -1);
+    assert_eq!(size_of(fd as usize), 10);
+    close(fd as usize);
+    // 只带CREATE打开已有的文件不会清空, 带TRUNC才清空
+    let fd = open(path.as_str(), OpenFlags::CREATE | OpenFlags::WRONLY);
+    assert!(fd >= 0);
+    assert_eq!(size_of(fd as usize), 10);
+    close(fd as usize);
+    let fd = open(path.as_str(), OpenFlags::WRONLY | OpenFlags::TRUNC);
+    assert!(fd >= 0);
+    assert_eq!(size_of(fd as usize), 0);
+    close(fd as usize);
+    assert_eq!(unlink(path.as_str()), 0);
+}
+
+#[no_mangle]
+pub fn main() -> i32 {
+    test_in(".");
+    test_in("/tmp");
+    println!("truncate_simple passed!");
+    0
+}
@@ -2,10 +2,11 @@
 #![no_main]
 #![allow(clippy::println_empty_string)]
 
+use alloc::format;
 use alloc::string::String;
 use alloc::vec::Vec;
 use user_lib::console::getchar;
-use user_lib::{chdir, close, dup, exec, fork, open, pipe, waitpid, OpenFlags};
+use user_lib::{chdir, close, dup, exec, fork, getcwd, open, pipe, stat, waitpid, OpenFlags, Stat};
 
 extern crate alloc;
 
@@ -91,12 +92,25 @@ pub fn main() -> i32 {
                 println!("");
                 if line.starts_with("cd ") {
                     line.push('\0');
-                    println!("change dir");
-                    let path = &line[3..line.len()];
-
-                    chdir(path);
-                    current_working_dir = String::from(path);
-                    print!("{} >>", path);
+                    let path = line[3..line.len()].trim_start();
+                    if chdir(path) != 0 {
+                        let name = &path[..path.len() - 1];
+                        // chdir失败只返回-1, 用stat区分是不存在还是不是目录
+                        let mut st = Stat::default();
+                        if stat(path, &mut st) != 0 {
+                            println!("cd: {}: no such file or directory", name);
+                        } else if !st.is_dir() {
+                            println!("cd: {}: not a directory", name);
+                        } else {
+                            println!("cd: cannot change directory to {}", name);
+                        }
+                    }
+                    // 提示符显示内核里真正的当前目录
+                    let mut buf = [0u8; 256];
+                    if let Some(cwd) = getcwd(&mut buf) {
+                        current_working_dir = String::from(cwd);
+                    }
+                    print!("{} >>", current_working_dir);
                     line.clear(); //回车意味着提示符清空
                     continue;
                 }
@@ -163,7 +177,7 @@ pub fn main() -> i32 {
                                 if !output.is_empty() {
                                     let output_fd = open(
                                         output.as_str(),
-                                        OpenFlags::CREATE | OpenFlags::WRONLY,
+                                        OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY,
                                     );
                                     if output_fd == -1 {
                                         println!("Error when opening file {}", output);
@@ -199,7 +213,13 @@ pub fn main() -> i32 {
                                 }
                                 // execute new application
                                 // 根据命令行 要打开多个带参数新进程
-                                if exec(args_copy[0].as_str(), args_addr.as_slice()) == -1 {
+                                // 不带'/'的命令都在根目录下找, 相当于PATH=/
+                                let app_path = if args_copy[0].contains('/') {
+                                    args_copy[0].clone()
+                                } else {
+                                    format!("/{}", args_copy[0])
+                                };
+                                if exec(app_path.as_str(), args_addr.as_slice()) == -1 {
                                     println!("Error when executing!");
                                     return -4;
                                 }
@@ -10,6 +10,20 @@ extern crate user_lib;
 // item of TESTS : app_name(argv_0), argv_1, argv_2, argv_3, exit_code
 static SUCC_TESTS: &[(&str, &str, &str, &str, i32)] = &[
     ("filetest_simple\0", "\0", "\0", "\0", 0),
+    ("mmap_simple\0", "\0", "\0", "\0", 0),
+    ("heap_grow\0", "\0", "\0", "\0", 0),
+    ("link_simple\0", "\0", "\0", "\0", 0),
+    ("symlink_simple\0", "\0", "\0", "\0", 0),
+    ("stat_simple\0", "\0", "\0", "\0", 0),
+    ("cwd_simple\0", "\0", "\0", "\0", 0),
+    ("mount_simple\0", "\0", "\0", "\0", 0),
+    ("procfs_simple\0", "\0", "\0", "\0", 0),
+    ("devfs_simple\0", "\0", "\0", "\0", 0),
+    ("tmpfs_simple\0", "\0", "\0", "\0", 0),
+    ("fat_simple\0", "\0", "\0", "\0", 0),
+    ("truncate_simple\0", "\0", "\0", "\0", 0),
+    ("seek_simple\0", "\0", "\0", "\0", 0),
+    ("dirent_simple\0", "\0", "\0", "\0", 0),
     ("cat_filea\0", "\0", "\0", "\0", 0),
     ("exit\0", "\0", "\0", "\0", 0),
     ("fantastic_text\0", "\0", "\0", "\0", 0),
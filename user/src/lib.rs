@@ -15,16 +15,54 @@ extern crate bitflags;
 
 use alloc::vec::Vec;
 use buddy_system_allocator::LockedHeap;
+use core::alloc::{GlobalAlloc, Layout};
+use core::convert::TryInto;
+use core::ptr::NonNull;
 pub use io::*;
 use syscall::*;
 
 const USER_HEAP_SIZE: usize = 32768;
+const PAGE_SIZE: usize = 4096;
+const HEAP_GROW_SIZE: usize = PAGE_SIZE * 16; //堆不够用时每次至少向内核多要这么多
 const LOG_FLAG: bool = true;
+const AT_FDCWD: isize = -100; //路径相对于当前目录
+const AT_REMOVEDIR: u32 = 0x200; //unlinkat删除空目录
+const AT_SYMLINK_NOFOLLOW: u32 = 0x100; //fstatat不跟随最后一个符号链接
+pub const SEEK_SET: usize = 0; //从文件开头计算偏移
+pub const SEEK_CUR: usize = 1; //从当前位置计算偏移
+pub const SEEK_END: usize = 2; //从文件末尾计算偏移
 
 static mut HEAP_SPACE: [u8; USER_HEAP_SIZE] = [0; USER_HEAP_SIZE];
 
+/// 先用静态的HEAP_SPACE, 不够用时通过sbrk向内核申请更多内存加入堆中
+struct GrowableHeap(LockedHeap);
+
+unsafe impl GlobalAlloc for GrowableHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut heap = self.0.lock();
+        if let Ok(ptr) = heap.alloc(layout) {
+            return ptr.as_ptr();
+        }
+        // 伙伴分配器要一块对齐的2的幂大小的空间, 申请两倍大小才能保证放得下
+        let size = (layout.size().max(layout.align()).next_power_of_two() * 2).max(HEAP_GROW_SIZE);
+        let size = (size + PAGE_SIZE - 1) / PAGE_SIZE * PAGE_SIZE;
+        let start = sys_sbrk(size as isize);
+        if start == -1 {
+            return core::ptr::null_mut();
+        }
+        heap.add_to_heap(start as usize, start as usize + size);
+        return heap
+            .alloc(layout)
+            .map_or(core::ptr::null_mut(), |ptr| ptr.as_ptr());
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(NonNull::new_unchecked(ptr), layout);
+    }
+}
+
 #[global_allocator]
-static HEAP: LockedHeap = LockedHeap::empty();
+static HEAP: GrowableHeap = GrowableHeap(LockedHeap::empty());
 
 #[alloc_error_handler]
 pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
@@ -40,7 +78,8 @@ pub fn handle_alloc_error(layout: core::alloc::Layout) -> ! {
 #[link_section = ".text.entry"]
 pub extern "C" fn _start(argc: usize, argv: usize) -> ! {
     unsafe {
-        HEAP.lock()
+        HEAP.0
+            .lock()
             .init(HEAP_SPACE.as_ptr() as usize, USER_HEAP_SIZE);
     }
 
@@ -82,11 +121,97 @@ bitflags! {
         const RDWR=1<<1; //第1位 设置为1 可读可写
         const CREATE=1<<9; //第9位 设置为1 创建文件
         const TRUNC=1<<10; //第10位 设置为1 清空文件内容 并将该文件的大小归零
+        const APPEND=1<<11; //第11位 设置为1 每次写都写在文件末尾
+        const DIRECTORY=1<<16; //第16位 设置为1 只能打开目录, 之后用getdents读目录项
+        const NOFOLLOW=1<<17; //第17位 设置为1 路径最后是符号链接就打开失败
     }
 
 
 }
 
+/// 文件元数据, 和内核的Stat布局一致. 时间是开机后的时间
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stat {
+    pub dev: u64,
+    pub ino: u64,
+    pub mode: u32,
+    pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub rdev: u64,
+    pad: u64,
+    pub size: i64,
+    pub blksize: u32,
+    pad2: u32,
+    pub blocks: u64,
+    pub atime_sec: i64,
+    pub atime_nsec: i64,
+    pub mtime_sec: i64,
+    pub mtime_nsec: i64,
+    pub ctime_sec: i64,
+    pub ctime_nsec: i64,
+    unused: [u32; 2],
+}
+
+pub const S_IFMT: u32 = 0o170000; //文件类型的位
+pub const S_IFCHR: u32 = 0o020000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFBLK: u32 = 0o060000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+impl Stat {
+    pub fn is_dir(&self) -> bool {
+        return self.mode & S_IFMT == S_IFDIR;
+    }
+    pub fn is_symlink(&self) -> bool {
+        return self.mode & S_IFMT == S_IFLNK;
+    }
+}
+
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+
+/// getdents读出来的一条目录项(linux_dirent64)
+pub struct Dirent<'a> {
+    pub ino: u64,
+    pub d_type: u8,
+    pub name: &'a str,
+}
+
+impl Dirent<'_> {
+    pub fn is_dir(&self) -> bool {
+        return self.d_type == DT_DIR;
+    }
+}
+
+/// 遍历getdents填进缓冲区的目录项
+pub struct Dirents<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Iterator for Dirents<'a> {
+    type Item = Dirent<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.buf.is_empty() {
+            return None;
+        }
+        // d_ino(8) d_off(8) d_reclen(2) d_type(1) d_name
+        let ino = u64::from_le_bytes(self.buf[..8].try_into().unwrap());
+        let reclen = u16::from_le_bytes(self.buf[16..18].try_into().unwrap()) as usize;
+        let d_type = self.buf[18];
+        let name = &self.buf[19..reclen];
+        let len = name.iter().position(|b| *b == 0).unwrap();
+        let name = core::str::from_utf8(&name[..len]).unwrap();
+        self.buf = &self.buf[reclen..];
+        return Some(Dirent { ino, d_type, name });
+    }
+}
+
 bitflags! {
     pub struct EventfdFlags:u32{
         const DEFAULT = 0;
@@ -95,6 +220,24 @@ bitflags! {
     }
 }
 
+bitflags! {
+    pub struct ProtFlags:u32{
+        const NONE = 0;
+        const READ = 1<<0;
+        const WRITE = 1<<1;
+        const EXEC = 1<<2;
+    }
+}
+
+bitflags! {
+    pub struct MapFlags:u32{
+        const SHARED = 0x01; //修改写回文件
+        const PRIVATE = 0x02; //修改只对自己可见
+        const FIXED = 0x10; //必须映射在addr处
+        const ANONYMOUS = 0x20; //不映射文件, 页面内容全为0
+    }
+}
+
 pub fn dup(fd: usize) -> isize {
     sys_dup(fd)
 }
@@ -103,6 +246,84 @@ pub fn open(path: &str, flags: OpenFlags) -> isize {
     return sys_open(path, flags.bits);
 }
 
+/// 读目录fd的目录项到buf, 返回填了多少字节, 0表示读完了, 出错返回-1
+pub fn getdents(fd: usize, buf: &mut [u8]) -> isize {
+    return sys_getdents64(fd, buf);
+}
+
+/// 解析getdents填进buf前`len`字节的目录项
+pub fn dirents(buf: &[u8], len: usize) -> Dirents {
+    return Dirents { buf: &buf[..len] };
+}
+
+pub fn mkdir(path: &str) -> isize {
+    return sys_mkdirat(AT_FDCWD, path, 0);
+}
+
+pub fn unlink(path: &str) -> isize {
+    return sys_unlinkat(AT_FDCWD, path, 0);
+}
+
+pub fn rmdir(path: &str) -> isize {
+    return sys_unlinkat(AT_FDCWD, path, AT_REMOVEDIR);
+}
+
+pub fn link(old_path: &str, new_path: &str) -> isize {
+    return sys_linkat(AT_FDCWD, old_path, AT_FDCWD, new_path, 0);
+}
+
+/// 创建指向`target`的符号链接`link_path`, `target`可以不存在
+pub fn symlink(target: &str, link_path: &str) -> isize {
+    return sys_symlinkat(target, AT_FDCWD, link_path);
+}
+
+/// 读符号链接指向的路径到buf, 不以'\0'结尾, 返回读了多少字节
+pub fn readlink(path: &str, buf: &mut [u8]) -> isize {
+    return sys_readlinkat(AT_FDCWD, path, buf);
+}
+
+pub fn rename(old_path: &str, new_path: &str) -> isize {
+    return sys_renameat(AT_FDCWD, old_path, AT_FDCWD, new_path);
+}
+
+pub fn fstat(fd: usize, st: &mut Stat) -> isize {
+    return sys_fstat(fd, st);
+}
+
+/// 把内核块缓存里的脏块写回磁盘
+pub fn sync() -> isize {
+    return sys_sync();
+}
+
+/// 把设备`source`上类型为`fs_type`的文件系统挂载到目录`target`, 例如 mount("/dev/vda\0", "/mnt\0", "easyfs\0")
+pub fn mount(source: &str, target: &str, fs_type: &str) -> isize {
+    return sys_mount(source, target, fs_type, 0);
+}
+
+/// 卸载挂载在`target`上的文件系统, 还有打开的文件或者当前目录在里面时失败
+pub fn umount(target: &str) -> isize {
+    return sys_umount2(target, 0);
+}
+
+pub fn stat(path: &str, st: &mut Stat) -> isize {
+    return sys_fstatat(AT_FDCWD, path, st, 0);
+}
+
+/// 把普通文件`path`的大小改成`length`, 变长的部分读出来是0
+pub fn truncate(path: &str, length: isize) -> isize {
+    return sys_truncate(path, length);
+}
+
+/// 和truncate一样, 文件`fd`要可写打开
+pub fn ftruncate(fd: usize, length: isize) -> isize {
+    return sys_ftruncate(fd, length);
+}
+
+/// 和stat一样, 但路径最后是符号链接时返回链接本身的元数据
+pub fn lstat(path: &str, st: &mut Stat) -> isize {
+    return sys_fstatat(AT_FDCWD, path, st, AT_SYMLINK_NOFOLLOW);
+}
+
 pub fn close(fd: usize) -> isize {
     return sys_close(fd);
 }
@@ -119,6 +340,18 @@ pub fn write(fd: usize, buf: &[u8]) -> isize {
     return sys_write(fd, buf);
 }
 
+pub fn lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    return sys_lseek(fd, offset, whence);
+}
+
+pub fn pread(fd: usize, buf: &mut [u8], offset: usize) -> isize {
+    return sys_pread64(fd, buf, offset);
+}
+
+pub fn pwrite(fd: usize, buf: &[u8], offset: usize) -> isize {
+    return sys_pwrite64(fd, buf, offset);
+}
+
 pub fn exit(exit_code: i32) -> ! {
     println!(
         "\x1b[93m [USER] this is call exit from user lib -- pid : [{}] -- exit_code : [{}] \x1b[0m",
@@ -140,14 +373,49 @@ pub fn getpid() -> isize {
     sys_getpid()
 }
 
-pub fn get_cwd() -> isize {
-    sys_get_cwd()
+/// 把当前目录的绝对路径写进buf, 返回路径(不含结尾的'\0'), buf太小时返回None
+pub fn getcwd(buf: &mut [u8]) -> Option<&str> {
+    if sys_getcwd(buf) < 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap();
+    return core::str::from_utf8(&buf[..len]).ok();
 }
 
 pub fn chdir(path: &str) -> isize {
     return sys_chdir(path);
 }
 
+/// 映射成功返回起始地址, 失败返回-1
+pub fn mmap(
+    addr: usize,
+    len: usize,
+    prot: ProtFlags,
+    flags: MapFlags,
+    fd: usize,
+    offset: usize,
+) -> isize {
+    sys_mmap(addr, len, prot.bits, flags.bits, fd, offset)
+}
+
+pub fn munmap(addr: usize, len: usize) -> isize {
+    sys_munmap(addr, len)
+}
+
+pub fn mprotect(addr: usize, len: usize, prot: ProtFlags) -> isize {
+    sys_mprotect(addr, len, prot.bits)
+}
+
+/// 设置program break, 返回新的program break(失败时返回原来的)
+pub fn brk(addr: usize) -> isize {
+    sys_brk(addr)
+}
+
+/// 堆增长increment个字节, 返回原来的program break, 失败返回-1
+pub fn sbrk(increment: isize) -> isize {
+    sys_sbrk(increment)
+}
+
 pub fn fork() -> isize {
     sys_fork()
 }